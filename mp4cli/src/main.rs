@@ -7,12 +7,14 @@ use clap;
 use structopt::StructOpt;
 
 use mp4lib::boxes::*;
+use mp4lib::chapters::ChapterFormat;
+use mp4lib::metadata;
 use mp4lib::debug;
 use mp4lib::first_box;
 use mp4lib::io::Mp4File;
 use mp4lib::ioerr;
-use mp4lib::iter_box;
 use mp4lib::mp4box::{MP4Box, MP4};
+use mp4lib::streaming::es;
 use mp4lib::streaming::fragment::FragmentSource;
 use mp4lib::streaming::http_file::HttpFile;
 use mp4lib::streaming::subtitle;
@@ -35,17 +37,37 @@ pub enum Command {
     Mediainfo(MediainfoOpts),
 
     #[structopt(display_order = 2)]
-    /// Rewrite the mp4 file.
+    /// Rewrite the mp4 file (or convert a matroska / mpeg-ts file).
     Rewrite(RewriteOpts),
 
+    #[structopt(display_order = 2)]
+    /// Build an mp4 file from elementary streams (.h264, .hevc, .aac, .ac3, .ec3).
+    Mux(MuxOpts),
+
     #[structopt(display_order = 3)]
     /// extract subtitles.
     Subtitles(SubtitlesOpts),
 
+    #[structopt(display_order = 3)]
+    /// export or import chapters.
+    Chapters(ChaptersOpts),
+
+    #[structopt(display_order = 3)]
+    /// show or edit iTunes metadata tags.
+    Tags(TagsOpts),
+
+    #[structopt(display_order = 3)]
+    /// extract or set the cover art.
+    Cover(CoverOpts),
+
     #[structopt(display_order = 4)]
     /// fragment an mp4 file.
     Fragment(FragmentOpts),
 
+    #[structopt(display_order = 4)]
+    /// turn a fragmented mp4 file into a standard one.
+    Defragment(DefragmentOpts),
+
     #[structopt(display_order = 5)]
     /// interleave and optimize an mp4 file.
     Interleave(InterleaveOpts),
@@ -78,6 +100,24 @@ pub struct RewriteOpts {
     pub output: String,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct MuxOpts {
+    #[structopt(long)]
+    /// Video elementary stream (.h264, .hevc).
+    pub video: Option<String>,
+
+    #[structopt(long, number_of_values = 1)]
+    /// Audio elementary stream (.aac, .ac3, .ec3). Can be used more than once.
+    pub audio: Vec<String>,
+
+    #[structopt(long)]
+    /// Video frame rate. Default: from the SPS, or 25.
+    pub fps: Option<f64>,
+
+    /// Output filename.
+    pub output: String,
+}
+
 #[derive(StructOpt, Debug)]
 pub struct MediainfoOpts {
     #[structopt(short, long)]
@@ -110,6 +150,108 @@ pub struct SubtitlesOpts {
     pub input: String,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct ChaptersOpts {
+    #[structopt(short, long)]
+    /// Format (json, vtt, ogm). Default: json, or guessed from the import filename.
+    pub format: Option<mp4lib::chapters::ChapterFormat>,
+
+    #[structopt(short, long)]
+    /// Import chapters from this file, and write the result to the output file.
+    pub import: Option<String>,
+
+    #[structopt(short, long, default_value = "both")]
+    /// How to store imported chapters (quicktime, nero, both)
+    pub kind: mp4lib::chapters::ChapterKind,
+
+    /// Input filename.
+    pub input: String,
+    /// Output filename (when importing).
+    pub output: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TagsOpts {
+    #[structopt(subcommand)]
+    pub cmd: TagsCommand,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum TagsCommand {
+    /// Show the tags.
+    Show(TagsShowOpts),
+    /// Set one or more tags.
+    Set(TagsSetOpts),
+    /// Remove one or more tags.
+    Remove(TagsRemoveOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TagsShowOpts {
+    #[structopt(short, long)]
+    /// Output in JSON
+    pub json: bool,
+
+    /// Input filename.
+    pub input: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TagsSetOpts {
+    /// Input filename.
+    pub input: String,
+    /// Output filename.
+    pub output: String,
+    #[structopt(required = true)]
+    /// Tags to set, as KEY=VALUE. Freeform tags are ----:NAME or ----:MEAN:NAME (put `--` in front).
+    pub tags: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TagsRemoveOpts {
+    /// Input filename.
+    pub input: String,
+    /// Output filename.
+    pub output: String,
+    #[structopt(required = true)]
+    /// Tags to remove.
+    pub keys: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CoverOpts {
+    #[structopt(subcommand)]
+    pub cmd: CoverCommand,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum CoverCommand {
+    /// Extract the cover art.
+    Extract(CoverExtractOpts),
+    /// Set the cover art.
+    Set(CoverSetOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CoverExtractOpts {
+    /// Input filename.
+    pub input: String,
+    /// Output filename. Default: cover.jpg (or .png / .bmp)
+    pub output: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CoverSetOpts {
+    /// Input filename.
+    pub input: String,
+    /// Image filename (JPEG, PNG or BMP).
+    pub image: String,
+    /// Output filename.
+    pub output: String,
+}
+
 #[derive(StructOpt, Debug)]
 pub struct FragmentOpts {
     #[structopt(long, use_delimiter = true)]
@@ -131,6 +273,15 @@ pub struct FragmentOpts {
     pub output: String,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct DefragmentOpts {
+    /// Input filename.
+    pub input: String,
+
+    /// Output filename.
+    pub output: String,
+}
+
 #[derive(StructOpt, Debug)]
 pub struct InterleaveOpts {
     #[structopt(short, long, use_delimiter = true)]
@@ -164,6 +315,14 @@ pub struct DumpOpts {
     /// Select a track.
     pub track: u32,
 
+    #[structopt(short, long, default_value = "raw", possible_values = &["raw", "es"])]
+    /// Format (raw: sample data as-is, es: playable h264/hevc/aac/ac3/srt elementary stream)
+    pub format: String,
+
+    #[structopt(short, long)]
+    /// Output filename (default: stdout).
+    pub output: Option<String>,
+
     /// Input filename.
     pub input: String,
 }
@@ -178,6 +337,14 @@ pub struct DebugOpts {
     /// Generate HLS playlist.
     pub hls: bool,
 
+    #[structopt(long)]
+    /// Generate HLS playlists with MPEG-TS segments.
+    pub mpegts: bool,
+
+    #[structopt(long)]
+    /// Generate DASH manifest.
+    pub dash: bool,
+
     #[structopt(short, long)]
     /// Show the samples for a track.
     pub samples: bool,
@@ -217,31 +384,64 @@ fn main() -> Result<()> {
 
     match opts.cmd {
         Command::Boxes(opts) => return boxes(opts),
+        Command::Chapters(opts) => return chapters(opts),
+        Command::Cover(opts) => return cover(opts),
         Command::Debug(opts) => return debug(opts),
+        Command::Defragment(opts) => return defragment(opts),
         Command::Dump(opts) => return dump(opts),
         Command::Fragment(opts) => return fragment(opts),
         Command::Interleave(opts) => return interleave(opts),
         Command::Mediainfo(opts) => return mediainfo(opts),
+        Command::Mux(opts) => return mux(opts),
         Command::Rewrite(opts) => return rewrite(opts),
         Command::Subtitles(opts) => return subtitles(opts),
+        Command::Tags(opts) => return tags(opts),
+    }
+}
+
+// Read an mp4 file, or a matroska / mpeg-ts file as if it were one.
+fn read_mp4(path: &str) -> Result<MP4> {
+    if mp4lib::matroska::is_matroska(path) {
+        return Ok(mp4lib::matroska::open(path)?);
+    }
+    if mp4lib::mpegts::is_mpegts(path) {
+        return Ok(mp4lib::mpegts::open(path)?);
     }
+    let mut reader = Mp4File::open(path, false)?;
+    Ok(MP4::read(&mut reader)?)
 }
 
 fn rewrite(opts: RewriteOpts) -> Result<()> {
-    let mut reader = Mp4File::open(&opts.input, false)?;
-    let mut mp4 = MP4::read(&mut reader)?;
+    let mut mp4 = read_mp4(&opts.input)?;
+    let writer = File::create(&opts.output)?;
+
+    // Matroska and MPEG-TS files have no MediaDataBox, write a new one.
+    if first_box!(mp4, MediaDataBox).is_none() {
+        mp4lib::rewrite::write_interleaved(&mp4, writer)?;
+        return Ok(());
+    }
 
     mp4lib::rewrite::movie_at_front(&mut mp4);
+    mp4.write(writer)?;
+
+    Ok(())
+}
+
+fn mux(opts: MuxOpts) -> Result<()> {
+    let inputs: Vec<&str> = opts.video.iter().chain(opts.audio.iter()).map(|s| s.as_str()).collect();
+    if inputs.is_empty() {
+        return Err(anyhow!("mux: need at least one --video or --audio input"));
+    }
+    let mp4 = mp4lib::import::open(&inputs, opts.fps)?;
 
     let writer = File::create(&opts.output)?;
-    mp4.write(writer)?;
+    mp4lib::rewrite::write_interleaved(&mp4, writer)?;
 
     Ok(())
 }
 
 fn subtitles(opts: SubtitlesOpts) -> Result<()> {
-    let mut reader = Mp4File::open(&opts.input, false)?;
-    let mp4 = MP4::read(&mut reader)?;
+    let mp4 = read_mp4(&opts.input)?;
 
     let movie = mp4.movie();
     let tracks = movie.tracks();
@@ -257,16 +457,128 @@ fn subtitles(opts: SubtitlesOpts) -> Result<()> {
     Ok(())
 }
 
-fn fragment(opts: FragmentOpts) -> Result<()> {
+fn chapters(opts: ChaptersOpts) -> Result<()> {
     let mut reader = Mp4File::open(&opts.input, false)?;
-    let mp4 = MP4::read(&mut reader)?;
+    let mut mp4 = MP4::read(&mut reader)?;
+
+    let import = match opts.import {
+        Some(import) => import,
+        None => {
+            let chapters = mp4lib::chapters::chapters(&mp4)?;
+            let format = opts.format.unwrap_or(ChapterFormat::Json);
+            print!("{}", mp4lib::chapters::export(&chapters, format));
+            return Ok(());
+        },
+    };
+
+    let output = match opts.output {
+        Some(output) => output,
+        None => return Err(anyhow!("chapters: output filename required with --import")),
+    };
+    let format = match opts.format {
+        Some(format) => format,
+        None => import.parse().unwrap_or(ChapterFormat::Json),
+    };
+    let data = std::fs::read_to_string(&import)?;
+    let chapters = mp4lib::chapters::import(&data, format)?;
+    mp4lib::chapters::set_chapters(&mut mp4, &chapters, opts.kind)?;
+
+    let writer = File::create(&output)?;
+    mp4.write(writer)?;
+
+    Ok(())
+}
+
+fn tags(opts: TagsOpts) -> Result<()> {
+    let (input, output) = match &opts.cmd {
+        TagsCommand::Show(opts) => (&opts.input, None),
+        TagsCommand::Set(opts) => (&opts.input, Some(&opts.output)),
+        TagsCommand::Remove(opts) => (&opts.input, Some(&opts.output)),
+    };
+    let mut reader = Mp4File::open(input, false)?;
+    let mut mp4 = MP4::read(&mut reader)?;
+    let mut meta = metadata::metadata(&mp4)?;
+
+    match &opts.cmd {
+        TagsCommand::Show(opts) => {
+            if opts.json {
+                println!("{}", serde_json::to_string_pretty(&meta).unwrap());
+                return Ok(());
+            }
+            for key in metadata::Metadata::keys() {
+                if let Some(value) = meta.get(key) {
+                    println!("{}: {}", key, value);
+                }
+            }
+            if let Some(cover) = &meta.cover {
+                println!("cover: {:?}, {} bytes", cover.format, cover.data.len());
+            }
+            for f in &meta.freeform {
+                println!("----:{}:{}: {}", f.mean, f.name, f.value);
+            }
+            return Ok(());
+        },
+        TagsCommand::Set(opts) => {
+            for tag in &opts.tags {
+                let (key, value) = match tag.split_once('=') {
+                    Some(kv) => kv,
+                    None => return Err(anyhow!("tags: expected KEY=VALUE: {}", tag)),
+                };
+                meta.set(key, value)?;
+            }
+        },
+        TagsCommand::Remove(opts) => {
+            for key in &opts.keys {
+                meta.remove(key)?;
+            }
+        },
+    }
+    metadata::set_metadata(&mut mp4, &meta)?;
+
+    let writer = File::create(output.unwrap())?;
+    mp4.write(writer)?;
+
+    Ok(())
+}
+
+fn cover(opts: CoverOpts) -> Result<()> {
+    match opts.cmd {
+        CoverCommand::Extract(opts) => {
+            let mut reader = Mp4File::open(&opts.input, false)?;
+            let mp4 = MP4::read(&mut reader)?;
+            let cover = match metadata::cover_art(&mp4)? {
+                Some(cover) => cover,
+                None => return Err(anyhow!("cover: {}: no cover art", opts.input)),
+            };
+            let output = opts.output.unwrap_or_else(|| format!("cover.{}", cover.extension()));
+            std::fs::write(&output, &cover.data)?;
+        },
+        CoverCommand::Set(opts) => {
+            let data = std::fs::read(&opts.image)?;
+            let cover = match metadata::CoverArt::from_data(data) {
+                Some(cover) => cover,
+                None => return Err(anyhow!("cover: {}: not a JPEG, PNG or BMP image", opts.image)),
+            };
+            let mut reader = Mp4File::open(&opts.input, false)?;
+            let mut mp4 = MP4::read(&mut reader)?;
+            metadata::set_cover_art(&mut mp4, Some(cover))?;
+
+            let writer = File::create(&opts.output)?;
+            mp4.write(writer)?;
+        },
+    }
+    Ok(())
+}
+
+fn fragment(opts: FragmentOpts) -> Result<()> {
+    let mp4 = read_mp4(&opts.input)?;
     let mut tracks = Vec::new();
 
     let track = mp4
         .movie()
         .track_by_id(opts.track)
         .ok_or(anyhow!("track {} not found", opts.track))?;
-    let segments = mp4lib::streaming::segmenter::track_to_segments(track, opts.duration, None)?;
+    let segments = mp4lib::streaming::segmenter::track_to_segments(&mp4, track, opts.duration, None)?;
     tracks.push(opts.track);
 
     // See if we wanted an extra track.
@@ -277,13 +589,13 @@ fn fragment(opts: FragmentOpts) -> Result<()> {
             .movie()
             .track_by_id(t2)
             .ok_or(anyhow!("track {} not found", t2))?;
-        segments2 = mp4lib::streaming::segmenter::track_to_segments_timed(track, &segments)?;
+        segments2 = mp4lib::streaming::segmenter::track_to_segments_timed(&mp4, track, &segments)?;
         track2 = t2;
         tracks.push(track2);
     }
 
     // Media init section (empty moov).
-    let mut mp4_frag = mp4lib::streaming::fragment::media_init_section(&mp4, &tracks);
+    let mut mp4_frag = mp4lib::streaming::fragment::media_init_section(&mp4, &tracks)?;
 
     let mut segments_iter = segments.iter();
     let mut segments2_iter = segments2.iter();
@@ -337,6 +649,25 @@ fn fragment(opts: FragmentOpts) -> Result<()> {
     Ok(())
 }
 
+fn defragment(opts: DefragmentOpts) -> Result<()> {
+    // The sample tables of a fragmented file are empty, so it only
+    // is a valid MP4 after defragmenting.
+    let mut reader = Mp4File::open(&opts.input, false)?;
+    let mp4 = MP4::read_dont_validate(&mut reader)?;
+    if first_box!(mp4, MovieBox).is_none() {
+        return Err(anyhow!("defragment: {}: no MovieBox present", opts.input));
+    }
+    let mp4 = mp4lib::rewrite::defragment(&mp4)?;
+    if !mp4.is_valid() {
+        return Err(anyhow!("defragment: {}: invalid MP4 file", opts.input));
+    }
+
+    let writer = File::create(&opts.output)?;
+    mp4lib::rewrite::write_interleaved(&mp4, writer)?;
+
+    Ok(())
+}
+
 fn interleave(opts: InterleaveOpts) -> Result<()> {
     let mut reader = mp4lib::streaming::pseudo::Mp4Stream::open(&opts.input, &opts.tracks[..])
         .map_err(|e| ioerr!(e.kind(), "{}: {}", opts.input, e))?;
@@ -359,15 +690,18 @@ fn interleave(opts: InterleaveOpts) -> Result<()> {
 }
 
 fn short(track: &mp4lib::track::TrackInfo) {
+    let encrypted = match track.protection.as_ref() {
+        Some(p) => format!(", encrypted [{}]", p.scheme_type),
+        None => String::new(),
+    };
     println!(
-        "{}. type [{}], length {:?}, lang {}, codec {}",
-        track.id, track.track_type, track.duration, track.language, track.specific_info
+        "{}. type [{}], length {:?}, lang {}, codec {}{}",
+        track.id, track.track_type, track.duration, track.language, track.specific_info, encrypted
     );
 }
 
 fn mediainfo(opts: MediainfoOpts) -> Result<()> {
-    let mut reader = Mp4File::open(&opts.input, false)?;
-    let mp4 = MP4::read(&mut reader)?;
+    let mp4 = read_mp4(&opts.input)?;
     let mp4 = mp4.clone();
 
     let res = mp4lib::track::track_info(&mp4);
@@ -415,12 +749,27 @@ fn mediainfo(opts: MediainfoOpts) -> Result<()> {
 }
 
 fn dump(opts: DumpOpts) -> Result<()> {
+    let mut handle: Box<dyn Write> = match opts.output.as_ref() {
+        Some(output) => Box::new(BufWriter::with_capacity(128000, File::create(output)?)),
+        None => Box::new(BufWriter::with_capacity(128000, io::stdout())),
+    };
+
+    if opts.format == "es" {
+        let mp4 = read_mp4(&opts.input)?;
+        let movie = mp4.movie();
+        let track = match movie.track_by_id(opts.track) {
+            Some(track) => track,
+            None => return Err(anyhow!("dump: track id {} not found", opts.track)),
+        };
+        es::export(&mp4, track, &mut handle)?;
+        handle.flush()?;
+        return Ok(());
+    }
+
     let mut reader = Mp4File::open(&opts.input, false)?;
     let mp4 = MP4::read_dont_validate(&mut reader)?;
 
     let infh = reader.file();
-    let stdout = io::stdout();
-    let mut handle = BufWriter::with_capacity(128000, stdout.lock());
     let mut buffer = Vec::new();
 
     if let Some(movie) = mp4lib::first_box!(mp4, MovieBox) {
@@ -430,8 +779,8 @@ fn dump(opts: DumpOpts) -> Result<()> {
             None => return Err(anyhow!("dump: track id {} not found", opts.track)),
         };
 
-        // tracks.
-        for sample_info in track.sample_info_iter() {
+        // tracks, including the samples in the fragments.
+        for sample_info in mp4.sample_info_iter(track) {
             let sz = sample_info.size as usize;
             if buffer.len() < sz {
                 buffer.resize(sz, 0);
@@ -441,33 +790,7 @@ fn dump(opts: DumpOpts) -> Result<()> {
         }
     }
 
-    let dfl_sample_size = 0;
-
-    for moof in iter_box!(mp4, MovieFragmentBox) {
-        for traf in iter_box!(moof, TrackFragmentBox) {
-            let tfhd = first_box!(traf, TrackFragmentHeaderBox).unwrap();
-            if !tfhd.default_base_is_moof {
-                return Err(anyhow!(
-                    "dump: track_id {}: default_base_is_moof not set in fragment",
-                    opts.track
-                ));
-            }
-            for trun in iter_box!(traf, TrackRunBox) {
-                let offset = moof.offset + trun.data_offset.unwrap_or(0) as u64;
-                let mut size = 0;
-                for entry in &trun.entries {
-                    size += entry.sample_size.unwrap_or(dfl_sample_size);
-                }
-                let sz = size as usize;
-                if buffer.len() < sz {
-                    buffer.resize(sz, 0);
-                }
-                infh.read_exact_at(&mut buffer[..sz], offset)?;
-                handle.write_all(&buffer[..sz])?;
-            }
-        }
-    }
-
+    handle.flush()?;
     Ok(())
 }
 
@@ -538,12 +861,15 @@ fn boxes(opts: BoxesOpts) -> Result<()> {
 }
 
 fn debug(opts: DebugOpts) -> Result<()> {
-    let mut reader = Mp4File::open(&opts.input, false)?;
-    let mp4 = MP4::read(&mut reader)?;
+    let mp4 = read_mp4(&opts.input)?;
 
     if opts.hls {
         let m3u = if let Some(track) = opts.track {
-            mp4lib::streaming::hls::hls_track(&mp4, track, None)?
+            mp4lib::streaming::hls::hls_track(&mp4, track, None, opts.mpegts)?
+        } else if opts.mpegts {
+            let mut master = mp4lib::streaming::hls::HlsMaster::new(&mp4, false);
+            master.set_mpegts();
+            master.to_string()
         } else {
             mp4lib::streaming::hls::hls_master(&mp4, false, false)
         };
@@ -551,6 +877,12 @@ fn debug(opts: DebugOpts) -> Result<()> {
         return Ok(());
     }
 
+    if opts.dash {
+        let mpd = mp4lib::streaming::dash::dash_manifest(&mp4, false, false, None)?;
+        print!("{}", mpd);
+        return Ok(());
+    }
+
     if opts.samples {
         let track = match opts.track {
             Some(track) => track,
@@ -568,7 +900,7 @@ fn debug(opts: DebugOpts) -> Result<()> {
                 .ok_or(anyhow!("track {} not found", track))?,
             None => return Err(anyhow!("debug: fragment: need --track")),
         };
-        let segments = mp4lib::streaming::segmenter::track_to_segments(track, None, None)?;
+        let segments = mp4lib::streaming::segmenter::track_to_segments(&mp4, track, None, None)?;
         let longest = segments.iter().fold(0_f64, |max, t| {
             if t.duration.partial_cmp(&max) == Some(std::cmp::Ordering::Greater) {
                 t.duration
@@ -52,7 +52,7 @@ impl<'a> BitReader<'a> {
         if cnt == 0 {
             return Ok(0);
         }
-        if cnt > 8 {
+        if cnt > 31 {
             return Err(io::ErrorKind::InvalidData.into());
         }
         let val = self.read_bits(cnt as u8)?;
@@ -76,3 +76,85 @@ impl<'a> BitReader<'a> {
         Ok(res)
     }
 }
+
+// Remove the emulation prevention bytes: 00 00 03 xx -> 00 00 xx.
+pub(crate) fn unescape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(data.len());
+    let mut zeroes = 0;
+    for &b in data {
+        if zeroes >= 2 && b == 3 {
+            zeroes = 0;
+            continue;
+        }
+        zeroes = if b == 0 { zeroes + 1 } else { 0 };
+        v.push(b);
+    }
+    v
+}
+
+// Add the emulation prevention bytes: 00 00 xx -> 00 00 03 xx, for xx <= 3.
+#[cfg(test)]
+pub(crate) fn escape_rbsp(data: &[u8]) -> Vec<u8> {
+    let mut v = Vec::with_capacity(data.len());
+    let mut zeroes = 0;
+    for &b in data {
+        if zeroes >= 2 && b <= 3 {
+            v.push(3);
+            zeroes = 0;
+        }
+        zeroes = if b == 0 { zeroes + 1 } else { 0 };
+        v.push(b);
+    }
+    v
+}
+
+// Write binary data bit-by-bit, to build bitstreams for the tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct BitWriter {
+    pub data: Vec<u8>,
+    pub pos: usize,
+}
+
+#[cfg(test)]
+impl BitWriter {
+    pub fn bit(&mut self, bit: bool) -> &mut Self {
+        if self.pos % 8 == 0 {
+            self.data.push(0);
+        }
+        if bit {
+            *self.data.last_mut().unwrap() |= 0x80 >> (self.pos % 8);
+        }
+        self.pos += 1;
+        self
+    }
+
+    pub fn bits(&mut self, n: u8, val: u64) -> &mut Self {
+        for i in (0..n).rev() {
+            self.bit((val >> i) & 1 != 0);
+        }
+        self
+    }
+
+    /// Write unsigned exp-golomb code
+    pub fn ue(&mut self, val: u32) -> &mut Self {
+        let val = val as u64 + 1;
+        let len = 64 - val.leading_zeros() as u8;
+        self.bits(len - 1, 0).bits(len, val)
+    }
+
+    /// Write signed exp-golomb code
+    pub fn se(&mut self, val: i32) -> &mut Self {
+        let val = if val > 0 { 2 * val - 1 } else { -2 * val };
+        self.ue(val as u32)
+    }
+
+    /// rbsp_trailing_bits(): a stop bit, then zero bits up to the next byte.
+    pub fn trailing(&mut self) -> &mut Self {
+        self.bit(true);
+        while self.pos % 8 != 0 {
+            self.bit(false);
+        }
+        self
+    }
+}
@@ -41,6 +41,9 @@ def_boxes! {
     UserDataBox, b"udta";
 
     // Below are boxes that are defined manually in boxes/ *.rs
+    Av1SampleEntry, b"av01" => av01;
+    AV1CodecConfigurationBox, b"av1C";
+
     AvcSampleEntry, b"avc1" => avc1;
     AvcConfigurationBox, b"avcC" => avcc;
 
@@ -49,10 +52,17 @@ def_boxes! {
 
     Ac3SampleEntry, b"ac-3" => ac_3;
     AC3SpecificBox, b"dac3";
+    Ec3SampleEntry, b"ec-3";
+    EC3SpecificBox, b"dec3";
 
     AppleItemListBox, b"ilst" => ilst;
 
     ChapterListBox, b"chpl" => chpl;
+
+    ColourInformationBox, b"colr" => colr;
+    MasteringDisplayColourVolumeBox, b"mdcv";
+    ContentLightLevelBox, b"clli";
+
     ChunkOffsetBox, b"stco" => stco;
     ChunkLargeOffsetBox, b"co64";
     CompositionOffsetBox, b"ctts" => ctts;
@@ -62,9 +72,27 @@ def_boxes! {
     DataEntryUrnBox, b"urn ";
     DataReferenceBox, b"dref";
 
+    DolbyVisionConfigurationBox, b"dvcC" => dvcc;
+    DolbyVisionExtConfigurationBox, b"dvvC";
+
     EditBox, b"edts" => edts;
     EditListBox, b"elst";
 
+    EncryptedVideoSampleEntry, b"encv" => cenc;
+    EncryptedAudioSampleEntry, b"enca";
+    ProtectionSchemeInfoBox, b"sinf";
+    OriginalFormatBox, b"frma";
+    SchemeTypeBox, b"schm";
+    SchemeInformationBox, b"schi";
+    TrackEncryptionBox, b"tenc";
+    ProtectionSystemSpecificHeaderBox, b"pssh";
+    SampleEncryptionBox, b"senc";
+    SampleAuxiliaryInformationSizesBox, b"saiz";
+    SampleAuxiliaryInformationOffsetsBox, b"saio";
+
+    FlacSampleEntry, b"fLaC" => flac;
+    FlacSpecificBox, b"dfLa";
+
     HandlerBox, b"hdlr" => hdlr;
     MediaBox, b"mdia" => mdia;
     MediaDataBox, b"mdat" => mdat;
@@ -79,6 +107,11 @@ def_boxes! {
     HEVCSampleEntry, b"hvc1" => hvc1;
     HEV1SampleEntry, b"hev1";
     HEVCConfigurationBox, b"hvcC";
+    DVH1SampleEntry, b"dvh1";
+    DVHESampleEntry, b"dvhe";
+
+    OpusSampleEntry, b"Opus" => opus;
+    OpusSpecificBox, b"dOps";
 
     ProgressiveDownloadInfoBox, b"pdin" => pdin;
 
@@ -111,4 +144,19 @@ def_boxes! {
     XMLSubtitleSampleEntry, b"stpp";
 
     VideoMediaHeaderBox, b"vmhd" => vmhd;
+
+    Vp9SampleEntry, b"vp09" => vp09;
+    VPCodecConfigurationBox, b"vpcC";
+
+    WebVTTSampleEntry, b"wvtt" => wvtt;
+    WebVTTConfigurationBox, b"vttC";
+    WebVTTSourceLabelBox, b"vlab";
+    VTTCueBox, b"vttc";
+    VTTEmptyCueBox, b"vtte";
+    VTTAdditionalTextBox, b"vtta";
+    CueSourceIDBox, b"vsid";
+    CueIDBox, b"iden";
+    CueTimeBox, b"ctim";
+    CueSettingsBox, b"sttg";
+    CuePayloadBox, b"payl";
 }
@@ -134,29 +134,63 @@ pub enum AudioService {
     VoiceOver,
 }
 
+// Channel layout as coded in `acmod`.
+fn acmod_channel_configuration(acmod: u8) -> &'static str {
+    match acmod {
+        0 => "1+2",
+        1 => "C",
+        2 => "L,R",
+        3 => "L,C,R",
+        4 => "L,R,S",
+        5 => "L,C,R,S",
+        6 => "L,R,SL,SR",
+        7 => "L,C,R,SL,SR",
+        _ => "unknown",
+    }
+}
+
+// Number of channels as coded in `acmod`.
+fn acmod_num_channels(acmod: u8) -> u8 {
+    if acmod > 7 {
+        // unknown, so it's probably going to be decoded as stereo.
+        return 2;
+    }
+    [2, 1, 2, 3, 3, 4, 4, 5][acmod as usize]
+}
+
+// Sampling rate as coded in `fscod`.
+fn fscod_sampling_rate(fscod: u8) -> u32 {
+    if fscod > 2 {
+        return 0;
+    }
+    [48000, 44100, 32000][fscod as usize]
+}
+
+// Audio service as coded in `bsmod`.
+fn bsmod_audio_service(bsmod: u8) -> AudioService {
+    use AudioService::*;
+    match bsmod {
+        0 => CompleteMain,
+        1 => MusicAndEffects,
+        2 => VisuallyImpaired,
+        3 => HearingImpaired,
+        4 => Dialog,
+        5 => Commentary,
+        6 => Emergency,
+        7 => VoiceOver,
+        _ => CompleteMain,
+    }
+}
+
 impl AC3SpecificBox {
     /// Sampling rate as coded in `fscod`. `0` means "unknown" (fscod 0b11).
     pub fn sampling_rate(&self) -> u32 {
-        if self.fscod > 2 {
-            return 0;
-        }
-        [48000, 44100, 32000][self.fscod as usize]
+        fscod_sampling_rate(self.fscod)
     }
 
     /// Audio service from bsmod.
     pub fn audio_service(&self) -> AudioService {
-        use AudioService::*;
-        match self.bsmod {
-            0 => CompleteMain,
-            1 => MusicAndEffects,
-            2 => VisuallyImpaired,
-            3 => HearingImpaired,
-            4 => Dialog,
-            5 => Commentary,
-            6 => Emergency,
-            7 => VoiceOver,
-            _ => CompleteMain,
-        }
+        bsmod_audio_service(self.bsmod)
     }
 
     /// Audio channels. "1+2", "L,C,R,SL,SR", etc.
@@ -167,26 +201,12 @@ impl AC3SpecificBox {
     /// LFE=Low Frequency Effects (sub)
     ///
     pub fn channel_configuration(&self) -> &'static str {
-        match self.acmod {
-            0 => "1+2",
-            1 => "C",
-            2 => "L,R",
-            3 => "L,C,R",
-            4 => "L,R,S",
-            5 => "L,C,R,S",
-            6 => "L,R,SL,SR",
-            7 => "L,C,R,SL,SR",
-            _ => "unknown",
-        }
+        acmod_channel_configuration(self.acmod)
     }
 
     /// Number of audio channels.
     pub fn num_channels(&self) -> u8 {
-        if self.acmod > 7 {
-            // unknown, so it's probably going to be decoded as stereo.
-            return 2;
-        }
-        [2, 1, 2, 3, 3, 4, 4, 5][self.acmod as usize]
+        acmod_num_channels(self.acmod)
     }
 
     /// LFE (sub) channel?
@@ -218,3 +238,402 @@ impl std::fmt::Debug for AC3SpecificBox {
     }
 }
 
+def_box! {
+    /// E-AC-3 sample entry.
+    Ec3SampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   8,
+        // (mono = 1 ; stereo = 2)
+        channel_count: u16,
+        // audio sample number of bits 8 or 16
+        sample_size: u16,
+        skip:                   4,
+        sample_rate_hi:         u16,
+        sample_rate_lo:         u16,
+        // sub boxes, probably only dec3.
+        boxes: Vec<MP4Box>,
+    },
+    fourcc => "ec-3",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl Default for Ec3SampleEntry {
+    fn default() -> Ec3SampleEntry {
+        Ec3SampleEntry {
+            data_reference_index:   1,
+            channel_count:          2,
+            sample_size:            16,
+            sample_rate_hi:         0,
+            sample_rate_lo:         0,
+            boxes:                  Vec::new(),
+        }
+    }
+}
+
+impl Ec3SampleEntry {
+    /// Return audio specific track info.
+    pub fn track_info(&self) -> AudioTrackInfo {
+        let mut ai = AudioTrackInfo {
+            codec_id:   "ec-3".to_string(),
+            codec_name: Some("E-AC-3 Dolby Digital Plus".to_string()),
+            channel_count:  self.channel_count,
+            bit_depth: if self.sample_size > 0 { Some(self.sample_size) } else { None },
+            sample_rate: if self.sample_rate_hi > 0 { Some(self.sample_rate_hi as u32) } else { None },
+            ..AudioTrackInfo::default()
+        };
+
+        if let Some(dec3) = first_box!(&self.boxes, EC3SpecificBox) {
+            if !dec3.substreams.is_empty() {
+                ai.channel_count = dec3.num_channels() as u16;
+                ai.lfe_channel = dec3.lfe_channel();
+                if dec3.sampling_rate() > 0 {
+                    ai.sample_rate = Some(dec3.sampling_rate());
+                }
+                ai.channel_configuration = Some(dec3.channel_configuration());
+            }
+            ai.avg_bitrate = if dec3.bitrate() > 0 { Some(dec3.bitrate()) } else { None };
+            ai.max_bitrate = if dec3.bitrate() > 0 { Some(dec3.bitrate()) } else { None };
+            ai.joc_complexity = dec3.joc_complexity;
+            if dec3.joc_complexity.is_some() {
+                ai.codec_name = Some("E-AC-3 Dolby Digital Plus with Dolby Atmos".to_string());
+            }
+        }
+
+        ai
+    }
+}
+
+// class EC3SpecificBox {
+//     unsigned int(13) data_rate;
+//     unsigned int(3) num_ind_sub;
+//     for (i = 0; i < num_ind_sub + 1; i++) {
+//         unsigned int(2) fscod;
+//         unsigned int(5) bsid;
+//         unsigned int(1) reserved;
+//         unsigned int(1) asvc;
+//         unsigned int(3) bsmod;
+//         unsigned int(3) acmod;
+//         unsigned int(1) lfeon;
+//         unsigned int(3) reserved;
+//         unsigned int(4) num_dep_sub;
+//         if (num_dep_sub > 0) {
+//             unsigned int(9) chan_loc;
+//         } else {
+//             unsigned int(1) reserved;
+//         }
+//     }
+//     // optional, present for Dolby Atmos (JOC).
+//     unsigned int(7) reserved;
+//     unsigned int(1) flag_ec3_extension_type_a;
+//     unsigned int(8) complexity_index_type_a;
+// }
+def_box! {
+    /// E-AC-3 specific box (ETSI TS 102 366 Annex F).
+    EC3SpecificBox {
+        // in kbit/s.
+        data_rate: u16,
+        substreams: Vec<EC3IndependentSubstream>,
+        // Dolby Atmos: Joint Object Coding complexity index.
+        joc_complexity: Option<u8>,
+    },
+    fourcc => "dec3",
+    version => [],
+    impls => [ basebox, boxinfo ],
+}
+
+/// E-AC-3 independent substream.
+#[derive(Clone, Debug, Default)]
+pub struct EC3IndependentSubstream {
+    pub fscod: u8,
+    pub bsid: u8,
+    pub asvc: bool,
+    pub bsmod: u8,
+    pub acmod: u8,
+    pub lfeon: bool,
+    pub num_dep_sub: u8,
+    /// Extra channel locations in the dependent substreams.
+    pub chan_loc: u16,
+}
+
+impl FromBytes for EC3SpecificBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<EC3SpecificBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let data = Data::from_bytes(&mut reader)?;
+        let mut b = BitReader::new(&data.0);
+
+        let data_rate = b.read_bits(13)? as u16;
+        let num_ind_sub = b.read_bits(3)? + 1;
+        let mut substreams = Vec::new();
+        for _ in 0 .. num_ind_sub {
+            let fscod = b.read_bits(2)? as u8;
+            let bsid = b.read_bits(5)? as u8;
+            b.read_bits(1)?;
+            let asvc = b.read_bit()?;
+            let bsmod = b.read_bits(3)? as u8;
+            let acmod = b.read_bits(3)? as u8;
+            let lfeon = b.read_bit()?;
+            b.read_bits(3)?;
+            let num_dep_sub = b.read_bits(4)? as u8;
+            let chan_loc = if num_dep_sub > 0 {
+                b.read_bits(9)? as u16
+            } else {
+                b.read_bits(1)?;
+                0
+            };
+            substreams.push(EC3IndependentSubstream {
+                fscod,
+                bsid,
+                asvc,
+                bsmod,
+                acmod,
+                lfeon,
+                num_dep_sub,
+                chan_loc,
+            });
+        }
+
+        // Optional Dolby Atmos extension.
+        let mut joc_complexity = None;
+        if b.read_bits(7).is_ok() && b.read_bit().unwrap_or(false) {
+            joc_complexity = b.read_u8().ok();
+        }
+
+        Ok(EC3SpecificBox {
+            data_rate,
+            substreams,
+            joc_complexity,
+        })
+    }
+    fn min_size() -> usize { 13 }
+}
+
+impl ToBytes for EC3SpecificBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+
+        // Pack all fields into a bitstream, MSB first.
+        let mut bits = Vec::<(u32, u8)>::new();
+        bits.push((self.data_rate as u32, 13));
+        bits.push((self.substreams.len().saturating_sub(1) as u32, 3));
+        for s in &self.substreams {
+            bits.push((s.fscod as u32, 2));
+            bits.push((s.bsid as u32, 5));
+            bits.push((0, 1));
+            bits.push((s.asvc as u32, 1));
+            bits.push((s.bsmod as u32, 3));
+            bits.push((s.acmod as u32, 3));
+            bits.push((s.lfeon as u32, 1));
+            bits.push((0, 3));
+            bits.push((s.num_dep_sub as u32, 4));
+            if s.num_dep_sub > 0 {
+                bits.push((s.chan_loc as u32, 9));
+            } else {
+                bits.push((0, 1));
+            }
+        }
+        if let Some(complexity) = self.joc_complexity {
+            bits.push((1, 8));
+            bits.push((complexity as u32, 8));
+        }
+
+        let mut data = Vec::new();
+        let mut acc = 0u8;
+        let mut nbits = 0;
+        for (value, count) in bits {
+            for i in (0 .. count).rev() {
+                acc = (acc << 1) | ((value >> i) & 1) as u8;
+                nbits += 1;
+                if nbits == 8 {
+                    data.push(acc);
+                    acc = 0;
+                    nbits = 0;
+                }
+            }
+        }
+        if nbits > 0 {
+            data.push(acc << (8 - nbits));
+        }
+        writer.write(&data)?;
+
+        writer.finalize()
+    }
+}
+
+impl EC3SpecificBox {
+    /// Sampling rate of the main independent substream. `0` means "unknown".
+    pub fn sampling_rate(&self) -> u32 {
+        self.substreams.first().map(|s| fscod_sampling_rate(s.fscod)).unwrap_or(0)
+    }
+
+    /// Audio service of the main independent substream.
+    pub fn audio_service(&self) -> AudioService {
+        bsmod_audio_service(self.substreams.first().map(|s| s.bsmod).unwrap_or(0))
+    }
+
+    /// Audio channels of the main independent substream, including
+    /// the channels in its dependent substreams. "L,C,R,SL,SR,BSL,BSR", etc.
+    ///
+    /// Next to the channels from `AC3SpecificBox::channel_configuration`:
+    ///
+    /// LC/RC=Left/Right Center, BSL/BSR=Back Surround Left/Right, BS=Back Surround,
+    /// TS=Top Surround, SDL/SDR=Surround Direct Left/Right, WL/WR=Wide Left/Right,
+    /// VHL/VHR=Vertical Height Left/Right, VHC=Vertical Height Center,
+    /// LFE2=second LFE channel.
+    pub fn channel_configuration(&self) -> String {
+        let s = match self.substreams.first() {
+            Some(s) => s,
+            None => return "unknown".to_string(),
+        };
+        let mut cfg = acmod_channel_configuration(s.acmod).to_string();
+        for (bit, name) in CHAN_LOC.iter() {
+            if s.chan_loc & bit != 0 {
+                cfg.push(',');
+                cfg.push_str(name);
+            }
+        }
+        cfg
+    }
+
+    /// Number of audio channels in the main independent substream,
+    /// including dependent substreams, not including the LFE channel.
+    pub fn num_channels(&self) -> u8 {
+        let s = match self.substreams.first() {
+            Some(s) => s,
+            None => return 2,
+        };
+        let extra = CHAN_LOC
+            .iter()
+            .filter(|(bit, name)| s.chan_loc & bit != 0 && *name != "LFE2")
+            .map(|(_, name)| name.split(',').count() as u8)
+            .sum::<u8>();
+        acmod_num_channels(s.acmod) + extra
+    }
+
+    /// LFE (sub) channel?
+    pub fn lfe_channel(&self) -> bool {
+        self.substreams.first().map(|s| s.lfeon).unwrap_or(false)
+    }
+
+    /// Bitrate from data_rate.
+    pub fn bitrate(&self) -> u32 {
+        self.data_rate as u32 * 1000
+    }
+
+    /// Is this Dolby Atmos (Joint Object Coding)?
+    pub fn atmos(&self) -> bool {
+        self.joc_complexity.is_some()
+    }
+}
+
+// Channel locations in `chan_loc`, MSB first.
+const CHAN_LOC: [(u16, &str); 9] = [
+    (0x100, "LC,RC"),
+    (0x080, "BSL,BSR"),
+    (0x040, "BS"),
+    (0x020, "TS"),
+    (0x010, "SDL,SDR"),
+    (0x008, "WL,WR"),
+    (0x004, "VHL,VHR"),
+    (0x002, "VHC"),
+    (0x001, "LFE2"),
+];
+
+impl std::fmt::Debug for EC3SpecificBox {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut dbg = f.debug_struct("EC3SpecificBox");
+        dbg.field("bitrate", &self.bitrate());
+        dbg.field("audio_service", &self.audio_service());
+        dbg.field("channel_configuration", &self.channel_configuration());
+        dbg.field("sub_channel", &self.lfe_channel());
+        dbg.field("num_channels", &self.num_channels());
+        dbg.field("joc_complexity", &self.joc_complexity);
+        dbg.field("substreams", &self.substreams);
+        dbg.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::BitWriter;
+    use crate::io::MemBuffer;
+
+    // One independent substream at 48 kHz, bsid 16.
+    fn dec3_box(data_rate: u16, acmod: u8, lfeon: bool, chan_loc: Option<u16>, joc: Option<u8>) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(13, data_rate as u64).bits(3, 0);
+        w.bits(2, 0).bits(5, 16).bit(false).bit(false).bits(3, 0);
+        w.bits(3, acmod as u64).bit(lfeon).bits(3, 0);
+        match chan_loc {
+            Some(chan_loc) => w.bits(4, 1).bits(9, chan_loc as u64),
+            None => w.bits(4, 0).bit(false),
+        };
+        if let Some(complexity) = joc {
+            w.bits(8, 1).bits(8, complexity as u64);
+        }
+        while w.pos % 8 != 0 {
+            w.bit(false);
+        }
+
+        let mut b = Vec::new();
+        b.extend_from_slice(&(8 + w.data.len() as u32).to_be_bytes());
+        b.extend_from_slice(b"dec3");
+        b.extend_from_slice(&w.data);
+        b
+    }
+
+    fn round_trip(data: &[u8]) -> EC3SpecificBox {
+        let dec3 = EC3SpecificBox::from_bytes(&mut &data[..]).unwrap();
+        let mut buffer = MemBuffer::new();
+        dec3.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+        dec3
+    }
+
+    #[test]
+    fn dec3_5_1() {
+        let dec3 = round_trip(&dec3_box(640, 7, true, None, None));
+        assert_eq!(dec3.substreams.len(), 1);
+        assert_eq!(dec3.substreams[0].bsid, 16);
+        assert_eq!(dec3.sampling_rate(), 48000);
+        assert_eq!(dec3.bitrate(), 640_000);
+        assert_eq!(dec3.channel_configuration(), "L,C,R,SL,SR");
+        assert_eq!(dec3.num_channels(), 5);
+        assert!(dec3.lfe_channel());
+        assert!(!dec3.atmos());
+    }
+
+    #[test]
+    fn dec3_7_1_dependent_substream() {
+        let dec3 = round_trip(&dec3_box(1024, 7, true, Some(0x080), None));
+        assert_eq!(dec3.substreams[0].num_dep_sub, 1);
+        assert_eq!(dec3.channel_configuration(), "L,C,R,SL,SR,BSL,BSR");
+        assert_eq!(dec3.num_channels(), 7);
+    }
+
+    #[test]
+    fn dec3_atmos() {
+        let dec3 = round_trip(&dec3_box(768, 7, true, None, Some(16)));
+        assert!(dec3.atmos());
+        assert_eq!(dec3.joc_complexity, Some(16));
+    }
+
+    #[test]
+    fn dec3_truncated() {
+        // Cut off in the middle of the substream.
+        let mut data = dec3_box(640, 7, true, None, None);
+        data.truncate(11);
+        data[3] = 11;
+        let err = EC3SpecificBox::from_bytes(&mut &data[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // Atmos extension flag without the complexity index.
+        let mut data = dec3_box(768, 7, true, None, Some(16));
+        data.truncate(14);
+        data[3] = 14;
+        let dec3 = EC3SpecificBox::from_bytes(&mut &data[..]).unwrap();
+        assert!(!dec3.atmos());
+    }
+}
@@ -0,0 +1,633 @@
+//
+// AV1 Codec ISO Media File Format Binding v1.2.0
+// https://aomediacodec.github.io/av1-isobmff/
+//
+
+use std::io;
+
+use crate::bitreader::BitReader;
+use crate::boxes::prelude::*;
+use crate::track::{ColorInfo, VideoTrackInfo};
+
+def_box! {
+    /// AV1 sample entry (VideoSampleEntry 'av01').
+    ///
+    /// Contains:
+    ///
+    /// - AV1CodecConfigurationBox (one)
+    /// - extra boxes (btrt, colr, pasp, etc).
+    Av1SampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   16,
+        width:                  u16,
+        height:                 u16,
+        // defaults to 72, 72
+        _video_horizontal_dpi:  FixedFloat16_16,
+        _video_vertical_dpi:    FixedFloat16_16,
+        skip:                   4,
+        // defaults to 1
+        _video_frame_count:     u16,
+        // Video encoder name is a fixed-size pascal string.
+        // _video_encoder_name: PascalString<32>,
+        skip:                   32,
+        // defaults to 0x0018;
+        video_pixel_depth:      u16,
+        // always -1
+        _pre_defined:           u16,
+        // av1C, etc.
+        boxes:              Vec<MP4Box>,
+    },
+    fourcc => "av01",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl Default for Av1SampleEntry {
+    fn default() -> Self {
+        Av1SampleEntry {
+            data_reference_index:     1,
+            width:                    1280,
+            height:                   720,
+            _video_horizontal_dpi:    FixedFloat16_16::from(72f64),
+            _video_vertical_dpi:      FixedFloat16_16::from(72f64),
+            _video_frame_count:       1,
+            video_pixel_depth:        24,
+            _pre_defined:             0xffff,
+            boxes:                    Vec::new(),
+        }
+    }
+}
+
+impl Av1SampleEntry {
+    /// Return video specific track info.
+    pub fn track_info(&self) -> VideoTrackInfo {
+        let config = first_box!(self.boxes, AV1CodecConfigurationBox);
+        let codec_id = match config {
+            Some(c) => c.configuration.codec_id(),
+            None => "av01.unknown".to_string(),
+        };
+        let codec_name = match config {
+            Some(c) => c.configuration.codec_name(),
+            None => "AV1",
+        };
+        let seq_hdr = config.and_then(|c| c.configuration.sequence_header().ok().flatten());
+        let frame_rate = seq_hdr.as_ref().and_then(|s| s.frame_rate()).unwrap_or(0f64);
+
+        // The sample entry width / height should always be set, but
+        // if it isn't, use the max frame size from the sequence header.
+        let (mut width, mut height) = (self.width, self.height);
+        if let Some(seq_hdr) = seq_hdr.as_ref() {
+            if width == 0 || height == 0 {
+                width = seq_hdr.max_frame_width as u16;
+                height = seq_hdr.max_frame_height as u16;
+            }
+        }
+
+        VideoTrackInfo {
+            codec_id,
+            codec_name: Some(codec_name.to_string()),
+            width,
+            height,
+            frame_rate,
+            color: seq_hdr.as_ref().map(|s| ColorInfo {
+                colour_primaries: s.color_primaries as u16,
+                transfer_characteristics: s.transfer_characteristics as u16,
+                matrix_coefficients: s.matrix_coefficients as u16,
+                full_range: s.color_range,
+            }),
+            ..VideoTrackInfo::default()
+        }
+    }
+}
+
+def_box! {
+    /// AV1 Codec Configuration box.
+    ///
+    /// Contains just the AV1CodecConfigurationRecord.
+    AV1CodecConfigurationBox {
+        configuration:  AV1CodecConfigurationRecord,
+    },
+    fourcc => "av1C",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+// aligned (8) class AV1CodecConfigurationRecord {
+//     unsigned int (1) marker = 1;
+//     unsigned int (7) version = 1;
+//     unsigned int (3) seq_profile;
+//     unsigned int (5) seq_level_idx_0;
+//     unsigned int (1) seq_tier_0;
+//     unsigned int (1) high_bitdepth;
+//     unsigned int (1) twelve_bit;
+//     unsigned int (1) monochrome;
+//     unsigned int (1) chroma_subsampling_x;
+//     unsigned int (1) chroma_subsampling_y;
+//     unsigned int (2) chroma_sample_position;
+//     unsigned int (3) reserved = 0;
+//     unsigned int (1) initial_presentation_delay_present;
+//     if (initial_presentation_delay_present) {
+//         unsigned int (4) initial_presentation_delay_minus_one;
+//     } else {
+//         unsigned int (4) reserved = 0;
+//     }
+//     unsigned int (8) configOBUs[];
+// }
+//
+def_struct! {
+    /// AV1 Codec Configuration Record.
+    AV1CodecConfigurationRecord,
+        // 1 bit: marker, 7 bits: version.
+        marker_version: u8,
+        // 3 bits: seq_profile, 5 bits: seq_level_idx_0.
+        profile_level: u8,
+        // 1 bit each: seq_tier_0, high_bitdepth, twelve_bit, monochrome,
+        // chroma_subsampling_x, chroma_subsampling_y.
+        // 2 bits: chroma_sample_position.
+        flags: u8,
+        // 3 bits reserved, 1 bit: initial_presentation_delay_present,
+        // 4 bits: initial_presentation_delay_minus_one.
+        presentation_delay: u8,
+        // Sequence Header OBU, and optionally Metadata OBUs.
+        config_obus: Data,
+}
+
+impl AV1CodecConfigurationRecord {
+    /// seq_profile: 0 = Main, 1 = High, 2 = Professional.
+    pub fn profile(&self) -> u8 {
+        self.profile_level >> 5
+    }
+
+    /// seq_level_idx_0.
+    pub fn level(&self) -> u8 {
+        self.profile_level & 0b00011111
+    }
+
+    /// seq_tier_0: false = Main, true = High.
+    pub fn tier(&self) -> bool {
+        self.flags & 0x80 != 0
+    }
+
+    /// Bit depth, 8, 10 or 12.
+    pub fn bit_depth(&self) -> u8 {
+        let high_bitdepth = self.flags & 0x40 != 0;
+        let twelve_bit = self.flags & 0x20 != 0;
+        match (high_bitdepth, twelve_bit) {
+            (true, true) => 12,
+            (true, false) => 10,
+            _ => 8,
+        }
+    }
+
+    /// Monochrome.
+    pub fn monochrome(&self) -> bool {
+        self.flags & 0x10 != 0
+    }
+
+    /// Return human name of codec, like "AV1 Main".
+    pub fn codec_name(&self) -> &'static str {
+        match self.profile() {
+            0 => "AV1 Main",
+            1 => "AV1 High",
+            2 => "AV1 Professional",
+            _ => "AV1",
+        }
+    }
+
+    /// Return codec id as `av01.P.LLT.DD` (e.g. `av01.0.08M.10`).
+    ///
+    /// See [the AV1 ISOBMFF binding, Codecs Parameter String](https://aomediacodec.github.io/av1-isobmff/#codecsparam).
+    pub fn codec_id(&self) -> String {
+        format!(
+            "av01.{}.{:02}{}.{:02}",
+            self.profile(),
+            self.level(),
+            if self.tier() { 'H' } else { 'M' },
+            self.bit_depth()
+        )
+    }
+
+    /// Find and decode the Sequence Header OBU in `config_obus`.
+    pub fn sequence_header(&self) -> io::Result<Option<SequenceHeader>> {
+        let mut data = &self.config_obus.0[..];
+        while !data.is_empty() {
+            let (obu_type, payload, rest) = read_obu(data)?;
+            if obu_type == OBU_SEQUENCE_HEADER {
+                let mut reader = BitReader::new(payload);
+                return SequenceHeader::read(&mut reader).map(Some);
+            }
+            data = rest;
+        }
+        Ok(None)
+    }
+}
+
+const OBU_SEQUENCE_HEADER: u8 = 1;
+
+// Read leb128 encoded value.
+fn read_leb128(data: &[u8]) -> io::Result<(u64, usize)> {
+    let mut value = 0u64;
+    for i in 0..8 {
+        let b = *data
+            .get(i)
+            .ok_or_else(|| ioerr!(UnexpectedEof, "av1: leb128: EOF"))?;
+        value |= ((b & 0x7f) as u64) << (i * 7);
+        if b & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(ioerr!(InvalidData, "av1: leb128: value too large"))
+}
+
+// Split one OBU off the front of `data`.
+//
+// Returns (obu_type, payload, rest).
+fn read_obu(data: &[u8]) -> io::Result<(u8, &[u8], &[u8])> {
+    if data.is_empty() {
+        return Err(ioerr!(UnexpectedEof, "av1: OBU header: EOF"));
+    }
+    let header = data[0];
+    let obu_type = (header >> 3) & 0x0f;
+    let extension_flag = header & 0x04 != 0;
+    let has_size_field = header & 0x02 != 0;
+    let mut idx = if extension_flag { 2 } else { 1 };
+    if idx > data.len() {
+        return Err(ioerr!(UnexpectedEof, "av1: OBU extension header: EOF"));
+    }
+    let size = if has_size_field {
+        let (size, len) = read_leb128(&data[idx..])?;
+        idx += len;
+        size as usize
+    } else {
+        data.len() - idx
+    };
+    if idx + size > data.len() {
+        return Err(ioerr!(UnexpectedEof, "av1: OBU payload: EOF"));
+    }
+    Ok((obu_type, &data[idx..idx + size], &data[idx + size..]))
+}
+
+// Read variable length unsigned n-bit number (uvlc() in the spec).
+fn read_uvlc(reader: &mut BitReader) -> io::Result<u32> {
+    let mut leading_zeros = 0u8;
+    while !reader.read_bit()? {
+        leading_zeros += 1;
+        if leading_zeros >= 32 {
+            return Ok(u32::MAX);
+        }
+    }
+    let value = reader.read_bits(leading_zeros)?;
+    Ok(value + ((1u64 << leading_zeros) - 1) as u32)
+}
+
+/// AV1 Sequence Header OBU.
+///
+/// Only the fields that are interesting for track information are kept.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceHeader {
+    pub seq_profile: u8,
+    pub still_picture: bool,
+    pub seq_level_idx: u8,
+    pub seq_tier: bool,
+    pub timing_info: Option<Av1TimingInfo>,
+    pub max_frame_width: u32,
+    pub max_frame_height: u32,
+    pub bit_depth: u8,
+    pub mono_chrome: bool,
+    pub color_primaries: u8,
+    pub transfer_characteristics: u8,
+    pub matrix_coefficients: u8,
+    pub color_range: bool,
+    pub subsampling_x: bool,
+    pub subsampling_y: bool,
+    pub chroma_sample_position: u8,
+    pub film_grain_params_present: bool,
+}
+
+/// AV1 timing info.
+#[derive(Clone, Debug, Default)]
+pub struct Av1TimingInfo {
+    pub num_units_in_display_tick: u32,
+    pub time_scale: u32,
+    pub num_ticks_per_picture: Option<u32>,
+}
+
+impl SequenceHeader {
+    /// Frame rate, if the sequence header has timing info with a fixed picture interval.
+    pub fn frame_rate(&self) -> Option<f64> {
+        let t = self.timing_info.as_ref()?;
+        let ticks = t.num_ticks_per_picture? as f64 * t.num_units_in_display_tick as f64;
+        if ticks == 0.0 {
+            return None;
+        }
+        let fr = t.time_scale as f64 / ticks;
+        if fr > 300.0 {
+            log::warn!("av1::SequenceHeader::frame_rate: impossible rate {}, ignoring", fr);
+            return None;
+        }
+        Some(fr)
+    }
+
+    fn read(r: &mut BitReader) -> io::Result<SequenceHeader> {
+        let mut sh = SequenceHeader {
+            seq_profile: r.read_bits(3)? as u8,
+            still_picture: r.read_bit()?,
+            ..SequenceHeader::default()
+        };
+        let reduced_still_picture_header = r.read_bit()?;
+
+        if reduced_still_picture_header {
+            sh.seq_level_idx = r.read_bits(5)? as u8;
+        } else {
+            let mut decoder_model_info_present = false;
+            let mut buffer_delay_length = 0;
+            if r.read_bit()? {
+                // timing_info()
+                let num_units_in_display_tick = r.read_bits(32)?;
+                let time_scale = r.read_bits(32)?;
+                let equal_picture_interval = r.read_bit()?;
+                let num_ticks_per_picture = if equal_picture_interval {
+                    Some(read_uvlc(r)?.saturating_add(1))
+                } else {
+                    None
+                };
+                sh.timing_info = Some(Av1TimingInfo {
+                    num_units_in_display_tick,
+                    time_scale,
+                    num_ticks_per_picture,
+                });
+                decoder_model_info_present = r.read_bit()?;
+                if decoder_model_info_present {
+                    // decoder_model_info()
+                    buffer_delay_length = r.read_bits(5)? as u8 + 1;
+                    let _num_units_in_decoding_tick = r.read_bits(32)?;
+                    let _buffer_removal_time_length_minus_1 = r.read_bits(5)?;
+                    let _frame_presentation_time_length_minus_1 = r.read_bits(5)?;
+                }
+            }
+            let initial_display_delay_present = r.read_bit()?;
+            let operating_points_cnt = r.read_bits(5)? + 1;
+            for i in 0..operating_points_cnt {
+                let _operating_point_idc = r.read_bits(12)?;
+                let seq_level_idx = r.read_bits(5)? as u8;
+                let seq_tier = seq_level_idx > 7 && r.read_bit()?;
+                if decoder_model_info_present && r.read_bit()? {
+                    // operating_parameters_info()
+                    let _decoder_buffer_delay = r.read_bits(buffer_delay_length)?;
+                    let _encoder_buffer_delay = r.read_bits(buffer_delay_length)?;
+                    let _low_delay_mode_flag = r.read_bit()?;
+                }
+                if initial_display_delay_present && r.read_bit()? {
+                    let _initial_display_delay_minus_1 = r.read_bits(4)?;
+                }
+                if i == 0 {
+                    sh.seq_level_idx = seq_level_idx;
+                    sh.seq_tier = seq_tier;
+                }
+            }
+        }
+
+        let frame_width_bits = r.read_bits(4)? as u8 + 1;
+        let frame_height_bits = r.read_bits(4)? as u8 + 1;
+        sh.max_frame_width = r.read_bits(frame_width_bits)? + 1;
+        sh.max_frame_height = r.read_bits(frame_height_bits)? + 1;
+
+        let frame_id_numbers_present = !reduced_still_picture_header && r.read_bit()?;
+        if frame_id_numbers_present {
+            let _delta_frame_id_length_minus_2 = r.read_bits(4)?;
+            let _additional_frame_id_length_minus_1 = r.read_bits(3)?;
+        }
+        let _use_128x128_superblock = r.read_bit()?;
+        let _enable_filter_intra = r.read_bit()?;
+        let _enable_intra_edge_filter = r.read_bit()?;
+
+        if !reduced_still_picture_header {
+            let _enable_interintra_compound = r.read_bit()?;
+            let _enable_masked_compound = r.read_bit()?;
+            let _enable_warped_motion = r.read_bit()?;
+            let _enable_dual_filter = r.read_bit()?;
+            let enable_order_hint = r.read_bit()?;
+            if enable_order_hint {
+                let _enable_jnt_comp = r.read_bit()?;
+                let _enable_ref_frame_mvs = r.read_bit()?;
+            }
+            let seq_choose_screen_content_tools = r.read_bit()?;
+            let seq_force_screen_content_tools = if seq_choose_screen_content_tools {
+                2
+            } else {
+                r.read_bits(1)?
+            };
+            if seq_force_screen_content_tools > 0 {
+                let seq_choose_integer_mv = r.read_bit()?;
+                if !seq_choose_integer_mv {
+                    let _seq_force_integer_mv = r.read_bit()?;
+                }
+            }
+            if enable_order_hint {
+                let _order_hint_bits_minus_1 = r.read_bits(3)?;
+            }
+        }
+
+        let _enable_superres = r.read_bit()?;
+        let _enable_cdef = r.read_bit()?;
+        let _enable_restoration = r.read_bit()?;
+
+        sh.read_color_config(r)?;
+        sh.film_grain_params_present = r.read_bit()?;
+
+        Ok(sh)
+    }
+
+    // color_config()
+    fn read_color_config(&mut self, r: &mut BitReader) -> io::Result<()> {
+        let high_bitdepth = r.read_bit()?;
+        self.bit_depth = if self.seq_profile == 2 && high_bitdepth {
+            if r.read_bit()? { 12 } else { 10 }
+        } else if high_bitdepth {
+            10
+        } else {
+            8
+        };
+        self.mono_chrome = self.seq_profile != 1 && r.read_bit()?;
+
+        // 2 = unspecified.
+        self.color_primaries = 2;
+        self.transfer_characteristics = 2;
+        self.matrix_coefficients = 2;
+        if r.read_bit()? {
+            self.color_primaries = r.read_u8()?;
+            self.transfer_characteristics = r.read_u8()?;
+            self.matrix_coefficients = r.read_u8()?;
+        }
+
+        if self.mono_chrome {
+            self.color_range = r.read_bit()?;
+            self.subsampling_x = true;
+            self.subsampling_y = true;
+            return Ok(());
+        }
+
+        if self.color_primaries == 1 && self.transfer_characteristics == 13 && self.matrix_coefficients == 0 {
+            // sRGB.
+            self.color_range = true;
+        } else {
+            self.color_range = r.read_bit()?;
+            match self.seq_profile {
+                0 => {
+                    self.subsampling_x = true;
+                    self.subsampling_y = true;
+                },
+                1 => {},
+                _ => {
+                    if self.bit_depth == 12 {
+                        self.subsampling_x = r.read_bit()?;
+                        self.subsampling_y = self.subsampling_x && r.read_bit()?;
+                    } else {
+                        self.subsampling_x = true;
+                    }
+                },
+            }
+            if self.subsampling_x && self.subsampling_y {
+                self.chroma_sample_position = r.read_bits(2)? as u8;
+            }
+        }
+        let _separate_uv_delta_q = r.read_bit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::BitWriter;
+    use crate::io::MemBuffer;
+
+    // Main profile, level 4.0 high tier, 10 bit 1920x1080 @ 59.94, BT.2020 PQ.
+    fn sequence_header_obu() -> Vec<u8> {
+        let mut w = BitWriter::default();
+        // seq_profile, still_picture, reduced_still_picture_header.
+        w.bits(3, 0).bit(false).bit(false);
+        // timing_info: 1001 / 60000, equal_picture_interval, num_ticks_per_picture_minus_1 0.
+        w.bit(true).bits(32, 1001).bits(32, 60000).bit(true).bit(true);
+        // decoder_model_info_present, initial_display_delay_present, operating_points_cnt_minus_1.
+        w.bit(false).bit(false).bits(5, 0);
+        // operating_point_idc, seq_level_idx, seq_tier.
+        w.bits(12, 0).bits(5, 8).bit(true);
+        // frame width / height bits, max frame width / height.
+        w.bits(4, 10).bits(4, 10).bits(11, 1919).bits(11, 1079);
+        // frame_id_numbers_present.
+        w.bit(false);
+        // 128x128 superblock up to and including enable_ref_frame_mvs.
+        w.bits(10, 0x3ff);
+        // seq_choose_screen_content_tools, seq_choose_integer_mv, order_hint_bits_minus_1.
+        w.bit(true).bit(true).bits(3, 6);
+        // enable_superres, enable_cdef, enable_restoration.
+        w.bit(false).bit(true).bit(true);
+        // color_config: high_bitdepth, mono_chrome, color_description_present.
+        w.bit(true).bit(false).bit(true).bits(8, 9).bits(8, 16).bits(8, 9);
+        // color_range, chroma_sample_position, separate_uv_delta_q.
+        w.bit(false).bits(2, 0).bit(false);
+        // film_grain_params_present.
+        w.bit(false).trailing();
+
+        let mut obu = vec![(OBU_SEQUENCE_HEADER << 3) | 0x02, w.data.len() as u8];
+        obu.extend_from_slice(&w.data);
+        obu
+    }
+
+    fn av1c_box(config_obus: &[u8]) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&(12 + config_obus.len() as u32).to_be_bytes());
+        b.extend_from_slice(b"av1C");
+        b.extend_from_slice(&[0x81, 0x08, 0xcc, 0x00]);
+        b.extend_from_slice(config_obus);
+        b
+    }
+
+    #[test]
+    fn av1c_round_trip() {
+        let data = av1c_box(&sequence_header_obu());
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let mut buffer = MemBuffer::new();
+        av1c.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+
+        let config = &av1c.configuration;
+        assert_eq!(config.profile(), 0);
+        assert_eq!(config.level(), 8);
+        assert!(config.tier());
+        assert_eq!(config.bit_depth(), 10);
+        assert!(!config.monochrome());
+        assert_eq!(config.codec_name(), "AV1 Main");
+        assert_eq!(config.codec_id(), "av01.0.08H.10");
+    }
+
+    #[test]
+    fn sequence_header() {
+        let data = av1c_box(&sequence_header_obu());
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let sh = av1c.configuration.sequence_header().unwrap().unwrap();
+        assert_eq!(sh.seq_level_idx, 8);
+        assert!(sh.seq_tier);
+        assert_eq!((sh.max_frame_width, sh.max_frame_height), (1920, 1080));
+        assert_eq!(sh.bit_depth, 10);
+        assert_eq!(sh.color_primaries, 9);
+        assert_eq!(sh.transfer_characteristics, 16);
+        assert_eq!(sh.matrix_coefficients, 9);
+        assert!(sh.subsampling_x && sh.subsampling_y);
+        assert!(!sh.film_grain_params_present);
+        let fr = sh.frame_rate().unwrap();
+        assert!((fr - 59.94).abs() < 0.01);
+    }
+
+    #[test]
+    fn sequence_header_after_metadata_obu() {
+        // A metadata OBU without size field would swallow the rest, so give it one.
+        let mut obus = vec![(5 << 3) | 0x02, 2, 0x01, 0x00];
+        obus.extend_from_slice(&sequence_header_obu());
+        let data = av1c_box(&obus);
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let sh = av1c.configuration.sequence_header().unwrap().unwrap();
+        assert_eq!((sh.max_frame_width, sh.max_frame_height), (1920, 1080));
+    }
+
+    #[test]
+    fn no_sequence_header() {
+        let data = av1c_box(&[]);
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        assert!(av1c.configuration.sequence_header().unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_obu() {
+        let obu = sequence_header_obu();
+
+        // Payload shorter than the OBU size field.
+        let data = av1c_box(&obu[..obu.len() - 1]);
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let err = av1c.configuration.sequence_header().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // Size field that never ends.
+        let data = av1c_box(&[(OBU_SEQUENCE_HEADER << 3) | 0x02, 0x80, 0x80]);
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let err = av1c.configuration.sequence_header().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // Sequence header cut off halfway, without size field.
+        let mut short = vec![OBU_SEQUENCE_HEADER << 3];
+        short.extend_from_slice(&obu[2..8]);
+        let data = av1c_box(&short);
+        let av1c = AV1CodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let err = av1c.configuration.sequence_header().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated_record() {
+        let data = [0, 0, 0, 10, b'a', b'v', b'1', b'C', 0x81, 0x08];
+        assert!(AV1CodecConfigurationBox::from_bytes(&mut &data[..]).is_err());
+    }
+}
@@ -76,6 +76,7 @@ impl AvcSampleEntry {
             width: self.width,
             height: self.height,
             frame_rate,
+            ..VideoTrackInfo::default()
         }
     }
 }
@@ -3,7 +3,7 @@
 use std::io;
 
 use crate::boxes::prelude::*;
-use crate::bitreader::BitReader;
+use crate::bitreader::{unescape_rbsp, BitReader};
 
 def_box! {
     /// AvcConfigurationBox (ISO/IEC 14496-15)
@@ -89,6 +89,19 @@ impl AvcDecoderConfigurationRecord {
         }
         Ok(None)
     }
+
+    /// Decode the first Sequence Parameter Set.
+    pub fn seq_parameter_set(&self) -> Option<SeqParameterSet> {
+        let parameter_sets = ParameterSet::parse(&self.data.0).ok()?;
+        match parameter_sets.sequence_parameters_sets() {
+            Ok(mut v) if !v.is_empty() => Some(v.remove(0)),
+            Ok(_) => None,
+            Err(e) => {
+                log::debug!("AvcDecoderConfigurationRecord: cannot parse SPS: {}", e);
+                None
+            },
+        }
+    }
 }
 
 /// delegated to AvcDecoderConfigurationRecord::codec_id().
@@ -115,8 +128,9 @@ impl std::fmt::Display for AvcDecoderConfigurationRecord {
 //
 #[derive(Debug, Default)]
 pub(crate) struct ParameterSet<'a> {
-    sps: Vec<&'a [u8]>,
-    _pps: Vec<&'a [u8]>,
+    pub(crate) sps: Vec<&'a [u8]>,
+    #[cfg_attr(not(feature = "streaming"), allow(dead_code))]
+    pub(crate) pps: Vec<&'a [u8]>,
     _length_size_minus_one: u8,
 }
 
@@ -169,7 +183,7 @@ impl<'a> ParameterSet<'a> {
 
         Ok(ParameterSet {
             sps: sps_items,
-            _pps: pps_items,
+            pps: pps_items,
             _length_size_minus_one: nalu_length_size_minus_one,
         })
     }
@@ -188,8 +202,8 @@ impl<'a> ParameterSet<'a> {
                 continue;
             }
             idx += 1;
-            // FIXME: unescape, so 00 00 03 01 -> 00 00 01
-            let mut reader = BitReader::new(&sps[idx..]);
+            let rbsp = unescape_rbsp(&sps[idx..]);
+            let mut reader = BitReader::new(&rbsp);
             let parsed = SeqParameterSet::read(&mut reader)?;
             v.push(parsed);
         }
@@ -198,7 +212,7 @@ impl<'a> ParameterSet<'a> {
 }
 
 // Helper.
-fn cond<F, T, E>(pred: bool, mut f: F) -> Result<Option<T>, E>
+pub(crate) fn cond<F, T, E>(pred: bool, mut f: F) -> Result<Option<T>, E>
 where
     F: FnMut() -> Result<T, E>
 {
@@ -230,7 +244,7 @@ pub struct SeqParameterSet {
 }
 
 impl SeqParameterSet {
-    fn read(reader: &mut BitReader) -> io::Result<SeqParameterSet> {
+    pub(crate) fn read(reader: &mut BitReader) -> io::Result<SeqParameterSet> {
 
         let profile_idc = reader.read_u8()?;
         Ok(SeqParameterSet {
@@ -251,6 +265,50 @@ impl SeqParameterSet {
             vui_parameters: cond(reader.read_bit()?, || VuiParameters::read(reader))?,
         })
     }
+
+    // Crop units (CropUnitX, CropUnitY), 7.4.2.1.1.
+    fn crop_units(&self) -> (u32, u32) {
+        let frame_height_factor = self.frame_height_factor();
+        let (chroma_format_idc, separate_colour_plane) = match self.chroma_format.as_ref() {
+            Some(c) => (c.chroma_format_idc, c.residual_color_transform_flag.unwrap_or(false)),
+            None => (1, false),
+        };
+        if separate_colour_plane {
+            return (1, frame_height_factor);
+        }
+        match chroma_format_idc {
+            1 => (2, 2 * frame_height_factor),
+            2 => (2, frame_height_factor),
+            _ => (1, frame_height_factor),
+        }
+    }
+
+    // 2 if the frame consists of two fields, 1 otherwise.
+    fn frame_height_factor(&self) -> u32 {
+        match self.frame_mbs_flags {
+            FrameMbsFlags::Frames => 1,
+            FrameMbsFlags::Fields { .. } => 2,
+        }
+    }
+
+    /// Display width, after frame cropping.
+    pub fn width(&self) -> u32 {
+        let (crop_unit_x, _) = self.crop_units();
+        let crop = self.frame_cropping.as_ref()
+            .map(|c| c.frame_crop_left_offset + c.frame_crop_right_offset)
+            .unwrap_or(0);
+        ((self.pic_width_in_mbs_minus1 + 1) * 16).saturating_sub(crop_unit_x * crop)
+    }
+
+    /// Display height, after frame cropping.
+    pub fn height(&self) -> u32 {
+        let (_, crop_unit_y) = self.crop_units();
+        let crop = self.frame_cropping.as_ref()
+            .map(|c| c.frame_crop_top_offset + c.frame_crop_bottom_offset)
+            .unwrap_or(0);
+        let height = self.frame_height_factor() * (self.pic_height_in_map_units_minus1 + 1) * 16;
+        height.saturating_sub(crop_unit_y * crop)
+    }
 }
 
 /// Picture Order Count Type.
@@ -355,7 +413,7 @@ pub struct ChromaFormat {
 impl ChromaFormat {
     fn read(reader: &mut BitReader, profile_indication: u8) -> io::Result<Option<ChromaFormat>> {
         match profile_indication {
-            100|110|122|144 => {},
+            100|110|122|244|44|83|86|118|128|138|139|134|135|144 => {},
             _ => return Ok(None),
         }
 
@@ -472,7 +530,7 @@ pub struct AspectRatioInfo {
 }
 
 impl AspectRatioInfo {
-    fn read(reader: &mut BitReader) -> io::Result<AspectRatioInfo> {
+    pub(crate) fn read(reader: &mut BitReader) -> io::Result<AspectRatioInfo> {
         let aspect_ratio = reader.read_u8()?;
         let extended_sar = if aspect_ratio == 255 {
             let sar_width = reader.read_bits(16)? as u16;
@@ -483,6 +541,22 @@ impl AspectRatioInfo {
         };
         Ok(AspectRatioInfo{ aspect_ratio, extended_sar })
     }
+
+    /// Sample aspect ratio (horizontal, vertical), Table E-1.
+    pub fn sample_aspect_ratio(&self) -> Option<(u16, u16)> {
+        const SAR: [(u16, u16); 17] = [
+            (0, 0), (1, 1), (12, 11), (10, 11), (16, 11), (40, 33), (24, 11), (20, 11), (32, 11),
+            (80, 33), (18, 11), (15, 11), (64, 33), (160, 99), (4, 3), (3, 2), (2, 1),
+        ];
+        let sar = match self.aspect_ratio {
+            255 => self.extended_sar?,
+            idx => *SAR.get(idx as usize)?,
+        };
+        if sar.0 == 0 || sar.1 == 0 {
+            return None;
+        }
+        Some(sar)
+    }
 }
 
 /// Video Signal Type.
@@ -494,7 +568,7 @@ pub struct VideoSignalType {
 }
 
 impl VideoSignalType {
-    fn read(reader: &mut BitReader) -> io::Result<VideoSignalType> {
+    pub(crate) fn read(reader: &mut BitReader) -> io::Result<VideoSignalType> {
         Ok(VideoSignalType {
             video_format: reader.read_bits(3)? as u8,
             video_full_range_flag: reader.read_bit()?,
@@ -529,7 +603,7 @@ pub struct ChromaLocInfo {
 }
 
 impl ChromaLocInfo {
-    fn read(reader: &mut BitReader) -> io::Result<ChromaLocInfo> {
+    pub(crate) fn read(reader: &mut BitReader) -> io::Result<ChromaLocInfo> {
         Ok(ChromaLocInfo {
             chroma_sample_loc_type_top_field: reader.read_ue()?,
             chroma_sample_loc_type_bottom_field: reader.read_ue()?,
@@ -0,0 +1,840 @@
+//
+// ISO/IEC 14496-12:2015(E)
+// 8.12 Support for protected streams
+//
+// ISO/IEC 23001-7:2016 Common encryption in ISO base media file format files
+//
+
+use std::io;
+
+use crate::boxes::prelude::*;
+use crate::boxes::*;
+
+def_box! {
+    /// Encrypted video sample entry (VisualSampleEntry).
+    ///
+    /// The original sample entry type is in `sinf/frma`.
+    EncryptedVideoSampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   16,
+        width:                  u16,
+        height:                 u16,
+        _video_horizontal_dpi:  FixedFloat16_16,
+        _video_vertical_dpi:    FixedFloat16_16,
+        skip:                   4,
+        _video_frame_count:     u16,
+        skip:                   32,
+        video_pixel_depth:      u16,
+        _pre_defined:           u16,
+        // sinf, and the boxes of the original sample entry.
+        boxes:              Vec<MP4Box>,
+    },
+    fourcc => "encv",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// Encrypted audio sample entry (AudioSampleEntry).
+    ///
+    /// The original sample entry type is in `sinf/frma`.
+    EncryptedAudioSampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   8,
+        channel_count:          u16,
+        sample_size:            u16,
+        skip:                   4,
+        sample_rate_hi:         u16,
+        sample_rate_lo:         u16,
+        // sinf, and the boxes of the original sample entry.
+        boxes:                  Vec<MP4Box>,
+    },
+    fourcc => "enca",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+// The boxes of the original sample entry, without the sinf box.
+fn original_boxes(boxes: &[MP4Box]) -> Vec<MP4Box> {
+    boxes
+        .iter()
+        .filter(|b| !matches!(b, MP4Box::ProtectionSchemeInfoBox(_)))
+        .cloned()
+        .collect()
+}
+
+macro_rules! original_video_entry {
+    ($self:expr, $type:ident) => {
+        $type {
+            data_reference_index: $self.data_reference_index,
+            width: $self.width,
+            height: $self.height,
+            _video_horizontal_dpi: $self._video_horizontal_dpi.clone(),
+            _video_vertical_dpi: $self._video_vertical_dpi.clone(),
+            _video_frame_count: $self._video_frame_count,
+            video_pixel_depth: $self.video_pixel_depth,
+            _pre_defined: $self._pre_defined,
+            boxes: original_boxes(&$self.boxes),
+        }
+        .to_mp4box()
+    };
+}
+
+macro_rules! original_audio_entry {
+    ($self:expr, $type:ident) => {
+        $type {
+            data_reference_index: $self.data_reference_index,
+            channel_count: $self.channel_count,
+            sample_size: $self.sample_size,
+            sample_rate_hi: $self.sample_rate_hi,
+            sample_rate_lo: $self.sample_rate_lo,
+            boxes: original_boxes(&$self.boxes),
+        }
+        .to_mp4box()
+    };
+}
+
+impl EncryptedVideoSampleEntry {
+    /// Protection scheme information.
+    pub fn protection_scheme_info(&self) -> Option<&ProtectionSchemeInfoBox> {
+        first_box!(&self.boxes, ProtectionSchemeInfoBox)
+    }
+
+    /// Return the sample entry as it was before encryption.
+    ///
+    /// Returns `None` if the original format is unknown or not supported.
+    pub fn original_sample_entry(&self) -> Option<MP4Box> {
+        let format = self.protection_scheme_info()?.original_format()?;
+        let entry = match &format.to_be_bytes() {
+            b"avc1" => original_video_entry!(self, AvcSampleEntry),
+            b"hvc1" => original_video_entry!(self, HEVCSampleEntry),
+            b"hev1" => original_video_entry!(self, HEV1SampleEntry),
+            b"dvh1" => original_video_entry!(self, DVH1SampleEntry),
+            b"dvhe" => original_video_entry!(self, DVHESampleEntry),
+            b"av01" => original_video_entry!(self, Av1SampleEntry),
+            b"vp09" => original_video_entry!(self, Vp9SampleEntry),
+            _ => return None,
+        };
+        Some(entry)
+    }
+}
+
+impl EncryptedAudioSampleEntry {
+    /// Protection scheme information.
+    pub fn protection_scheme_info(&self) -> Option<&ProtectionSchemeInfoBox> {
+        first_box!(&self.boxes, ProtectionSchemeInfoBox)
+    }
+
+    /// Return the sample entry as it was before encryption.
+    ///
+    /// Returns `None` if the original format is unknown or not supported.
+    pub fn original_sample_entry(&self) -> Option<MP4Box> {
+        let format = self.protection_scheme_info()?.original_format()?;
+        let entry = match &format.to_be_bytes() {
+            b"mp4a" => original_audio_entry!(self, AacSampleEntry),
+            b"ac-3" => original_audio_entry!(self, Ac3SampleEntry),
+            b"ec-3" => original_audio_entry!(self, Ec3SampleEntry),
+            b"Opus" => original_audio_entry!(self, OpusSampleEntry),
+            b"fLaC" => original_audio_entry!(self, FlacSampleEntry),
+            _ => return None,
+        };
+        Some(entry)
+    }
+}
+
+/// Protection scheme info of a sample entry, if it is encrypted.
+pub(crate) fn protection_scheme_info(entry: &MP4Box) -> Option<&ProtectionSchemeInfoBox> {
+    match entry {
+        MP4Box::EncryptedVideoSampleEntry(e) => e.protection_scheme_info(),
+        MP4Box::EncryptedAudioSampleEntry(e) => e.protection_scheme_info(),
+        _ => None,
+    }
+}
+
+/// If this is an encrypted sample entry, return the original sample entry.
+pub(crate) fn original_sample_entry(entry: &MP4Box) -> Option<MP4Box> {
+    match entry {
+        MP4Box::EncryptedVideoSampleEntry(e) => e.original_sample_entry(),
+        MP4Box::EncryptedAudioSampleEntry(e) => e.original_sample_entry(),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "streaming")]
+macro_rules! encrypted_video_entry {
+    ($entry:expr, $sinf:expr) => {
+        EncryptedVideoSampleEntry {
+            data_reference_index: $entry.data_reference_index,
+            width: $entry.width,
+            height: $entry.height,
+            _video_horizontal_dpi: $entry._video_horizontal_dpi.clone(),
+            _video_vertical_dpi: $entry._video_vertical_dpi.clone(),
+            _video_frame_count: $entry._video_frame_count,
+            video_pixel_depth: $entry.video_pixel_depth,
+            _pre_defined: $entry._pre_defined,
+            boxes: $entry.boxes.iter().cloned().chain(Some($sinf.to_mp4box())).collect(),
+        }
+        .to_mp4box()
+    };
+}
+
+#[cfg(feature = "streaming")]
+macro_rules! encrypted_audio_entry {
+    ($entry:expr, $sinf:expr) => {
+        EncryptedAudioSampleEntry {
+            data_reference_index: $entry.data_reference_index,
+            channel_count: $entry.channel_count,
+            sample_size: $entry.sample_size,
+            sample_rate_hi: $entry.sample_rate_hi,
+            sample_rate_lo: $entry.sample_rate_lo,
+            boxes: $entry.boxes.iter().cloned().chain(Some($sinf.to_mp4box())).collect(),
+        }
+        .to_mp4box()
+    };
+}
+
+/// Wrap a sample entry in an `encv` or `enca` sample entry.
+///
+/// The `sinf` box should not contain the `frma` box, it is added here.
+/// Returns `None` if this type of sample entry is not supported.
+#[cfg(feature = "streaming")]
+pub(crate) fn encrypted_sample_entry(entry: &MP4Box, sinf: &ProtectionSchemeInfoBox) -> Option<MP4Box> {
+    let mut sinf = sinf.clone();
+    let frma = OriginalFormatBox { data_format: entry.fourcc() };
+    sinf.boxes.insert(0, frma.to_mp4box());
+    let entry = match entry {
+        MP4Box::AvcSampleEntry(e) => encrypted_video_entry!(e, sinf),
+        MP4Box::HEVCSampleEntry(e) => encrypted_video_entry!(e, sinf),
+        MP4Box::HEV1SampleEntry(e) => encrypted_video_entry!(e, sinf),
+        MP4Box::DVH1SampleEntry(e) => encrypted_video_entry!(e, sinf),
+        MP4Box::DVHESampleEntry(e) => encrypted_video_entry!(e, sinf),
+        MP4Box::AacSampleEntry(e) => encrypted_audio_entry!(e, sinf),
+        MP4Box::Ac3SampleEntry(e) => encrypted_audio_entry!(e, sinf),
+        MP4Box::Ec3SampleEntry(e) => encrypted_audio_entry!(e, sinf),
+        MP4Box::OpusSampleEntry(e) => encrypted_audio_entry!(e, sinf),
+        MP4Box::FlacSampleEntry(e) => encrypted_audio_entry!(e, sinf),
+        _ => return None,
+    };
+    Some(entry)
+}
+
+def_box! {
+    /// 8.12.1 Protection Scheme Information Box (ISO/IEC 14496-12:2015(E))
+    ProtectionSchemeInfoBox {
+        boxes:      Vec<MP4Box>,
+    },
+    fourcc => "sinf",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl ProtectionSchemeInfoBox {
+    /// The original (unencrypted) format of the samples.
+    pub fn original_format(&self) -> Option<FourCC> {
+        first_box!(&self.boxes, OriginalFormatBox).map(|f| f.data_format)
+    }
+
+    /// The protection scheme, e.g. `cenc` or `cbcs`.
+    pub fn scheme_type(&self) -> Option<FourCC> {
+        first_box!(&self.boxes, SchemeTypeBox).map(|s| s.scheme_type)
+    }
+
+    /// The default encryption parameters for this track.
+    pub fn track_encryption(&self) -> Option<&TrackEncryptionBox> {
+        first_box!(&self.boxes, SchemeInformationBox / TrackEncryptionBox)
+    }
+}
+
+def_box! {
+    /// 8.12.2 Original Format Box (ISO/IEC 14496-12:2015(E))
+    OriginalFormatBox {
+        data_format:    FourCC,
+    },
+    fourcc => "frma",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+//  aligned(8) class SchemeTypeBox extends FullBox('schm', 0, flags) {
+//      unsigned int(32) scheme_type;
+//      unsigned int(32) scheme_version;
+//      if (flags & 0x000001) {
+//          unsigned int(8) scheme_uri[]; // browser uri
+//      }
+//  }
+def_box! {
+    /// 8.12.5 Scheme Type Box (ISO/IEC 14496-12:2015(E))
+    SchemeTypeBox {
+        scheme_type:    FourCC,
+        scheme_version: u32,
+        scheme_uri:     Option<ZString>,
+    },
+    fourcc => "schm",
+    version => [0],
+    impls => [ boxinfo, debug ],
+}
+
+impl FromBytes for SchemeTypeBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<SchemeTypeBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let scheme_type = FourCC::from_bytes(stream)?;
+        let scheme_version = u32::from_bytes(stream)?;
+        let scheme_uri = if (stream.flags() & 0x01) > 0 {
+            Some(ZString::from_bytes(stream)?)
+        } else {
+            None
+        };
+
+        Ok(SchemeTypeBox {
+            scheme_type,
+            scheme_version,
+            scheme_uri,
+        })
+    }
+
+    fn min_size() -> usize {
+        20
+    }
+}
+
+impl ToBytes for SchemeTypeBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        self.scheme_type.to_bytes(stream)?;
+        self.scheme_version.to_bytes(stream)?;
+        if let Some(uri) = self.scheme_uri.as_ref() {
+            uri.to_bytes(stream)?;
+        }
+
+        stream.finalize()
+    }
+}
+
+impl FullBox for SchemeTypeBox {
+    fn version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn flags(&self) -> u32 {
+        self.scheme_uri.is_some() as u32
+    }
+}
+
+def_box! {
+    /// 8.12.6 Scheme Information Box (ISO/IEC 14496-12:2015(E))
+    SchemeInformationBox {
+        boxes:      Vec<MP4Box>,
+    },
+    fourcc => "schi",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+//  aligned(8) class TrackEncryptionBox extends FullBox('tenc', version, flags=0) {
+//      unsigned int(8) reserved = 0;
+//      if (version==0) {
+//          unsigned int(8) reserved = 0;
+//      } else { // version is 1 or greater
+//          unsigned int(4) default_crypt_byte_block;
+//          unsigned int(4) default_skip_byte_block;
+//      }
+//      unsigned int(8) default_isProtected;
+//      unsigned int(8) default_Per_Sample_IV_Size;
+//      unsigned int(8)[16] default_KID;
+//      if (default_isProtected ==1 && default_Per_Sample_IV_Size == 0) {
+//          unsigned int(8) default_constant_IV_size;
+//          unsigned int(8)[default_constant_IV_size] default_constant_IV;
+//      }
+//  }
+def_box! {
+    /// 8.2 Track Encryption Box (ISO/IEC 23001-7:2016)
+    TrackEncryptionBox {
+        default_crypt_byte_block:   u8,
+        default_skip_byte_block:    u8,
+        default_is_protected:       u8,
+        default_per_sample_iv_size: u8,
+        default_kid:                Uuid,
+        default_constant_iv:        Option<Data>,
+    },
+    fourcc => "tenc",
+    version => [1],
+    impls => [ boxinfo, debug ],
+}
+
+impl FromBytes for TrackEncryptionBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<TrackEncryptionBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        u8::from_bytes(stream)?;
+        let v = u8::from_bytes(stream)?;
+        let (default_crypt_byte_block, default_skip_byte_block) = if stream.version() == 0 {
+            (0, 0)
+        } else {
+            (v >> 4, v & 0x0f)
+        };
+        let default_is_protected = u8::from_bytes(stream)?;
+        let default_per_sample_iv_size = u8::from_bytes(stream)?;
+        let default_kid = Uuid::from_bytes(stream)?;
+        let default_constant_iv = if default_is_protected == 1 && default_per_sample_iv_size == 0 {
+            let size = u8::from_bytes(stream)?;
+            Some(Data::read(stream, size as usize)?)
+        } else {
+            None
+        };
+
+        Ok(TrackEncryptionBox {
+            default_crypt_byte_block,
+            default_skip_byte_block,
+            default_is_protected,
+            default_per_sample_iv_size,
+            default_kid,
+            default_constant_iv,
+        })
+    }
+
+    fn min_size() -> usize {
+        32
+    }
+}
+
+impl ToBytes for TrackEncryptionBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        0u8.to_bytes(stream)?;
+        let v = (self.default_crypt_byte_block << 4) | (self.default_skip_byte_block & 0x0f);
+        v.to_bytes(stream)?;
+        self.default_is_protected.to_bytes(stream)?;
+        self.default_per_sample_iv_size.to_bytes(stream)?;
+        self.default_kid.to_bytes(stream)?;
+        if self.default_is_protected == 1 && self.default_per_sample_iv_size == 0 {
+            let iv = self.default_constant_iv.as_ref().map(|d| &d.0[..]).unwrap_or(&[]);
+            (iv.len() as u8).to_bytes(stream)?;
+            stream.write(iv)?;
+        }
+
+        stream.finalize()
+    }
+}
+
+impl FullBox for TrackEncryptionBox {
+    fn version(&self) -> Option<u8> {
+        if self.default_crypt_byte_block > 0 || self.default_skip_byte_block > 0 {
+            Some(1)
+        } else {
+            Some(0)
+        }
+    }
+}
+
+//  aligned(8) class ProtectionSystemSpecificHeaderBox extends FullBox('pssh', version, flags=0) {
+//      unsigned int(8)[16] SystemID;
+//      if (version > 0) {
+//          unsigned int(32) KID_count;
+//          {
+//              unsigned int(8)[16] KID;
+//          } [KID_count];
+//      }
+//      unsigned int(32) DataSize;
+//      unsigned int(8)[DataSize] Data;
+//  }
+def_box! {
+    /// 8.1 Protection System Specific Header Box (ISO/IEC 23001-7:2016)
+    ProtectionSystemSpecificHeaderBox {
+        system_id:  Uuid,
+        kids:       Vec<Uuid>,
+        data:       Data,
+    },
+    fourcc => "pssh",
+    version => [1],
+    impls => [ boxinfo, debug ],
+}
+
+impl ProtectionSystemSpecificHeaderBox {
+    /// Name of the DRM system, if it is a well-known one.
+    pub fn system_name(&self) -> Option<&'static str> {
+        let name = match self.system_id.to_string().as_str() {
+            "1077efec-c0b2-4d02-ace3-3c1e52e2fb4b" => "W3C Common PSSH",
+            "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed" => "Widevine",
+            "9a04f079-9840-4286-ab92-e65be0885f95" => "PlayReady",
+            "94ce86fb-07ff-4f43-adb8-93d2fa968ca2" => "FairPlay",
+            "5e629af5-38da-4063-8977-97ffbd9902d4" => "Marlin",
+            "e2719d58-a985-b3c9-781a-b030af78d30e" => "ClearKey",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl FromBytes for ProtectionSystemSpecificHeaderBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<ProtectionSystemSpecificHeaderBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let system_id = Uuid::from_bytes(stream)?;
+        let mut kids = Vec::new();
+        if stream.version() > 0 {
+            let kid_count = u32::from_bytes(stream)?;
+            for _ in 0 .. kid_count {
+                kids.push(Uuid::from_bytes(stream)?);
+            }
+        }
+        let size = u32::from_bytes(stream)?;
+        let data = Data::read(stream, size as usize)?;
+
+        Ok(ProtectionSystemSpecificHeaderBox {
+            system_id,
+            kids,
+            data,
+        })
+    }
+
+    fn min_size() -> usize {
+        32
+    }
+}
+
+impl ToBytes for ProtectionSystemSpecificHeaderBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        self.system_id.to_bytes(stream)?;
+        if !self.kids.is_empty() {
+            (self.kids.len() as u32).to_bytes(stream)?;
+            for kid in &self.kids {
+                kid.to_bytes(stream)?;
+            }
+        }
+        (self.data.len() as u32).to_bytes(stream)?;
+        self.data.to_bytes(stream)?;
+
+        stream.finalize()
+    }
+}
+
+impl FullBox for ProtectionSystemSpecificHeaderBox {
+    fn version(&self) -> Option<u8> {
+        if self.kids.is_empty() {
+            Some(0)
+        } else {
+            Some(1)
+        }
+    }
+}
+
+//  aligned(8) class SampleEncryptionBox extends FullBox('senc', version=0, flags) {
+//      unsigned int(32) sample_count;
+//      {
+//          unsigned int(Per_Sample_IV_Size*8) InitializationVector;
+//          if (flags & 0x000002) {
+//              unsigned int(16) subsample_count;
+//              {
+//                  unsigned int(16) BytesOfClearData;
+//                  unsigned int(32) BytesOfProtectedData;
+//              } [ subsample_count ]
+//          }
+//      }[ sample_count ]
+//  }
+def_box! {
+    /// 7.2 Sample Encryption Box (ISO/IEC 23001-7:2016)
+    ///
+    /// The size of the IV is not stored in this box, it is
+    /// in the `tenc` box (or a sample group description). When reading
+    /// the box we guess the size (16, 8, or 0) from the box size.
+    #[derive(Default)]
+    SampleEncryptionBox {
+        entries:    Vec<SampleEncryptionEntry>,
+    },
+    fourcc => "senc",
+    version => [0],
+    impls => [ boxinfo, debug ],
+}
+
+/// Per-sample encryption info in a `SampleEncryptionBox`.
+#[derive(Clone, Debug, Default)]
+pub struct SampleEncryptionEntry {
+    pub iv: Data,
+    pub subsamples: Vec<SubsampleEntry>,
+}
+
+/// Clear and protected byte ranges in a sample.
+#[derive(Clone, Debug, Default)]
+pub struct SubsampleEntry {
+    pub bytes_of_clear_data: u16,
+    pub bytes_of_protected_data: u32,
+}
+
+impl SampleEncryptionBox {
+    // Try to parse the entries with a specific IV size.
+    fn parse_entries(data: &[u8], sample_count: u32, iv_size: usize, subsamples: bool) -> Option<Vec<SampleEncryptionEntry>> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        for _ in 0 .. sample_count {
+            let iv = Data(data.get(pos .. pos + iv_size)?.to_vec());
+            pos += iv_size;
+            let mut entry = SampleEncryptionEntry { iv, subsamples: Vec::new() };
+            if subsamples {
+                let b = data.get(pos .. pos + 2)?;
+                let count = u16::from_be_bytes([b[0], b[1]]);
+                pos += 2;
+                for _ in 0 .. count {
+                    let b = data.get(pos .. pos + 6)?;
+                    entry.subsamples.push(SubsampleEntry {
+                        bytes_of_clear_data: u16::from_be_bytes([b[0], b[1]]),
+                        bytes_of_protected_data: u32::from_be_bytes([b[2], b[3], b[4], b[5]]),
+                    });
+                    pos += 6;
+                }
+            }
+            entries.push(entry);
+        }
+        if pos != data.len() {
+            return None;
+        }
+        Some(entries)
+    }
+}
+
+impl FromBytes for SampleEncryptionBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<SampleEncryptionBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let subsamples = (stream.flags() & 0x02) > 0;
+        let sample_count = u32::from_bytes(stream)?;
+        let data = Data::from_bytes(stream)?;
+
+        for &iv_size in &[ 16, 8, 0 ] {
+            if let Some(entries) = SampleEncryptionBox::parse_entries(&data.0, sample_count, iv_size, subsamples) {
+                return Ok(SampleEncryptionBox { entries });
+            }
+        }
+        Err(ioerr!(InvalidData, "senc: cannot determine IV size"))
+    }
+
+    fn min_size() -> usize {
+        16
+    }
+}
+
+impl ToBytes for SampleEncryptionBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        let subsamples = (stream.flags() & 0x02) > 0;
+        (self.entries.len() as u32).to_bytes(stream)?;
+        for e in &self.entries {
+            e.iv.to_bytes(stream)?;
+            if subsamples {
+                (e.subsamples.len() as u16).to_bytes(stream)?;
+                for s in &e.subsamples {
+                    s.bytes_of_clear_data.to_bytes(stream)?;
+                    s.bytes_of_protected_data.to_bytes(stream)?;
+                }
+            }
+        }
+
+        stream.finalize()
+    }
+}
+
+impl FullBox for SampleEncryptionBox {
+    fn version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn flags(&self) -> u32 {
+        self.entries.iter().any(|e| !e.subsamples.is_empty()) as u32 * 0x02
+    }
+}
+
+//  aligned(8) class SampleAuxiliaryInformationSizesBox extends FullBox('saiz', version = 0, flags) {
+//      if (flags & 1) {
+//          unsigned int(32) aux_info_type;
+//          unsigned int(32) aux_info_type_parameter;
+//      }
+//      unsigned int(8) default_sample_info_size;
+//      unsigned int(32) sample_count;
+//      if (default_sample_info_size == 0) {
+//          unsigned int(8) sample_info_size[ sample_count ];
+//      }
+//  }
+def_box! {
+    /// 8.7.8 Sample Auxiliary Information Sizes Box (ISO/IEC 14496-12:2015(E))
+    #[derive(Default)]
+    SampleAuxiliaryInformationSizesBox {
+        aux_info_type:              Option<FourCC>,
+        aux_info_type_parameter:    u32,
+        default_sample_info_size:   u8,
+        sample_count:               u32,
+        sample_info_sizes:          Vec<u8>,
+    },
+    fourcc => "saiz",
+    version => [0],
+    impls => [ boxinfo, debug ],
+}
+
+impl FromBytes for SampleAuxiliaryInformationSizesBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<SampleAuxiliaryInformationSizesBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let mut aux_info_type = None;
+        let mut aux_info_type_parameter = 0;
+        if (stream.flags() & 0x01) > 0 {
+            aux_info_type = Some(FourCC::from_bytes(stream)?);
+            aux_info_type_parameter = u32::from_bytes(stream)?;
+        }
+        let default_sample_info_size = u8::from_bytes(stream)?;
+        let sample_count = u32::from_bytes(stream)?;
+        let sample_info_sizes = if default_sample_info_size == 0 {
+            stream.read(sample_count as u64)?.to_vec()
+        } else {
+            Vec::new()
+        };
+
+        Ok(SampleAuxiliaryInformationSizesBox {
+            aux_info_type,
+            aux_info_type_parameter,
+            default_sample_info_size,
+            sample_count,
+            sample_info_sizes,
+        })
+    }
+
+    fn min_size() -> usize {
+        17
+    }
+}
+
+impl ToBytes for SampleAuxiliaryInformationSizesBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        if let Some(aux_info_type) = self.aux_info_type.as_ref() {
+            aux_info_type.to_bytes(stream)?;
+            self.aux_info_type_parameter.to_bytes(stream)?;
+        }
+        self.default_sample_info_size.to_bytes(stream)?;
+        self.sample_count.to_bytes(stream)?;
+        if self.default_sample_info_size == 0 {
+            stream.write(&self.sample_info_sizes)?;
+        }
+
+        stream.finalize()
+    }
+}
+
+impl FullBox for SampleAuxiliaryInformationSizesBox {
+    fn version(&self) -> Option<u8> {
+        Some(0)
+    }
+    fn flags(&self) -> u32 {
+        self.aux_info_type.is_some() as u32
+    }
+}
+
+//  aligned(8) class SampleAuxiliaryInformationOffsetsBox extends FullBox('saio', version, flags) {
+//      if (flags & 1) {
+//          unsigned int(32) aux_info_type;
+//          unsigned int(32) aux_info_type_parameter;
+//      }
+//      unsigned int(32) entry_count;
+//      if ( version == 0 ) {
+//          unsigned int(32) offset[ entry_count ];
+//      } else {
+//          unsigned int(64) offset[ entry_count ];
+//      }
+//  }
+def_box! {
+    /// 8.7.9 Sample Auxiliary Information Offsets Box (ISO/IEC 14496-12:2015(E))
+    #[derive(Default)]
+    SampleAuxiliaryInformationOffsetsBox {
+        aux_info_type:              Option<FourCC>,
+        aux_info_type_parameter:    u32,
+        offsets:                    Vec<u64>,
+    },
+    fourcc => "saio",
+    version => [1],
+    impls => [ boxinfo, debug ],
+}
+
+impl FromBytes for SampleAuxiliaryInformationOffsetsBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<SampleAuxiliaryInformationOffsetsBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let mut aux_info_type = None;
+        let mut aux_info_type_parameter = 0;
+        if (stream.flags() & 0x01) > 0 {
+            aux_info_type = Some(FourCC::from_bytes(stream)?);
+            aux_info_type_parameter = u32::from_bytes(stream)?;
+        }
+        let entry_count = u32::from_bytes(stream)?;
+        let mut offsets = Vec::new();
+        for _ in 0 .. entry_count {
+            let offset = if stream.version() == 0 {
+                u32::from_bytes(stream)? as u64
+            } else {
+                u64::from_bytes(stream)?
+            };
+            offsets.push(offset);
+        }
+
+        Ok(SampleAuxiliaryInformationOffsetsBox {
+            aux_info_type,
+            aux_info_type_parameter,
+            offsets,
+        })
+    }
+
+    fn min_size() -> usize {
+        16
+    }
+}
+
+impl ToBytes for SampleAuxiliaryInformationOffsetsBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        if let Some(aux_info_type) = self.aux_info_type.as_ref() {
+            aux_info_type.to_bytes(stream)?;
+            self.aux_info_type_parameter.to_bytes(stream)?;
+        }
+        (self.offsets.len() as u32).to_bytes(stream)?;
+        let large = stream.version() > 0;
+        for &offset in &self.offsets {
+            if large {
+                offset.to_bytes(stream)?;
+            } else {
+                (offset as u32).to_bytes(stream)?;
+            }
+        }
+
+        stream.finalize()
+    }
+}
+
+impl FullBox for SampleAuxiliaryInformationOffsetsBox {
+    fn version(&self) -> Option<u8> {
+        if self.offsets.iter().any(|&o| o > u32::MAX as u64) {
+            Some(1)
+        } else {
+            Some(0)
+        }
+    }
+    fn flags(&self) -> u32 {
+        self.aux_info_type.is_some() as u32
+    }
+}
+
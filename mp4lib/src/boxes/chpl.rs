@@ -14,7 +14,7 @@ def_box! {
 
 def_struct! {
     /// Chapter ("Nero" format).
-    /// `start` units is in 10_000 * MovieHeaderBox.timescale.
+    /// `start` is in units of 100 nanoseconds.
     Chapter,
         start: u64,
         title: PString,
@@ -51,7 +51,8 @@ impl ToBytes for ChapterListBox {
     fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
         let mut writer = BoxWriter::new(stream, self)?;
         let stream = &mut writer;
-        let count = self.chapters.len() as u8;
+        0u8.to_bytes(stream)?;
+        let count = self.chapters.len() as u32;
         count.to_bytes(stream)?;
         self.chapters.to_bytes(stream)?;
         Ok(())
@@ -0,0 +1,185 @@
+//
+// ISO/IEC 14496-12:2015(E)
+// 12.1.5 Colour information
+//
+// ISO/IEC 23001-8 / SMPTE ST 2086 / CTA-861.3
+// Mastering display colour volume, content light level.
+//
+
+use std::io;
+
+use crate::boxes::prelude::*;
+use crate::track::{ColorInfo, ContentLightLevel, MasteringDisplay};
+
+//  class ColourInformationBox extends Box('colr'){
+//      unsigned int(32) colour_type;
+//      if (colour_type == 'nclx') /* on-screen colours */
+//      {
+//          unsigned int(16) colour_primaries;
+//          unsigned int(16) transfer_characteristics;
+//          unsigned int(16) matrix_coefficients;
+//          unsigned int(1) full_range_flag;
+//          unsigned int(7) reserved = 0;
+//      }
+//      else if (colour_type == 'rICC')
+//      {
+//          ICC_profile; // restricted ICC profile
+//      }
+//      else if (colour_type == 'prof')
+//      {
+//          ICC_profile; // unrestricted ICC profile
+//      }
+//  }
+def_box! {
+    /// 12.1.5 Colour information (ISO/IEC 14496-12:2015(E))
+    ///
+    /// Also handles the Quicktime 'nclc' colour type.
+    ColourInformationBox {
+        colour_type:    FourCC,
+        // Only set for 'nclx' and 'nclc'.
+        nclx:           Option<NclxColour>,
+        // ICC profile for 'rICC' or 'prof', raw data for unknown types.
+        profile:        Data,
+    },
+    fourcc => "colr",
+    version => [],
+    impls => [ basebox, boxinfo, debug ],
+}
+
+/// On-screen colours, see ISO/IEC 23091-2 for the values.
+#[derive(Clone, Debug, Default)]
+pub struct NclxColour {
+    pub colour_primaries:           u16,
+    pub transfer_characteristics:   u16,
+    pub matrix_coefficients:        u16,
+    pub full_range_flag:            bool,
+}
+
+impl FromBytes for ColourInformationBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<ColourInformationBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let colour_type = FourCC::from_bytes(stream)?;
+        let mut nclx = None;
+        if colour_type == b"nclx" || colour_type == b"nclc" {
+            let colour_primaries = u16::from_bytes(stream)?;
+            let transfer_characteristics = u16::from_bytes(stream)?;
+            let matrix_coefficients = u16::from_bytes(stream)?;
+            let full_range_flag = if colour_type == b"nclx" {
+                u8::from_bytes(stream)? & 0x80 != 0
+            } else {
+                false
+            };
+            nclx = Some(NclxColour {
+                colour_primaries,
+                transfer_characteristics,
+                matrix_coefficients,
+                full_range_flag,
+            });
+        }
+        let profile = Data::from_bytes(stream)?;
+
+        Ok(ColourInformationBox {
+            colour_type,
+            nclx,
+            profile,
+        })
+    }
+
+    fn min_size() -> usize {
+        12
+    }
+}
+
+impl ToBytes for ColourInformationBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        self.colour_type.to_bytes(stream)?;
+        if let Some(nclx) = self.nclx.as_ref() {
+            nclx.colour_primaries.to_bytes(stream)?;
+            nclx.transfer_characteristics.to_bytes(stream)?;
+            nclx.matrix_coefficients.to_bytes(stream)?;
+            if self.colour_type == b"nclx" {
+                ((nclx.full_range_flag as u8) << 7).to_bytes(stream)?;
+            }
+        }
+        self.profile.to_bytes(stream)?;
+
+        stream.finalize()
+    }
+}
+
+impl ColourInformationBox {
+    /// Colour information, if this is a 'nclx' or 'nclc' box.
+    pub fn color_info(&self) -> Option<ColorInfo> {
+        self.nclx.as_ref().map(|nclx| ColorInfo {
+            colour_primaries: nclx.colour_primaries,
+            transfer_characteristics: nclx.transfer_characteristics,
+            matrix_coefficients: nclx.matrix_coefficients,
+            full_range: nclx.full_range_flag,
+        })
+    }
+}
+
+def_box! {
+    /// Mastering display colour volume (SMPTE ST 2086).
+    ///
+    /// Chromaticity coordinates are in units of 0.00002,
+    /// luminance in units of 0.0001 cd/m2.
+    #[derive(Default)]
+    MasteringDisplayColourVolumeBox {
+        display_primaries_g_x:  u16,
+        display_primaries_g_y:  u16,
+        display_primaries_b_x:  u16,
+        display_primaries_b_y:  u16,
+        display_primaries_r_x:  u16,
+        display_primaries_r_y:  u16,
+        white_point_x:          u16,
+        white_point_y:          u16,
+        max_display_mastering_luminance:    u32,
+        min_display_mastering_luminance:    u32,
+    },
+    fourcc => "mdcv",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl MasteringDisplayColourVolumeBox {
+    /// Mastering display metadata, in real units.
+    pub fn mastering_display(&self) -> MasteringDisplay {
+        let xy = |x: u16, y: u16| (x as f64 * 0.00002, y as f64 * 0.00002);
+        MasteringDisplay {
+            red: xy(self.display_primaries_r_x, self.display_primaries_r_y),
+            green: xy(self.display_primaries_g_x, self.display_primaries_g_y),
+            blue: xy(self.display_primaries_b_x, self.display_primaries_b_y),
+            white_point: xy(self.white_point_x, self.white_point_y),
+            max_luminance: self.max_display_mastering_luminance as f64 * 0.0001,
+            min_luminance: self.min_display_mastering_luminance as f64 * 0.0001,
+        }
+    }
+}
+
+def_box! {
+    /// Content light level (CTA-861.3).
+    #[derive(Default)]
+    ContentLightLevelBox {
+        max_content_light_level:        u16,
+        max_pic_average_light_level:    u16,
+    },
+    fourcc => "clli",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl ContentLightLevelBox {
+    /// Content light level metadata.
+    pub fn content_light_level(&self) -> ContentLightLevel {
+        ContentLightLevel {
+            max_cll: self.max_content_light_level,
+            max_fall: self.max_pic_average_light_level,
+        }
+    }
+}
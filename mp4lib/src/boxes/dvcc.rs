@@ -0,0 +1,213 @@
+//
+// Dolby Vision Streams Within the ISO Base Media File Format, version 2.4
+//
+
+use std::io;
+
+use crate::bitreader::BitReader;
+use crate::boxes::prelude::*;
+use crate::track::DolbyVisionInfo;
+
+def_box! {
+    /// Dolby Vision configuration box, for profiles 0..7.
+    ///
+    /// Contains just the DOVIDecoderConfigurationRecord.
+    DolbyVisionConfigurationBox {
+        configuration:  DOVIDecoderConfigurationRecord,
+    },
+    fourcc => "dvcC",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// Dolby Vision configuration box, for profiles 8..10.
+    ///
+    /// Contains just the DOVIDecoderConfigurationRecord.
+    DolbyVisionExtConfigurationBox {
+        configuration:  DOVIDecoderConfigurationRecord,
+    },
+    fourcc => "dvvC",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+// align(8) class DOVIDecoderConfigurationRecord {
+//     unsigned int (8) dv_version_major;
+//     unsigned int (8) dv_version_minor;
+//     unsigned int (7) dv_profile;
+//     unsigned int (6) dv_level;
+//     bit (1) rpu_present_flag;
+//     bit (1) el_present_flag;
+//     bit (1) bl_present_flag;
+//     unsigned int (4) dv_bl_signal_compatibility_id;
+//     const unsigned int (28) reserved = 0;
+//     const unsigned int (32)[4] reserved = 0;
+// }
+/// Dolby Vision Decoder Configuration Record.
+#[derive(Clone, Debug, Default)]
+pub struct DOVIDecoderConfigurationRecord {
+    pub dv_version_major: u8,
+    pub dv_version_minor: u8,
+    pub dv_profile: u8,
+    pub dv_level: u8,
+    pub rpu_present_flag: bool,
+    pub el_present_flag: bool,
+    pub bl_present_flag: bool,
+    pub dv_bl_signal_compatibility_id: u8,
+}
+
+impl FromBytes for DOVIDecoderConfigurationRecord {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<DOVIDecoderConfigurationRecord> {
+        let data = Data::read(stream, 24)?;
+        let mut b = BitReader::new(&data.0);
+        Ok(DOVIDecoderConfigurationRecord {
+            dv_version_major: b.read_u8()?,
+            dv_version_minor: b.read_u8()?,
+            dv_profile: b.read_bits(7)? as u8,
+            dv_level: b.read_bits(6)? as u8,
+            rpu_present_flag: b.read_bit()?,
+            el_present_flag: b.read_bit()?,
+            bl_present_flag: b.read_bit()?,
+            dv_bl_signal_compatibility_id: b.read_bits(4)? as u8,
+        })
+    }
+
+    fn min_size() -> usize {
+        24
+    }
+}
+
+impl ToBytes for DOVIDecoderConfigurationRecord {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        self.dv_version_major.to_bytes(stream)?;
+        self.dv_version_minor.to_bytes(stream)?;
+        let v: u16 = ((self.dv_profile as u16 & 0x7f) << 9) |
+            ((self.dv_level as u16 & 0x3f) << 3) |
+            ((self.rpu_present_flag as u16) << 2) |
+            ((self.el_present_flag as u16) << 1) |
+            (self.bl_present_flag as u16);
+        v.to_bytes(stream)?;
+        let v: u32 = (self.dv_bl_signal_compatibility_id as u32 & 0x0f) << 28;
+        v.to_bytes(stream)?;
+        stream.write(&[0u8; 16])
+    }
+}
+
+impl DOVIDecoderConfigurationRecord {
+    /// Return human name of codec, like "Dolby Vision Profile 8.1".
+    pub fn codec_name(&self) -> String {
+        match self.dv_profile {
+            8 => format!("Dolby Vision Profile 8.{}", self.dv_bl_signal_compatibility_id),
+            p => format!("Dolby Vision Profile {}", p),
+        }
+    }
+
+    /// Return codec id as `dvh1.PP.LL` (e.g. `dvh1.05.06`).
+    ///
+    /// `fourcc` is the Dolby Vision sample entry type: "dvh1", "dvhe",
+    /// "dva1", "dvav" or "dav1".
+    pub fn codec_id(&self, fourcc: &str) -> String {
+        format!("{}.{:02}.{:02}", fourcc, self.dv_profile, self.dv_level)
+    }
+
+    /// Return the codec id plus compatibility brand, for the HLS
+    /// SUPPLEMENTAL-CODECS attribute (e.g. `dvh1.08.06/db1p`).
+    ///
+    /// Only cross-compatible profiles have a brand, for others this
+    /// is the same as `codec_id`.
+    pub fn supplemental_codec_id(&self, fourcc: &str) -> String {
+        let codec_id = self.codec_id(fourcc);
+        match self.compatibility_brand() {
+            Some(brand) => format!("{}/{}", codec_id, brand),
+            None => codec_id,
+        }
+    }
+
+    /// Compatibility brand of the base layer.
+    pub fn compatibility_brand(&self) -> Option<&'static str> {
+        match self.dv_bl_signal_compatibility_id {
+            1 => Some("db1p"),
+            2 => Some("db2g"),
+            4 => Some("db4h"),
+            _ => None,
+        }
+    }
+
+    /// Return Dolby Vision info.
+    pub fn info(&self) -> DolbyVisionInfo {
+        DolbyVisionInfo {
+            profile: self.dv_profile,
+            level: self.dv_level,
+            bl_signal_compatibility_id: self.dv_bl_signal_compatibility_id,
+        }
+    }
+}
+
+/// Find the Dolby Vision configuration in a list of boxes.
+pub(crate) fn dolby_vision_config(boxes: &[MP4Box]) -> Option<&DOVIDecoderConfigurationRecord> {
+    first_box!(boxes[..], DolbyVisionConfigurationBox)
+        .map(|b| &b.configuration)
+        .or_else(|| first_box!(boxes[..], DolbyVisionExtConfigurationBox).map(|b| &b.configuration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::MemBuffer;
+
+    fn dovi_box(fourcc: &[u8], profile: u16, level: u16, compat_id: u32) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&32u32.to_be_bytes());
+        b.extend_from_slice(fourcc);
+        b.extend_from_slice(&[1, 0]);
+        // rpu and bl present.
+        b.extend_from_slice(&(profile << 9 | level << 3 | 0b101).to_be_bytes());
+        b.extend_from_slice(&(compat_id << 28).to_be_bytes());
+        b.extend_from_slice(&[0; 16]);
+        b
+    }
+
+    #[test]
+    fn dvcc_round_trip() {
+        let data = dovi_box(b"dvcC", 5, 6, 0);
+        let dvcc = DolbyVisionConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let config = &dvcc.configuration;
+        assert_eq!((config.dv_version_major, config.dv_version_minor), (1, 0));
+        assert_eq!((config.dv_profile, config.dv_level), (5, 6));
+        assert!(config.rpu_present_flag && !config.el_present_flag && config.bl_present_flag);
+        assert_eq!(config.codec_name(), "Dolby Vision Profile 5");
+        assert_eq!(config.codec_id("dvh1"), "dvh1.05.06");
+        assert_eq!(config.supplemental_codec_id("dvh1"), "dvh1.05.06");
+
+        let mut buffer = MemBuffer::new();
+        dvcc.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+    }
+
+    #[test]
+    fn dvvc_round_trip() {
+        let data = dovi_box(b"dvvC", 8, 6, 1);
+        let dvvc = DolbyVisionExtConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        let config = &dvvc.configuration;
+        assert_eq!(config.codec_name(), "Dolby Vision Profile 8.1");
+        assert_eq!(config.compatibility_brand(), Some("db1p"));
+        assert_eq!(config.supplemental_codec_id("dvh1"), "dvh1.08.06/db1p");
+
+        let mut buffer = MemBuffer::new();
+        dvvc.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+
+        let boxes = vec![MP4Box::DolbyVisionExtConfigurationBox(dvvc)];
+        assert_eq!(dolby_vision_config(&boxes).map(|c| c.dv_profile), Some(8));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut data = dovi_box(b"dvcC", 5, 6, 0);
+        data.truncate(20);
+        data[3] = 20;
+        let err = DolbyVisionConfigurationBox::from_bytes(&mut &data[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
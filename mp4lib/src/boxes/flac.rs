@@ -0,0 +1,208 @@
+//
+// Encapsulation of FLAC in ISO Base Media File Format, version 0.0.4
+// https://github.com/xiph/flac/blob/master/doc/isoflac.txt
+//
+
+use std::io;
+
+use crate::boxes::prelude::*;
+use crate::bitreader::BitReader;
+use crate::track::AudioTrackInfo;
+
+def_box! {
+    /// FLAC sample entry (AudioSampleEntry 'fLaC').
+    FlacSampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   8,
+        // (mono = 1 ; stereo = 2)
+        channel_count: u16,
+        // audio sample number of bits
+        sample_size: u16,
+        skip:                   4,
+        // 0 if the sample rate is > 65535.
+        sample_rate_hi:         u16,
+        sample_rate_lo:         u16,
+        // sub boxes, probably only dfLa.
+        boxes: Vec<MP4Box>,
+    },
+    fourcc => "fLaC",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl Default for FlacSampleEntry {
+    fn default() -> FlacSampleEntry {
+        FlacSampleEntry {
+            data_reference_index:   1,
+            channel_count:          2,
+            sample_size:            16,
+            sample_rate_hi:         0,
+            sample_rate_lo:         0,
+            boxes:                  Vec::new(),
+        }
+    }
+}
+
+impl FlacSampleEntry {
+    /// Return audio specific track info.
+    pub fn track_info(&self) -> AudioTrackInfo {
+        let mut ai = AudioTrackInfo {
+            codec_id:   "flac".to_string(),
+            codec_name: Some("FLAC".to_string()),
+            channel_count:  self.channel_count,
+            bit_depth: if self.sample_size > 0 { Some(self.sample_size) } else { None },
+            sample_rate: if self.sample_rate_hi > 0 { Some(self.sample_rate_hi as u32) } else { None },
+            ..AudioTrackInfo::default()
+        };
+
+        if let Some(info) = first_box!(&self.boxes, FlacSpecificBox).and_then(|b| b.stream_info()) {
+            ai.channel_count = info.num_channels() as u16;
+            ai.lfe_channel = info.lfe_channel();
+            ai.channel_configuration = info.channel_configuration().map(|c| c.to_string());
+            ai.bit_depth = Some(info.bits_per_sample as u16);
+            if info.sample_rate > 0 {
+                ai.sample_rate = Some(info.sample_rate);
+            }
+        }
+
+        ai
+    }
+}
+
+// class FLACMetadataBlock {
+//     unsigned int(1) LastMetadataBlockFlag;
+//     unsigned int(7) BlockType;
+//     unsigned int(24) Length;
+//     byte BlockData[Length];
+// }
+//
+// aligned(8) class FLACSpecificBox extends FullBox('dfLa', version=0, 0) {
+//     for (i=0; ; i++) { // to end of box
+//         FLACMetadataBlock();
+//     }
+// }
+def_box! {
+    /// FLAC specific box.
+    ///
+    /// Contains FLAC metadata blocks. The first one is always STREAMINFO.
+    FlacSpecificBox {
+        blocks: Vec<FlacMetadataBlock>,
+    },
+    fourcc => "dfLa",
+    version => [0],
+    impls => [ boxinfo, debug, fullbox ],
+}
+
+/// FLAC metadata block.
+#[derive(Clone, Debug, Default)]
+pub struct FlacMetadataBlock {
+    /// 0 = STREAMINFO, 1 = PADDING, 2 = APPLICATION, 3 = SEEKTABLE,
+    /// 4 = VORBIS_COMMENT, 5 = CUESHEET, 6 = PICTURE.
+    pub block_type: u8,
+    pub data: Data,
+}
+
+impl FromBytes for FlacSpecificBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<FlacSpecificBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let mut blocks = Vec::new();
+        while stream.left() >= 4 {
+            let hdr = u32::from_bytes(stream)?;
+            let block_type = ((hdr >> 24) & 0x7f) as u8;
+            let length = hdr & 0xffffff;
+            let data = Data::read(stream, length as usize)?;
+            blocks.push(FlacMetadataBlock { block_type, data });
+            if hdr & 0x80000000 != 0 {
+                break;
+            }
+        }
+
+        Ok(FlacSpecificBox { blocks })
+    }
+
+    fn min_size() -> usize {
+        12
+    }
+}
+
+impl ToBytes for FlacSpecificBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        for (idx, block) in self.blocks.iter().enumerate() {
+            let last = if idx == self.blocks.len() - 1 { 0x80000000 } else { 0 };
+            let hdr = last | ((block.block_type as u32 & 0x7f) << 24) | (block.data.0.len() as u32 & 0xffffff);
+            hdr.to_bytes(stream)?;
+            block.data.to_bytes(stream)?;
+        }
+
+        stream.finalize()
+    }
+}
+
+impl FlacSpecificBox {
+    /// Decode the STREAMINFO metadata block.
+    pub fn stream_info(&self) -> Option<FlacStreamInfo> {
+        let block = self.blocks.iter().find(|b| b.block_type == 0)?;
+        FlacStreamInfo::read(&block.data.0).ok()
+    }
+}
+
+/// FLAC STREAMINFO.
+#[derive(Clone, Debug, Default)]
+pub struct FlacStreamInfo {
+    pub min_block_size: u16,
+    pub max_block_size: u16,
+    pub min_frame_size: u32,
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    pub total_samples: u64,
+}
+
+impl FlacStreamInfo {
+    fn read(data: &[u8]) -> io::Result<FlacStreamInfo> {
+        let mut b = BitReader::new(data);
+        Ok(FlacStreamInfo {
+            min_block_size: b.read_bits(16)? as u16,
+            max_block_size: b.read_bits(16)? as u16,
+            min_frame_size: b.read_bits(24)?,
+            max_frame_size: b.read_bits(24)?,
+            sample_rate: b.read_bits(20)?,
+            channels: b.read_bits(3)? as u8 + 1,
+            bits_per_sample: b.read_bits(5)? as u8 + 1,
+            total_samples: ((b.read_bits(4)? as u64) << 32) | b.read_bits(32)? as u64,
+        })
+    }
+
+    /// Audio channels, in FLAC channel order. "L,R", "L,R,C,SL,SR", etc.
+    ///
+    /// The LFE channel is not included, see `lfe_channel()`.
+    pub fn channel_configuration(&self) -> Option<&'static str> {
+        match self.channels {
+            1 => Some("C"),
+            2 => Some("L,R"),
+            3 => Some("L,R,C"),
+            4 => Some("L,R,BSL,BSR"),
+            5 | 6 => Some("L,R,C,BSL,BSR"),
+            7 => Some("L,R,C,BS,SL,SR"),
+            8 => Some("L,R,C,BSL,BSR,SL,SR"),
+            _ => None,
+        }
+    }
+
+    /// Number of audio channels, not including the LFE channel.
+    pub fn num_channels(&self) -> u8 {
+        self.channels - self.lfe_channel() as u8
+    }
+
+    /// LFE (sub) channel?
+    pub fn lfe_channel(&self) -> bool {
+        self.channels >= 6
+    }
+}
@@ -17,8 +17,10 @@ def_box! {
 
 impl HandlerBox {
     /// Is this a subtitle track.
+    ///
+    /// `text` is what `wvtt` (WebVTT-in-MP4) tracks use.
     pub fn is_subtitle(&self) -> bool {
-        self.handler_type == b"subt" || self.handler_type == b"sbtl"
+        self.handler_type == b"subt" || self.handler_type == b"sbtl" || self.handler_type == b"text"
     }
 
     /// Is this a video track.
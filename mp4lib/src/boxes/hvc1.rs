@@ -5,9 +5,11 @@
 
 use std::io;
 
-use crate::bitreader::BitReader;
+use crate::bitreader::{unescape_rbsp, BitReader};
 use crate::boxes::prelude::*;
-use crate::track::VideoTrackInfo;
+use crate::boxes::avcc::{cond, AspectRatioInfo, ChromaLocInfo, ColourDescription, VideoSignalType};
+use crate::boxes::dvcc::dolby_vision_config;
+use crate::track::{ColorInfo, VideoTrackInfo};
 
 def_box! {
     /// HEVC sample entry (VideoSampleEntry 'hvc1').
@@ -48,26 +50,7 @@ def_box! {
 impl HEVCSampleEntry {
     /// Return video specific track info.
     pub fn track_info(&self) -> VideoTrackInfo {
-        let config = first_box!(self.boxes, HEVCConfigurationBox);
-        let codec_id = match config {
-            Some(ref c) => c.configuration.codec_id("hvc1"),
-            None => "hvc1.unknown".to_string(),
-        };
-        let codec_name = match config {
-            Some(ref c) => c.configuration.codec_name(),
-            None => "HEVC",
-        }.to_string();
-        let frame_rate = match config {
-            Some(ref c) => c.configuration.frame_rate(),
-            None => 0f64,
-        };
-        VideoTrackInfo {
-            codec_id,
-            codec_name: Some(codec_name.to_string()),
-            width: self.width,
-            height: self.height,
-            frame_rate,
-        }
+        hevc_track_info(&self.boxes, self.width, self.height, "hvc1")
     }
 }
 
@@ -110,27 +93,147 @@ def_box! {
 impl HEV1SampleEntry {
     /// Return video specific track info.
     pub fn track_info(&self) -> VideoTrackInfo {
-        let config = first_box!(self.boxes, HEVCConfigurationBox);
-        let codec_id = match config {
-            Some(ref c) => c.configuration.codec_id("hev1"),
-            None => "hvc1.unknown".to_string(),
-        };
-        let codec_name = match config {
-            Some(ref c) => c.configuration.codec_name(),
-            None => "HEVC",
-        }.to_string();
-        let frame_rate = match config {
-            Some(ref c) => c.configuration.frame_rate(),
-            None => 0f64,
-        };
-        VideoTrackInfo {
-            codec_id,
-            codec_name: Some(codec_name.to_string()),
-            width: self.width,
-            height: self.height,
-            frame_rate,
+        hevc_track_info(&self.boxes, self.width, self.height, "hev1")
+    }
+}
+
+def_box! {
+    /// Dolby Vision HEVC sample entry (VideoSampleEntry 'dvh1').
+    ///
+    /// Contains:
+    ///
+    /// - HEVCConfigurationBox (one)
+    /// - DolbyVisionConfigurationBox or DolbyVisionExtConfigurationBox (one)
+    /// - extra boxes.
+    DVH1SampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   16,
+        width:                 u16,
+        height:                 u16,
+        // defaults to 72, 72
+        _video_horizontal_dpi:  FixedFloat16_16,
+        _video_vertical_dpi:    FixedFloat16_16,
+        skip:                   4,
+        // defaults to 1
+        _video_frame_count:     u16,
+        // Video encoder name is a fixed-size pascal string.
+        // _video_encoder_name: PascalString<32>,
+        skip:                   32,
+        // defaults to 0x0018;
+        video_pixel_depth:      u16,
+        // always -1
+        _pre_defined:           u16,
+        // hvcC, dvcC, etc.
+        boxes:              Vec<MP4Box>,
+    },
+    fourcc => "dvh1",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl DVH1SampleEntry {
+    /// Return video specific track info.
+    pub fn track_info(&self) -> VideoTrackInfo {
+        dolby_vision_track_info(&self.boxes, self.width, self.height, "dvh1")
+    }
+}
+
+def_box! {
+    /// Dolby Vision HEVC sample entry (VideoSampleEntry 'dvhe').
+    ///
+    /// Contains:
+    ///
+    /// - HEVCConfigurationBox (one)
+    /// - DolbyVisionConfigurationBox or DolbyVisionExtConfigurationBox (one)
+    /// - extra boxes.
+    DVHESampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   16,
+        width:                 u16,
+        height:                 u16,
+        // defaults to 72, 72
+        _video_horizontal_dpi:  FixedFloat16_16,
+        _video_vertical_dpi:    FixedFloat16_16,
+        skip:                   4,
+        // defaults to 1
+        _video_frame_count:     u16,
+        // Video encoder name is a fixed-size pascal string.
+        // _video_encoder_name: PascalString<32>,
+        skip:                   32,
+        // defaults to 0x0018;
+        video_pixel_depth:      u16,
+        // always -1
+        _pre_defined:           u16,
+        // hvcC, dvcC, etc.
+        boxes:              Vec<MP4Box>,
+    },
+    fourcc => "dvhe",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl DVHESampleEntry {
+    /// Return video specific track info.
+    pub fn track_info(&self) -> VideoTrackInfo {
+        dolby_vision_track_info(&self.boxes, self.width, self.height, "dvhe")
+    }
+}
+
+// Track info for the hvc1 / hev1 sample entries.
+fn hevc_track_info(boxes: &[MP4Box], width: u16, height: u16, fourcc: &'static str) -> VideoTrackInfo {
+    let config = first_box!(boxes[..], HEVCConfigurationBox);
+    let codec_id = match config {
+        Some(c) => c.configuration.codec_id(fourcc),
+        None => "hvc1.unknown".to_string(),
+    };
+    let codec_name = match config {
+        Some(c) => c.configuration.codec_name(),
+        None => "HEVC",
+    };
+    let mut info = VideoTrackInfo {
+        codec_id,
+        codec_name: Some(codec_name.to_string()),
+        width,
+        height,
+        frame_rate: config.map(|c| c.configuration.frame_rate()).unwrap_or(0f64),
+        ..VideoTrackInfo::default()
+    };
+
+    // Prefer the information from the SPS.
+    if let Some(sps) = config.and_then(|c| c.configuration.seq_parameter_set()) {
+        if sps.width() > 0 && sps.height() > 0 {
+            info.width = sps.width() as u16;
+            info.height = sps.height() as u16;
+        }
+        info.bit_depth = Some(sps.bit_depth());
+        if let Some((cd, full_range)) = sps.colour_description() {
+            info.color = Some(ColorInfo {
+                colour_primaries: cd.colour_primaries as u16,
+                transfer_characteristics: cd.transfer_characteristics as u16,
+                matrix_coefficients: cd.matrix_coefficients as u16,
+                full_range,
+            });
         }
     }
+
+    info
+}
+
+// Track info for the dvh1 / dvhe sample entries.
+fn dolby_vision_track_info(boxes: &[MP4Box], width: u16, height: u16, fourcc: &'static str) -> VideoTrackInfo {
+    let mut info = hevc_track_info(boxes, width, height, fourcc);
+    let dv_config = dolby_vision_config(boxes);
+    info.codec_id = match dv_config {
+        Some(c) => c.codec_id(fourcc),
+        None => format!("{}.unknown", fourcc),
+    };
+    info.codec_name = match dv_config {
+        Some(c) => Some(c.codec_name()),
+        None => Some("Dolby Vision".to_string()),
+    };
+    info
 }
 
 def_box! {
@@ -279,17 +382,15 @@ impl HEVCDecoderConfigurationRecord {
     }
 
     /// Decode the frame rate.
+    ///
+    /// Uses the VUI timing info from the SPS if present, otherwise `avg_frame_rate`.
     pub fn frame_rate(&self) -> f64 {
-        /*
-         debugging to get this complete
-        match crate::boxes::avcc::ParameterSet::parse_hevc(&self.data.0) {
-            Ok(sets) => {
-                println!("{:?}", sets);
-                println!("{:?}", sets.sequence_parameters_sets());
-            },
-            Err(e) => println!("could not decode ParameterSets: {}", e),
-        };
-        */
+        if let Some(fr) = self.seq_parameter_set().and_then(|sps| sps.frame_rate()) {
+            if fr > 0.0 && fr <= 120.0 {
+                return (fr * 1000.0).round() / 1000.0;
+            }
+            log::warn!("HEVCDecoderConfigurationRecord::frame_rate: impossible rate {}, ignoring", fr);
+        }
         // avg_frame_rate is in frames / (256 secs), so not accurate enough
         // to describe 23.976 etc. Fix that up.
         match self.avg_frame_rate {
@@ -299,6 +400,25 @@ impl HEVCDecoderConfigurationRecord {
             other => (other as f64) / 256.0,
         }
     }
+
+    /// Decode the first Sequence Parameter Set.
+    pub fn seq_parameter_set(&self) -> Option<SeqParameterSet> {
+        let parameter_sets = match ParameterSet::parse(&self.data.0) {
+            Ok(p) => p,
+            Err(e) => {
+                log::debug!("HEVCDecoderConfigurationRecord: cannot parse parameter sets: {}", e);
+                return None;
+            },
+        };
+        match parameter_sets.sequence_parameters_sets() {
+            Ok(mut v) if !v.is_empty() => Some(v.remove(0)),
+            Ok(_) => None,
+            Err(e) => {
+                log::debug!("HEVCDecoderConfigurationRecord: cannot parse SPS: {}", e);
+                None
+            },
+        }
+    }
 }
 
 // The hvcC 'data' member consists of arrays of ParameterSets.
@@ -310,11 +430,11 @@ impl HEVCDecoderConfigurationRecord {
 #[derive(Debug, Default)]
 pub(crate) struct ParameterSet<'a> {
     vps: Vec<&'a [u8]>,
-    sps: Vec<&'a [u8]>,
+    pub(crate) sps: Vec<&'a [u8]>,
+    pub(crate) pps: Vec<&'a [u8]>,
 }
 
 impl<'a> ParameterSet<'a> {
-    #[allow(dead_code)]
     pub(crate) fn parse(data: &'a [u8]) -> io::Result<ParameterSet<'a>> {
 
         if data.len() < 2 {
@@ -341,11 +461,11 @@ impl<'a> ParameterSet<'a> {
                 if idx + nalu_len > data.len() {
                     return Err(ioerr!(UnexpectedEof, "ParameterSet::parse_hevc: EOF (4)"));
                 }
-                // FIXME: unescape, so 00 00 03 01 -> 00 00 01
                 let nalu = &data[idx .. idx + nalu_len];
                 match nalu_type {
                     32 => set.vps.push(nalu),
                     33 => set.sps.push(nalu),
+                    34 => set.pps.push(nalu),
                     _ => {},
                 }
                 idx += nalu_len;
@@ -356,7 +476,6 @@ impl<'a> ParameterSet<'a> {
     }
 
     // Decode the SequenceParametersSets.
-    #[allow(dead_code)]
     pub(crate) fn sequence_parameters_sets(&self) -> io::Result<Vec<SeqParameterSet>> {
         let mut v = Vec::new();
         for sps in &self.sps {
@@ -371,7 +490,8 @@ impl<'a> ParameterSet<'a> {
             // int(3) nuh_temporal_id_plus1
             //
             // let's just skip it.
-            let mut reader = BitReader::new(&sps[2..]);
+            let rbsp = unescape_rbsp(&sps[2..]);
+            let mut reader = BitReader::new(&rbsp);
             let parsed = SeqParameterSet::read(&mut reader)?;
             v.push(parsed);
         }
@@ -379,14 +499,586 @@ impl<'a> ParameterSet<'a> {
     }
 }
 
-/// H.265 SPS.
-pub struct SeqParameterSet;
+/// H.265 Sequence Parameter Set (ITU-T H.265 7.3.2.2).
+///
+/// The fields after the VUI parameters (the SPS extensions) are not decoded.
+#[derive(Clone, Debug)]
+pub struct SeqParameterSet {
+    pub sps_video_parameter_set_id: u8,
+    pub sps_max_sub_layers_minus1: u8,
+    pub sps_temporal_id_nesting_flag: bool,
+    pub profile_tier_level: ProfileTierLevel,
+    pub sps_seq_parameter_set_id: u32,
+    pub chroma_format_idc: u32,
+    pub separate_colour_plane_flag: bool,
+    pub pic_width_in_luma_samples: u32,
+    pub pic_height_in_luma_samples: u32,
+    pub conformance_window: Option<ConformanceWindow>,
+    pub bit_depth_luma_minus8: u32,
+    pub bit_depth_chroma_minus8: u32,
+    pub log2_max_pic_order_cnt_lsb_minus4: u32,
+    pub sub_layer_ordering_info: Vec<SubLayerOrderingInfo>,
+    pub log2_min_luma_coding_block_size_minus3: u32,
+    pub log2_diff_max_min_luma_coding_block_size: u32,
+    pub log2_min_luma_transform_block_size_minus2: u32,
+    pub log2_diff_max_min_luma_transform_block_size: u32,
+    pub max_transform_hierarchy_depth_inter: u32,
+    pub max_transform_hierarchy_depth_intra: u32,
+    pub scaling_list_enabled_flag: bool,
+    pub amp_enabled_flag: bool,
+    pub sample_adaptive_offset_enabled_flag: bool,
+    pub pcm: Option<PcmParameters>,
+    pub num_short_term_ref_pic_sets: u32,
+    #[cfg_attr(not(feature = "streaming"), allow(dead_code))]
+    pub(crate) st_ref_pic_sets: Vec<ShortTermRefPicSet>,
+    pub long_term_ref_pics_present_flag: bool,
+    #[cfg_attr(not(feature = "streaming"), allow(dead_code))]
+    pub(crate) used_by_curr_pic_lt_sps_flags: Vec<bool>,
+    pub sps_temporal_mvp_enabled_flag: bool,
+    pub strong_intra_smoothing_enabled_flag: bool,
+    pub vui_parameters: Option<HevcVuiParameters>,
+}
 
 impl SeqParameterSet {
-    // TODO: implement h.265 VPS / SPS parser.
-    #[allow(dead_code)]
-    pub(crate) fn read(_reader: &mut BitReader) -> io::Result<SeqParameterSet> {
-        unimplemented!()
+    pub(crate) fn read(reader: &mut BitReader) -> io::Result<SeqParameterSet> {
+        let sps_video_parameter_set_id = reader.read_bits(4)? as u8;
+        let sps_max_sub_layers_minus1 = reader.read_bits(3)? as u8;
+        let sps_temporal_id_nesting_flag = reader.read_bit()?;
+        let profile_tier_level = ProfileTierLevel::read(reader, sps_max_sub_layers_minus1)?;
+        let sps_seq_parameter_set_id = reader.read_ue_max(15)?;
+        let chroma_format_idc = reader.read_ue_max(3)?;
+        let separate_colour_plane_flag = if chroma_format_idc == 3 { reader.read_bit()? } else { false };
+        let pic_width_in_luma_samples = reader.read_ue()?;
+        let pic_height_in_luma_samples = reader.read_ue()?;
+        let conformance_window = cond(reader.read_bit()?, || ConformanceWindow::read(reader))?;
+        let bit_depth_luma_minus8 = reader.read_ue_max(8)?;
+        let bit_depth_chroma_minus8 = reader.read_ue_max(8)?;
+        let log2_max_pic_order_cnt_lsb_minus4 = reader.read_ue_max(12)?;
+
+        let sps_sub_layer_ordering_info_present_flag = reader.read_bit()?;
+        let first = if sps_sub_layer_ordering_info_present_flag { 0 } else { sps_max_sub_layers_minus1 };
+        let mut sub_layer_ordering_info = Vec::new();
+        for _ in first ..= sps_max_sub_layers_minus1 {
+            sub_layer_ordering_info.push(SubLayerOrderingInfo::read(reader)?);
+        }
+
+        let log2_min_luma_coding_block_size_minus3 = reader.read_ue()?;
+        let log2_diff_max_min_luma_coding_block_size = reader.read_ue()?;
+        let log2_min_luma_transform_block_size_minus2 = reader.read_ue()?;
+        let log2_diff_max_min_luma_transform_block_size = reader.read_ue()?;
+        let max_transform_hierarchy_depth_inter = reader.read_ue()?;
+        let max_transform_hierarchy_depth_intra = reader.read_ue()?;
+
+        let scaling_list_enabled_flag = reader.read_bit()?;
+        if scaling_list_enabled_flag {
+            let sps_scaling_list_data_present_flag = reader.read_bit()?;
+            if sps_scaling_list_data_present_flag {
+                skip_scaling_list_data(reader)?;
+            }
+        }
+
+        let amp_enabled_flag = reader.read_bit()?;
+        let sample_adaptive_offset_enabled_flag = reader.read_bit()?;
+        let pcm = cond(reader.read_bit()?, || PcmParameters::read(reader))?;
+
+        let num_short_term_ref_pic_sets = reader.read_ue_max(64)?;
+        let num_sets = num_short_term_ref_pic_sets as usize;
+        let mut st_ref_pic_sets = Vec::new();
+        for idx in 0 .. num_sets {
+            let set = ShortTermRefPicSet::read(reader, idx, num_sets, &st_ref_pic_sets)?;
+            st_ref_pic_sets.push(set);
+        }
+
+        let long_term_ref_pics_present_flag = reader.read_bit()?;
+        let mut used_by_curr_pic_lt_sps_flags = Vec::new();
+        if long_term_ref_pics_present_flag {
+            let num_long_term_ref_pics_sps = reader.read_ue_max(32)?;
+            for _ in 0 .. num_long_term_ref_pics_sps {
+                // lt_ref_pic_poc_lsb_sps
+                reader.read_bits(log2_max_pic_order_cnt_lsb_minus4 as u8 + 4)?;
+                used_by_curr_pic_lt_sps_flags.push(reader.read_bit()?);
+            }
+        }
+
+        let sps_temporal_mvp_enabled_flag = reader.read_bit()?;
+        let strong_intra_smoothing_enabled_flag = reader.read_bit()?;
+        let vui_parameters = cond(reader.read_bit()?, || HevcVuiParameters::read(reader))?;
+
+        Ok(SeqParameterSet {
+            sps_video_parameter_set_id,
+            sps_max_sub_layers_minus1,
+            sps_temporal_id_nesting_flag,
+            profile_tier_level,
+            sps_seq_parameter_set_id,
+            chroma_format_idc,
+            separate_colour_plane_flag,
+            pic_width_in_luma_samples,
+            pic_height_in_luma_samples,
+            conformance_window,
+            bit_depth_luma_minus8,
+            bit_depth_chroma_minus8,
+            log2_max_pic_order_cnt_lsb_minus4,
+            sub_layer_ordering_info,
+            log2_min_luma_coding_block_size_minus3,
+            log2_diff_max_min_luma_coding_block_size,
+            log2_min_luma_transform_block_size_minus2,
+            log2_diff_max_min_luma_transform_block_size,
+            max_transform_hierarchy_depth_inter,
+            max_transform_hierarchy_depth_intra,
+            scaling_list_enabled_flag,
+            amp_enabled_flag,
+            sample_adaptive_offset_enabled_flag,
+            pcm,
+            num_short_term_ref_pic_sets,
+            st_ref_pic_sets,
+            long_term_ref_pics_present_flag,
+            used_by_curr_pic_lt_sps_flags,
+            sps_temporal_mvp_enabled_flag,
+            strong_intra_smoothing_enabled_flag,
+            vui_parameters,
+        })
+    }
+
+    // Chroma subsampling factors (SubWidthC, SubHeightC).
+    fn sub_width_height_c(&self) -> (u32, u32) {
+        if self.separate_colour_plane_flag {
+            return (1, 1);
+        }
+        match self.chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        }
+    }
+
+    /// Display width, after conformance window cropping.
+    pub fn width(&self) -> u32 {
+        let (sub_width_c, _) = self.sub_width_height_c();
+        let crop = self.conformance_window.as_ref().map(|w| w.left_offset + w.right_offset).unwrap_or(0);
+        self.pic_width_in_luma_samples.saturating_sub(sub_width_c * crop)
+    }
+
+    /// Display height, after conformance window cropping.
+    pub fn height(&self) -> u32 {
+        let (_, sub_height_c) = self.sub_width_height_c();
+        let crop = self.conformance_window.as_ref().map(|w| w.top_offset + w.bottom_offset).unwrap_or(0);
+        self.pic_height_in_luma_samples.saturating_sub(sub_height_c * crop)
+    }
+
+    /// Luma bit depth.
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth_luma_minus8 as u8 + 8
+    }
+
+    /// Frame rate from the VUI timing info, if present.
+    pub fn frame_rate(&self) -> Option<f64> {
+        let vui = self.vui_parameters.as_ref()?;
+        let t_inf = vui.timing_info.as_ref()?;
+        if t_inf.num_units_in_tick == 0 {
+            return None;
+        }
+        let mut fr = t_inf.time_scale as f64 / t_inf.num_units_in_tick as f64;
+        if vui.field_seq_flag {
+            // each picture is a field.
+            fr /= 2.0;
+        }
+        Some(fr)
+    }
+
+    /// Colour description from the VUI, if present.
+    pub fn colour_description(&self) -> Option<(&ColourDescription, bool)> {
+        let vst = self.vui_parameters.as_ref()?.video_signal_type.as_ref()?;
+        vst.colour_description.as_ref().map(|cd| (cd, vst.video_full_range_flag))
     }
 }
 
+/// Profile, tier and level (ITU-T H.265 7.3.3).
+///
+/// Only the general part, the sub-layer info is skipped.
+#[derive(Clone, Debug)]
+pub struct ProfileTierLevel {
+    pub general_profile_space: u8,
+    pub general_tier_flag: bool,
+    pub general_profile_idc: u8,
+    pub general_profile_compatibility_flags: u32,
+    // 48 bits.
+    pub general_constraint_indicator_flags: u64,
+    pub general_level_idc: u8,
+}
+
+impl ProfileTierLevel {
+    fn read(reader: &mut BitReader, max_sub_layers_minus1: u8) -> io::Result<ProfileTierLevel> {
+        let general_profile_space = reader.read_bits(2)? as u8;
+        let general_tier_flag = reader.read_bit()?;
+        let general_profile_idc = reader.read_bits(5)? as u8;
+        let general_profile_compatibility_flags = reader.read_bits(32)?;
+        let hi = reader.read_bits(16)? as u64;
+        let lo = reader.read_bits(32)? as u64;
+        let general_constraint_indicator_flags = (hi << 32) | lo;
+        let general_level_idc = reader.read_u8()?;
+
+        let mut sub_layer_profile_present = Vec::new();
+        let mut sub_layer_level_present = Vec::new();
+        for _ in 0 .. max_sub_layers_minus1 {
+            sub_layer_profile_present.push(reader.read_bit()?);
+            sub_layer_level_present.push(reader.read_bit()?);
+        }
+        if max_sub_layers_minus1 > 0 {
+            for _ in max_sub_layers_minus1 .. 8 {
+                reader.read_bits(2)?;
+            }
+        }
+        for i in 0 .. max_sub_layers_minus1 as usize {
+            if sub_layer_profile_present[i] {
+                // profile_space .. constraint flags: 88 bits.
+                reader.read_bits(32)?;
+                reader.read_bits(32)?;
+                reader.read_bits(24)?;
+            }
+            if sub_layer_level_present[i] {
+                reader.read_u8()?;
+            }
+        }
+
+        Ok(ProfileTierLevel {
+            general_profile_space,
+            general_tier_flag,
+            general_profile_idc,
+            general_profile_compatibility_flags,
+            general_constraint_indicator_flags,
+            general_level_idc,
+        })
+    }
+}
+
+/// Conformance window / default display window offsets.
+#[derive(Clone, Debug)]
+pub struct ConformanceWindow {
+    pub left_offset: u32,
+    pub right_offset: u32,
+    pub top_offset: u32,
+    pub bottom_offset: u32,
+}
+
+impl ConformanceWindow {
+    fn read(reader: &mut BitReader) -> io::Result<ConformanceWindow> {
+        Ok(ConformanceWindow {
+            left_offset: reader.read_ue()?,
+            right_offset: reader.read_ue()?,
+            top_offset: reader.read_ue()?,
+            bottom_offset: reader.read_ue()?,
+        })
+    }
+}
+
+/// Sub layer ordering info.
+#[derive(Clone, Debug)]
+pub struct SubLayerOrderingInfo {
+    pub sps_max_dec_pic_buffering_minus1: u32,
+    pub sps_max_num_reorder_pics: u32,
+    pub sps_max_latency_increase_plus1: u32,
+}
+
+impl SubLayerOrderingInfo {
+    fn read(reader: &mut BitReader) -> io::Result<SubLayerOrderingInfo> {
+        Ok(SubLayerOrderingInfo {
+            sps_max_dec_pic_buffering_minus1: reader.read_ue()?,
+            sps_max_num_reorder_pics: reader.read_ue()?,
+            sps_max_latency_increase_plus1: reader.read_ue()?,
+        })
+    }
+}
+
+/// PCM parameters.
+#[derive(Clone, Debug)]
+pub struct PcmParameters {
+    pub pcm_sample_bit_depth_luma_minus1: u8,
+    pub pcm_sample_bit_depth_chroma_minus1: u8,
+    pub log2_min_pcm_luma_coding_block_size_minus3: u32,
+    pub log2_diff_max_min_pcm_luma_coding_block_size: u32,
+    pub pcm_loop_filter_disabled_flag: bool,
+}
+
+impl PcmParameters {
+    fn read(reader: &mut BitReader) -> io::Result<PcmParameters> {
+        Ok(PcmParameters {
+            pcm_sample_bit_depth_luma_minus1: reader.read_bits(4)? as u8,
+            pcm_sample_bit_depth_chroma_minus1: reader.read_bits(4)? as u8,
+            log2_min_pcm_luma_coding_block_size_minus3: reader.read_ue()?,
+            log2_diff_max_min_pcm_luma_coding_block_size: reader.read_ue()?,
+            pcm_loop_filter_disabled_flag: reader.read_bit()?,
+        })
+    }
+}
+
+// scaling_list_data() (7.3.4). We don't need it, just skip it.
+pub(crate) fn skip_scaling_list_data(reader: &mut BitReader) -> io::Result<()> {
+    for size_id in 0 .. 4 {
+        let step = if size_id == 3 { 3 } else { 1 };
+        for _ in (0 .. 6).step_by(step) {
+            let scaling_list_pred_mode_flag = reader.read_bit()?;
+            if !scaling_list_pred_mode_flag {
+                // scaling_list_pred_matrix_id_delta
+                reader.read_ue()?;
+            } else {
+                let coef_num = std::cmp::min(64, 1 << (4 + (size_id << 1)));
+                if size_id > 1 {
+                    // scaling_list_dc_coef_minus8
+                    reader.read_se()?;
+                }
+                for _ in 0 .. coef_num {
+                    // scaling_list_delta_coef
+                    reader.read_se()?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// A short-term reference picture set, st_ref_pic_set() (7.3.7, 7.4.8).
+//
+// The delta POCs of the pictures before and after the current one, and
+// if they are used by the current picture.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ShortTermRefPicSet {
+    pub negative: Vec<(i32, bool)>,
+    pub positive: Vec<(i32, bool)>,
+}
+
+impl ShortTermRefPicSet {
+    // `idx` is stRpsIdx, `sets` are the sets in the SPS before this one.
+    // In a slice header, `idx` is num_short_term_ref_pic_sets.
+    pub(crate) fn read(
+        reader: &mut BitReader,
+        idx: usize,
+        num_short_term_ref_pic_sets: usize,
+        sets: &[ShortTermRefPicSet],
+    ) -> io::Result<ShortTermRefPicSet> {
+        let inter_ref_pic_set_prediction_flag = if idx != 0 { reader.read_bit()? } else { false };
+        if !inter_ref_pic_set_prediction_flag {
+            let num_negative_pics = reader.read_ue_max(16)?;
+            let num_positive_pics = reader.read_ue_max(16)?;
+            let mut set = ShortTermRefPicSet::default();
+            let mut poc = 0i32;
+            for _ in 0 .. num_negative_pics {
+                poc -= reader.read_ue_max(0x7fff)? as i32 + 1;
+                set.negative.push((poc, reader.read_bit()?));
+            }
+            poc = 0;
+            for _ in 0 .. num_positive_pics {
+                poc += reader.read_ue_max(0x7fff)? as i32 + 1;
+                set.positive.push((poc, reader.read_bit()?));
+            }
+            return Ok(set);
+        }
+
+        // Predicted from an earlier set. In the SPS that is always the previous one.
+        let delta_idx_minus1 = if idx == num_short_term_ref_pic_sets {
+            reader.read_ue()? as usize
+        } else {
+            0
+        };
+        let ref_set = idx
+            .checked_sub(delta_idx_minus1 + 1)
+            .and_then(|i| sets.get(i))
+            .ok_or_else(|| ioerr!(InvalidData, "st_ref_pic_set: bad delta_idx_minus1"))?;
+        let delta_rps_sign = reader.read_bit()?;
+        let abs_delta_rps_minus1 = reader.read_ue_max(0x7fff)? as i32;
+        let delta_rps = if delta_rps_sign { -1 } else { 1 } * (abs_delta_rps_minus1 + 1);
+
+        // For every picture in the reference set, and delta_rps itself:
+        // used_by_curr_pic_flag and use_delta_flag.
+        let num_delta_pocs = ref_set.negative.len() + ref_set.positive.len();
+        let mut flags = Vec::new();
+        for _ in 0 ..= num_delta_pocs {
+            let used_by_curr_pic_flag = reader.read_bit()?;
+            let use_delta_flag = if !used_by_curr_pic_flag { reader.read_bit()? } else { true };
+            flags.push((used_by_curr_pic_flag, use_delta_flag));
+        }
+
+        // Equations 7-61 and 7-62.
+        let neg = ref_set.negative.len();
+        let shift = |(p, f): (&(i32, bool), &(bool, bool))| (p.0 + delta_rps, *f);
+        let negative: Vec<_> = ref_set.negative.iter().zip(&flags[..neg]).map(shift).collect();
+        let positive: Vec<_> = ref_set.positive.iter().zip(&flags[neg..]).map(shift).collect();
+        let own = (delta_rps, flags[num_delta_pocs]);
+        let mut set = ShortTermRefPicSet::default();
+        for &(poc, (used, use_delta)) in positive.iter().rev().chain(Some(&own)).chain(&negative) {
+            if poc < 0 && use_delta {
+                set.negative.push((poc, used));
+            }
+        }
+        for &(poc, (used, use_delta)) in negative.iter().rev().chain(Some(&own)).chain(&positive) {
+            if poc > 0 && use_delta {
+                set.positive.push((poc, used));
+            }
+        }
+        Ok(set)
+    }
+
+    // The number of pictures in the set that are used by the current picture.
+    #[cfg(feature = "streaming")]
+    pub(crate) fn num_used(&self) -> u32 {
+        self.negative.iter().chain(self.positive.iter()).filter(|p| p.1).count() as u32
+    }
+}
+
+/// HEVC VUI parameters (ITU-T H.265 E.2.1).
+///
+/// Decoding stops after the timing info.
+#[derive(Clone, Debug)]
+pub struct HevcVuiParameters {
+    pub aspect_ratio_info: Option<AspectRatioInfo>,
+    pub overscan_appropriate: Option<bool>,
+    pub video_signal_type: Option<VideoSignalType>,
+    pub chroma_loc_info: Option<ChromaLocInfo>,
+    pub neutral_chroma_indication_flag: bool,
+    pub field_seq_flag: bool,
+    pub frame_field_info_present_flag: bool,
+    pub default_display_window: Option<ConformanceWindow>,
+    pub timing_info: Option<HevcTimingInfo>,
+}
+
+impl HevcVuiParameters {
+    fn read(reader: &mut BitReader) -> io::Result<HevcVuiParameters> {
+        Ok(HevcVuiParameters {
+            aspect_ratio_info: cond(reader.read_bit()?, || AspectRatioInfo::read(reader))?,
+            overscan_appropriate: cond(reader.read_bit()?, || reader.read_bit())?,
+            video_signal_type: cond(reader.read_bit()?, || VideoSignalType::read(reader))?,
+            chroma_loc_info: cond(reader.read_bit()?, || ChromaLocInfo::read(reader))?,
+            neutral_chroma_indication_flag: reader.read_bit()?,
+            field_seq_flag: reader.read_bit()?,
+            frame_field_info_present_flag: reader.read_bit()?,
+            default_display_window: cond(reader.read_bit()?, || ConformanceWindow::read(reader))?,
+            timing_info: cond(reader.read_bit()?, || HevcTimingInfo::read(reader))?,
+        })
+    }
+}
+
+/// HEVC VUI timing info.
+#[derive(Clone, Debug)]
+pub struct HevcTimingInfo {
+    pub num_units_in_tick: u32,
+    pub time_scale: u32,
+    pub num_ticks_poc_diff_one_minus1: Option<u32>,
+}
+
+impl HevcTimingInfo {
+    fn read(reader: &mut BitReader) -> io::Result<HevcTimingInfo> {
+        let num_units_in_tick = reader.read_bits(32)?;
+        let time_scale = reader.read_bits(32)?;
+        let num_ticks_poc_diff_one_minus1 = cond(reader.read_bit()?, || reader.read_ue())?;
+        Ok(HevcTimingInfo {
+            num_units_in_tick,
+            time_scale,
+            num_ticks_poc_diff_one_minus1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::{escape_rbsp, BitWriter};
+    use crate::io::MemBuffer;
+
+    // Main 10, level 5.1, 1920x1080 (coded as 1088 plus cropping), BT.2020 PQ, 59.94 fps.
+    fn sps() -> Vec<u8> {
+        let mut w = BitWriter::default();
+        // vps_id, max_sub_layers_minus1, temporal_id_nesting, profile_tier_level().
+        w.bits(4, 0).bits(3, 0).bit(true);
+        w.bits(8, 2).bits(32, 0x2000_0000);
+        w.bits(48, 0xb000_0000_0000).bits(8, 153);
+        // sps_id, 4:2:0, 1920x1088, conformance window 8 lines at the bottom.
+        w.ue(0).ue(1).ue(1920).ue(1088);
+        w.bit(true).ue(0).ue(0).ue(0).ue(4);
+        // 10 bits, log2_max_pic_order_cnt_lsb 8, sub_layer_ordering_info.
+        w.ue(2).ue(2).ue(4).bit(true).ue(4).ue(2).ue(0);
+        // 8x8 .. 64x64 coding blocks, 4x4 .. 32x32 transform blocks.
+        w.ue(0).ue(3).ue(0).ue(3).ue(0).ue(0);
+        // no scaling lists, no AMP, SAO, no PCM, no short-term RPS, no long-term references.
+        w.bit(false).bit(false).bit(true).bit(false).ue(0).bit(false);
+        // temporal MVP, strong intra smoothing, VUI.
+        w.bit(true).bit(true).bit(true);
+        // no aspect ratio, no overscan, video signal type with colour description.
+        w.bit(false).bit(false).bit(true).bits(3, 5).bit(false).bit(true);
+        w.bits(8, 9).bits(8, 16).bits(8, 9);
+        // no chroma loc, not neutral, frames, no frame field info, no default display window.
+        w.bit(false).bit(false).bit(false).bit(false).bit(false);
+        // timing info.
+        w.bit(true).bits(32, 1001).bits(32, 60000).bit(false);
+        w.trailing();
+
+        let mut nal = vec![0x42, 0x01];
+        nal.extend_from_slice(&escape_rbsp(&w.data));
+        nal
+    }
+
+    fn hvcc_record(sps: &[u8]) -> Vec<u8> {
+        let mut b = vec![1, 0x02, 0x20, 0, 0, 0, 0xb0, 0, 0, 0, 0, 0, 153];
+        b.extend_from_slice(&[0xf0, 0, 0xfc, 0xfd, 0xfa, 0xfa, 0, 0, 0x0f]);
+        // One array with one SPS.
+        b.extend_from_slice(&[1, 0xa1, 0, 1]);
+        b.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        b.extend_from_slice(sps);
+        b
+    }
+
+    #[test]
+    fn hvcc_round_trip() {
+        let data = hvcc_record(&sps());
+        let hvcc = HEVCDecoderConfigurationRecord::from_bytes(&mut &data[..]).unwrap();
+        assert_eq!(hvcc.codec_id("hvc1"), "hvc1.2.4.L153.b0");
+
+        let mut buffer = MemBuffer::new();
+        hvcc.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+    }
+
+    #[test]
+    fn seq_parameter_set() {
+        let data = hvcc_record(&sps());
+        let hvcc = HEVCDecoderConfigurationRecord::from_bytes(&mut &data[..]).unwrap();
+        let sps = hvcc.seq_parameter_set().unwrap();
+        assert_eq!(sps.profile_tier_level.general_profile_idc, 2);
+        assert_eq!(sps.profile_tier_level.general_level_idc, 153);
+        assert_eq!((sps.width(), sps.height()), (1920, 1080));
+        assert_eq!(sps.bit_depth(), 10);
+        let (colour, full_range) = sps.colour_description().unwrap();
+        assert_eq!(colour.colour_primaries, 9);
+        assert_eq!(colour.transfer_characteristics, 16);
+        assert_eq!(colour.matrix_coefficients, 9);
+        assert!(!full_range);
+        assert_eq!(hvcc.frame_rate(), 59.94);
+    }
+
+    #[test]
+    fn predicted_ref_pic_set() {
+        // {-1}, then predicted from it with deltaRps -1: {-1, -2}.
+        let mut w = BitWriter::default();
+        w.ue(1).ue(0).ue(0).bit(true);
+        w.bit(true).bit(true).ue(0).bit(true).bit(true);
+        let mut r = BitReader::new(&w.data);
+        let first = ShortTermRefPicSet::read(&mut r, 0, 2, &[]).unwrap();
+        let second = ShortTermRefPicSet::read(&mut r, 1, 2, &[first]).unwrap();
+        assert_eq!(second.negative, [(-1, true), (-2, true)]);
+        assert!(second.positive.is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        // SPS cut off halfway.
+        let sps = sps();
+        let data = hvcc_record(&sps[..sps.len() / 2]);
+        let hvcc = HEVCDecoderConfigurationRecord::from_bytes(&mut &data[..]).unwrap();
+        assert!(hvcc.seq_parameter_set().is_none());
+        let set = ParameterSet::parse(&hvcc.data.0).unwrap();
+        let err = set.sequence_parameters_sets().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // NAL unit length past the end of the record.
+        let err = ParameterSet::parse(&[1, 0xa1, 0, 1, 0, 200, 0x42, 0x01]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -111,7 +111,8 @@ impl FromBytes for AppleItem {
             match &b {
                 b"name" => name = Some(INameBox::from_bytes(stream)?),
                 b"mean" =>mean = Some(IMeanBox::from_bytes(stream)?),
-                b"data" => data = Some(IDataBox::from_bytes(stream)?),
+                // More than one data box (several covers) are kept as-is.
+                b"data" if data.is_none() => data = Some(IDataBox::from_bytes(stream)?),
                 _ => boxes.push(GenericBox::from_bytes(stream)?),
             }
         }
@@ -142,6 +143,9 @@ impl ToBytes for AppleItem {
         if let Some(ref data) = self.data {
             data.to_bytes(stream)?;
         }
+        for b in &self.boxes {
+            b.to_bytes(stream)?;
+        }
         writer.finalize()
     }
 }
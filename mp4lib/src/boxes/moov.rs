@@ -1,7 +1,7 @@
 use std::io;
 
 use crate::boxes::prelude::*;
-use crate::boxes::{MovieHeaderBox, TrackBox, TrackExtendsBox};
+use crate::boxes::{MovieExtendsBox, MovieHeaderBox, TrackBox, TrackExtendsBox};
 
 def_box! {
     /// 8.2.1 Movie Box (ISO/IEC 14496-12:2015(E))
@@ -76,7 +76,8 @@ impl MovieBox {
 
     /// Get the Track Extends box for this track.
     pub fn track_extends_by_id(&self, track_id: u32) -> Option<&TrackExtendsBox> {
-        self.boxes.iter().find_map(|b| {
+        let mvex = first_box!(&self.boxes, MovieExtendsBox)?;
+        mvex.boxes.iter().find_map(|b| {
             match b {
                 MP4Box::TrackExtendsBox(t) => {
                     if t.track_id == track_id {
@@ -99,9 +100,14 @@ impl MovieBox {
             log::error!("MovieBox: no MovieHeaderBox present");
             valid = false;
         }
+        // Tracks without samples in the sample table are only valid in a fragmented file.
+        let fragmented = first_box!(&self.boxes, MovieExtendsBox).is_some();
         for t in &self.tracks() {
             if !t.is_valid() {
                 valid = false;
+            } else if !fragmented && t.media().media_info().sample_table().is_empty() {
+                log::error!("TrackBox(id {}): no samples", t.track_id());
+                valid = false;
             }
         }
         valid
@@ -48,44 +48,14 @@ impl AacSampleEntry {
             ai.codec_name = Some(esds.codec_name().to_string());
             let config = &esds.es_descriptor.decoder_config;
             if let Some(ref audio) = config.specific_info.audio {
-                ai.channel_configuration = match audio.channel_config {
-                    1 => Some("C"),
-                    2 => Some("L,R"),
-                    3 => Some("C,L,R"),
-                    4 => Some("C,L,R,S"),
-                    5 => Some("C,L,R,LS,RS"),
-                    6 => Some("C,L,R,LS,RS"),
-                    _ => None,
-                }.map(|x| x.to_string());
-                let c = match audio.channel_config {
-                    1 => 1,
-                    2 => 2,
-                    3 => 3,
-                    4 => 4,
-                    5 => 5,
-                    6 => 5,
-                    7 => 7,
-                    11 => 6,
-                    12 => 7,
-                    13 => 22,
-                    14 =>7,
-                    _ => 0,
-                };
+                ai.channel_configuration = audio.channel_configuration().map(|x| x.to_string());
+                let c = audio.channel_count();
                 if c > 0 {
                     ai.channel_count = c;
                 }
-                ai.lfe_channel = match audio.channel_config {
-                    6|7|11|12|13|14 => true,
-                    _ => false,
-                };
-                if audio.sampling_frequency_index < 12 {
-                    let sr: u32 = [ 
-                        96000, 88200, 64000, 48000, 44100, 32000,
-                        24000, 22050, 16000, 12000, 11025, 8000
-                    ][audio.sampling_frequency_index as usize];
+                ai.lfe_channel = audio.lfe_channel();
+                if let Some(sr) = audio.sample_rate() {
                     ai.sample_rate = Some(sr);
-                } else if audio.sampling_frequency_index == 0xf && audio.sampling_frequency > 0 {
-                    ai.sample_rate = Some(audio.sampling_frequency);
                 }
             }
         }
@@ -126,10 +96,11 @@ impl ESDescriptorBox {
             return "mp4a";
         }
         match config.specific_info.audio {
-            Some(ref audio) => match audio.profile {
+            Some(ref audio) => match audio.codec_profile() {
                 2 => "AAC-LC",
                 5 => "HE-AAC",
                 29 => "HE-AACv2",
+                42 => "xHE-AAC",
                 _ => "AAC",
             },
             None => "MPEG-4 Audio",
@@ -143,7 +114,7 @@ impl ESDescriptorBox {
             return "mp4a".to_string();
         }
         match config.specific_info.audio {
-            Some(ref audio) => format!("mp4a.{:02x}.{}", config.object_type, audio.profile),
+            Some(ref audio) => format!("mp4a.{:02x}.{}", config.object_type, audio.codec_profile()),
             None => format!("mp4a.{:02x}", config.object_type),
         }
     }
@@ -155,6 +126,7 @@ impl ESDescriptorBox {
 // - mp4a.40.2
 // - mp4a.40.5
 // - mp4a.40.29
+// - mp4a.40.42
 //
 // A lot of thanks to the code-as-documentation in:
 // https://github.com/sannies/mp4parser/tree/master/isoparser/src/main/java/org/mp4parser
@@ -404,12 +376,411 @@ pub struct DecoderSpecificInfo {
 /// 2:  AAC-LC
 /// 5:  HE-AAC   (AAC-LC + SBR)
 /// 29: HE-AACv2 (AAC-LC + SBR + PS)
+/// 42: xHE-AAC  (USAC)
+///
+/// `profile` is the first audio object type in the config. With
+/// backwards compatible signaling of SBR / PS that is "2", use
+/// `codec_profile()` to get the real profile.
 #[derive(Clone, Debug, Default)]
 pub struct AudioSpecificConfig {
     pub profile:    u8,
     pub sampling_frequency_index:    u8,
     pub sampling_frequency:    u32,
     pub channel_config: u8,
+    /// Audio object type of the core codec (e.g. 2 for HE-AAC).
+    pub core_profile: u8,
+    /// Spectral Band Replication present (HE-AAC).
+    pub sbr_present: bool,
+    /// Parametric Stereo present (HE-AACv2).
+    pub ps_present: bool,
+    /// Output sampling frequency if SBR is present, or 0.
+    pub extension_sampling_frequency: u32,
+    /// Present if `channel_config` is 0.
+    pub program_config: Option<ProgramConfigElement>,
+    /// Present for xHE-AAC.
+    pub usac_config: Option<UsacConfig>,
+}
+
+const SAMPLING_FREQUENCIES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000,
+    24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// Read a sampling frequency index, and the frequency if it was escaped.
+fn read_sampling_frequency(b: &mut BitReader) -> io::Result<(u8, u32)> {
+    let idx = b.read_bits(4)? as u8;
+    let freq = if idx == 0xf {
+        b.read_bits(24)?
+    } else {
+        SAMPLING_FREQUENCIES.get(idx as usize).cloned().unwrap_or(0)
+    };
+    Ok((idx, freq))
+}
+
+// GetAudioObjectType()
+fn read_audio_object_type(b: &mut BitReader) -> io::Result<u8> {
+    let mut aot = b.read_bits(5)? as u8;
+    if aot == 31 {
+        aot = 32 + b.read_bits(6)? as u8;
+    }
+    Ok(aot)
+}
+
+impl AudioSpecificConfig {
+    // AudioSpecificConfig() (ISO/IEC 14496-3 1.6.2.1).
+    fn read(data: &[u8]) -> io::Result<AudioSpecificConfig> {
+        let mut b = BitReader::new(data);
+
+        let profile = read_audio_object_type(&mut b)?;
+        let sampling_frequency_index = b.read_bits(4)? as u8;
+        let mut sampling_frequency = 0;
+        if sampling_frequency_index == 0xf {
+            sampling_frequency = b.read_bits(24)?;
+        }
+        let channel_config = b.read_bits(4)? as u8;
+
+        let mut asc = AudioSpecificConfig {
+            profile,
+            sampling_frequency_index,
+            sampling_frequency,
+            channel_config,
+            core_profile: profile,
+            ..AudioSpecificConfig::default()
+        };
+
+        // Errors in the rest of the config are not fatal.
+        if let Err(e) = asc.read_extended(&mut b) {
+            log::debug!("AudioSpecificConfig: {:?}: {}", asc, e);
+        }
+
+        Ok(asc)
+    }
+
+    // The part of the AudioSpecificConfig after channelConfiguration.
+    fn read_extended(&mut self, b: &mut BitReader) -> io::Result<()> {
+        // Explicit hierarchical signaling of SBR / PS.
+        if self.profile == 5 || self.profile == 29 {
+            self.sbr_present = true;
+            self.ps_present = self.profile == 29;
+            self.extension_sampling_frequency = read_sampling_frequency(b)?.1;
+            self.core_profile = read_audio_object_type(b)?;
+            if self.core_profile == 22 {
+                // extensionChannelConfiguration
+                b.read_bits(4)?;
+            }
+        }
+
+        match self.core_profile {
+            1 | 2 | 3 | 4 | 6 | 7 | 17 | 19 | 20 | 21 | 22 | 23 => {
+                self.read_ga_specific_config(b)?;
+            },
+            42 => {
+                self.usac_config = Some(UsacConfig::read(b)?);
+                return Ok(());
+            },
+            _ => return Ok(()),
+        }
+        match self.core_profile {
+            17 | 19 | 20 | 21 | 22 | 23 | 24 | 25 | 26 | 27 | 39 => {
+                // epConfig
+                let ep_config = b.read_bits(2)?;
+                if ep_config == 2 || ep_config == 3 {
+                    // ErrorProtectionSpecificConfig, not supported.
+                    return Ok(());
+                }
+            },
+            _ => {},
+        }
+
+        // Backwards compatible signaling of SBR / PS.
+        if !self.sbr_present && b.data.len() * 8 >= b.pos + 16 {
+            let sync_extension_type = b.read_bits(11)?;
+            if sync_extension_type == 0x2b7 {
+                let extension_profile = read_audio_object_type(b)?;
+                if extension_profile == 5 {
+                    self.sbr_present = b.read_bit()?;
+                    if self.sbr_present {
+                        self.extension_sampling_frequency = read_sampling_frequency(b)?.1;
+                        if b.data.len() * 8 >= b.pos + 12 {
+                            let sync_extension_type = b.read_bits(11)?;
+                            if sync_extension_type == 0x548 {
+                                self.ps_present = b.read_bit()?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // GASpecificConfig() (ISO/IEC 14496-3 4.4.1).
+    fn read_ga_specific_config(&mut self, b: &mut BitReader) -> io::Result<()> {
+        // frameLengthFlag
+        b.read_bit()?;
+        let depends_on_core_coder = b.read_bit()?;
+        if depends_on_core_coder {
+            // coreCoderDelay
+            b.read_bits(14)?;
+        }
+        let extension_flag = b.read_bit()?;
+        if self.channel_config == 0 {
+            self.program_config = Some(ProgramConfigElement::read(b)?);
+        }
+        if self.core_profile == 6 || self.core_profile == 20 {
+            // layerNr
+            b.read_bits(3)?;
+        }
+        if extension_flag {
+            if self.core_profile == 22 {
+                // numOfSubFrame, layer_length
+                b.read_bits(5)?;
+                b.read_bits(11)?;
+            }
+            if matches!(self.core_profile, 17 | 19 | 20 | 23) {
+                // aacSectionDataResilienceFlag, aacScalefactorDataResilienceFlag,
+                // aacSpectralDataResilienceFlag
+                b.read_bits(3)?;
+            }
+            // extensionFlag3
+            b.read_bit()?;
+        }
+        Ok(())
+    }
+
+    /// The profile to use in the codec id (mp4a.40.<profile>).
+    pub fn codec_profile(&self) -> u8 {
+        if self.usac_config.is_some() {
+            42
+        } else if self.ps_present {
+            29
+        } else if self.sbr_present {
+            5
+        } else {
+            self.profile
+        }
+    }
+
+    /// Output sample rate.
+    pub fn sample_rate(&self) -> Option<u32> {
+        if let Some(usac) = self.usac_config.as_ref() {
+            if usac.sampling_frequency > 0 {
+                return Some(usac.sampling_frequency);
+            }
+        }
+        if self.sbr_present && self.extension_sampling_frequency > 0 {
+            return Some(self.extension_sampling_frequency);
+        }
+        if self.sampling_frequency_index < 13 {
+            Some(SAMPLING_FREQUENCIES[self.sampling_frequency_index as usize])
+        } else if self.sampling_frequency_index == 0xf && self.sampling_frequency > 0 {
+            Some(self.sampling_frequency)
+        } else {
+            None
+        }
+    }
+
+    // channelConfiguration, or the USAC channelConfigurationIndex.
+    fn channel_configuration_index(&self) -> u8 {
+        match self.usac_config.as_ref() {
+            Some(usac) => usac.channel_configuration_index,
+            None => self.channel_config,
+        }
+    }
+
+    /// Audio channels. "L,R", "C,L,R,LS,RS", etc.
+    ///
+    /// The LFE channel is not included, see `lfe_channel()`.
+    pub fn channel_configuration(&self) -> Option<&'static str> {
+        if self.ps_present && self.channel_config <= 1 {
+            return Some("L,R");
+        }
+        match self.channel_configuration_index() {
+            1 => Some("C"),
+            2 => Some("L,R"),
+            3 => Some("C,L,R"),
+            4 => Some("C,L,R,S"),
+            5 => Some("C,L,R,LS,RS"),
+            6 => Some("C,L,R,LS,RS"),
+            7 => Some("C,LC,RC,L,R,LS,RS"),
+            11 => Some("C,L,R,LS,RS,CS"),
+            12 => Some("C,L,R,LS,RS,LSR,RSR"),
+            14 => Some("C,L,R,LS,RS,LH,RH"),
+            _ => None,
+        }
+    }
+
+    /// Number of audio channels, not including the LFE channel(s).
+    ///
+    /// Returns 0 if unknown.
+    pub fn channel_count(&self) -> u16 {
+        if self.ps_present && self.channel_config <= 1 {
+            return 2;
+        }
+        let cfg = self.channel_configuration_index();
+        if cfg == 0 {
+            return self.program_config.as_ref().map(|p| p.channel_count()).unwrap_or(0);
+        }
+        match cfg {
+            1 => 1,
+            2 => 2,
+            3 => 3,
+            4 => 4,
+            5 => 5,
+            6 => 5,
+            7 => 7,
+            11 => 6,
+            12 => 7,
+            13 => 22,
+            14 => 7,
+            _ => 0,
+        }
+    }
+
+    /// LFE (sub) channel?
+    pub fn lfe_channel(&self) -> bool {
+        match self.channel_configuration_index() {
+            0 => self.program_config.as_ref().map(|p| p.num_lfe_channel_elements > 0).unwrap_or(false),
+            6 | 7 | 11 | 12 | 13 | 14 => true,
+            _ => false,
+        }
+    }
+}
+
+/// Program Config Element (ISO/IEC 14496-3 4.4.1.1).
+///
+/// Only used if the `channel_config` is 0.
+#[derive(Clone, Debug, Default)]
+pub struct ProgramConfigElement {
+    pub element_instance_tag: u8,
+    pub object_type: u8,
+    pub sampling_frequency_index: u8,
+    /// For each element, `true` if it is a channel pair (CPE).
+    pub front_channel_elements: Vec<bool>,
+    pub side_channel_elements: Vec<bool>,
+    pub back_channel_elements: Vec<bool>,
+    pub num_lfe_channel_elements: u8,
+    pub comment: Vec<u8>,
+}
+
+impl ProgramConfigElement {
+    fn read(b: &mut BitReader) -> io::Result<ProgramConfigElement> {
+        let element_instance_tag = b.read_bits(4)? as u8;
+        let object_type = b.read_bits(2)? as u8;
+        let sampling_frequency_index = b.read_bits(4)? as u8;
+        let num_front_channel_elements = b.read_bits(4)?;
+        let num_side_channel_elements = b.read_bits(4)?;
+        let num_back_channel_elements = b.read_bits(4)?;
+        let num_lfe_channel_elements = b.read_bits(2)? as u8;
+        let num_assoc_data_elements = b.read_bits(3)?;
+        let num_valid_cc_elements = b.read_bits(4)?;
+        if b.read_bit()? {
+            // mono_mixdown_element_number
+            b.read_bits(4)?;
+        }
+        if b.read_bit()? {
+            // stereo_mixdown_element_number
+            b.read_bits(4)?;
+        }
+        if b.read_bit()? {
+            // matrix_mixdown_idx, pseudo_surround_enable
+            b.read_bits(3)?;
+        }
+
+        let mut read_elements = |count: u32| -> io::Result<Vec<bool>> {
+            let mut v = Vec::new();
+            for _ in 0 .. count {
+                let is_cpe = b.read_bit()?;
+                // element_tag_select
+                b.read_bits(4)?;
+                v.push(is_cpe);
+            }
+            Ok(v)
+        };
+        let front_channel_elements = read_elements(num_front_channel_elements)?;
+        let side_channel_elements = read_elements(num_side_channel_elements)?;
+        let back_channel_elements = read_elements(num_back_channel_elements)?;
+
+        // lfe_element_tag_select, assoc_data_element_tag_select
+        for _ in 0 .. num_lfe_channel_elements as u32 + num_assoc_data_elements {
+            b.read_bits(4)?;
+        }
+        // cc_element_is_ind_sw, valid_cc_element_tag_select
+        for _ in 0 .. num_valid_cc_elements {
+            b.read_bits(5)?;
+        }
+
+        // byte_alignment()
+        if !b.pos.is_multiple_of(8) {
+            b.read_bits(8 - (b.pos % 8) as u8)?;
+        }
+        let comment_field_bytes = b.read_u8()?;
+        let mut comment = Vec::new();
+        for _ in 0 .. comment_field_bytes {
+            comment.push(b.read_u8()?);
+        }
+
+        Ok(ProgramConfigElement {
+            element_instance_tag,
+            object_type,
+            sampling_frequency_index,
+            front_channel_elements,
+            side_channel_elements,
+            back_channel_elements,
+            num_lfe_channel_elements,
+            comment,
+        })
+    }
+
+    /// Number of audio channels, not including the LFE channels.
+    pub fn channel_count(&self) -> u16 {
+        self.front_channel_elements
+            .iter()
+            .chain(self.side_channel_elements.iter())
+            .chain(self.back_channel_elements.iter())
+            .map(|&is_cpe| if is_cpe { 2 } else { 1 })
+            .sum()
+    }
+}
+
+/// USAC config (ISO/IEC 23003-3 5.2), just the start of it.
+#[derive(Clone, Debug, Default)]
+pub struct UsacConfig {
+    pub sampling_frequency_index: u8,
+    pub sampling_frequency: u32,
+    /// 0, 1: no SBR, 2: SBR 8:3, 3: SBR 2:1, 4: SBR 4:1.
+    pub core_sbr_frame_length_index: u8,
+    pub channel_configuration_index: u8,
+}
+
+const USAC_SAMPLING_FREQUENCIES: [u32; 31] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050,
+    16000, 12000, 11025, 8000, 7350, 0, 0, 57600,
+    51200, 40000, 38400, 34150, 28800, 25600, 20000, 19200,
+    17075, 14400, 12800, 9600, 0, 0, 0,
+];
+
+impl UsacConfig {
+    fn read(b: &mut BitReader) -> io::Result<UsacConfig> {
+        let sampling_frequency_index = b.read_bits(5)? as u8;
+        let sampling_frequency = if sampling_frequency_index == 0x1f {
+            b.read_bits(24)?
+        } else {
+            USAC_SAMPLING_FREQUENCIES[sampling_frequency_index as usize]
+        };
+        Ok(UsacConfig {
+            sampling_frequency_index,
+            sampling_frequency,
+            core_sbr_frame_length_index: b.read_bits(3)? as u8,
+            channel_configuration_index: b.read_bits(5)? as u8,
+        })
+    }
+
+    /// Is SBR used?
+    pub fn sbr_present(&self) -> bool {
+        self.core_sbr_frame_length_index >= 2
+    }
 }
 
 impl DecoderSpecificInfo {
@@ -420,26 +791,7 @@ impl DecoderSpecificInfo {
         let data = Data::read(stream, base.size as usize)?;
 
         let audio = if object_type == 0x40 || data.len() >= 2 {
-
-            let mut b = BitReader::new(&data.0);
-
-            let mut profile = b.read_bits(5)? as u8;
-            if profile == 31 {
-                profile = 32 + b.read_bits(6)? as u8;
-            }
-            let sampling_frequency_index = b.read_bits(4)? as u8;
-            let mut sampling_frequency = 0;
-            if sampling_frequency_index == 0xf {
-                sampling_frequency = b.read_bits(24)?;
-            }
-            let channel_config = b.read_bits(4)? as u8;
-
-            Some(AudioSpecificConfig {
-                profile,
-                sampling_frequency_index,
-                sampling_frequency,
-                channel_config,
-            })
+            Some(AudioSpecificConfig::read(&data.0)?)
         } else {
             None
         };
@@ -542,3 +894,143 @@ fn trailing_data<R: ReadBytes>(stream: &mut R, start: u64, size: u32) -> io::Res
     Ok(data)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::BitWriter;
+    use crate::io::MemBuffer;
+
+    // esds box with an AudioSpecificConfig, descriptor lengths coded in 4 bytes.
+    fn esds_box(asc: &[u8]) -> Vec<u8> {
+        let descriptor = |tag: u8, body: &[u8]| {
+            let mut d = vec![tag, 0x80, 0x80, 0x80, body.len() as u8];
+            d.extend_from_slice(body);
+            d
+        };
+        let mut config = vec![0x40, 0x15, 0, 0, 0];
+        config.extend_from_slice(&128000u32.to_be_bytes());
+        config.extend_from_slice(&128000u32.to_be_bytes());
+        config.extend_from_slice(&descriptor(DECODER_SPECIFIC_INFO_TAG, asc));
+        let mut es = vec![0, 1, 0];
+        es.extend_from_slice(&descriptor(DECODER_CONFIG_DESCRIPTOR_TAG, &config));
+        es.extend_from_slice(&descriptor(SLCONFIG_DESCRIPTOR_TAG, &[2]));
+        let es = descriptor(ESDESCRIPTOR_TAG, &es);
+
+        let mut b = Vec::new();
+        b.extend_from_slice(&(12 + es.len() as u32).to_be_bytes());
+        b.extend_from_slice(b"esds");
+        b.extend_from_slice(&[0, 0, 0, 0]);
+        b.extend_from_slice(&es);
+        b
+    }
+
+    #[test]
+    fn esds_round_trip() {
+        let data = esds_box(&[0x12, 0x10]);
+        let esds = ESDescriptorBox::from_bytes(&mut &data[..]).unwrap();
+        assert_eq!(esds.codec_id(), "mp4a.40.2");
+        assert_eq!(esds.codec_name(), "AAC-LC");
+
+        let mut buffer = MemBuffer::new();
+        esds.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+    }
+
+    #[test]
+    fn aac_lc() {
+        // 44.1 kHz, stereo.
+        let asc = AudioSpecificConfig::read(&[0x12, 0x10]).unwrap();
+        assert_eq!(asc.codec_profile(), 2);
+        assert_eq!(asc.sample_rate(), Some(44100));
+        assert_eq!(asc.channel_configuration(), Some("L,R"));
+        assert_eq!(asc.channel_count(), 2);
+        assert!(!asc.sbr_present && !asc.lfe_channel());
+
+        // 48 kHz, 5.1.
+        let asc = AudioSpecificConfig::read(&[0x11, 0xb0]).unwrap();
+        assert_eq!(asc.sample_rate(), Some(48000));
+        assert_eq!(asc.channel_count(), 5);
+        assert!(asc.lfe_channel());
+    }
+
+    #[test]
+    fn he_aac_explicit() {
+        // 24 kHz core, 48 kHz SBR, stereo, core AAC-LC.
+        let mut w = BitWriter::default();
+        w.bits(5, 5).bits(4, 6).bits(4, 2).bits(4, 3).bits(5, 2).bits(3, 0);
+        let asc = AudioSpecificConfig::read(&w.data).unwrap();
+        assert_eq!(asc.core_profile, 2);
+        assert!(asc.sbr_present && !asc.ps_present);
+        assert_eq!(asc.codec_profile(), 5);
+        assert_eq!(asc.sample_rate(), Some(48000));
+    }
+
+    #[test]
+    fn he_aac_v2_backwards_compatible() {
+        // AAC-LC 24 kHz mono, then the SBR and PS sync extensions.
+        let mut w = BitWriter::default();
+        w.bits(5, 2).bits(4, 6).bits(4, 1).bits(3, 0);
+        w.bits(11, 0x2b7).bits(5, 5).bit(true).bits(4, 3);
+        w.bits(11, 0x548).bit(true);
+        let asc = AudioSpecificConfig::read(&w.data).unwrap();
+        assert_eq!(asc.profile, 2);
+        assert!(asc.sbr_present && asc.ps_present);
+        assert_eq!(asc.codec_profile(), 29);
+        assert_eq!(asc.sample_rate(), Some(48000));
+        assert_eq!(asc.channel_configuration(), Some("L,R"));
+        assert_eq!(asc.channel_count(), 2);
+    }
+
+    #[test]
+    fn xhe_aac() {
+        // Escaped object type 42, then UsacConfig: 48 kHz, SBR 2:1, stereo.
+        let mut w = BitWriter::default();
+        w.bits(5, 31).bits(6, 42 - 32).bits(4, 3).bits(4, 0);
+        w.bits(5, 3).bits(3, 3).bits(5, 2);
+        let asc = AudioSpecificConfig::read(&w.data).unwrap();
+        let usac = asc.usac_config.as_ref().unwrap();
+        assert!(usac.sbr_present());
+        assert_eq!(asc.codec_profile(), 42);
+        assert_eq!(asc.sample_rate(), Some(48000));
+        assert_eq!(asc.channel_count(), 2);
+    }
+
+    #[test]
+    fn program_config_element() {
+        // AAC-LC 48 kHz, channel config 0: front SCE + CPE, back CPE, one LFE.
+        let mut w = BitWriter::default();
+        w.bits(5, 2).bits(4, 3).bits(4, 0).bits(3, 0);
+        w.bits(4, 0).bits(2, 1).bits(4, 3);
+        w.bits(4, 2).bits(4, 0).bits(4, 1).bits(2, 1).bits(3, 0).bits(4, 0);
+        w.bit(false).bit(false).bit(false);
+        w.bit(false).bits(4, 0).bit(true).bits(4, 0).bit(true).bits(4, 1);
+        w.bits(4, 0);
+        while w.pos % 8 != 0 {
+            w.bit(false);
+        }
+        w.bits(8, 0);
+        let asc = AudioSpecificConfig::read(&w.data).unwrap();
+        let pce = asc.program_config.as_ref().unwrap();
+        assert_eq!(pce.front_channel_elements, [false, true]);
+        assert_eq!(pce.back_channel_elements, [true]);
+        assert_eq!(asc.channel_count(), 5);
+        assert!(asc.lfe_channel());
+    }
+
+    #[test]
+    fn truncated() {
+        let err = AudioSpecificConfig::read(&[0x12]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A truncated extension is not fatal.
+        let asc = AudioSpecificConfig::read(&[0x2b, 0x10]).unwrap();
+        assert!(asc.sbr_present);
+        assert_eq!(asc.extension_sampling_frequency, 0);
+
+        // Descriptor length past the end of the box.
+        let mut data = esds_box(&[0x12, 0x10]);
+        let len = data.len();
+        data[len - 9] = 20;
+        assert!(ESDescriptorBox::from_bytes(&mut &data[..]).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//
+// Encapsulation of Opus in ISO Base Media File Format, version 0.8.1
+// https://opus-codec.org/docs/opus_in_isobmff.html
+//
+
+use std::io;
+
+use crate::boxes::prelude::*;
+use crate::track::AudioTrackInfo;
+
+def_box! {
+    /// Opus sample entry (AudioSampleEntry 'Opus').
+    OpusSampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   8,
+        // (mono = 1 ; stereo = 2)
+        channel_count: u16,
+        // always 16.
+        sample_size: u16,
+        skip:                   4,
+        // always 48000.
+        sample_rate_hi:         u16,
+        sample_rate_lo:         u16,
+        // sub boxes, probably only dOps.
+        boxes: Vec<MP4Box>,
+    },
+    fourcc => "Opus",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl Default for OpusSampleEntry {
+    fn default() -> OpusSampleEntry {
+        OpusSampleEntry {
+            data_reference_index:   1,
+            channel_count:          2,
+            sample_size:            16,
+            sample_rate_hi:         48000,
+            sample_rate_lo:         0,
+            boxes:                  Vec::new(),
+        }
+    }
+}
+
+impl OpusSampleEntry {
+    /// Return audio specific track info.
+    pub fn track_info(&self) -> AudioTrackInfo {
+        let mut ai = AudioTrackInfo {
+            codec_id:   "opus".to_string(),
+            codec_name: Some("Opus".to_string()),
+            channel_count:  self.channel_count,
+            // Opus is always decoded at 48000 Hz.
+            sample_rate: Some(48000),
+            ..AudioTrackInfo::default()
+        };
+
+        if let Some(dops) = first_box!(&self.boxes, OpusSpecificBox) {
+            ai.channel_count = dops.num_channels() as u16;
+            ai.lfe_channel = dops.lfe_channel();
+            ai.channel_configuration = dops.channel_configuration().map(|c| c.to_string());
+            ai.pre_skip = Some(dops.pre_skip);
+        }
+
+        ai
+    }
+}
+
+// class ChannelMappingTable (unsigned int(8) OutputChannelCount) {
+//     unsigned int(8) StreamCount;
+//     unsigned int(8) CoupledCount;
+//     unsigned int(8 * OutputChannelCount) ChannelMapping;
+// }
+//
+// aligned(8) class OpusSpecificBox extends Box('dOps') {
+//     unsigned int(8) Version;
+//     unsigned int(8) OutputChannelCount;
+//     unsigned int(16) PreSkip;
+//     unsigned int(32) InputSampleRate;
+//     signed int(16) OutputGain;
+//     unsigned int(8) ChannelMappingFamily;
+//     if (ChannelMappingFamily != 0) {
+//         ChannelMappingTable(OutputChannelCount);
+//     }
+// }
+def_box! {
+    /// Opus specific box.
+    OpusSpecificBox {
+        version: u8,
+        output_channel_count: u8,
+        // number of samples (at 48000 Hz) to discard at the start.
+        pre_skip: u16,
+        input_sample_rate: u32,
+        // Q7.8 in dB.
+        output_gain: i16,
+        channel_mapping_family: u8,
+        // only present if channel_mapping_family != 0.
+        channel_mapping_table: Option<OpusChannelMappingTable>,
+    },
+    fourcc => "dOps",
+    version => [],
+    impls => [ basebox, boxinfo, debug ],
+}
+
+/// Opus channel mapping table.
+#[derive(Clone, Debug, Default)]
+pub struct OpusChannelMappingTable {
+    pub stream_count: u8,
+    pub coupled_count: u8,
+    pub channel_mapping: Vec<u8>,
+}
+
+impl FromBytes for OpusSpecificBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<OpusSpecificBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let version = u8::from_bytes(stream)?;
+        let output_channel_count = u8::from_bytes(stream)?;
+        let pre_skip = u16::from_bytes(stream)?;
+        let input_sample_rate = u32::from_bytes(stream)?;
+        let output_gain = i16::from_bytes(stream)?;
+        let channel_mapping_family = u8::from_bytes(stream)?;
+        let mut channel_mapping_table = None;
+        if channel_mapping_family != 0 {
+            let stream_count = u8::from_bytes(stream)?;
+            let coupled_count = u8::from_bytes(stream)?;
+            let channel_mapping = stream.read(output_channel_count as u64)?.to_vec();
+            channel_mapping_table = Some(OpusChannelMappingTable {
+                stream_count,
+                coupled_count,
+                channel_mapping,
+            });
+        }
+
+        Ok(OpusSpecificBox {
+            version,
+            output_channel_count,
+            pre_skip,
+            input_sample_rate,
+            output_gain,
+            channel_mapping_family,
+            channel_mapping_table,
+        })
+    }
+
+    fn min_size() -> usize {
+        19
+    }
+}
+
+impl ToBytes for OpusSpecificBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        self.version.to_bytes(stream)?;
+        self.output_channel_count.to_bytes(stream)?;
+        self.pre_skip.to_bytes(stream)?;
+        self.input_sample_rate.to_bytes(stream)?;
+        self.output_gain.to_bytes(stream)?;
+        self.channel_mapping_family.to_bytes(stream)?;
+        if self.channel_mapping_family != 0 {
+            let table = self.channel_mapping_table.clone().unwrap_or_default();
+            table.stream_count.to_bytes(stream)?;
+            table.coupled_count.to_bytes(stream)?;
+            let mut mapping = table.channel_mapping;
+            mapping.resize(self.output_channel_count as usize, 255);
+            stream.write(&mapping)?;
+        }
+
+        stream.finalize()
+    }
+}
+
+impl OpusSpecificBox {
+    /// Audio channels, in Vorbis channel order. "L,R", "L,C,R,SL,SR", etc.
+    ///
+    /// Only known for channel mapping family 0 and 1. The LFE channel
+    /// is not included, see `lfe_channel()`.
+    pub fn channel_configuration(&self) -> Option<&'static str> {
+        match (self.channel_mapping_family, self.output_channel_count) {
+            (0, 1) | (1, 1) => Some("C"),
+            (0, 2) | (1, 2) => Some("L,R"),
+            (1, 3) => Some("L,C,R"),
+            (1, 4) => Some("L,R,SL,SR"),
+            (1, 5) | (1, 6) => Some("L,C,R,SL,SR"),
+            (1, 7) => Some("L,C,R,SL,SR,BS"),
+            (1, 8) => Some("L,C,R,SL,SR,BSL,BSR"),
+            _ => None,
+        }
+    }
+
+    /// Number of audio channels, not including the LFE channel.
+    pub fn num_channels(&self) -> u8 {
+        self.output_channel_count - self.lfe_channel() as u8
+    }
+
+    /// LFE (sub) channel?
+    pub fn lfe_channel(&self) -> bool {
+        self.channel_mapping_family == 1 && self.output_channel_count >= 6
+    }
+}
@@ -54,6 +54,57 @@ def_box! {
     impls => [ basebox, boxinfo, debug, fromtobytes ],
 }
 
+// Bottom-centered, white "Sans-Serif" text on a transparent background.
+impl Default for Tx3gTextSampleEntry {
+    fn default() -> Tx3gTextSampleEntry {
+        let mut fonts = ArraySized16::new();
+        fonts.push(Tx3gFontRecord {
+            font_id:    1,
+            font_name:  PString::from("Sans-Serif"),
+        });
+        Tx3gTextSampleEntry {
+            data_reference_index:       1,
+            display_flags:              0,
+            horizontal_justification:   1,
+            vertical_justification:     0xff,
+            background_color_rgba:      0,
+            default_text_box: Tx3gBoxRecord {
+                top:    0,
+                left:   0,
+                bottom: 0,
+                right:  0,
+            },
+            default_style: Tx3gStyleRecord {
+                start_char_offset:  0,
+                end_char_offset:    0,
+                font_id:            1,
+                face_style_flags:   0,
+                font_size:          18,
+                text_color_rgba:    0xffffffff,
+            },
+            fonts: vec![Tx3gFontTableBox { fonts }],
+        }
+    }
+}
+
+// QuickTime extension of the display flags.
+const ALL_SAMPLES_FORCED: u32 = 0x80000000;
+
+impl Tx3gTextSampleEntry {
+    /// Are all samples in this track "forced" subtitles.
+    pub fn forced(&self) -> bool {
+        self.display_flags & ALL_SAMPLES_FORCED != 0
+    }
+
+    /// Mark all samples in this track as "forced" subtitles.
+    pub fn set_forced(&mut self, forced: bool) {
+        match forced {
+            true => self.display_flags |= ALL_SAMPLES_FORCED,
+            false => self.display_flags &= !ALL_SAMPLES_FORCED,
+        }
+    }
+}
+
 def_struct! {
     /// 5.16. Box Record (ETSI TS 126 245 V10.0.0)
     Tx3gBoxRecord,
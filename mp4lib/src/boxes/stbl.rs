@@ -74,9 +74,21 @@ impl SampleTableBox {
         unreachable!()
     }
 
+    /// Does this SampleTableBox have no samples.
+    ///
+    /// In a fragmented MP4 file the samples are in the MovieFragmentBoxes.
+    pub fn is_empty(&self) -> bool {
+        first_box!(&self.boxes, SampleSizeBox)
+            .map(|stsz| stsz.count == 0)
+            .unwrap_or(true)
+    }
+
     /// Check if this SampleTableBox is valid (has stsd, stts, stsc, stco boxes).
+    ///
+    /// The tables can be empty, see [`is_empty`](Self::is_empty).
     pub fn is_valid(&self) -> bool {
         let mut valid = true;
+        let empty = self.is_empty();
 
         if let Some(box_) = first_box!(&self.boxes, SampleDescriptionBox) {
             if box_.entries.len() == 0 {
@@ -94,7 +106,7 @@ impl SampleTableBox {
         }
 
         if let Some(box_) = first_box!(&self.boxes, TimeToSampleBox) {
-            if box_.entries.len() == 0 {
+            if !empty && box_.entries.len() == 0 {
                 log::error!("SampleTableBox: TimeToSampleBox: no entries");
                 valid = false;
             }
@@ -104,7 +116,7 @@ impl SampleTableBox {
         }
 
         if let Some(box_) = first_box!(&self.boxes, SampleToChunkBox) {
-            if box_.entries.len() == 0 {
+            if !empty && box_.entries.len() == 0 {
                 log::error!("SampleTableBox: SampleToChunkBox: no entries");
                 valid = false;
             }
@@ -114,12 +126,12 @@ impl SampleTableBox {
         }
 
         if let Some(box_) = first_box!(&self.boxes, ChunkOffsetBox) {
-            if box_.entries.len() == 0 {
+            if !empty && box_.entries.len() == 0 {
                 log::error!("SampleTableBox: ChunkOffsetBox: no entries");
                 valid = false;
             }
         } else if let Some(box_) = first_box!(&self.boxes, ChunkLargeOffsetBox) {
-            if box_.entries.len() == 0 {
+            if !empty && box_.entries.len() == 0 {
                 log::error!("SampleTableBox: ChunkLargeOffsetBox: no entries");
                 valid = false;
             }
@@ -129,7 +141,7 @@ impl SampleTableBox {
         }
 
         if let Some(box_) = first_box!(&self.boxes, SampleSizeBox) {
-            if box_.size == 0 && box_.entries.len() == 0 {
+            if !empty && box_.size == 0 && box_.entries.len() == 0 {
                 log::error!("SampleTableBox: SampleSizeBox: no entries");
                 valid = false;
             }
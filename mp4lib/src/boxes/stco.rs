@@ -108,7 +108,7 @@ impl ChunkOffsetBox {
     /// the offset any entry is larger than 4G (2^32 - 1), the box will
     /// be serialized as a ChunkLargeOffsetBox (`co64`).
     pub fn add_offset(&mut self, move_offset: i64) {
-        self.offset = move_offset;
+        self.offset += move_offset;
         self.check_offsets();
     }
 
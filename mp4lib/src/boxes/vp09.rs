@@ -0,0 +1,353 @@
+//
+// VP Codec ISO Media File Format Binding v1.0
+// https://www.webmproject.org/vp9/mp4/
+//
+
+use std::io;
+
+use crate::boxes::prelude::*;
+use crate::track::{ColorInfo, VideoTrackInfo};
+
+def_box! {
+    /// VP9 sample entry (VideoSampleEntry 'vp09').
+    ///
+    /// Contains:
+    ///
+    /// - VPCodecConfigurationBox (one)
+    /// - extra boxes (btrt, colr, pasp, etc).
+    Vp9SampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        skip:                   16,
+        width:                  u16,
+        height:                 u16,
+        // defaults to 72, 72
+        _video_horizontal_dpi:  FixedFloat16_16,
+        _video_vertical_dpi:    FixedFloat16_16,
+        skip:                   4,
+        // defaults to 1
+        _video_frame_count:     u16,
+        // Video encoder name is a fixed-size pascal string.
+        // _video_encoder_name: PascalString<32>,
+        skip:                   32,
+        // defaults to 0x0018;
+        video_pixel_depth:      u16,
+        // always -1
+        _pre_defined:           u16,
+        // vpcC, etc.
+        boxes:              Vec<MP4Box>,
+    },
+    fourcc => "vp09",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl Default for Vp9SampleEntry {
+    fn default() -> Self {
+        Vp9SampleEntry {
+            data_reference_index:     1,
+            width:                    1280,
+            height:                   720,
+            _video_horizontal_dpi:    FixedFloat16_16::from(72f64),
+            _video_vertical_dpi:      FixedFloat16_16::from(72f64),
+            _video_frame_count:       1,
+            video_pixel_depth:        24,
+            _pre_defined:             0xffff,
+            boxes:                    Vec::new(),
+        }
+    }
+}
+
+impl Vp9SampleEntry {
+    /// Return video specific track info.
+    pub fn track_info(&self) -> VideoTrackInfo {
+        let config = first_box!(self.boxes, VPCodecConfigurationBox);
+        let codec_id = match config {
+            Some(c) => c.codec_id(),
+            None => "vp09.unknown".to_string(),
+        };
+        let codec_name = match config {
+            Some(c) => c.codec_name(),
+            None => "VP9",
+        };
+        VideoTrackInfo {
+            codec_id,
+            codec_name: Some(codec_name.to_string()),
+            width: self.width,
+            height: self.height,
+            frame_rate: 0f64,
+            color: config.map(|c| ColorInfo {
+                colour_primaries: c.colour_primaries as u16,
+                transfer_characteristics: c.transfer_characteristics as u16,
+                matrix_coefficients: c.matrix_coefficients as u16,
+                full_range: c.video_full_range_flag,
+            }),
+            ..VideoTrackInfo::default()
+        }
+    }
+}
+
+// aligned (8) class VPCodecConfigurationBox extends FullBox('vpcC', version = 1, 0) {
+//     VPCodecConfigurationRecord() vpcConfig;
+// }
+//
+// aligned (8) class VPCodecConfigurationRecord {
+//     unsigned int (8)     profile;
+//     unsigned int (8)     level;
+//     unsigned int (4)     bitDepth;
+//     unsigned int (3)     chromaSubsampling;
+//     unsigned int (1)     videoFullRangeFlag;
+//     unsigned int (8)     colourPrimaries;
+//     unsigned int (8)     transferCharacteristics;
+//     unsigned int (8)     matrixCoefficients;
+//     unsigned int (16)    codecInitializationDataSize;
+//     unsigned int (8)[]   codecInitializationData;
+// }
+//
+// Version 0 of the box (older WebM-derived files) has the layout:
+//
+//     unsigned int (8)     profile;
+//     unsigned int (8)     level;
+//     unsigned int (4)     bitDepth;
+//     unsigned int (4)     colorSpace;
+//     unsigned int (4)     chromaSubsampling;
+//     unsigned int (3)     transferFunction;
+//     unsigned int (1)     videoFullRangeFlag;
+//     unsigned int (16)    codecIntializationDataSize;
+//     unsigned int (8)[]   codecIntializationData;
+//
+def_box! {
+    /// VP Codec Configuration box.
+    ///
+    /// Always written as version 1.
+    VPCodecConfigurationBox {
+        profile:                    u8,
+        level:                      u8,
+        bit_depth:                  u8,
+        chroma_subsampling:         u8,
+        video_full_range_flag:      bool,
+        colour_primaries:           u8,
+        transfer_characteristics:   u8,
+        matrix_coefficients:        u8,
+        codec_initialization_data:  Data,
+    },
+    fourcc => "vpcC",
+    version => [1],
+    impls => [ boxinfo, debug ],
+}
+
+impl Default for VPCodecConfigurationBox {
+    fn default() -> Self {
+        // Defaults as specified in the "Codecs Parameter String" section.
+        VPCodecConfigurationBox {
+            profile:                    0,
+            level:                      10,
+            bit_depth:                  8,
+            chroma_subsampling:         1,
+            video_full_range_flag:      false,
+            colour_primaries:           1,
+            transfer_characteristics:   1,
+            matrix_coefficients:        1,
+            codec_initialization_data:  Data::default(),
+        }
+    }
+}
+
+impl FullBox for VPCodecConfigurationBox {
+    fn version(&self) -> Option<u8> {
+        Some(1)
+    }
+}
+
+impl FromBytes for VPCodecConfigurationBox {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<VPCodecConfigurationBox> {
+        let mut reader = BoxReader::new(stream)?;
+        let stream = &mut reader;
+
+        let profile = u8::from_bytes(stream)?;
+        let level = u8::from_bytes(stream)?;
+
+        let bit_depth;
+        let chroma_subsampling;
+        let video_full_range_flag;
+        let mut colour_primaries = 2;
+        let mut transfer_characteristics = 2;
+        let matrix_coefficients;
+
+        if stream.version() == 0 {
+            let v = u8::from_bytes(stream)?;
+            bit_depth = v >> 4;
+            // Only the most common colorSpace values map cleanly.
+            matrix_coefficients = match v & 0x0f {
+                1 => 6,
+                2 => 1,
+                5 => 9,
+                7 => 0,
+                _ => 2,
+            };
+            let v = u8::from_bytes(stream)?;
+            chroma_subsampling = v >> 4;
+            video_full_range_flag = v & 0x01 != 0;
+        } else {
+            let v = u8::from_bytes(stream)?;
+            bit_depth = v >> 4;
+            chroma_subsampling = (v >> 1) & 0x07;
+            video_full_range_flag = v & 0x01 != 0;
+            colour_primaries = u8::from_bytes(stream)?;
+            transfer_characteristics = u8::from_bytes(stream)?;
+            matrix_coefficients = u8::from_bytes(stream)?;
+        }
+
+        let size = u16::from_bytes(stream)?;
+        let codec_initialization_data = Data(stream.read(size as u64)?.to_vec());
+
+        Ok(VPCodecConfigurationBox {
+            profile,
+            level,
+            bit_depth,
+            chroma_subsampling,
+            video_full_range_flag,
+            colour_primaries,
+            transfer_characteristics,
+            matrix_coefficients,
+            codec_initialization_data,
+        })
+    }
+
+    fn min_size() -> usize {
+        16
+    }
+}
+
+impl ToBytes for VPCodecConfigurationBox {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        let mut writer = BoxWriter::new(stream, self)?;
+        let stream = &mut writer;
+
+        self.profile.to_bytes(stream)?;
+        self.level.to_bytes(stream)?;
+        let v = (self.bit_depth << 4) |
+            ((self.chroma_subsampling & 0x07) << 1) |
+            (self.video_full_range_flag as u8);
+        v.to_bytes(stream)?;
+        self.colour_primaries.to_bytes(stream)?;
+        self.transfer_characteristics.to_bytes(stream)?;
+        self.matrix_coefficients.to_bytes(stream)?;
+        let data = &self.codec_initialization_data.0;
+        (data.len() as u16).to_bytes(stream)?;
+        stream.write(data)?;
+
+        stream.finalize()
+    }
+}
+
+impl VPCodecConfigurationBox {
+    /// Return human name of codec, like "VP9 Profile 0".
+    pub fn codec_name(&self) -> &'static str {
+        match self.profile {
+            0 => "VP9 Profile 0",
+            1 => "VP9 Profile 1",
+            2 => "VP9 Profile 2",
+            3 => "VP9 Profile 3",
+            _ => "VP9",
+        }
+    }
+
+    /// Return codec id as `vp09.PP.LL.DD` (e.g. `vp09.00.31.08`).
+    ///
+    /// If any of the optional fields differ from their defaults, the
+    /// full form `vp09.PP.LL.DD.CC.cp.tc.mc.FF` is returned.
+    ///
+    /// See [the VP Codec ISO Media File Format Binding, Codecs Parameter String](https://www.webmproject.org/vp9/mp4/#codecs-parameter-string).
+    pub fn codec_id(&self) -> String {
+        let short = format!("vp09.{:02}.{:02}.{:02}", self.profile, self.level, self.bit_depth);
+        let dfl = VPCodecConfigurationBox::default();
+        if self.chroma_subsampling == dfl.chroma_subsampling &&
+            self.colour_primaries == dfl.colour_primaries &&
+            self.transfer_characteristics == dfl.transfer_characteristics &&
+            self.matrix_coefficients == dfl.matrix_coefficients &&
+            self.video_full_range_flag == dfl.video_full_range_flag {
+            return short;
+        }
+        format!(
+            "{}.{:02}.{:02}.{:02}.{:02}.{:02}",
+            short,
+            self.chroma_subsampling,
+            self.colour_primaries,
+            self.transfer_characteristics,
+            self.matrix_coefficients,
+            self.video_full_range_flag as u8,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::MemBuffer;
+
+    fn vpcc_box(version: u8, body: &[u8]) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&(12 + body.len() as u32).to_be_bytes());
+        b.extend_from_slice(b"vpcC");
+        b.extend_from_slice(&[version, 0, 0, 0]);
+        b.extend_from_slice(body);
+        b
+    }
+
+    #[test]
+    fn vpcc_round_trip() {
+        // Profile 2, level 4.1, 10 bit 4:2:0 colocated, BT.2020 PQ, limited range.
+        let data = vpcc_box(1, &[2, 41, 0xa2, 9, 16, 9, 0, 2, 0xaa, 0xbb]);
+        let vpcc = VPCodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        assert_eq!(vpcc.profile, 2);
+        assert_eq!(vpcc.level, 41);
+        assert_eq!(vpcc.bit_depth, 10);
+        assert_eq!(vpcc.chroma_subsampling, 1);
+        assert!(!vpcc.video_full_range_flag);
+        assert_eq!(vpcc.codec_initialization_data.0, [0xaa, 0xbb]);
+        assert_eq!(vpcc.codec_name(), "VP9 Profile 2");
+        assert_eq!(vpcc.codec_id(), "vp09.02.41.10.01.09.16.09.00");
+
+        let mut buffer = MemBuffer::new();
+        vpcc.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), data);
+    }
+
+    #[test]
+    fn codec_id_defaults() {
+        let data = vpcc_box(1, &[0, 31, 0x82, 1, 1, 1, 0, 0]);
+        let vpcc = VPCodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        assert_eq!(vpcc.codec_id(), "vp09.00.31.08");
+    }
+
+    #[test]
+    fn version_0() {
+        // 8 bit, colorSpace BT.709, chroma subsampling 1, full range.
+        let data = vpcc_box(0, &[0, 30, 0x82, 0x11, 0, 0]);
+        let vpcc = VPCodecConfigurationBox::from_bytes(&mut &data[..]).unwrap();
+        assert_eq!(vpcc.bit_depth, 8);
+        assert_eq!(vpcc.chroma_subsampling, 1);
+        assert!(vpcc.video_full_range_flag);
+        assert_eq!(vpcc.matrix_coefficients, 1);
+        assert_eq!(vpcc.colour_primaries, 2);
+
+        // Always written as version 1.
+        let mut buffer = MemBuffer::new();
+        vpcc.to_bytes(&mut buffer).unwrap();
+        assert_eq!(buffer.into_vec(), vpcc_box(1, &[0, 30, 0x83, 2, 2, 1, 0, 0]));
+    }
+
+    #[test]
+    fn truncated() {
+        // codecInitializationDataSize larger than the box.
+        let data = vpcc_box(1, &[0, 31, 0x82, 1, 1, 1, 0, 4, 0xaa, 0xbb]);
+        let err = VPCodecConfigurationBox::from_bytes(&mut &data[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // No room for the codecInitializationDataSize.
+        let data = vpcc_box(1, &[0, 31, 0x82, 1, 1, 1]);
+        let err = VPCodecConfigurationBox::from_bytes(&mut &data[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
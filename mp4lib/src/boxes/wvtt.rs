@@ -0,0 +1,181 @@
+//
+// ISO/IEC 14496-30:2018(E)
+// 7 Carriage of WebVTT.
+//
+
+use std::io;
+
+use crate::boxes::prelude::*;
+
+def_box! {
+    /// 7.5 WebVTT Sample Entry.
+    ///
+    /// Contains a `WebVTTConfigurationBox` and optionally a `WebVTTSourceLabelBox`.
+    WebVTTSampleEntry {
+        skip:                   6,
+        data_reference_index:   u16,
+        boxes:                  Vec<MP4Box>,
+    },
+    fourcc => "wvtt",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl Default for WebVTTSampleEntry {
+    fn default() -> WebVTTSampleEntry {
+        WebVTTSampleEntry::new()
+    }
+}
+
+impl WebVTTSampleEntry {
+    /// New sample entry with the minimal `WEBVTT` header as configuration.
+    pub fn new() -> WebVTTSampleEntry {
+        WebVTTSampleEntry {
+            data_reference_index: 1,
+            boxes: vec![WebVTTConfigurationBox {
+                config: BoxString::from("WEBVTT"),
+            }
+            .to_mp4box()],
+        }
+    }
+
+    /// The WebVTT file header (everything up to the first cue).
+    pub fn config(&self) -> Option<&str> {
+        first_box!(&self.boxes, WebVTTConfigurationBox).map(|c| c.config.as_str())
+    }
+}
+
+def_box! {
+    /// 7.5 WebVTT Configuration Box.
+    WebVTTConfigurationBox {
+        config:     BoxString,
+    },
+    fourcc => "vttC",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.5 WebVTT Source Label Box.
+    WebVTTSourceLabelBox {
+        source_label:   BoxString,
+    },
+    fourcc => "vlab",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 WebVTT Cue Box.
+    ///
+    /// Contains an optional `CueSourceIDBox`, `CueIDBox`, `CueTimeBox`,
+    /// `CueSettingsBox`, and a `CuePayloadBox`.
+    VTTCueBox {
+        boxes:      Vec<MP4Box>,
+    },
+    fourcc => "vttc",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+impl VTTCueBox {
+    /// New cue with optional id and settings.
+    pub fn new(id: Option<&str>, settings: Option<&str>, payload: &str) -> VTTCueBox {
+        let mut boxes = Vec::new();
+        if let Some(id) = id {
+            boxes.push(CueIDBox { cue_id: id.into() }.to_mp4box());
+        }
+        if let Some(settings) = settings {
+            boxes.push(CueSettingsBox { settings: settings.into() }.to_mp4box());
+        }
+        boxes.push(CuePayloadBox { cue_text: payload.into() }.to_mp4box());
+        VTTCueBox { boxes }
+    }
+
+    /// Cue identifier.
+    pub fn id(&self) -> Option<&str> {
+        first_box!(&self.boxes, CueIDBox).map(|b| b.cue_id.as_str())
+    }
+
+    /// Cue settings (position, alignment, etc).
+    pub fn settings(&self) -> Option<&str> {
+        first_box!(&self.boxes, CueSettingsBox).map(|b| b.settings.as_str())
+    }
+
+    /// Cue text.
+    pub fn payload(&self) -> &str {
+        first_box!(&self.boxes, CuePayloadBox)
+            .map(|b| b.cue_text.as_str())
+            .unwrap_or("")
+    }
+}
+
+def_box! {
+    /// 7.4 WebVTT Empty Cue Box.
+    #[derive(Default)]
+    VTTEmptyCueBox {
+    },
+    fourcc => "vtte",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 WebVTT Additional Text Box (comments).
+    VTTAdditionalTextBox {
+        cue_additional_text:    BoxString,
+    },
+    fourcc => "vtta",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 Cue Source ID Box.
+    CueSourceIDBox {
+        source_id:  u32,
+    },
+    fourcc => "vsid",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 Cue ID Box.
+    CueIDBox {
+        cue_id:     BoxString,
+    },
+    fourcc => "iden",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 Cue Time Box.
+    CueTimeBox {
+        cue_current_time:   BoxString,
+    },
+    fourcc => "ctim",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 Cue Settings Box.
+    CueSettingsBox {
+        settings:   BoxString,
+    },
+    fourcc => "sttg",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
+
+def_box! {
+    /// 7.4 Cue Payload Box.
+    CuePayloadBox {
+        cue_text:   BoxString,
+    },
+    fourcc => "payl",
+    version => [],
+    impls => [ basebox, boxinfo, debug, fromtobytes ],
+}
@@ -0,0 +1,647 @@
+//! Chapters.
+//!
+//! There are two ways chapters are stored in an `mp4` file:
+//!
+//! - QuickTime chapters: a text track that is referenced from the audio
+//!   and video tracks by a `chap` track reference. There can also be a
+//!   second referenced (video) track with a `jpeg` image per chapter.
+//! - Nero chapters: a `chpl` box in `moov/udta`.
+//!
+//! [`chapters`] reads either of them, [`set_chapters`] writes one or both.
+//! [`export`] and [`import`] convert chapters to and from `JSON`,
+//! `WebVTT` chapters and `OGM` text.
+//!
+use std::cmp;
+use std::fmt::Write;
+use std::io;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::boxes::*;
+use crate::mp4box::MP4;
+use crate::rewrite;
+use crate::types::*;
+
+/// One chapter.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Chapter {
+    /// Start time in seconds.
+    pub start: f64,
+    /// End time in seconds.
+    #[serde(default)]
+    pub end: f64,
+    /// Title.
+    pub title: String,
+    /// Chapter image (`jpeg`), only present in QuickTime chapters.
+    #[serde(skip)]
+    pub image: Option<Vec<u8>>,
+}
+
+/// Where to store chapters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChapterKind {
+    /// QuickTime chapter track.
+    QuickTime,
+    /// Nero `chpl` box.
+    Nero,
+    /// Both.
+    Both,
+}
+
+impl FromStr for ChapterKind {
+    type Err = io::Error;
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "qt" | "quicktime" => Ok(ChapterKind::QuickTime),
+            "nero" | "chpl" => Ok(ChapterKind::Nero),
+            "both" => Ok(ChapterKind::Both),
+            _ => Err(ioerr!(InvalidInput, "Could not parse chapter kind")),
+        }
+    }
+}
+
+/// Chapter file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChapterFormat {
+    /// `JSON` array of [`Chapter`].
+    Json,
+    /// `WebVTT` chapters.
+    Vtt,
+    /// `OGM` style `CHAPTER01=00:00:00.000` / `CHAPTER01NAME=Title`.
+    Ogm,
+}
+
+impl FromStr for ChapterFormat {
+    type Err = io::Error;
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        let mut format = format;
+        if let Some(idx) = format.rfind(".") {
+            format = &format[idx + 1..];
+        }
+        match format {
+            "json" => Ok(ChapterFormat::Json),
+            "vtt" => Ok(ChapterFormat::Vtt),
+            "ogm" | "txt" => Ok(ChapterFormat::Ogm),
+            _ => Err(ioerr!(InvalidInput, "Could not parse format")),
+        }
+    }
+}
+
+/// Track ids of the tracks that are referenced by a `chap` track reference.
+///
+/// These are the QuickTime chapter text and image tracks.
+pub fn chapter_track_ids(mp4: &MP4) -> Vec<u32> {
+    let mut ids = Vec::new();
+    for track in mp4.movie().tracks() {
+        if let Some(tref) = first_box!(&track.boxes, TrackReferenceBox) {
+            if tref.reference_type == b"chap" {
+                for id in &tref.track_ids {
+                    if !ids.contains(id) {
+                        ids.push(*id);
+                    }
+                }
+            }
+        }
+    }
+    ids
+}
+
+/// Get the chapters.
+///
+/// If a file has both QuickTime and Nero chapters, the QuickTime
+/// chapters are returned.
+pub fn chapters(mp4: &MP4) -> io::Result<Vec<Chapter>> {
+    let mut chapters = quicktime_chapters(mp4)?;
+    if chapters.is_empty() {
+        chapters = nero_chapters(mp4);
+    }
+    fix_end_times(&mut chapters, movie_duration(mp4));
+    Ok(chapters)
+}
+
+fn quicktime_chapters(mp4: &MP4) -> io::Result<Vec<Chapter>> {
+    let movie = mp4.movie();
+    let tracks: Vec<_> = chapter_track_ids(mp4)
+        .iter()
+        .filter_map(|id| movie.track_by_id(*id))
+        .collect();
+
+    let text = match tracks.iter().find(|t| !t.media().handler().is_video()) {
+        Some(text) => text,
+        None => return Ok(Vec::new()),
+    };
+    let mut chapters = Vec::new();
+    let iter = mp4.sample_info_iter(text);
+    let timescale = iter.timescale() as f64;
+    for sample in iter {
+        let mut data = vec![0; sample.size as usize];
+        mp4.data_ref.read_exact_at(&mut data, sample.fpos)?;
+        let start = sample.decode_time as f64 / timescale;
+        chapters.push(Chapter {
+            start,
+            end: start + sample.duration as f64 / timescale,
+            title: text_sample(&data),
+            image: None,
+        });
+    }
+
+    // An empty first sample just fills the gap before the first chapter.
+    if chapters.len() > 1 && chapters[0].title.is_empty() {
+        chapters.remove(0);
+    }
+
+    // Chapter images, one sample per chapter.
+    if let Some(video) = tracks.iter().find(|t| t.media().handler().is_video()) {
+        let iter = mp4.sample_info_iter(video);
+        let timescale = iter.timescale() as f64;
+        let samples: Vec<_> = iter.collect();
+        for chapter in chapters.iter_mut() {
+            let sample = samples
+                .iter()
+                .take_while(|s| s.decode_time as f64 / timescale <= chapter.start + 0.001)
+                .last();
+            if let Some(sample) = sample {
+                let mut data = vec![0; sample.size as usize];
+                mp4.data_ref.read_exact_at(&mut data, sample.fpos)?;
+                chapter.image = Some(data);
+            }
+        }
+    }
+
+    Ok(chapters)
+}
+
+// A QuickTime text sample: 16 bits length, the text, and optionally
+// modifier atoms (like `encd`). The text is UTF-8, or UTF-16 with a BOM.
+fn text_sample(data: &[u8]) -> String {
+    if data.len() < 2 {
+        return String::new();
+    }
+    let len = cmp::min(u16::from_be_bytes([data[0], data[1]]) as usize, data.len() - 2);
+    let text = &data[2..2 + len];
+    if text.starts_with(&[0xfe, 0xff]) || text.starts_with(&[0xff, 0xfe]) {
+        let be = text[0] == 0xfe;
+        let utf16: Vec<_> = text[2..]
+            .chunks_exact(2)
+            .map(|c| {
+                if be {
+                    u16::from_be_bytes([c[0], c[1]])
+                } else {
+                    u16::from_le_bytes([c[0], c[1]])
+                }
+            })
+            .collect();
+        return String::from_utf16_lossy(&utf16);
+    }
+    String::from_utf8_lossy(text).to_string()
+}
+
+fn nero_chapters(mp4: &MP4) -> Vec<Chapter> {
+    let chpl =
+        first_box!(&mp4.movie().boxes, UserDataBox).and_then(|udta| first_box!(&udta.boxes, ChapterListBox));
+    let chpl = match chpl {
+        Some(chpl) => chpl,
+        None => return Vec::new(),
+    };
+    chpl.chapters
+        .iter()
+        .map(|c| Chapter {
+            start: c.start as f64 / 10_000_000.0,
+            end: 0.0,
+            title: c.title.to_string(),
+            image: None,
+        })
+        .collect()
+}
+
+fn movie_duration(mp4: &MP4) -> f64 {
+    let mvhd = mp4.movie().movie_header();
+    if mvhd.timescale == 0 {
+        return 0.0;
+    }
+    mvhd.duration.0 as f64 / mvhd.timescale as f64
+}
+
+// Sort the chapters, and fill in missing end times.
+fn fix_end_times(chapters: &mut [Chapter], duration: f64) {
+    chapters.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap_or(cmp::Ordering::Equal));
+    for idx in 0..chapters.len() {
+        if chapters[idx].end > chapters[idx].start {
+            continue;
+        }
+        let next = chapters.get(idx + 1).map(|c| c.start).unwrap_or(duration);
+        chapters[idx].end = next.max(chapters[idx].start);
+    }
+}
+
+/// Replace the chapters of an `mp4` file.
+///
+/// Existing chapters, of both kinds, are removed first. Chapter images
+/// are not written, so existing QuickTime chapter images are removed as
+/// well. An empty `chapters` list just removes all chapters.
+///
+/// The samples of the QuickTime chapter track are stored in a new `mdat`
+/// box at the end of the file. Chunk offsets are adjusted if the `moov`
+/// box is in front of the media data. The samples of a previous chapter
+/// track are not removed from the file, just unreferenced.
+pub fn set_chapters(mp4: &mut MP4, chapters: &[Chapter], kind: ChapterKind) -> io::Result<()> {
+    let mut chapters = chapters.to_vec();
+    fix_end_times(&mut chapters, movie_duration(mp4));
+
+    if mp4.boxes.iter().all(|b| !matches!(b, MP4Box::MovieBox(_))) {
+        return Err(ioerr!(InvalidInput, "no moov box"));
+    }
+    let old_size = rewrite::movie_size(mp4);
+
+    remove_chapters(mp4);
+
+    if kind != ChapterKind::QuickTime && !chapters.is_empty() {
+        add_nero_chapters(mp4, &chapters);
+    }
+
+    let mut mdat = None;
+    if kind != ChapterKind::Nero && !chapters.is_empty() {
+        mdat = Some(add_chapter_track(mp4, &chapters)?);
+    }
+
+    // If the movie box changed size, move the existing media data.
+    let chapter_track_id = chapter_track_ids(mp4).first().cloned();
+    rewrite::movie_resized(mp4, old_size, chapter_track_id.as_slice());
+
+    // Now that the size of everything is known, point the chapter
+    // track to the new mdat box at the end of the file.
+    if let (Some(data), Some(track_id)) = (mdat, chapter_track_id) {
+        let offset = mp4.boxes.iter().map(|b| b.size()).sum::<u64>() + 8;
+        let track = mp4
+            .movie_mut()
+            .tracks_mut()
+            .into_iter()
+            .find(|t| t.track_id() == track_id);
+        if let Some(track) = track {
+            let stco = track
+                .media_mut()
+                .media_info_mut()
+                .sample_table_mut()
+                .chunk_offset_table_mut();
+            let mut new_stco = ChunkOffsetBox::default();
+            if offset > u32::MAX as u64 {
+                new_stco.set_large();
+            }
+            new_stco.push(offset);
+            *stco = new_stco;
+        }
+        let mut mdat = MediaDataBox::default();
+        mdat.data.resize(data.len());
+        mdat.data.bytes_mut().copy_from_slice(&data);
+        mp4.boxes.push(mdat.to_mp4box());
+    }
+
+    Ok(())
+}
+
+// Remove QuickTime chapter tracks, `chap` references and the Nero `chpl` box.
+fn remove_chapters(mp4: &mut MP4) {
+    let ids = chapter_track_ids(mp4);
+    let movie = mp4.movie_mut();
+    movie.boxes.retain(|b| match b {
+        MP4Box::TrackBox(t) => !ids.contains(&t.track_id()),
+        _ => true,
+    });
+    for track in movie.tracks_mut() {
+        track.boxes.retain(|b| match b {
+            MP4Box::TrackReferenceBox(tref) => tref.reference_type != b"chap",
+            _ => true,
+        });
+    }
+    if let Some(udta) = first_box_mut!(&mut movie.boxes, UserDataBox) {
+        udta.boxes.retain(|b| !matches!(b, MP4Box::ChapterListBox(_)));
+    }
+}
+
+fn add_nero_chapters(mp4: &mut MP4, chapters: &[Chapter]) {
+    let chpl = ChapterListBox {
+        chapters: chapters
+            .iter()
+            .map(|c| crate::boxes::Chapter {
+                start: (c.start * 10_000_000.0).round() as u64,
+                title: PString::from(c.title.as_str()),
+            })
+            .collect(),
+    };
+    let movie = mp4.movie_mut();
+    if first_box!(&movie.boxes, UserDataBox).is_none() {
+        movie.boxes.push(UserDataBox { boxes: Vec::new() }.to_mp4box());
+    }
+    let udta = first_box_mut!(&mut movie.boxes, UserDataBox).unwrap();
+    udta.boxes.push(chpl.to_mp4box());
+}
+
+// Add a QuickTime chapter track, and refer to it from the audio and video
+// tracks. Returns the sample data, which still needs to be put in a mdat.
+fn add_chapter_track(mp4: &mut MP4, chapters: &[Chapter]) -> io::Result<Vec<u8>> {
+    let movie = mp4.movie_mut();
+
+    // Base the headers on the first track.
+    let (mut mdhd, mut tkhd) = match movie.tracks().first() {
+        Some(first) => (first.media().media_header().clone(), first.track_header().clone()),
+        None => return Err(ioerr!(InvalidInput, "set_chapters: no tracks")),
+    };
+
+    // Refer to the chapter track from the audio and video tracks,
+    // or from all tracks if there are no audio or video tracks.
+    let av_only = movie.tracks().iter().any(|t| {
+        let hdlr = t.media().handler();
+        hdlr.is_audio() || hdlr.is_video()
+    });
+    let mut referring = Vec::new();
+    for track in movie.tracks() {
+        let hdlr = track.media().handler();
+        if av_only && !hdlr.is_audio() && !hdlr.is_video() {
+            continue;
+        }
+        if first_box!(&track.boxes, TrackReferenceBox).is_some() {
+            // TrackReferenceBox can only hold one reference type.
+            log::warn!(
+                "set_chapters: track {} already has a track reference",
+                track.track_id()
+            );
+            continue;
+        }
+        referring.push(track.track_id());
+    }
+    if referring.is_empty() {
+        return Err(ioerr!(
+            InvalidInput,
+            "set_chapters: no track can refer to a chapter track"
+        ));
+    }
+
+    // One sample per chapter, with a timescale of 1000. If the
+    // first chapter doesn't start at 0, add an empty chapter.
+    let mut samples = Vec::new();
+    if chapters[0].start > 0.0 {
+        samples.push((0.0, chapters[0].start, ""));
+    }
+    for (idx, chapter) in chapters.iter().enumerate() {
+        let end = chapters.get(idx + 1).map(|c| c.start).unwrap_or(chapter.end);
+        samples.push((chapter.start, end, chapter.title.as_str()));
+    }
+
+    let mut data = Vec::new();
+    let mut stts = TimeToSampleBox::default();
+    let mut stsz = SampleSizeBox::default();
+    let mut duration = 0u64;
+    for (start, end, title) in samples.iter() {
+        let start_ms = (start * 1000.0).round() as u64;
+        let end_ms = cmp::max((end * 1000.0).round() as u64, start_ms);
+        let delta = (end_ms - start_ms) as u32;
+        match stts.entries.last_mut() {
+            Some(entry) if entry.delta == delta => entry.count += 1,
+            _ => stts.entries.push(TimeToSampleEntry { count: 1, delta }),
+        }
+        duration += delta as u64;
+
+        // Text sample with an `encd` atom that says "UTF-8".
+        let mut len = cmp::min(title.len(), 1024);
+        while !title.is_char_boundary(len) {
+            len -= 1;
+        }
+        let title = &title[..len];
+        let len = data.len();
+        data.extend_from_slice(&(title.len() as u16).to_be_bytes());
+        data.extend_from_slice(title.as_bytes());
+        data.extend_from_slice(&[0, 0, 0, 12, b'e', b'n', b'c', b'd', 0, 0, 1, 0]);
+        stsz.entries.push((data.len() - len) as u32);
+    }
+    stsz.count = stsz.entries.len() as u32;
+
+    let mut stsc = SampleToChunkBox::default();
+    stsc.entries.push(SampleToChunkEntry {
+        first_chunk: 1,
+        samples_per_chunk: samples.len() as u32,
+        sample_description_index: 1,
+    });
+    // Placeholder, the real offset is set later.
+    let mut stco = ChunkOffsetBox::default();
+    stco.push(0);
+
+    let tx3g = Tx3gTextSampleEntry::default();
+    let mut entries = ArraySized32::new();
+    entries.push(tx3g.to_mp4box());
+    let stsd = SampleDescriptionBox { entries };
+
+    let stbl = SampleTableBox {
+        boxes: vec![
+            stsd.to_mp4box(),
+            stts.to_mp4box(),
+            stsc.to_mp4box(),
+            stsz.to_mp4box(),
+            stco.to_mp4box(),
+        ],
+    };
+
+    let mut dref = DataReferenceBox {
+        flags: DataEntryFlags(0),
+        entries: ArraySized32::new(),
+    };
+    let mut url = DataEntryUrlBox {
+        flags: DataEntryFlags(0),
+        location: ZString::default(),
+    };
+    url.flags.set_in_same_file(true);
+    dref.entries.push(url.to_mp4box());
+
+    let minf = MediaInformationBox {
+        boxes: vec![
+            NullMediaHeaderBox::default().to_mp4box(),
+            DataInformationBox {
+                boxes: vec![dref.to_mp4box()],
+            }
+            .to_mp4box(),
+            stbl.to_mp4box(),
+        ],
+    };
+
+    mdhd.timescale = 1000;
+    mdhd.duration = Duration_(duration);
+    mdhd.language = IsoLanguageCode::default();
+    mdhd.quality = 0;
+
+    let mdia = MediaBox {
+        boxes: vec![
+            mdhd.to_mp4box(),
+            HandlerBox {
+                handler_type: FourCC::new("text"),
+                name: ZString::from("ChapterHandler"),
+            }
+            .to_mp4box(),
+            minf.to_mp4box(),
+        ],
+    };
+
+    let track_id = movie.tracks().iter().map(|t| t.track_id()).max().unwrap_or(0) + 1;
+    let movie_timescale = movie.movie_header().timescale;
+    tkhd.flags = TrackFlags(0);
+    tkhd.track_id = track_id;
+    tkhd.duration = Duration_(duration * movie_timescale as u64 / 1000);
+    tkhd.layer = 0;
+    tkhd.alt_group = 0;
+    tkhd.volume = FixedFloat8_8::from(0.0);
+    tkhd.width = FixedFloat16_16::from(0.0);
+    tkhd.height = FixedFloat16_16::from(0.0);
+
+    let trak = TrackBox {
+        movie_timescale,
+        boxes: vec![tkhd.to_mp4box(), mdia.to_mp4box()],
+    };
+
+    for track in movie.tracks_mut() {
+        if !referring.contains(&track.track_id()) {
+            continue;
+        }
+        let tref = TrackReferenceBox {
+            reference_type: FourCC::new("chap"),
+            track_ids: vec![track_id],
+        };
+        // Put it right after the track header.
+        track.boxes.insert(1, tref.to_mp4box());
+    }
+
+    if let Some(mvhd) = first_box_mut!(&mut movie.boxes, MovieHeaderBox) {
+        mvhd.next_track_id = cmp::max(mvhd.next_track_id, track_id + 1);
+    }
+    movie.boxes.push(trak.to_mp4box());
+
+    Ok(data)
+}
+
+/// Export chapters as `JSON`, `WebVTT` or `OGM` text.
+pub fn export(chapters: &[Chapter], format: ChapterFormat) -> String {
+    let mut out = String::new();
+    match format {
+        ChapterFormat::Json => {
+            out = serde_json::to_string_pretty(chapters).unwrap();
+            out.push('\n');
+        },
+        ChapterFormat::Vtt => {
+            out.push_str("WEBVTT\n\n");
+            for (idx, c) in chapters.iter().enumerate() {
+                let title = c
+                    .title
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;");
+                let _ = write!(
+                    out,
+                    "{}\n{} --> {}\n{}\n\n",
+                    idx + 1,
+                    fmt_time(c.start),
+                    fmt_time(c.end),
+                    title
+                );
+            }
+        },
+        ChapterFormat::Ogm => {
+            for (idx, c) in chapters.iter().enumerate() {
+                let _ = writeln!(out, "CHAPTER{:02}={}", idx + 1, fmt_time(c.start));
+                let _ = writeln!(out, "CHAPTER{:02}NAME={}", idx + 1, c.title);
+            }
+        },
+    }
+    out
+}
+
+/// Import chapters from `JSON`, `WebVTT` or `OGM` text.
+///
+/// Missing end times are filled in by [`set_chapters`].
+pub fn import(data: &str, format: ChapterFormat) -> io::Result<Vec<Chapter>> {
+    let data = data.trim_start_matches('\u{feff}');
+    let mut chapters = Vec::new();
+    match format {
+        ChapterFormat::Json => {
+            chapters = serde_json::from_str(data).map_err(|e| ioerr!(InvalidData, "json: {}", e))?;
+        },
+        ChapterFormat::Vtt => {
+            let mut lines = data.lines();
+            while let Some(line) = lines.next() {
+                let mut times = line.split("-->");
+                let (start, end) = match (times.next(), times.next()) {
+                    (Some(s), Some(e)) => (s, e.split_whitespace().next().unwrap_or("")),
+                    _ => continue,
+                };
+                let start =
+                    parse_time(start).ok_or_else(|| ioerr!(InvalidData, "vtt: bad time: {}", line))?;
+                let end = parse_time(end).ok_or_else(|| ioerr!(InvalidData, "vtt: bad time: {}", line))?;
+                let mut title = String::new();
+                for line in lines.by_ref().take_while(|l| !l.trim().is_empty()) {
+                    if !title.is_empty() {
+                        title.push(' ');
+                    }
+                    title.push_str(line.trim());
+                }
+                let title = title
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&amp;", "&");
+                chapters.push(Chapter {
+                    start,
+                    end,
+                    title,
+                    image: None,
+                });
+            }
+        },
+        ChapterFormat::Ogm => {
+            let mut numbered: Vec<(String, Chapter)> = Vec::new();
+            for line in data.lines() {
+                let (key, value) = match line.trim().split_once('=') {
+                    Some(kv) => kv,
+                    None => continue,
+                };
+                if let Some(num) = key.strip_prefix("CHAPTER").and_then(|k| k.strip_suffix("NAME")) {
+                    if let Some(c) = numbered.iter_mut().rev().find(|(n, _)| n == num) {
+                        c.1.title = value.to_string();
+                    }
+                } else if let Some(num) = key.strip_prefix("CHAPTER") {
+                    let start =
+                        parse_time(value).ok_or_else(|| ioerr!(InvalidData, "ogm: bad time: {}", line))?;
+                    numbered.push((
+                        num.to_string(),
+                        Chapter {
+                            start,
+                            ..Chapter::default()
+                        },
+                    ));
+                }
+            }
+            chapters = numbered.into_iter().map(|(_, c)| c).collect();
+        },
+    }
+    Ok(chapters)
+}
+
+// hh:mm:ss.mmm
+fn fmt_time(secs: f64) -> String {
+    let mut tm = (secs * 1000.0).round() as u64;
+    let ms = tm % 1000;
+    tm /= 1000;
+    let s = tm % 60;
+    tm /= 60;
+    let m = tm % 60;
+    let h = tm / 60;
+    format!("{:02}:{:02}:{:02}.{:03}", h, m, s, ms)
+}
+
+// [hh:]mm:ss[.mmm]
+fn parse_time(time: &str) -> Option<f64> {
+    let mut secs = 0.0;
+    for field in time.trim().replace(',', ".").split(':') {
+        let v = field.parse::<f64>().ok()?;
+        if v < 0.0 {
+            return None;
+        }
+        secs = secs * 60.0 + v;
+    }
+    Some(secs)
+}
@@ -15,7 +15,7 @@ pub fn dump_track_samples(mp4: &MP4, track_id: u32, first_sample: u32, last_samp
     };
 
     let trak = movie.tracks()[track_idx];
-    let mut samples = trak.sample_info_iter();
+    let mut samples = mp4.sample_info_iter(trak);
     samples.seek(first_sample)?;
 
     let mut idx = first_sample;
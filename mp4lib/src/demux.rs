@@ -0,0 +1,624 @@
+// Helpers for the demuxers (`matroska`, `mpegts`) that build an `MP4`
+// from a file in another container format.
+//
+// A demuxer collects the samples of each track, with their position
+// in the file (or in the in-memory buffer of the `DataRef`), and the
+// sample entry. The functions in here turn that into a `TrackBox`
+// with a complete sample table, and the tracks into an `MP4`.
+//
+use std::cmp;
+use std::io;
+
+use crate::bitreader::BitReader;
+use crate::boxes::*;
+use crate::io::DataRef;
+use crate::mp4box::{MP4Box, MP4};
+use crate::sample_info::FragmentIndex;
+use crate::serialize::FromBytes;
+use crate::types::*;
+
+pub(crate) const AAC_SAMPLE_RATES: [u32; 12] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000,
+];
+
+/// The kind of media in a track.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+}
+
+// What the TrackHeaderBox, MediaHeaderBox and UserDataBox need to know.
+pub(crate) struct TrackParams {
+    pub track_id: u32,
+    pub kind: TrackKind,
+    pub enabled: bool,
+    pub language: String,
+    pub name: Option<String>,
+    pub display_width: u32,
+    pub display_height: u32,
+}
+
+// A track as MP4 sample table entries, plus timing info.
+pub(crate) struct Samples {
+    pub timescale: u32,
+    pub entries: Vec<Sample>,
+    // Presentation time of the first sample, in the track timescale.
+    pub start: u64,
+    // Composition time of the first sample that is presented.
+    pub shift: i32,
+}
+
+pub(crate) struct Sample {
+    pub fpos: u64,
+    pub size: u32,
+    pub duration: u32,
+    pub composition_offset: i32,
+    pub is_sync: bool,
+}
+
+// Put the tracks in a MovieBox, and add a FileTypeBox.
+pub(crate) fn build_mp4(path: &str, tracks: Vec<TrackBox>, data_ref: DataRef) -> io::Result<MP4> {
+    if tracks.is_empty() {
+        return Err(ioerr!(InvalidData, "{}: no supported tracks", path));
+    }
+    Ok(MP4 {
+        boxes: vec![file_type_box().to_mp4box(), movie_box(tracks).to_mp4box()],
+        data_ref,
+        input_file: Some(path.to_string()),
+        fragment_index: FragmentIndex::default(),
+    })
+}
+
+pub(crate) fn movie_box(tracks: Vec<TrackBox>) -> MovieBox {
+    let movie_duration = tracks
+        .iter()
+        .map(|t| t.track_header().duration.0)
+        .max()
+        .unwrap_or(0);
+    let next_track_id = tracks.iter().map(|t| t.track_id()).max().unwrap_or(0) + 1;
+
+    let mvhd = MovieHeaderBox {
+        cr_time: Time::default(),
+        mod_time: Time::default(),
+        timescale: 1000,
+        duration: Duration_(movie_duration),
+        pref_rate: FixedFloat16_16::from(1.0),
+        pref_vol: FixedFloat8_8::from(1.0),
+        matrix: Matrix::identity(),
+        preview_time: 0,
+        preview_duration: 0,
+        poster_time: 0,
+        selection_time: 0,
+        selection_duration: 0,
+        current_time: 0,
+        next_track_id,
+    };
+    let mut moov = MovieBox::default();
+    moov.boxes.push(mvhd.to_mp4box());
+    moov.boxes.extend(tracks.into_iter().map(|t| t.to_mp4box()));
+    moov
+}
+
+pub(crate) fn file_type_box() -> FileTypeBox {
+    FileTypeBox {
+        major_brand: FourCC::new("isom"),
+        minor_version: 512,
+        compatible_brands: ["isom", "iso2", "avc1", "mp41"]
+            .iter()
+            .map(|b| FourCC::new(b))
+            .collect(),
+    }
+}
+
+pub(crate) fn build_track(track: &TrackParams, samples: Samples, sample_entry: MP4Box) -> TrackBox {
+    let table = SampleTable::from_samples(&samples.entries);
+    track_box(
+        track,
+        samples.timescale,
+        table,
+        sample_entry,
+        samples.start,
+        samples.shift,
+    )
+}
+
+// A sample table that is built one sample, and one chunk, at a time.
+#[derive(Default)]
+pub(crate) struct SampleTable {
+    stts: TimeToSampleBox,
+    ctts: CompositionOffsetBox,
+    stss: SyncSampleBox,
+    stsz: SampleSizeBox,
+    stsc: SampleToChunkBox,
+    stco: ChunkOffsetBox,
+    // Sum of the durations of the samples.
+    pub duration: u64,
+}
+
+impl SampleTable {
+    // Samples that follow each other directly in the file go in the same chunk.
+    pub fn from_samples(samples: &[Sample]) -> SampleTable {
+        let mut table = SampleTable::default();
+        let mut chunk_start = 0;
+        let mut chunk_end = u64::MAX;
+        let mut chunk_samples = 0;
+        for sample in samples {
+            if sample.fpos != chunk_end {
+                if chunk_samples > 0 {
+                    table.add_chunk(chunk_start, chunk_samples);
+                }
+                chunk_start = sample.fpos;
+                chunk_samples = 0;
+            }
+            table.add_sample(
+                sample.size,
+                sample.duration,
+                sample.composition_offset,
+                sample.is_sync,
+            );
+            chunk_samples += 1;
+            chunk_end = sample.fpos + sample.size as u64;
+        }
+        if chunk_samples > 0 {
+            table.add_chunk(chunk_start, chunk_samples);
+        }
+        table
+    }
+
+    pub fn add_sample(&mut self, size: u32, duration: u32, composition_offset: i32, is_sync: bool) {
+        match self.stts.entries.last_mut() {
+            Some(entry) if entry.delta == duration => entry.count += 1,
+            _ => self.stts.entries.push(TimeToSampleEntry {
+                count: 1,
+                delta: duration,
+            }),
+        }
+        match self.ctts.entries.last_mut() {
+            Some(entry) if entry.offset == composition_offset => entry.count += 1,
+            _ => self.ctts.entries.push(CompositionOffsetEntry {
+                count: 1,
+                offset: composition_offset,
+            }),
+        }
+        self.stsz.entries.push(size);
+        self.stsz.count += 1;
+        if is_sync {
+            self.stss.entries.push(self.stsz.count);
+        }
+        self.duration += duration as u64;
+    }
+
+    // The next `samples` samples are in one chunk at `offset`.
+    pub fn add_chunk(&mut self, offset: u64, samples: u32) {
+        self.stco.push(offset);
+        if self.stsc.entries.last().map(|e| e.samples_per_chunk) != Some(samples) {
+            self.stsc.entries.push(SampleToChunkEntry {
+                first_chunk: self.stco.entries.len() as u32,
+                samples_per_chunk: samples,
+                sample_description_index: 1,
+            });
+        }
+    }
+
+    pub fn has_composition_offsets(&self) -> bool {
+        self.ctts.entries.iter().any(|e| e.offset != 0)
+    }
+
+    // The tables of the SampleTableBox, except the SampleDescriptionBox.
+    pub fn into_boxes(self) -> Vec<MP4Box> {
+        let mut boxes = vec![self.stts.to_mp4box()];
+        if self.ctts.entries.iter().any(|e| e.offset != 0) {
+            boxes.push(self.ctts.to_mp4box());
+        }
+        if self.stss.entries.len() < self.stsz.count as usize {
+            boxes.push(self.stss.to_mp4box());
+        }
+        boxes.push(self.stsc.to_mp4box());
+        boxes.push(self.stsz.to_mp4box());
+        boxes.push(self.stco.to_mp4box());
+        boxes
+    }
+
+    fn into_box(self, sample_entry: MP4Box) -> SampleTableBox {
+        let mut entries = ArraySized32::new();
+        entries.push(sample_entry);
+        let mut boxes = vec![SampleDescriptionBox { entries }.to_mp4box()];
+        boxes.extend(self.into_boxes());
+        SampleTableBox { boxes }
+    }
+}
+
+// Build a TrackBox around a sample table. `start` is the presentation time of
+// the first sample, `shift` the composition time of the first sample that is
+// presented, both in the track timescale.
+pub(crate) fn track_box(
+    track: &TrackParams,
+    timescale: u32,
+    table: SampleTable,
+    sample_entry: MP4Box,
+    start: u64,
+    shift: i32,
+) -> TrackBox {
+    let to_movie = |t: u64| t * 1000 / timescale as u64;
+    let duration = table.duration;
+    let has_ctts = table.has_composition_offsets();
+    let stbl = table.into_box(sample_entry);
+
+    let (handler, handler_name, media_header) = match track.kind {
+        TrackKind::Video => {
+            let vmhd = VideoMediaHeaderBox {
+                flags: VideoMediaHeaderFlags::default(),
+                graphics_mode: 0,
+                opcolor: OpColor {
+                    red: 0,
+                    green: 0,
+                    blue: 0,
+                },
+            };
+            ("vide", "VideoHandler", vmhd.to_mp4box())
+        },
+        TrackKind::Audio => {
+            let smhd = SoundMediaHeaderBox { balance: 0 };
+            ("soun", "SoundHandler", smhd.to_mp4box())
+        },
+        TrackKind::Subtitle => (
+            "sbtl",
+            "SubtitleHandler",
+            NullMediaHeaderBox::default().to_mp4box(),
+        ),
+    };
+
+    let mut dref = DataReferenceBox {
+        flags: DataEntryFlags(0),
+        entries: ArraySized32::new(),
+    };
+    let mut url = DataEntryUrlBox {
+        flags: DataEntryFlags(0),
+        location: ZString::default(),
+    };
+    url.flags.set_in_same_file(true);
+    dref.entries.push(url.to_mp4box());
+
+    let minf = MediaInformationBox {
+        boxes: vec![
+            media_header,
+            DataInformationBox {
+                boxes: vec![dref.to_mp4box()],
+            }
+            .to_mp4box(),
+            stbl.to_mp4box(),
+        ],
+    };
+
+    let mdhd = MediaHeaderBox {
+        cr_time: Time::default(),
+        mod_time: Time::default(),
+        timescale,
+        duration: Duration_(duration),
+        language: IsoLanguageCode::from(track.language.as_str()),
+        quality: 0,
+    };
+    let hdlr = HandlerBox {
+        handler_type: FourCC::new(handler),
+        name: ZString::from(handler_name),
+    };
+    let mdia = MediaBox {
+        boxes: vec![mdhd.to_mp4box(), hdlr.to_mp4box(), minf.to_mp4box()],
+    };
+
+    let mut flags = TrackFlags(0);
+    flags.set_enabled(track.enabled);
+    flags.set_in_movie(true);
+    flags.set_in_preview(true);
+    let audio = track.kind == TrackKind::Audio;
+    let tkhd = TrackHeaderBox {
+        flags,
+        cr_time: Time::default(),
+        mod_time: Time::default(),
+        track_id: track.track_id,
+        duration: Duration_(to_movie(start + duration)),
+        layer: 0,
+        alt_group: if audio { 1 } else { 0 },
+        volume: FixedFloat8_8::from(if audio { 1.0 } else { 0.0 }),
+        matrix: Matrix::identity(),
+        width: FixedFloat16_16::from(track.display_width as f64),
+        height: FixedFloat16_16::from(track.display_height as f64),
+    };
+
+    let mut boxes = vec![tkhd.to_mp4box()];
+
+    // An edit list for the start offset and the composition offset.
+    if start > 0 || shift != 0 || has_ctts {
+        let mut elst = EditListBox::default();
+        if start > 0 {
+            elst.entries.push(EditListEntry {
+                segment_duration: to_movie(start),
+                media_time: -1,
+                media_rate: 1,
+            });
+        }
+        elst.entries.push(EditListEntry {
+            segment_duration: to_movie(duration),
+            media_time: shift as i64,
+            media_rate: 1,
+        });
+        boxes.push(EditBox { boxes: vec![elst] }.to_mp4box());
+    }
+
+    boxes.push(mdia.to_mp4box());
+
+    if let Some(name) = track.name.as_ref() {
+        let name = NameBox {
+            name: ZString::from(name.as_str()),
+        };
+        boxes.push(
+            UserDataBox {
+                boxes: vec![name.to_mp4box()],
+            }
+            .to_mp4box(),
+        );
+    }
+
+    TrackBox {
+        movie_timescale: 1000,
+        boxes,
+    }
+}
+
+// AvcSampleEntry or HEVCSampleEntry, with the avcC / hvcC box built from `config`.
+pub(crate) fn video_sample_entry(
+    fourcc: &str,
+    config: &[u8],
+    width: u32,
+    height: u32,
+    pasp: Option<MP4Box>,
+) -> io::Result<MP4Box> {
+    let mut boxes = vec![config_box(fourcc, config)?];
+    boxes.extend(pasp);
+    let entry = match fourcc {
+        "avcC" => AvcSampleEntry {
+            width: width as u16,
+            height: height as u16,
+            boxes,
+            ..AvcSampleEntry::default()
+        }
+        .to_mp4box(),
+        _ => HEVCSampleEntry {
+            data_reference_index: 1,
+            width: width as u16,
+            height: height as u16,
+            _video_horizontal_dpi: FixedFloat16_16::from(72.0),
+            _video_vertical_dpi: FixedFloat16_16::from(72.0),
+            _video_frame_count: 1,
+            video_pixel_depth: 24,
+            _pre_defined: 0xffff,
+            boxes,
+        }
+        .to_mp4box(),
+    };
+    Ok(entry)
+}
+
+pub(crate) fn aac_sample_entry(
+    es_id: u16,
+    channels: u16,
+    sample_size: u16,
+    sample_rate: u32,
+    asc: &[u8],
+) -> io::Result<MP4Box> {
+    let entry = AacSampleEntry {
+        data_reference_index: 1,
+        channel_count: cmp::max(channels, 1),
+        sample_size: if sample_size > 0 { sample_size } else { 16 },
+        sample_rate_hi: sample_rate as u16,
+        sample_rate_lo: 0,
+        boxes: vec![esds(es_id, asc)?],
+    };
+    Ok(entry.to_mp4box())
+}
+
+pub(crate) fn ac3_sample_entry(hdr: &Ac3Header) -> MP4Box {
+    let dac3 = AC3SpecificBox {
+        fscod: hdr.fscod,
+        bsid: hdr.bsid,
+        bsmod: hdr.bsmod,
+        acmod: hdr.acmod,
+        lfeon: hdr.lfeon,
+        bitrate_code: hdr.frmsizecod >> 1,
+        reserved: 0,
+    };
+    Ac3SampleEntry {
+        channel_count: hdr.channels(),
+        sample_rate_hi: hdr.sample_rate as u16,
+        boxes: vec![dac3.to_mp4box()],
+        ..Ac3SampleEntry::default()
+    }
+    .to_mp4box()
+}
+
+pub(crate) fn eac3_sample_entry(hdr: &Ac3Header) -> MP4Box {
+    // Bytes per frame, times frames per second.
+    let data_rate = hdr.frame_size * 8 * hdr.sample_rate / (hdr.num_blocks * 256) / 1000;
+    let dec3 = EC3SpecificBox {
+        data_rate: data_rate as u16,
+        substreams: vec![EC3IndependentSubstream {
+            fscod: hdr.fscod,
+            bsid: hdr.bsid,
+            bsmod: hdr.bsmod,
+            acmod: hdr.acmod,
+            lfeon: hdr.lfeon,
+            ..EC3IndependentSubstream::default()
+        }],
+        joc_complexity: None,
+    };
+    Ec3SampleEntry {
+        channel_count: hdr.channels(),
+        sample_rate_hi: hdr.sample_rate as u16,
+        boxes: vec![dec3.to_mp4box()],
+        ..Ec3SampleEntry::default()
+    }
+    .to_mp4box()
+}
+
+// Build a box from its fourcc and the body.
+pub(crate) fn config_box(fourcc: &str, body: &[u8]) -> io::Result<MP4Box> {
+    if body.is_empty() {
+        return Err(ioerr!(InvalidData, "{}: no decoder configuration", fourcc));
+    }
+    let mut data = Vec::new();
+    data.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    data.extend_from_slice(fourcc.as_bytes());
+    data.extend_from_slice(body);
+    MP4Box::from_bytes(&mut &data[..])
+}
+
+// Pixel aspect ratio box, if it is not 1:1.
+pub(crate) fn pasp(h_spacing: u64, v_spacing: u64) -> Option<MP4Box> {
+    let (h, v) = (h_spacing, v_spacing);
+    if h == v || h == 0 || v == 0 {
+        return None;
+    }
+    let (mut a, mut b) = (h, v);
+    while b != 0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    let pasp = PixelAspectRatioBox {
+        h_spacing: (h / a) as u32,
+        v_spacing: (v / a) as u32,
+    };
+    Some(pasp.to_mp4box())
+}
+
+// ES_Descriptor with a DecoderConfigDescriptor for AAC.
+fn esds(es_id: u16, asc: &[u8]) -> io::Result<MP4Box> {
+    let mut dsi = vec![0x05, asc.len() as u8];
+    dsi.extend_from_slice(asc);
+
+    let mut dcd = vec![0x04, 13 + dsi.len() as u8, 0x40, 0x15, 0, 0, 0];
+    dcd.extend_from_slice(&[0u8; 8]);
+    dcd.extend_from_slice(&dsi);
+
+    let mut es = vec![0x03, 3 + dcd.len() as u8 + 3];
+    es.extend_from_slice(&es_id.to_be_bytes());
+    es.push(0);
+    es.extend_from_slice(&dcd);
+    es.extend_from_slice(&[0x06, 0x01, 0x02]);
+
+    let mut body = vec![0u8; 4];
+    body.extend_from_slice(&es);
+    config_box("esds", &body)
+}
+
+// A 2-byte AudioSpecificConfig.
+pub(crate) fn audio_specific_config(object_type: u8, rate_index: u8, channels: u8) -> Vec<u8> {
+    vec![
+        (object_type << 3) | (rate_index >> 1),
+        ((rate_index & 1) << 7) | (channels << 3),
+    ]
+}
+
+// The fields of an AC-3 or E-AC-3 frame header that we need.
+#[derive(Default)]
+pub(crate) struct Ac3Header {
+    pub fscod: u8,
+    pub frmsizecod: u8,
+    pub bsid: u8,
+    pub bsmod: u8,
+    pub acmod: u8,
+    pub lfeon: bool,
+    pub sample_rate: u32,
+    pub num_blocks: u32,
+    pub frame_size: u32,
+    // E-AC-3 stream type, 1 is a dependent substream.
+    pub strmtyp: u8,
+}
+
+impl Ac3Header {
+    pub fn channels(&self) -> u16 {
+        [2, 1, 2, 3, 3, 4, 4, 5][self.acmod as usize & 7] + self.lfeon as u16
+    }
+}
+
+fn check_syncword(data: &[u8]) -> io::Result<()> {
+    if data.len() < 8 || data[0] != 0x0b || data[1] != 0x77 {
+        return Err(ioerr!(InvalidData, "no AC-3 syncword"));
+    }
+    Ok(())
+}
+
+// ATSC A/52 5.4.1 Synchronization information / bit stream information.
+pub(crate) fn ac3_header(data: &[u8]) -> io::Result<Ac3Header> {
+    check_syncword(data)?;
+    let mut b = BitReader::new(&data[4..]);
+    let mut hdr = Ac3Header {
+        fscod: b.read_bits(2)? as u8,
+        frmsizecod: b.read_bits(6)? as u8,
+        ..Ac3Header::default()
+    };
+    hdr.bsid = b.read_bits(5)? as u8;
+    hdr.bsmod = b.read_bits(3)? as u8;
+    hdr.acmod = b.read_bits(3)? as u8;
+    if hdr.acmod & 0x01 != 0 && hdr.acmod != 1 {
+        b.read_bits(2)?; // cmixlev
+    }
+    if hdr.acmod & 0x04 != 0 {
+        b.read_bits(2)?; // surmixlev
+    }
+    if hdr.acmod == 2 {
+        b.read_bits(2)?; // dsurmod
+    }
+    hdr.lfeon = b.read_bit()?;
+    hdr.sample_rate = match hdr.fscod {
+        0 => 48000,
+        1 => 44100,
+        2 => 32000,
+        _ => return Err(ioerr!(InvalidData, "AC-3: invalid fscod")),
+    };
+    hdr.num_blocks = 6;
+
+    // Table 5.18, frame size in 16-bit words.
+    const BITRATES: [u32; 19] = [
+        32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 448, 512, 576, 640,
+    ];
+    let bitrate = *BITRATES
+        .get(hdr.frmsizecod as usize >> 1)
+        .ok_or_else(|| ioerr!(InvalidData, "AC-3: invalid frmsizecod"))?;
+    let words = match hdr.fscod {
+        0 => bitrate * 2,
+        1 => bitrate * 96000 / 44100 + (hdr.frmsizecod & 1) as u32,
+        _ => bitrate * 3,
+    };
+    hdr.frame_size = words * 2;
+    Ok(hdr)
+}
+
+// ATSC A/52 E.1.2 Syntax of the E-AC-3 bit stream.
+pub(crate) fn eac3_header(data: &[u8]) -> io::Result<Ac3Header> {
+    check_syncword(data)?;
+    let mut b = BitReader::new(&data[2..]);
+    let strmtyp = b.read_bits(2)? as u8;
+    b.read_bits(3)?; // substreamid
+    let mut hdr = Ac3Header {
+        strmtyp,
+        ..Ac3Header::default()
+    };
+    hdr.frame_size = (b.read_bits(11)? + 1) * 2;
+    hdr.fscod = b.read_bits(2)? as u8;
+    let numblkscod = if hdr.fscod == 3 {
+        let fscod2 = b.read_bits(2)?;
+        hdr.sample_rate = [24000, 22050, 16000, 0][fscod2 as usize];
+        3
+    } else {
+        hdr.sample_rate = [48000, 44100, 32000][hdr.fscod as usize];
+        b.read_bits(2)?
+    };
+    hdr.num_blocks = [1, 2, 3, 6][numblkscod as usize];
+    hdr.acmod = b.read_bits(3)? as u8;
+    hdr.lfeon = b.read_bit()?;
+    hdr.bsid = b.read_bits(5)? as u8;
+    Ok(hdr)
+}
@@ -0,0 +1,424 @@
+//! Elementary stream import.
+//!
+//! [`open`] builds an [`MP4`] from raw elementary streams: `H.264` or
+//! `HEVC` video in Annex-B format, `AAC` audio with `ADTS` headers, and
+//! `AC-3` or `E-AC-3` audio. The type of a stream is taken from the
+//! filename extension (`.h264`, `.hevc`, `.aac`, `.ac3`, `.ec3`).
+//!
+//! An elementary stream has no timestamps. Every video frame gets the
+//! same duration, from the frame rate in the SPS (25 fps if it has none),
+//! and the presentation order is derived from the picture order count
+//! in the slice headers. Audio frames have a fixed duration anyway.
+//!
+//! The frames are found the same way as in the [`mpegts`](crate::mpegts)
+//! demuxer, and like there the sample data is kept in memory. To write
+//! the result as a standard MP4 file, use
+//! [`rewrite::write_interleaved`](crate::rewrite::write_interleaved).
+//!
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+
+use crate::bitreader::{unescape_rbsp, BitReader};
+use crate::boxes::*;
+use crate::demux::*;
+use crate::io::DataRef;
+use crate::mp4box::{MP4Box, MP4};
+use crate::mpegts::{Codec, Memory, Stream, VideoConfig};
+
+// Frame rate if the SPS does not have one.
+const DEFAULT_FRAME_RATE: f64 = 25.0;
+
+/// Build an `MP4` from one or more elementary streams.
+///
+/// Each file becomes a track, in the order they are passed. `frame_rate`
+/// overrides the frame rate of the video track.
+pub fn open(paths: &[&str], frame_rate: Option<f64>) -> io::Result<MP4> {
+    let first = paths
+        .first()
+        .ok_or_else(|| ioerr!(InvalidInput, "no input files"))?;
+    if let Some(fr) = frame_rate {
+        // The timescale is 1000 * the frame rate, it must fit in an u32 and not be 0.
+        let timescale = (fr * 1000.0).round();
+        if !fr.is_finite() || fr <= 0.0 || timescale < 1.0 || timescale > u32::MAX as f64 {
+            return Err(ioerr!(InvalidInput, "invalid frame rate: {}", fr));
+        }
+    }
+    let file = fs::File::open(first)?;
+    let mut mem = Memory::new(file.metadata()?.len());
+
+    let mut tracks = Vec::new();
+    for (idx, path) in paths.iter().enumerate() {
+        let codec = codec(path)?;
+        let mut stream = Stream::elementary(idx as u32 + 1, codec);
+        let mut input = fs::File::open(path)?;
+        let mut buf = vec![0u8; 1_000_000];
+        loop {
+            let n = input.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            stream.push_data(&buf[..n], None);
+            stream.process(&mut mem, false);
+        }
+        stream.process(&mut mem, true);
+        if stream.frames.is_empty() {
+            return Err(ioerr!(InvalidData, "{}: no frames found", path));
+        }
+
+        let mut params = TrackParams {
+            track_id: idx as u32 + 1,
+            kind: TrackKind::Audio,
+            enabled: true,
+            language: "und".to_string(),
+            name: None,
+            display_width: 0,
+            display_height: 0,
+        };
+        let (samples, sample_entry) = match stream.video_config() {
+            Some(config) => {
+                params.kind = TrackKind::Video;
+                params.display_width = config.display_width;
+                params.display_height = config.display_height;
+                let samples = video_samples(codec, &stream, config, &mem, frame_rate);
+                (samples, config.sample_entry.clone())
+            },
+            None => stream.audio_samples(0)?,
+        };
+        tracks.push(build_track(&params, samples, sample_entry));
+    }
+
+    build_mp4(first, tracks, DataRef::with_memory(file, mem.data)?)
+}
+
+fn codec(path: &str) -> io::Result<Codec> {
+    let ext = path.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    match ext.as_str() {
+        "h264" | "264" | "avc" => Ok(Codec::Avc),
+        "hevc" | "h265" | "265" => Ok(Codec::Hevc),
+        "aac" | "adts" => Ok(Codec::Aac),
+        "ac3" => Ok(Codec::Ac3),
+        "ec3" | "eac3" => Ok(Codec::Ec3),
+        _ => Err(ioerr!(InvalidInput, "{}: unknown elementary stream type", path)),
+    }
+}
+
+// Frames in decode order, with a constant duration. The composition
+// offsets follow from the presentation order.
+fn video_samples(
+    codec: Codec,
+    stream: &Stream,
+    config: &VideoConfig,
+    mem: &Memory,
+    frame_rate: Option<f64>,
+) -> Samples {
+    let frame_rate = frame_rate
+        .or_else(|| sps_frame_rate(&config.sample_entry))
+        .unwrap_or(DEFAULT_FRAME_RATE);
+    let timescale = (frame_rate * 1000.0).round() as u32;
+    let duration = 1000;
+
+    // Presentation order, if we can find the picture order count of every frame.
+    let mut order = PictureOrder::new(codec, config);
+    let pocs: Option<Vec<_>> = stream
+        .frames
+        .iter()
+        .map(|f| {
+            let start = (f.fpos - mem.file_len) as usize;
+            order.picture_order_count(&mem.data[start..start + f.size as usize])
+        })
+        .collect();
+    let mut rank: Vec<_> = (0..stream.frames.len() as i64).collect();
+    match pocs {
+        Some(pocs) => {
+            let mut by_poc: Vec<_> = (0..pocs.len()).collect();
+            by_poc.sort_by_key(|&idx| pocs[idx]);
+            for (r, idx) in by_poc.into_iter().enumerate() {
+                rank[idx] = r as i64;
+            }
+        },
+        None => log::debug!("import: picture order count unknown, using decode order"),
+    }
+
+    // Composition offsets can't be negative.
+    let delay = rank
+        .iter()
+        .enumerate()
+        .map(|(idx, r)| idx as i64 - r)
+        .max()
+        .unwrap_or(0);
+
+    let entries = stream
+        .frames
+        .iter()
+        .enumerate()
+        .map(|(idx, f)| Sample {
+            fpos: f.fpos,
+            size: f.size,
+            duration,
+            composition_offset: ((rank[idx] - idx as i64 + delay) * duration as i64) as i32,
+            is_sync: f.is_sync,
+        })
+        .collect();
+
+    Samples {
+        timescale,
+        entries,
+        start: 0,
+        shift: (delay * duration as i64) as i32,
+    }
+}
+
+fn sps_frame_rate(sample_entry: &MP4Box) -> Option<f64> {
+    let frame_rate = match sample_entry {
+        MP4Box::AvcSampleEntry(e) => {
+            let avcc = first_box!(e.boxes, AvcConfigurationBox)?;
+            avcc.configuration.frame_rate().ok()??
+        },
+        MP4Box::HEVCSampleEntry(e) => {
+            let hvcc = first_box!(e.boxes, HEVCConfigurationBox)?;
+            hvcc.configuration.seq_parameter_set()?.frame_rate()?
+        },
+        _ => return None,
+    };
+    Some(frame_rate).filter(|&fr| fr > 0.0 && fr <= 120.0)
+}
+
+// What we need from the SPS to find the picture order count in a slice header.
+#[derive(Default)]
+struct SpsParams {
+    log2_max_frame_num: u8,
+    frame_mbs_only: bool,
+    separate_colour_plane: bool,
+    // `None` if the presentation order is the decode order,
+    // or if it cannot be derived from the slice headers.
+    log2_max_poc_lsb: Option<u8>,
+}
+
+// And what we need from the PPS (HEVC only).
+#[derive(Default)]
+struct PpsParams {
+    output_flag_present: bool,
+    num_extra_slice_header_bits: u8,
+}
+
+// Picture order count, ISO/IEC 14496-10 8.2.1.1 and ISO/IEC 23008-2 8.3.1.
+//
+// The parameters come from the SPS in the sample entry, and the PPSs in
+// the sample entry and in the stream.
+struct PictureOrder {
+    codec: Codec,
+    sps: SpsParams,
+    pps: HashMap<u32, PpsParams>,
+    prev_msb: i32,
+    prev_lsb: i32,
+    // Goes up every time the picture order count starts over.
+    epoch: u32,
+    first: bool,
+}
+
+impl PictureOrder {
+    fn new(codec: Codec, config: &VideoConfig) -> PictureOrder {
+        let mut order = PictureOrder {
+            codec,
+            sps: sps_params(&config.sample_entry).unwrap_or_default(),
+            pps: HashMap::new(),
+            prev_msb: 0,
+            prev_lsb: 0,
+            epoch: 0,
+            first: true,
+        };
+        for nal in &config.parameter_sets {
+            order.nal(nal);
+        }
+        order
+    }
+
+    // Picture order count of a sample, with 4 byte NAL unit lengths.
+    fn picture_order_count(&mut self, mut data: &[u8]) -> Option<(u32, i32)> {
+        let mut poc = None;
+        while data.len() > 4 {
+            let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let nal = data.get(4..4 + len)?;
+            data = &data[4 + len..];
+            if poc.is_none() {
+                poc = self.nal(nal);
+            }
+        }
+        poc
+    }
+
+    // Handle a PPS, or get the picture order count from the first slice.
+    fn nal(&mut self, nal: &[u8]) -> Option<(u32, i32)> {
+        match self.codec {
+            Codec::Avc => match nal.first()? & 0x1f {
+                1 | 5 => self.avc_slice(nal),
+                _ => None,
+            },
+            _ => match (nal.first()? >> 1) & 0x3f {
+                0..=31 => self.hevc_slice(nal),
+                34 => {
+                    self.hevc_pps(nal);
+                    None
+                },
+                _ => None,
+            },
+        }
+    }
+
+    // 7.3.3 Slice header syntax.
+    fn avc_slice(&mut self, nal: &[u8]) -> Option<(u32, i32)> {
+        let sps = &self.sps;
+        let log2_max_poc_lsb = sps.log2_max_poc_lsb?;
+        let is_idr = nal[0] & 0x1f == 5;
+        let is_reference = nal[0] & 0x60 != 0;
+
+        let rbsp = unescape_rbsp(&nal[1..std::cmp::min(nal.len(), 64)]);
+        let mut reader = BitReader::new(&rbsp);
+        reader.read_ue().ok()?; // first_mb_in_slice
+        reader.read_ue().ok()?; // slice_type
+        reader.read_ue().ok()?; // pic_parameter_set_id
+        if sps.separate_colour_plane {
+            reader.read_bits(2).ok()?; // colour_plane_id
+        }
+        reader.read_bits(sps.log2_max_frame_num).ok()?; // frame_num
+        if !sps.frame_mbs_only && reader.read_bit().ok()? {
+            reader.read_bit().ok()?; // bottom_field_flag
+        }
+        if is_idr {
+            reader.read_ue().ok()?; // idr_pic_id
+        }
+        let lsb = reader.read_bits(log2_max_poc_lsb).ok()? as i32;
+
+        if is_idr {
+            self.restart();
+        }
+        let msb = self.msb(lsb, log2_max_poc_lsb);
+        if is_reference {
+            self.prev_msb = msb;
+            self.prev_lsb = lsb;
+        }
+        Some((self.epoch, msb + lsb))
+    }
+
+    // ISO/IEC 23008-2 7.3.6.1 General slice segment header syntax.
+    fn hevc_slice(&mut self, nal: &[u8]) -> Option<(u32, i32)> {
+        let log2_max_poc_lsb = self.sps.log2_max_poc_lsb?;
+        let nal_type = (nal[0] >> 1) & 0x3f;
+        let temporal_id = (*nal.get(1)? & 0x07) as i32 - 1;
+
+        let rbsp = unescape_rbsp(&nal[2..std::cmp::min(nal.len(), 64)]);
+        let mut reader = BitReader::new(&rbsp);
+        if !reader.read_bit().ok()? {
+            // Not the first_slice_segment_in_pic.
+            return None;
+        }
+        if (16..=23).contains(&nal_type) {
+            reader.read_bit().ok()?; // no_output_of_prior_pics_flag
+        }
+        let pps_id = reader.read_ue().ok()?;
+        let pps = self.pps.get(&pps_id)?;
+        reader.read_bits(pps.num_extra_slice_header_bits).ok()?;
+        reader.read_ue().ok()?; // slice_type
+        if pps.output_flag_present {
+            reader.read_bit().ok()?; // pic_output_flag
+        }
+        if self.sps.separate_colour_plane {
+            reader.read_bits(2).ok()?; // colour_plane_id
+        }
+        let is_idr = nal_type == 19 || nal_type == 20;
+        let lsb = match is_idr {
+            true => 0,
+            false => reader.read_bits(log2_max_poc_lsb).ok()? as i32,
+        };
+
+        // IDR, BLA, and a CRA at the start restart the picture order count.
+        let msb = if (16..=20).contains(&nal_type) || (nal_type == 21 && self.first) {
+            self.restart();
+            0
+        } else {
+            self.msb(lsb, log2_max_poc_lsb)
+        };
+        self.first = false;
+
+        // Only TemporalId 0 pictures that are not RADL, RASL or
+        // sub-layer non-reference pictures count as previous picture.
+        let sub_layer_non_reference = nal_type <= 14 && nal_type & 1 == 0;
+        if temporal_id == 0 && !(6..=9).contains(&nal_type) && !sub_layer_non_reference {
+            self.prev_msb = msb;
+            self.prev_lsb = lsb;
+        }
+        Some((self.epoch, msb + lsb))
+    }
+
+    // 7.3.2.3.1 Picture parameter set RBSP syntax.
+    fn hevc_pps(&mut self, nal: &[u8]) -> Option<()> {
+        let rbsp = unescape_rbsp(&nal[2..std::cmp::min(nal.len(), 16)]);
+        let mut reader = BitReader::new(&rbsp);
+        let pps_id = reader.read_ue().ok()?;
+        reader.read_ue().ok()?; // pps_seq_parameter_set_id
+        reader.read_bit().ok()?; // dependent_slice_segments_enabled_flag
+        let pps = PpsParams {
+            output_flag_present: reader.read_bit().ok()?,
+            num_extra_slice_header_bits: reader.read_bits(3).ok()? as u8,
+        };
+        self.pps.insert(pps_id, pps);
+        Some(())
+    }
+
+    fn restart(&mut self) {
+        self.prev_msb = 0;
+        self.prev_lsb = 0;
+        self.epoch += 1;
+    }
+
+    // PicOrderCntMsb, from the lsb and the previous picture.
+    fn msb(&self, lsb: i32, log2_max_poc_lsb: u8) -> i32 {
+        let max_lsb = 1i32 << log2_max_poc_lsb;
+        if lsb < self.prev_lsb && self.prev_lsb - lsb >= max_lsb / 2 {
+            self.prev_msb + max_lsb
+        } else if lsb > self.prev_lsb && lsb - self.prev_lsb > max_lsb / 2 {
+            self.prev_msb - max_lsb
+        } else {
+            self.prev_msb
+        }
+    }
+}
+
+fn sps_params(sample_entry: &MP4Box) -> Option<SpsParams> {
+    match sample_entry {
+        MP4Box::AvcSampleEntry(e) => {
+            let avcc = first_box!(e.boxes, AvcConfigurationBox)?;
+            let sps = avcc.configuration.seq_parameter_set()?;
+            let separate_colour_plane = sps
+                .chroma_format
+                .as_ref()
+                .map(|c| c.chroma_format_idc == 3 && c.residual_color_transform_flag == Some(true))
+                .unwrap_or(false);
+            // Type 1 is rare, we do not support it. With type 2 the
+            // presentation order is the decode order.
+            let log2_max_poc_lsb = match sps.pic_order_cnt_type {
+                PicOrderCntType::Zero {
+                    log2_max_pic_order_cnt_lsb_minus4,
+                } => Some(log2_max_pic_order_cnt_lsb_minus4 + 4),
+                _ => None,
+            };
+            Some(SpsParams {
+                log2_max_frame_num: sps.log2_max_frame_num_minus4 + 4,
+                frame_mbs_only: matches!(sps.frame_mbs_flags, FrameMbsFlags::Frames),
+                separate_colour_plane,
+                log2_max_poc_lsb,
+            })
+        },
+        MP4Box::HEVCSampleEntry(e) => {
+            let hvcc = first_box!(e.boxes, HEVCConfigurationBox)?;
+            let sps = hvcc.configuration.seq_parameter_set()?;
+            Some(SpsParams {
+                separate_colour_plane: sps.separate_colour_plane_flag,
+                log2_max_poc_lsb: Some(sps.log2_max_pic_order_cnt_lsb_minus4 as u8 + 4),
+                ..SpsParams::default()
+            })
+        },
+        _ => None,
+    }
+}
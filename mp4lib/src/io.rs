@@ -191,6 +191,7 @@ impl BoxBytes for Mp4File {
             file: self.file.clone(),
             start: self.pos as usize,
             end: (self.pos + size) as usize,
+            mem: None,
         })
     }
 
@@ -206,10 +207,16 @@ impl BoxBytes for Mp4File {
 /// it through `mmap`.
 ///
 /// This is done so that we don't have to `mmap` gigabytes of memory.
+///
+/// A `DataRef` can also have an in-memory buffer that sits right after
+/// the end of the file. Offsets past the end of the file are read from
+/// that buffer. This is used for sample data that is generated, not read
+/// from the file, like the subtitles of a [`matroska`](crate::matroska) file.
 pub struct DataRef {
     pub(crate) file: Arc<fs::File>,
     start: usize,
     end: usize,
+    mem: Option<Arc<Vec<u8>>>,
 }
 
 impl DataRef {
@@ -221,6 +228,23 @@ impl DataRef {
         Ok(data_ref)
     }
 
+    // A DataRef that covers an entire file, plus an in-memory buffer.
+    pub(crate) fn with_memory(file: fs::File, mem: Vec<u8>) -> io::Result<DataRef> {
+        let end = file.metadata()?.len() as usize;
+        Ok(DataRef {
+            file: Arc::new(file),
+            start: 0,
+            end,
+            mem: Some(Arc::new(mem)),
+        })
+    }
+
+    /// Is the data at this offset in the in-memory buffer.
+    #[cfg(feature = "streaming")]
+    pub(crate) fn in_memory(&self, offset: u64) -> bool {
+        self.mem.is_some() && offset >= self.len()
+    }
+
     /// Number of items.
     pub fn len(&self) -> u64 {
         (self.end - self.start) as u64
@@ -232,6 +256,12 @@ impl DataRef {
     }
 
     pub fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        if let Some(mem) = self.mem.as_ref().filter(|_| offset >= self.len()) {
+            let start = (offset - self.len()) as usize;
+            let data = mem.get(start..start + buf.len()).ok_or_else(|| ioerr!(UnexpectedEof))?;
+            buf.copy_from_slice(data);
+            return Ok(());
+        }
         self.file.read_exact_at(buf, offset + self.start as u64)
     }
 }
@@ -277,6 +307,7 @@ impl Default for DataRef {
             file: Arc::new(devzero),
             start: 0,
             end: 0,
+            mem: None,
         }
     }
 }
@@ -287,6 +318,7 @@ impl Clone for DataRef {
             file: self.file.clone(),
             start: self.start,
             end: self.end,
+            mem: self.mem.clone(),
         }
     }
 }
@@ -338,7 +370,6 @@ impl BoxBytes for CountBytes {
     }
 }
 
-#[cfg(feature = "streaming")]
 mod membuffer {
     use super::*;
 
@@ -403,7 +434,6 @@ mod membuffer {
         }
     }
 }
-#[cfg(feature = "streaming")]
 pub(crate) use membuffer::*;
 
 impl<'a, B: ?Sized + ReadBytes + 'a> ReadBytes for Box<B> {
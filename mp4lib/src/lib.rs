@@ -40,24 +40,31 @@
 #[macro_use]
 mod ioerr;
 mod bitreader;
-pub(crate) mod sample_info;
 #[macro_use]
 #[doc(hidden)]
 pub mod macros;
+pub(crate) mod sample_info;
 
 #[macro_use]
 pub mod serialize;
 #[macro_use]
 pub mod types;
 pub mod boxes;
+pub mod chapters;
 pub mod debug;
+mod demux;
+pub mod import;
 pub mod io;
+pub mod matroska;
+pub mod metadata;
 pub mod mp4box;
+pub mod mpegts;
 pub mod rewrite;
 #[cfg_attr(docsrs, doc(cfg(feature = "streaming")))]
 #[cfg(feature = "streaming")]
 pub mod streaming;
 pub mod track;
+pub mod writer;
 
 pub use crate::io::Mp4File;
 pub use crate::mp4box::MP4;
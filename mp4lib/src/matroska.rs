@@ -0,0 +1,1047 @@
+//! Matroska / WebM demuxer.
+//!
+//! [`open`] reads a `.mkv` or `.webm` file and builds an [`MP4`] from it,
+//! with the same `moov/trak/stbl` structure that a regular `mp4` file has.
+//! The sample tables point straight into the Matroska file, so HLS,
+//! pseudo-streaming and [`track_info`](crate::track::track_info) work
+//! on it unchanged.
+//!
+//! Supported are `H.264`, `HEVC`, `AAC`, `AC-3` and `E-AC-3` tracks, and
+//! `SRT` and `ASS` subtitle tracks. Subtitles are converted to `tx3g`,
+//! those samples are kept in memory. `FlagForced` is mapped to the
+//! "all samples forced" display flag of the `tx3g` sample entry.
+//! Other tracks, and tracks that use content compression or encryption,
+//! are skipped.
+//!
+use std::cmp;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::os::unix::fs::FileExt;
+
+use crate::boxes::*;
+use crate::demux::{self, *};
+use crate::io::DataRef;
+use crate::mp4box::{MP4Box, MP4};
+
+// Element IDs.
+const EBML: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMECODE_SCALE: u32 = 0x2AD7B1;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_ENABLED: u32 = 0xB9;
+const FLAG_FORCED: u32 = 0x55AA;
+const DEFAULT_DURATION: u32 = 0x23E383;
+const NAME: u32 = 0x536E;
+const LANGUAGE: u32 = 0x22B59C;
+const LANGUAGE_IETF: u32 = 0x22B59D;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const DISPLAY_WIDTH: u32 = 0x54B0;
+const DISPLAY_HEIGHT: u32 = 0x54BA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const BIT_DEPTH: u32 = 0x6264;
+const CONTENT_ENCODINGS: u32 = 0x6D80;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_DURATION: u32 = 0x9B;
+const REFERENCE_BLOCK: u32 = 0xFB;
+
+// Track types.
+const TYPE_VIDEO: u64 = 1;
+const TYPE_AUDIO: u64 = 2;
+const TYPE_SUBTITLE: u64 = 17;
+
+/// Does this file start with an EBML header.
+pub fn is_matroska(path: &str) -> bool {
+    let mut magic = [0u8; 4];
+    match fs::File::open(path) {
+        Ok(mut file) => file.read_exact(&mut magic).is_ok() && u32::from_be_bytes(magic) == EBML,
+        Err(_) => false,
+    }
+}
+
+/// Open a Matroska / WebM file and build an `MP4` from it.
+pub fn open(path: &str) -> io::Result<MP4> {
+    let file = fs::File::open(path)?;
+    let mkv = parse(&file).map_err(|e| ioerr!(e.kind(), "{}: {}", path, e))?;
+
+    let mut tracks = Vec::new();
+    let mut mem = Vec::new();
+    let file_len = file.metadata()?.len();
+
+    // The track that starts first starts at time 0.
+    let av = mkv
+        .tracks
+        .iter()
+        .filter(|t| t.kind != TYPE_SUBTITLE && !t.frames.is_empty());
+    let movie_start = av
+        .filter_map(|t| t.frames.iter().map(|f| f.pts).min())
+        .min()
+        .unwrap_or(0);
+
+    for track in &mkv.tracks {
+        if track.frames.is_empty() {
+            continue;
+        }
+        let samples = match track.kind {
+            TYPE_VIDEO => video_samples(track, mkv.timecode_scale, movie_start),
+            TYPE_AUDIO => audio_samples(&file, track, movie_start),
+            _ => subtitle_samples(&file, track, movie_start, file_len, &mut mem),
+        };
+        let samples = match samples {
+            Ok(samples) if !samples.entries.is_empty() => samples,
+            Ok(_) => continue,
+            Err(e) => {
+                log::warn!("matroska: {}: track {}: {}", path, track.number, e);
+                continue;
+            },
+        };
+        let sample_entry = match sample_entry(&file, track) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("matroska: {}: track {}: {}", path, track.number, e);
+                continue;
+            },
+        };
+        tracks.push(build_track(&track.params(), samples, sample_entry));
+    }
+
+    build_mp4(path, tracks, DataRef::with_memory(file, mem)?)
+}
+
+// What we need from the Matroska file.
+struct Matroska {
+    timecode_scale: u64,
+    tracks: Vec<Track>,
+}
+
+#[derive(Default)]
+struct Track {
+    number: u64,
+    kind: u64,
+    enabled: bool,
+    forced: bool,
+    default_duration: u64,
+    name: Option<String>,
+    language: String,
+    codec_id: String,
+    codec_private: Vec<u8>,
+    width: u32,
+    height: u32,
+    display_width: u32,
+    display_height: u32,
+    sample_rate: f64,
+    channels: u16,
+    bit_depth: u16,
+    encoded: bool,
+    frames: Vec<Frame>,
+}
+
+impl Track {
+    fn is_supported(&self) -> bool {
+        let codec = self.codec_id.as_str();
+        match self.kind {
+            TYPE_VIDEO => codec == "V_MPEG4/ISO/AVC" || codec == "V_MPEGH/ISO/HEVC",
+            TYPE_AUDIO => codec.starts_with("A_AAC") || codec == "A_AC3" || codec == "A_EAC3",
+            TYPE_SUBTITLE => codec == "S_TEXT/UTF8" || codec == "S_TEXT/ASS" || codec == "S_TEXT/SSA",
+            _ => false,
+        }
+    }
+
+    fn params(&self) -> TrackParams {
+        TrackParams {
+            track_id: self.number as u32,
+            kind: match self.kind {
+                TYPE_VIDEO => TrackKind::Video,
+                TYPE_AUDIO => TrackKind::Audio,
+                _ => TrackKind::Subtitle,
+            },
+            enabled: self.enabled,
+            language: self.language.clone(),
+            name: self.name.clone(),
+            display_width: self.display_width,
+            display_height: self.display_height,
+        }
+    }
+}
+
+// Times are in nanoseconds.
+struct Frame {
+    pts: i64,
+    duration: Option<u64>,
+    fpos: u64,
+    size: u32,
+    is_sync: bool,
+}
+
+// Parse the file, collect the tracks and the position of every frame.
+fn parse(file: &fs::File) -> io::Result<Matroska> {
+    let size = file.metadata()?.len();
+    let mut r = Reader {
+        file: BufReader::new(file.try_clone()?),
+        pos: 0,
+    };
+
+    let (id, hdr_size) = r.header()?;
+    if id != EBML {
+        return Err(ioerr!(InvalidData, "not a matroska file"));
+    }
+    let hdr = r.read(hdr_size)?;
+    let doc_type = children(&hdr)?.into_iter().find(|e| e.0 == DOC_TYPE);
+    match doc_type.map(|e| string(e.1)) {
+        Some(t) if t == "matroska" || t == "webm" => {},
+        Some(t) => return Err(ioerr!(InvalidData, "unsupported doctype {}", t)),
+        None => return Err(ioerr!(InvalidData, "no doctype")),
+    }
+
+    let mut mkv = Matroska {
+        timecode_scale: 1_000_000,
+        tracks: Vec::new(),
+    };
+    let mut track_idx = HashMap::new();
+    let mut cluster_timecode = 0;
+
+    while r.pos < size {
+        let (id, esize) = r.header()?;
+        // The size of a truncated Segment or Cluster goes past the end of
+        // the file, but what is in it can still be read.
+        let master = id == SEGMENT || id == CLUSTER;
+        if !master && esize.map(|s| r.pos + s > size).unwrap_or(false) {
+            log::warn!("matroska: file is truncated");
+            break;
+        }
+        match id {
+            // Descend into these.
+            SEGMENT | CLUSTER => continue,
+            INFO => {
+                for (id, data) in children(&r.read(esize)?)? {
+                    if id == TIMECODE_SCALE && uint(data) > 0 {
+                        mkv.timecode_scale = uint(data);
+                    }
+                }
+            },
+            TRACKS => {
+                for (id, data) in children(&r.read(esize)?)? {
+                    if id != TRACK_ENTRY {
+                        continue;
+                    }
+                    let track = track_entry(data)?;
+                    if track.encoded {
+                        log::warn!(
+                            "matroska: track {}: compressed or encrypted, skipped",
+                            track.number
+                        );
+                    } else if !track.is_supported() {
+                        log::info!(
+                            "matroska: track {}: codec {} not supported",
+                            track.number,
+                            track.codec_id
+                        );
+                    } else {
+                        track_idx.insert(track.number, mkv.tracks.len());
+                        mkv.tracks.push(track);
+                    }
+                }
+            },
+            TIMECODE => cluster_timecode = uint(&r.read(esize)?),
+            SIMPLE_BLOCK => {
+                let block = r.block(esize)?;
+                if let Some(idx) = track_idx.get(&block.track) {
+                    let is_sync = block.flags & 0x80 != 0;
+                    add_frames(&mut mkv, *idx, block, cluster_timecode, is_sync, None);
+                }
+            },
+            BLOCK_GROUP => {
+                let end = r.pos + known(esize)?;
+                let mut block = None;
+                let mut duration = None;
+                let mut is_sync = true;
+                while r.pos < end {
+                    let (id, esize) = r.header()?;
+                    match id {
+                        BLOCK => block = Some(r.block(esize)?),
+                        BLOCK_DURATION => duration = Some(uint(&r.read(esize)?)),
+                        REFERENCE_BLOCK => {
+                            is_sync = false;
+                            r.skip(esize)?;
+                        },
+                        _ => r.skip(esize)?,
+                    }
+                }
+                if let Some(block) = block {
+                    if let Some(idx) = track_idx.get(&block.track) {
+                        add_frames(&mut mkv, *idx, block, cluster_timecode, is_sync, duration);
+                    }
+                }
+            },
+            _ => r.skip(esize)?,
+        }
+    }
+
+    Ok(mkv)
+}
+
+fn add_frames(
+    mkv: &mut Matroska,
+    idx: usize,
+    block: Block,
+    cluster_tc: u64,
+    is_sync: bool,
+    dur: Option<u64>,
+) {
+    let scale = mkv.timecode_scale as i64;
+    let track = &mut mkv.tracks[idx];
+    let mut pts = (cluster_tc as i64 + block.timecode as i64) * scale;
+    for (fpos, size) in block.frames {
+        track.frames.push(Frame {
+            pts,
+            duration: dur.map(|d| d * scale as u64),
+            fpos,
+            size,
+            is_sync,
+        });
+        // Laced frames only have a timestamp for the first frame.
+        pts += track.default_duration as i64;
+    }
+}
+
+fn track_entry(data: &[u8]) -> io::Result<Track> {
+    let mut track = Track {
+        enabled: true,
+        language: "eng".to_string(),
+        ..Track::default()
+    };
+    for (id, data) in children(data)? {
+        match id {
+            TRACK_NUMBER => track.number = uint(data),
+            TRACK_TYPE => track.kind = uint(data),
+            FLAG_ENABLED => track.enabled = uint(data) != 0,
+            FLAG_FORCED => track.forced = uint(data) != 0,
+            DEFAULT_DURATION => track.default_duration = uint(data),
+            NAME => track.name = Some(string(data)),
+            LANGUAGE => track.language = string(data),
+            LANGUAGE_IETF => {
+                if let Some(lang) = ietf_language(&string(data)) {
+                    track.language = lang;
+                }
+            },
+            CODEC_ID => track.codec_id = string(data),
+            CODEC_PRIVATE => track.codec_private = data.to_vec(),
+            VIDEO => {
+                for (id, data) in children(data)? {
+                    match id {
+                        PIXEL_WIDTH => track.width = uint(data) as u32,
+                        PIXEL_HEIGHT => track.height = uint(data) as u32,
+                        DISPLAY_WIDTH => track.display_width = uint(data) as u32,
+                        DISPLAY_HEIGHT => track.display_height = uint(data) as u32,
+                        _ => {},
+                    }
+                }
+            },
+            AUDIO => {
+                for (id, data) in children(data)? {
+                    match id {
+                        SAMPLING_FREQUENCY => track.sample_rate = float(data),
+                        CHANNELS => track.channels = uint(data) as u16,
+                        BIT_DEPTH => track.bit_depth = uint(data) as u16,
+                        _ => {},
+                    }
+                }
+            },
+            CONTENT_ENCODINGS => track.encoded = true,
+            _ => {},
+        }
+    }
+    if track.display_width == 0 || track.display_height == 0 {
+        track.display_width = track.width;
+        track.display_height = track.height;
+    }
+    Ok(track)
+}
+
+// "en-US" -> "eng".
+fn ietf_language(tag: &str) -> Option<String> {
+    let lang = tag.split('-').next()?.to_lowercase();
+    let lang = match lang.len() {
+        2 => isolang::Language::from_639_1(&lang)?,
+        3 => isolang::Language::from_639_3(&lang)?,
+        _ => return None,
+    };
+    Some(lang.to_639_3().to_string())
+}
+
+// Convert nanoseconds to the timescale of the track.
+fn ticks(ns: i64, timescale: u32) -> i64 {
+    (ns as i128 * timescale as i128 / 1_000_000_000) as i64
+}
+
+fn video_samples(track: &Track, timecode_scale: u64, movie_start: i64) -> io::Result<Samples> {
+    // Use the Matroska timestamp resolution as timescale if we can.
+    let timescale = match 1_000_000_000 % timecode_scale {
+        0 => (1_000_000_000 / timecode_scale) as u32,
+        _ => 90000,
+    };
+
+    // Frames are stored in decode order, with presentation timestamps.
+    // Sorting the presentation timestamps gets us the decode timestamps.
+    let pts: Vec<_> = track.frames.iter().map(|f| ticks(f.pts, timescale)).collect();
+    let mut dts = pts.clone();
+    dts.sort_unstable();
+    let shift = pts
+        .iter()
+        .zip(dts.iter())
+        .map(|(p, d)| d - p)
+        .max()
+        .unwrap_or(0)
+        .max(0);
+
+    let mut entries = Vec::new();
+    for (idx, frame) in track.frames.iter().enumerate() {
+        let duration = match dts.get(idx + 1) {
+            Some(next) => next - dts[idx],
+            None if track.default_duration > 0 => ticks(track.default_duration as i64, timescale),
+            None if idx > 0 => dts[idx] - dts[idx - 1],
+            None => 1,
+        };
+        entries.push(Sample {
+            fpos: frame.fpos,
+            size: frame.size,
+            duration: duration as u32,
+            composition_offset: (pts[idx] - dts[idx] + shift) as i32,
+            is_sync: frame.is_sync,
+        });
+    }
+
+    Ok(Samples {
+        timescale,
+        entries,
+        start: cmp::max(dts[0] - ticks(movie_start, timescale), 0) as u64,
+        shift: shift as i32,
+    })
+}
+
+fn audio_samples(file: &fs::File, track: &Track, movie_start: i64) -> io::Result<Samples> {
+    let first = &track.frames[0];
+    let (timescale, frame_duration) = match track.codec_id.as_str() {
+        "A_AC3" => {
+            let hdr = ac3_header(&frame_header(file, first)?)?;
+            (hdr.sample_rate, 1536)
+        },
+        "A_EAC3" => {
+            let hdr = eac3_header(&frame_header(file, first)?)?;
+            (hdr.sample_rate, hdr.num_blocks * 256)
+        },
+        _ => (track.sample_rate as u32, 1024),
+    };
+    if timescale == 0 {
+        return Err(ioerr!(InvalidData, "unknown sample rate"));
+    }
+
+    // Every frame has a fixed duration, but the stream can have gaps. Keep track of
+    // where we are, and if the next block starts more than half a frame later than
+    // expected, stretch the duration of the current frame to close the gap.
+    let start = ticks(first.pts, timescale);
+    let mut pos = start;
+    let mut entries = Vec::with_capacity(track.frames.len());
+    for (idx, frame) in track.frames.iter().enumerate() {
+        let mut duration = frame_duration as i64;
+        if let Some(next) = track.frames.get(idx + 1) {
+            let gap = ticks(next.pts, timescale) - (pos + duration);
+            if gap > duration / 2 {
+                duration += gap;
+            }
+        }
+        pos += duration;
+        entries.push(Sample {
+            fpos: frame.fpos,
+            size: frame.size,
+            duration: cmp::min(duration, u32::MAX as i64) as u32,
+            composition_offset: 0,
+            is_sync: true,
+        });
+    }
+
+    Ok(Samples {
+        timescale,
+        entries,
+        start: cmp::max(start - ticks(movie_start, timescale), 0) as u64,
+        shift: 0,
+    })
+}
+
+// Subtitles are converted to tx3g samples with a timescale of 1000.
+// The samples are appended to `mem`, which is addressed as if it
+// follows right after the end of the file.
+fn subtitle_samples(
+    file: &fs::File,
+    track: &Track,
+    movie_start: i64,
+    file_len: u64,
+    mem: &mut Vec<u8>,
+) -> io::Result<Samples> {
+    let ms = |ns: i64| cmp::max((ns - movie_start) / 1_000_000, 0) as u64;
+
+    let mut cues = Vec::new();
+    for (idx, frame) in track.frames.iter().enumerate() {
+        let mut data = vec![0u8; frame.size as usize];
+        file.read_exact_at(&mut data, frame.fpos)?;
+        let text = String::from_utf8_lossy(&data);
+        let text = match track.codec_id.as_str() {
+            "S_TEXT/UTF8" => text.trim_end_matches(['\0', '\r', '\n']).to_string(),
+            _ => ass_text(&text),
+        };
+        let start = ms(frame.pts);
+        let duration = frame.duration.or(Some(track.default_duration).filter(|&d| d > 0));
+        let end = match duration {
+            Some(d) => ms(frame.pts + d as i64),
+            None => track
+                .frames
+                .get(idx + 1)
+                .map(|f| ms(f.pts))
+                .unwrap_or(start + 5000),
+        };
+        cues.push((start, end, text));
+    }
+    cues.sort_by_key(|c| c.0);
+
+    let mut entries = Vec::new();
+    let mut sample = |text: &str, duration: u64| {
+        let fpos = file_len + mem.len() as u64;
+        let mut len = cmp::min(text.len(), 0xffff);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        mem.extend_from_slice(&(len as u16).to_be_bytes());
+        mem.extend_from_slice(&text.as_bytes()[..len]);
+        entries.push(Sample {
+            fpos,
+            size: len as u32 + 2,
+            duration: duration as u32,
+            composition_offset: 0,
+            is_sync: true,
+        });
+    };
+
+    // Fill gaps with empty samples, and cut off overlapping cues.
+    let mut time = 0;
+    for (idx, (start, end, text)) in cues.iter().enumerate() {
+        let start = cmp::max(*start, time);
+        let end = match cues.get(idx + 1) {
+            Some(next) => cmp::min(*end, next.0),
+            None => *end,
+        };
+        if end <= start {
+            continue;
+        }
+        if start > time {
+            sample("", start - time);
+        }
+        sample(text, end - start);
+        time = end;
+    }
+
+    Ok(Samples {
+        timescale: 1000,
+        entries,
+        start: 0,
+        shift: 0,
+    })
+}
+
+// The text of an ASS / SSA event in a Matroska block is the 9th field:
+// ReadOrder, Layer, Style, Name, MarginL, MarginR, MarginV, Effect, Text.
+fn ass_text(event: &str) -> String {
+    let text = event.splitn(9, ',').nth(8).unwrap_or("");
+    let mut res = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_tag = true,
+            '}' if in_tag => in_tag = false,
+            _ if in_tag => {},
+            _ => res.push(c),
+        }
+    }
+    res.replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+        .trim()
+        .to_string()
+}
+
+fn sample_entry(file: &fs::File, track: &Track) -> io::Result<MP4Box> {
+    let entry = match track.codec_id.as_str() {
+        "V_MPEG4/ISO/AVC" => video_sample_entry(
+            "avcC",
+            &track.codec_private,
+            track.width,
+            track.height,
+            pasp(track),
+        )?,
+        "V_MPEGH/ISO/HEVC" => video_sample_entry(
+            "hvcC",
+            &track.codec_private,
+            track.width,
+            track.height,
+            pasp(track),
+        )?,
+        "A_AC3" => ac3_sample_entry(&ac3_header(&frame_header(file, &track.frames[0])?)?),
+        "A_EAC3" => eac3_sample_entry(&eac3_header(&frame_header(file, &track.frames[0])?)?),
+        codec if codec.starts_with("A_AAC") => {
+            let asc = match track.codec_private.len() {
+                0 => aac_config(track)?,
+                _ => track.codec_private.clone(),
+            };
+            aac_sample_entry(
+                track.number as u16,
+                track.channels,
+                track.bit_depth,
+                track.sample_rate as u32,
+                &asc,
+            )?
+        },
+        _ => {
+            let mut tx3g = Tx3gTextSampleEntry::default();
+            tx3g.set_forced(track.forced);
+            tx3g.to_mp4box()
+        },
+    };
+    Ok(entry)
+}
+
+// Pixel aspect ratio, if the display size differs from the coded size.
+fn pasp(track: &Track) -> Option<MP4Box> {
+    let h = track.display_width as u64 * track.height as u64;
+    let v = track.display_height as u64 * track.width as u64;
+    demux::pasp(h, v)
+}
+
+// Old-style "A_AAC/MPEG4/LC" codec ids have no CodecPrivate,
+// so build the AudioSpecificConfig ourselves.
+fn aac_config(track: &Track) -> io::Result<Vec<u8>> {
+    let profile = track.codec_id.rsplit('/').next().unwrap_or("");
+    let object_type: u8 = match profile {
+        "MAIN" => 1,
+        "SSR" => 3,
+        "LTP" => 4,
+        _ => 2,
+    };
+    let rate = track.sample_rate as u32;
+    let index = AAC_SAMPLE_RATES.iter().position(|&r| r == rate).ok_or_else(|| {
+        ioerr!(
+            InvalidData,
+            "{}: unsupported sample rate {}",
+            track.codec_id,
+            rate
+        )
+    })?;
+    let channels = cmp::min(track.channels, 7) as u8;
+    Ok(audio_specific_config(object_type, index as u8, channels))
+}
+
+fn frame_header(file: &fs::File, frame: &Frame) -> io::Result<Vec<u8>> {
+    let mut data = vec![0u8; cmp::min(frame.size, 16) as usize];
+    file.read_exact_at(&mut data, frame.fpos)?;
+    Ok(data)
+}
+
+// Reads EBML elements from the file.
+struct Reader {
+    file: BufReader<fs::File>,
+    pos: u64,
+}
+
+impl Reader {
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut b = [0u8; 1];
+        self.file.read_exact(&mut b)?;
+        self.pos += 1;
+        Ok(b[0])
+    }
+
+    fn header(&mut self) -> io::Result<(u32, Option<u64>)> {
+        element_header(|| self.byte())
+    }
+
+    fn read(&mut self, size: Option<u64>) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; known(size)? as usize];
+        self.file.read_exact(&mut data)?;
+        self.pos += data.len() as u64;
+        Ok(data)
+    }
+
+    fn skip(&mut self, size: Option<u64>) -> io::Result<()> {
+        let size = known(size)?;
+        self.file.seek_relative(size as i64)?;
+        self.pos += size;
+        Ok(())
+    }
+
+    // Read the header of a (Simple)Block, and the size of each frame.
+    fn block(&mut self, size: Option<u64>) -> io::Result<Block> {
+        let end = self.pos + known(size)?;
+        let (track, _) = read_vint(|| self.byte())?;
+        let timecode = i16::from_be_bytes([self.byte()?, self.byte()?]);
+        let flags = self.byte()?;
+
+        let mut sizes = Vec::new();
+        let lacing = (flags >> 1) & 0x03;
+        if lacing != 0 {
+            let count = self.byte()? as u64 + 1;
+            for _ in 1..count {
+                let size = match lacing {
+                    // Xiph
+                    1 => {
+                        let mut size = 0;
+                        loop {
+                            let b = self.byte()?;
+                            size += b as u64;
+                            if b != 255 {
+                                break size;
+                            }
+                        }
+                    },
+                    // Fixed-size.
+                    2 => (end - self.pos) / count,
+                    // EBML. The first size is absolute, the rest relative.
+                    _ => {
+                        let (val, len) = read_vint(|| self.byte())?;
+                        match sizes.last() {
+                            Some(prev) => (*prev as i64 + val as i64 - ((1i64 << (7 * len - 1)) - 1)) as u64,
+                            None => val,
+                        }
+                    },
+                };
+                sizes.push(size);
+            }
+        }
+        let used: u64 = sizes.iter().sum();
+        if self.pos + used > end {
+            return Err(ioerr!(InvalidData, "invalid block lacing"));
+        }
+        sizes.push(end - self.pos - used);
+
+        let mut frames = Vec::new();
+        let mut fpos = self.pos;
+        for size in sizes {
+            frames.push((fpos, size as u32));
+            fpos += size;
+        }
+        self.skip(Some(end - self.pos))?;
+
+        Ok(Block {
+            track,
+            timecode,
+            flags,
+            frames,
+        })
+    }
+}
+
+struct Block {
+    track: u64,
+    timecode: i16,
+    flags: u8,
+    // file position and size.
+    frames: Vec<(u64, u32)>,
+}
+
+// Returns the value with the length marker removed, and the length.
+fn read_vint(mut next_byte: impl FnMut() -> io::Result<u8>) -> io::Result<(u64, u32)> {
+    let b = next_byte()?;
+    if b == 0 {
+        return Err(ioerr!(InvalidData, "invalid EBML integer"));
+    }
+    let len = b.leading_zeros() + 1;
+    let mut val = b as u64 & ((1 << (8 - len)) - 1);
+    for _ in 1..len {
+        val = (val << 8) | next_byte()? as u64;
+    }
+    Ok((val, len))
+}
+
+// Element ID and size. A size of `None` means "unknown".
+fn element_header(mut next_byte: impl FnMut() -> io::Result<u8>) -> io::Result<(u32, Option<u64>)> {
+    let (id, len) = read_vint(&mut next_byte)?;
+    if len > 4 {
+        return Err(ioerr!(InvalidData, "invalid EBML element id"));
+    }
+    let id = id | (1 << (7 * len));
+    let (size, len) = read_vint(&mut next_byte)?;
+    let unknown = (1u64 << (7 * len)) - 1;
+    Ok((id as u32, if size == unknown { None } else { Some(size) }))
+}
+
+fn known(size: Option<u64>) -> io::Result<u64> {
+    size.ok_or_else(|| ioerr!(InvalidData, "unexpected element of unknown size"))
+}
+
+// The child elements of a master element that was read into memory.
+fn children(mut data: &[u8]) -> io::Result<Vec<(u32, &[u8])>> {
+    let mut res = Vec::new();
+    while !data.is_empty() {
+        let (id, size) = element_header(|| {
+            let (b, rest) = data.split_first().ok_or_else(|| ioerr!(UnexpectedEof))?;
+            data = rest;
+            Ok(*b)
+        })?;
+        let size = known(size)? as usize;
+        if size > data.len() {
+            return Err(ioerr!(UnexpectedEof));
+        }
+        res.push((id, &data[..size]));
+        data = &data[size..];
+    }
+    Ok(res)
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+fn float(data: &[u8]) -> f64 {
+    match data.len() {
+        4 => f32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64,
+        8 => f64::from_be_bytes([
+            data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7],
+        ]),
+        _ => 0.0,
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end_matches('\0').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample_info::SampleInfoIterator;
+
+    // Element with a one byte size, or an eight byte size if it does not fit.
+    fn el(id: u32, body: &[u8]) -> Vec<u8> {
+        let id = id.to_be_bytes();
+        let skip = id.iter().take_while(|&&b| b == 0).count();
+        let mut e = id[skip..].to_vec();
+        if body.len() < 127 {
+            e.push(0x80 | body.len() as u8);
+        } else {
+            e.push(0x01);
+            e.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        }
+        e.extend_from_slice(body);
+        e
+    }
+
+    // Master element of unknown size.
+    fn el_unknown(id: u32) -> Vec<u8> {
+        let mut e = id.to_be_bytes().to_vec();
+        e.extend_from_slice(&[0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        e
+    }
+
+    // Block for track 1, frame `n` consists of bytes with value `n`.
+    fn block(timecode: i16, flags: u8, lacing: &[u8], sizes: &[usize]) -> Vec<u8> {
+        let mut b = vec![0x81];
+        b.extend_from_slice(&timecode.to_be_bytes());
+        b.push(flags);
+        b.extend_from_slice(lacing);
+        for (n, size) in sizes.iter().enumerate() {
+            b.extend(vec![n as u8; *size]);
+        }
+        b
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("mp4lib-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn read_block(name: &str, data: &[u8]) -> io::Result<Block> {
+        let path = temp_file(name, data);
+        let mut r = Reader {
+            file: BufReader::new(fs::File::open(&path)?),
+            pos: 0,
+        };
+        let res = r.header().and_then(|(_, size)| r.block(size));
+        fs::remove_file(&path)?;
+        res
+    }
+
+    // Frame sizes, and check that every frame is at the right position.
+    fn frame_sizes(data: &[u8], block: &Block) -> Vec<u32> {
+        for (n, &(fpos, size)) in block.frames.iter().enumerate() {
+            let frame = &data[fpos as usize..fpos as usize + size as usize];
+            assert!(frame.iter().all(|&b| b == n as u8));
+        }
+        block.frames.iter().map(|f| f.1).collect()
+    }
+
+    fn aac_track() -> Vec<u8> {
+        let mut audio = el(SAMPLING_FREQUENCY, &48000f64.to_be_bytes());
+        audio.extend(el(CHANNELS, &[2]));
+        let mut entry = el(TRACK_NUMBER, &[1]);
+        entry.extend(el(TRACK_TYPE, &[TYPE_AUDIO as u8]));
+        entry.extend(el(LANGUAGE_IETF, b"nl-NL"));
+        entry.extend(el(CODEC_ID, b"A_AAC"));
+        entry.extend(el(CODEC_PRIVATE, &[0x11, 0x90]));
+        entry.extend(el(DEFAULT_DURATION, &21_333_333u32.to_be_bytes()));
+        entry.extend(el(AUDIO, &audio));
+        el(TRACK_ENTRY, &entry)
+    }
+
+    // One AAC track, and a VP8 track that is skipped.
+    fn mkv_file() -> Vec<u8> {
+        let mut f = el(EBML, &el(DOC_TYPE, b"matroska"));
+        f.extend(el_unknown(SEGMENT));
+        f.extend(el(INFO, &el(TIMECODE_SCALE, &[0x0f, 0x42, 0x40])));
+        let mut vp8 = el(TRACK_NUMBER, &[2]);
+        vp8.extend(el(TRACK_TYPE, &[TYPE_VIDEO as u8]));
+        vp8.extend(el(CODEC_ID, b"V_VP8"));
+        let mut tracks = aac_track();
+        tracks.extend(el(TRACK_ENTRY, &vp8));
+        f.extend(el(TRACKS, &tracks));
+        f.extend(el_unknown(CLUSTER));
+        f.extend(el(TIMECODE, &[0]));
+        f.extend(el(SIMPLE_BLOCK, &block(0, 0x80, &[], &[10])));
+        // Three Xiph laced frames at 21 ms.
+        f.extend(el(SIMPLE_BLOCK, &block(21, 0x82, &[2, 5, 6], &[5, 6, 7])));
+        // A frame at 128 ms, after a gap.
+        let mut group = el(BLOCK, &block(128, 0x00, &[], &[4]));
+        group.extend(el(BLOCK_DURATION, &[21]));
+        f.extend(el(BLOCK_GROUP, &group));
+        f
+    }
+
+    fn next_byte(data: &[u8]) -> impl FnMut() -> io::Result<u8> + '_ {
+        let mut bytes = data.iter();
+        move || bytes.next().cloned().ok_or_else(|| ioerr!(UnexpectedEof))
+    }
+
+    #[test]
+    fn element_header() {
+        let data = [0x1a, 0x45, 0xdf, 0xa3, 0x42, 0x00];
+        let header = super::element_header(next_byte(&data)).unwrap();
+        assert_eq!(header, (EBML, Some(512)));
+
+        let data = el_unknown(CLUSTER);
+        let header = super::element_header(next_byte(&data)).unwrap();
+        assert_eq!(header, (CLUSTER, None));
+
+        let err = read_vint(next_byte(&[0x00, 0x81])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = super::element_header(next_byte(&[0x1a, 0x45])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn track_entry() {
+        let data = aac_track();
+        let elements = children(&data).unwrap();
+        let track = super::track_entry(elements[0].1).unwrap();
+        assert_eq!((track.number, track.kind), (1, TYPE_AUDIO));
+        assert_eq!(track.codec_id, "A_AAC");
+        assert_eq!(track.codec_private, [0x11, 0x90]);
+        assert_eq!(track.language, "nld");
+        assert_eq!(track.sample_rate, 48000.0);
+        assert_eq!(track.channels, 2);
+        assert!(track.enabled && track.is_supported());
+
+        // Child element that goes past the end of its parent.
+        let mut data = aac_track();
+        data.truncate(data.len() - 1);
+        let err = super::track_entry(&data[2..]).map(|_| ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn xiph_lacing() {
+        let data = el(SIMPLE_BLOCK, &block(0, 0x82, &[2, 255, 45, 6], &[300, 6, 7]));
+        let block = read_block("xiph", &data).unwrap();
+        assert_eq!(frame_sizes(&data, &block), [300, 6, 7]);
+    }
+
+    #[test]
+    fn ebml_lacing() {
+        // 500, then 500 - 100 (signed, two bytes), then the rest.
+        let diff = (-100i32 + 0x1fff) as u16 | 0x4000;
+        let mut lacing = vec![2, 0x41, 0xf4];
+        lacing.extend_from_slice(&diff.to_be_bytes());
+        let data = el(SIMPLE_BLOCK, &block(0, 0x86, &lacing, &[500, 400, 3]));
+        let block = read_block("ebml", &data).unwrap();
+        assert_eq!(frame_sizes(&data, &block), [500, 400, 3]);
+    }
+
+    #[test]
+    fn fixed_lacing() {
+        let data = el(SIMPLE_BLOCK, &block(0, 0x84, &[3], &[4, 4, 4, 4]));
+        let block = read_block("fixed", &data).unwrap();
+        assert_eq!(frame_sizes(&data, &block), [4, 4, 4, 4]);
+    }
+
+    #[test]
+    fn invalid_lacing() {
+        let data = el(SIMPLE_BLOCK, &block(0, 0x82, &[1, 200], &[5, 5]));
+        let err = read_block("invalid", &data).map(|_| ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn open_file() {
+        let data = mkv_file();
+        let path = temp_file("open.mkv", &data);
+        let mp4 = open(&path);
+        fs::remove_file(&path).unwrap();
+        let mp4 = mp4.unwrap();
+
+        let tracks = mp4.movie().tracks();
+        assert_eq!(tracks.len(), 1);
+        let samples: Vec<_> = SampleInfoIterator::new(tracks[0]).collect();
+        let sizes: Vec<_> = samples.iter().map(|s| s.size).collect();
+        assert_eq!(sizes, [10, 5, 6, 7, 4]);
+        let times: Vec<_> = samples.iter().map(|s| s.decode_time).collect();
+        assert_eq!(times, [0, 1024, 2048, 3072, 6144]);
+        for s in &samples {
+            let frame = &data[s.fpos as usize..(s.fpos + s.size as u64) as usize];
+            assert!(frame.iter().all(|&b| b == frame[0]));
+        }
+    }
+
+    #[test]
+    fn truncated_file() {
+        // The last block is cut off, the frames before it are still there.
+        let mut data = mkv_file();
+        data.truncate(data.len() - 3);
+        let path = temp_file("truncated.mkv", &data);
+        let mp4 = open(&path);
+        fs::remove_file(&path).unwrap();
+        let mp4 = mp4.unwrap();
+        let samples = SampleInfoIterator::new(mp4.movie().tracks()[0]).count();
+        assert_eq!(samples, 4);
+    }
+
+    #[test]
+    fn not_matroska() {
+        let mut data = el(EBML, &el(DOC_TYPE, b"other"));
+        data.extend(el_unknown(SEGMENT));
+        let path = temp_file("other.mkv", &data);
+        let err = open(&path).map(|_| ()).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
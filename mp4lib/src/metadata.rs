@@ -0,0 +1,759 @@
+//! iTunes style metadata.
+//!
+//! The metadata is stored in `moov/udta/meta/ilst` (or sometimes in
+//! `moov/meta/ilst`), as a list of items
+//! that each contain a `data` box. [`metadata`] reads the items that we
+//! know about into a [`Metadata`] struct, [`set_metadata`] writes it back.
+//! Items that are not part of [`Metadata`] are left alone.
+//!
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::boxes::*;
+use crate::mp4box::{BoxInfo, MP4};
+use crate::rewrite;
+use crate::types::*;
+
+/// Freeform (`----`) item.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Freeform {
+    /// Reverse DNS domain, usually `com.apple.iTunes`.
+    pub mean: String,
+    /// Name.
+    pub name: String,
+    /// Value.
+    pub value: String,
+}
+
+/// Cover art image type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverFormat {
+    Jpeg,
+    Png,
+    Bmp,
+}
+
+/// Cover art (`covr`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CoverArt {
+    /// Image type.
+    pub format: CoverFormat,
+    /// Image data.
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl CoverArt {
+    /// Cover art from image data, if it is a `JPEG`, `PNG` or `BMP` image.
+    pub fn from_data(data: Vec<u8>) -> Option<CoverArt> {
+        let format = if data.starts_with(b"\xff\xd8\xff") {
+            CoverFormat::Jpeg
+        } else if data.starts_with(b"\x89PNG") {
+            CoverFormat::Png
+        } else if data.starts_with(b"BM") {
+            CoverFormat::Bmp
+        } else {
+            return None;
+        };
+        Some(CoverArt { format, data })
+    }
+
+    /// `MIME` type.
+    pub fn mime(&self) -> &'static str {
+        match self.format {
+            CoverFormat::Jpeg => "image/jpeg",
+            CoverFormat::Png => "image/png",
+            CoverFormat::Bmp => "image/bmp",
+        }
+    }
+
+    /// Filename extension.
+    pub fn extension(&self) -> &'static str {
+        match self.format {
+            CoverFormat::Jpeg => "jpg",
+            CoverFormat::Png => "png",
+            CoverFormat::Bmp => "bmp",
+        }
+    }
+}
+
+/// Metadata.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    /// `©nam`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// `©ART`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    /// `aART`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_artist: Option<String>,
+    /// `©alb`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    /// `©wrt`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub composer: Option<String>,
+    /// `©gen`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    /// `©day`, usually `YYYY` or `YYYY-MM-DD`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<String>,
+    /// `©cmt`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    /// `desc`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// `ldes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub long_description: Option<String>,
+    /// `tvsh`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub show: Option<String>,
+    /// `tvsn`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub season: Option<u32>,
+    /// `tves`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub episode: Option<u32>,
+    /// `tven`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub episode_id: Option<String>,
+    /// `tvnn`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    /// `stik` (1: music, 9: movie, 10: tv show, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<u32>,
+    /// `trkn`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<u32>,
+    /// `covr`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover: Option<CoverArt>,
+    /// `----`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub freeform: Vec<Freeform>,
+}
+
+// Reference to a field, by type.
+enum Field<'a> {
+    Text(&'a mut Option<String>),
+    Int(&'a mut Option<u32>),
+}
+
+// Text items.
+const TEXT_ITEMS: [(&[u8; 4], &str); 13] = [
+    (b"\xa9nam", "title"),
+    (b"\xa9ART", "artist"),
+    (b"aART", "album_artist"),
+    (b"\xa9alb", "album"),
+    (b"\xa9wrt", "composer"),
+    (b"\xa9gen", "genre"),
+    (b"\xa9day", "release_date"),
+    (b"\xa9cmt", "comment"),
+    (b"desc", "description"),
+    (b"ldes", "long_description"),
+    (b"tvsh", "show"),
+    (b"tven", "episode_id"),
+    (b"tvnn", "network"),
+];
+
+// Integer items. `trkn` is special, it's a binary item.
+const INT_ITEMS: [(&[u8; 4], &str); 4] = [
+    (b"tvsn", "season"),
+    (b"tves", "episode"),
+    (b"stik", "media_type"),
+    (b"trkn", "track"),
+];
+
+const ITUNES: &str = "com.apple.iTunes";
+
+macro_rules! metadata_fields {
+    ($($name:ident: $kind:ident,)*) => {
+        impl Metadata {
+            fn field(&mut self, key: &str) -> io::Result<Field<'_>> {
+                match key {
+                    $(
+                        stringify!($name) => Ok(Field::$kind(&mut self.$name)),
+                    )*
+                    _ => Err(ioerr!(InvalidInput, "unknown metadata key: {}", key)),
+                }
+            }
+
+            fn value(&self, key: &str) -> Option<String> {
+                match key {
+                    $(
+                        stringify!($name) => self.$name.as_ref().map(|v| v.to_string()),
+                    )*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+metadata_fields! {
+    title: Text,
+    artist: Text,
+    album_artist: Text,
+    album: Text,
+    composer: Text,
+    genre: Text,
+    release_date: Text,
+    comment: Text,
+    description: Text,
+    long_description: Text,
+    show: Text,
+    episode_id: Text,
+    network: Text,
+    season: Int,
+    episode: Int,
+    media_type: Int,
+    track: Int,
+}
+
+impl Metadata {
+    /// Names of the fields that can be used with `get`, `set` and `remove`.
+    ///
+    /// Freeform items are named `----:NAME` or `----:MEAN:NAME`.
+    pub fn keys() -> Vec<&'static str> {
+        TEXT_ITEMS
+            .iter()
+            .chain(INT_ITEMS.iter())
+            .map(|(_, k)| *k)
+            .collect()
+    }
+
+    // "----:NAME" or "----:MEAN:NAME".
+    fn freeform_key(key: &str) -> Option<(&str, &str)> {
+        let key = key.strip_prefix("----:")?;
+        Some(key.rsplit_once(':').unwrap_or((ITUNES, key)))
+    }
+
+    /// Get a field by name.
+    pub fn get(&self, key: &str) -> Option<String> {
+        if let Some((mean, name)) = Self::freeform_key(key) {
+            let f = self.freeform.iter().find(|f| f.mean == mean && f.name == name)?;
+            return Some(f.value.clone());
+        }
+        self.value(key)
+    }
+
+    /// Set a field by name.
+    pub fn set(&mut self, key: &str, value: &str) -> io::Result<()> {
+        if let Some((mean, name)) = Self::freeform_key(key) {
+            let freeform = Freeform {
+                mean: mean.to_string(),
+                name: name.to_string(),
+                value: value.to_string(),
+            };
+            match self
+                .freeform
+                .iter_mut()
+                .find(|f| f.mean == mean && f.name == name)
+            {
+                Some(f) => *f = freeform,
+                None => self.freeform.push(freeform),
+            }
+            return Ok(());
+        }
+        match self.field(key)? {
+            Field::Text(t) => *t = Some(value.to_string()),
+            Field::Int(i) => {
+                let v = value
+                    .parse::<u32>()
+                    .map_err(|_| ioerr!(InvalidInput, "{}: not a number: {}", key, value))?;
+                *i = Some(v);
+            },
+        }
+        Ok(())
+    }
+
+    /// Remove a field by name.
+    pub fn remove(&mut self, key: &str) -> io::Result<()> {
+        if key == "cover" {
+            self.cover = None;
+            return Ok(());
+        }
+        if let Some((mean, name)) = Self::freeform_key(key) {
+            self.freeform.retain(|f| f.mean != mean || f.name != name);
+            return Ok(());
+        }
+        match self.field(key)? {
+            Field::Text(t) => *t = None,
+            Field::Int(i) => *i = None,
+        }
+        Ok(())
+    }
+}
+
+// Find the ilst box, in moov/udta/meta or moov/meta.
+fn item_list(mp4: &MP4) -> Option<&AppleItemListBox> {
+    let movie = mp4.movie();
+    let udta_ilst = first_box!(&movie.boxes, UserDataBox)
+        .and_then(|udta| first_box!(&udta.boxes, MetaBox))
+        .and_then(|meta| first_box!(&meta.boxes, AppleItemListBox));
+    let moov_ilst = first_box!(&movie.boxes, MetaBox).and_then(|meta| first_box!(&meta.boxes, AppleItemListBox));
+    udta_ilst.or(moov_ilst)
+}
+
+// Find the ilst box that `item_list` reads, or create moov/udta/meta/ilst.
+fn item_list_mut(mp4: &mut MP4) -> &mut AppleItemListBox {
+    let movie = mp4.movie_mut();
+    let in_udta = first_box!(&movie.boxes, UserDataBox)
+        .and_then(|udta| first_box!(&udta.boxes, MetaBox))
+        .and_then(|meta| first_box!(&meta.boxes, AppleItemListBox))
+        .is_some();
+    let in_moov = first_box!(&movie.boxes, MetaBox)
+        .and_then(|meta| first_box!(&meta.boxes, AppleItemListBox))
+        .is_some();
+    if in_moov && !in_udta {
+        let meta_box = first_box_mut!(&mut movie.boxes, MetaBox).unwrap();
+        return first_box_mut!(&mut meta_box.boxes, AppleItemListBox).unwrap();
+    }
+
+    if first_box!(&movie.boxes, UserDataBox).is_none() {
+        movie.boxes.push(UserDataBox { boxes: Vec::new() }.to_mp4box());
+    }
+    let udta = first_box_mut!(&mut movie.boxes, UserDataBox).unwrap();
+    if first_box!(&udta.boxes, MetaBox).is_none() {
+        let hdlr = HandlerBox {
+            handler_type: FourCC::new("mdir"),
+            name: ZString::from(""),
+        };
+        udta.boxes.push(
+            MetaBox {
+                boxes: vec![hdlr.to_mp4box()],
+            }
+            .to_mp4box(),
+        );
+    }
+    let meta_box = first_box_mut!(&mut udta.boxes, MetaBox).unwrap();
+    if first_box!(&meta_box.boxes, AppleItemListBox).is_none() {
+        meta_box
+            .boxes
+            .push(AppleItemListBox { items: Vec::new() }.to_mp4box());
+    }
+    first_box_mut!(&mut meta_box.boxes, AppleItemListBox).unwrap()
+}
+
+fn item_data(item: &AppleItem) -> io::Result<Option<(u32, Vec<u8>)>> {
+    let data = match item.data {
+        Some(ref data) => data,
+        None => return Ok(None),
+    };
+    let bytes = match data.data {
+        AppleData::Text(ref t) => t.as_bytes().to_vec(),
+        AppleData::Binary(ref b) => b.0.clone(),
+        AppleData::Extern(ref e) => {
+            let mut buf = vec![0u8; e.len() as usize];
+            e.read_exact_at(&mut buf, 0)?;
+            buf
+        },
+    };
+    Ok(Some((data.flags, bytes)))
+}
+
+fn be_int(data: &[u8]) -> Option<u32> {
+    match data.len() {
+        1 => Some(data[0] as u32),
+        2 => Some(u16::from_be_bytes([data[0], data[1]]) as u32),
+        4 => Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+        8 => {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(data);
+            let v = u64::from_be_bytes(b);
+            (v <= u32::MAX as u64).then_some(v as u32)
+        },
+        _ => None,
+    }
+}
+
+/// Read the metadata.
+pub fn metadata(mp4: &MP4) -> io::Result<Metadata> {
+    let mut meta = Metadata::default();
+    let ilst = match item_list(mp4) {
+        Some(ilst) => ilst,
+        None => return Ok(meta),
+    };
+
+    for item in &ilst.items {
+        let (flags, data) = match item_data(item)? {
+            Some(d) => d,
+            None => continue,
+        };
+        let tag = item.tag.to_be_bytes();
+
+        if let Some((_, key)) = TEXT_ITEMS.iter().find(|(t, _)| **t == tag) {
+            if let Field::Text(t) = meta.field(key)? {
+                *t = Some(String::from_utf8_lossy(&data).to_string());
+            }
+            continue;
+        }
+
+        if let Some((_, key)) = INT_ITEMS.iter().find(|(t, _)| **t == tag) {
+            // trkn: 2 bytes reserved, 2 bytes track number, 2 bytes total.
+            let value = if &tag == b"trkn" {
+                (data.len() >= 4).then(|| u16::from_be_bytes([data[2], data[3]]) as u32)
+            } else {
+                be_int(&data)
+            };
+            if let Field::Int(i) = meta.field(key)? {
+                *i = value;
+            }
+            continue;
+        }
+
+        match &tag {
+            b"covr" if meta.cover.is_none() => {
+                let format = match flags {
+                    13 => CoverFormat::Jpeg,
+                    14 => CoverFormat::Png,
+                    27 => CoverFormat::Bmp,
+                    _ if data.starts_with(b"\x89PNG") => CoverFormat::Png,
+                    _ if data.starts_with(b"BM") => CoverFormat::Bmp,
+                    _ => CoverFormat::Jpeg,
+                };
+                meta.cover = Some(CoverArt { format, data });
+            },
+            b"----" => {
+                let mean = item.mean.as_ref().map(|m| m.mean.as_str()).unwrap_or(ITUNES);
+                let name = match item.name {
+                    Some(ref name) => name.name.clone(),
+                    None => continue,
+                };
+                meta.freeform.push(Freeform {
+                    mean: mean.to_string(),
+                    name,
+                    value: String::from_utf8_lossy(&data).to_string(),
+                });
+            },
+            _ => {},
+        }
+    }
+    Ok(meta)
+}
+
+/// Get the cover art.
+///
+/// This is the `covr` item. If there is none, it is the first image of
+/// a `jpeg` or `png ` video track, like a QuickTime chapter image track.
+pub fn cover_art(mp4: &MP4) -> io::Result<Option<CoverArt>> {
+    if let Some(cover) = metadata(mp4)?.cover {
+        return Ok(Some(cover));
+    }
+    for track in mp4.movie().tracks() {
+        let stsd = track.media().media_info().sample_table().sample_description();
+        let is_image = stsd.entries.iter().any(|e| e.fourcc() == b"jpeg" || e.fourcc() == b"png ");
+        if !is_image {
+            continue;
+        }
+        if let Some(sample) = mp4.sample_info_iter(track).next() {
+            let mut data = vec![0; sample.size as usize];
+            mp4.data_ref.read_exact_at(&mut data, sample.fpos)?;
+            if let Some(cover) = CoverArt::from_data(data) {
+                return Ok(Some(cover));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Set or remove the `covr` item.
+pub fn set_cover_art(mp4: &mut MP4, cover: Option<CoverArt>) -> io::Result<()> {
+    let mut meta = metadata(mp4)?;
+    meta.cover = cover;
+    set_metadata(mp4, &meta)
+}
+
+fn new_item(tag: &[u8; 4], flags: u32, data: AppleData) -> AppleItem {
+    AppleItem {
+        tag: FourCC(u32::from_be_bytes(*tag)),
+        name: None,
+        mean: None,
+        data: Some(IDataBox { flags, data }),
+        boxes: Vec::new(),
+    }
+}
+
+/// Write the metadata.
+///
+/// Replaces all items in `moov/udta/meta/ilst` (or `moov/meta/ilst`, if
+/// that is where the metadata is) that are known to [`Metadata`], the
+/// other items are kept. The total in `trkn` and any covers after the first
+/// one are kept as well. If the `moov` box is in front of the media data,
+/// the chunk offsets are adjusted.
+pub fn set_metadata(mp4: &mut MP4, meta: &Metadata) -> io::Result<()> {
+    if mp4.boxes.iter().all(|b| !matches!(b, MP4Box::MovieBox(_))) {
+        return Err(ioerr!(InvalidInput, "no moov box"));
+    }
+    let old_size = rewrite::movie_size(mp4);
+    let ilst = item_list_mut(mp4);
+
+    let mut meta = meta.clone();
+    let mut items = Vec::new();
+
+    // `trkn` also has the total number of tracks, that we keep.
+    let old_trkn = match ilst.items.iter().find(|item| &item.tag.to_be_bytes() == b"trkn") {
+        Some(item) => item_data(item)?.map(|(_, data)| data),
+        None => None,
+    };
+
+    for (tag, key) in TEXT_ITEMS.iter() {
+        if let Field::Text(Some(text)) = meta.field(key)? {
+            items.push(new_item(tag, 1, AppleData::Text(text.clone())));
+        }
+    }
+    for (tag, key) in INT_ITEMS.iter() {
+        if let Field::Int(Some(value)) = meta.field(key)? {
+            let item = match &tag[..] {
+                b"trkn" => {
+                    let mut data = old_trkn.clone().filter(|d| d.len() >= 8).unwrap_or_else(|| vec![0u8; 8]);
+                    data[2..4].copy_from_slice(&(*value as u16).to_be_bytes());
+                    new_item(tag, 0, AppleData::Binary(Data(data)))
+                },
+                b"stik" => new_item(tag, 21, AppleData::Binary(Data(vec![*value as u8]))),
+                _ => new_item(tag, 21, AppleData::Binary(Data(value.to_be_bytes().to_vec()))),
+            };
+            items.push(item);
+        }
+    }
+
+    // Replace the first cover. The others, either in the same
+    // `covr` item or in another one, are kept.
+    let mut covers: Vec<_> = ilst
+        .items
+        .iter()
+        .filter(|item| &item.tag.to_be_bytes() == b"covr")
+        .cloned()
+        .collect();
+    match meta.cover.take() {
+        Some(cover) => {
+            let flags = match cover.format {
+                CoverFormat::Jpeg => 13,
+                CoverFormat::Png => 14,
+                CoverFormat::Bmp => 27,
+            };
+            let data = AppleData::Binary(Data(cover.data));
+            match covers.first_mut() {
+                Some(item) => item.data = Some(IDataBox { flags, data }),
+                None => covers.push(new_item(b"covr", flags, data)),
+            }
+        },
+        None => covers.clear(),
+    }
+    items.extend(covers);
+    for f in &meta.freeform {
+        let mut item = new_item(b"----", 1, AppleData::Text(f.value.clone()));
+        item.mean = Some(IMeanBox { mean: f.mean.clone() });
+        item.name = Some(INameBox { name: f.name.clone() });
+        items.push(item);
+    }
+
+    // Keep the items we do not know about.
+    ilst.items.retain(|item| {
+        let tag = item.tag.to_be_bytes();
+        !TEXT_ITEMS.iter().chain(INT_ITEMS.iter()).any(|(t, _)| **t == tag)
+            && &tag != b"covr"
+            && &tag != b"----"
+    });
+    ilst.items.extend(items);
+
+    rewrite::movie_resized(mp4, old_size, &[]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{MemBuffer, Mp4File};
+
+    fn bx(fourcc: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut b = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(fourcc);
+        b.extend_from_slice(body);
+        b
+    }
+
+    fn data(flags: u32, value: &[u8]) -> Vec<u8> {
+        bx(b"data", &[&flags.to_be_bytes()[..], &[0; 4], value].concat())
+    }
+
+    fn freeform(name: &str, value: &str) -> Vec<u8> {
+        let mean = bx(b"mean", &[&[0; 4][..], ITUNES.as_bytes()].concat());
+        let name = bx(b"name", &[&[0; 4][..], name.as_bytes()].concat());
+        bx(b"----", &[mean, name, data(1, value.as_bytes())].concat())
+    }
+
+    // moov/udta/meta/ilst with the items.
+    fn moov(items: &[Vec<u8>]) -> Vec<u8> {
+        let hdlr = bx(b"hdlr", &[&[0; 8][..], b"mdir", &[0; 13]].concat());
+        let meta = bx(
+            b"meta",
+            &[&[0; 4][..], &hdlr, &bx(b"ilst", &items.concat())].concat(),
+        );
+        bx(b"moov", &bx(b"udta", &meta))
+    }
+
+    fn ilst_items() -> Vec<Vec<u8>> {
+        vec![
+            bx(b"\xa9nam", &data(1, b"Title")),
+            bx(b"trkn", &data(0, &[0, 0, 0, 3, 0, 12, 0, 0])),
+            bx(b"tvsn", &data(21, &[0, 0, 0, 2])),
+            bx(b"stik", &data(21, &[10])),
+            bx(
+                b"covr",
+                &[data(13, b"\xff\xd8\xffjpeg"), data(14, b"\x89PNGpng")].concat(),
+            ),
+            bx(b"\xa9too", &data(1, b"Encoder")),
+            freeform("iTunSMPB", "00000000"),
+        ]
+    }
+
+    fn read(data: &[u8]) -> io::Result<MP4> {
+        let path = std::env::temp_dir().join(format!("mp4lib-{}-{}.mp4", std::process::id(), data.len()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, data)?;
+        let mp4 = Mp4File::open(path, true).and_then(MP4::read_dont_validate);
+        std::fs::remove_file(path)?;
+        mp4
+    }
+
+    fn write(mp4: &MP4) -> Vec<u8> {
+        let mut buffer = MemBuffer::new();
+        mp4.write(&mut buffer).unwrap();
+        buffer.into_vec()
+    }
+
+    fn item<'a>(mp4: &'a MP4, tag: &[u8; 4]) -> Option<&'a AppleItem> {
+        item_list(mp4)?
+            .items
+            .iter()
+            .find(|item| &item.tag.to_be_bytes() == tag)
+    }
+
+    #[test]
+    fn read_metadata() {
+        let mp4 = read(&moov(&ilst_items())).unwrap();
+        let meta = metadata(&mp4).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Title"));
+        assert_eq!(
+            (meta.track, meta.season, meta.media_type),
+            (Some(3), Some(2), Some(10))
+        );
+        assert_eq!(meta.artist, None);
+        let cover = meta.cover.as_ref().unwrap();
+        assert_eq!(
+            (cover.format, &cover.data[..]),
+            (CoverFormat::Jpeg, &b"\xff\xd8\xffjpeg"[..])
+        );
+        assert_eq!(meta.get("----:iTunSMPB").as_deref(), Some("00000000"));
+        assert_eq!(
+            meta.get("----:com.apple.iTunes:iTunSMPB").as_deref(),
+            Some("00000000")
+        );
+        assert_eq!(meta.get("season").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn write_metadata() {
+        let data = moov(&ilst_items());
+        let mut mp4 = read(&data).unwrap();
+
+        // Writing back unchanged metadata keeps the size.
+        let mut meta = metadata(&mp4).unwrap();
+        set_metadata(&mut mp4, &meta).unwrap();
+        assert_eq!(write(&mp4).len(), data.len());
+
+        meta.set("title", "New title").unwrap();
+        meta.set("track", "4").unwrap();
+        meta.set("artist", "Artist").unwrap();
+        meta.set("----:com.example:key", "value").unwrap();
+        meta.remove("season").unwrap();
+        meta.cover = CoverArt::from_data(b"BMbmp".to_vec());
+        set_metadata(&mut mp4, &meta).unwrap();
+
+        let mp4 = read(&write(&mp4)).unwrap();
+        let meta = metadata(&mp4).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("New title"));
+        assert_eq!(meta.artist.as_deref(), Some("Artist"));
+        assert_eq!(
+            (meta.track, meta.season, meta.media_type),
+            (Some(4), None, Some(10))
+        );
+        assert_eq!(meta.cover.as_ref().map(|c| c.format), Some(CoverFormat::Bmp));
+        assert_eq!(meta.freeform.len(), 2);
+        assert_eq!(meta.get("----:com.example:key").as_deref(), Some("value"));
+
+        // The total in trkn, the second cover and the unknown item are kept.
+        let trkn = item_data(item(&mp4, b"trkn").unwrap()).unwrap();
+        assert_eq!(trkn, Some((0, vec![0, 0, 0, 4, 0, 12, 0, 0])));
+        let covr = item(&mp4, b"covr").unwrap();
+        assert_eq!(covr.data.as_ref().map(|d| d.flags), Some(27));
+        assert_eq!(covr.boxes.len(), 1);
+        let too = item_data(item(&mp4, b"\xa9too").unwrap()).unwrap();
+        assert_eq!(too, Some((1, b"Encoder".to_vec())));
+
+        // Removing the cover removes all of them.
+        let mut mp4 = mp4;
+        set_cover_art(&mut mp4, None).unwrap();
+        assert!(item(&mp4, b"covr").is_none());
+    }
+
+    #[test]
+    fn metadata_location() {
+        // No metadata yet, moov/udta/meta/ilst is created.
+        let mut mp4 = read(&bx(b"moov", &[])).unwrap();
+        assert!(metadata(&mp4).unwrap().title.is_none());
+        let mut meta = Metadata::default();
+        meta.set("genre", "Jazz").unwrap();
+        set_metadata(&mut mp4, &meta).unwrap();
+        let mp4 = read(&write(&mp4)).unwrap();
+        assert_eq!(metadata(&mp4).unwrap().genre.as_deref(), Some("Jazz"));
+        assert!(first_box!(&mp4.movie().boxes, UserDataBox).is_some());
+
+        // Metadata in moov/meta/ilst is written back there.
+        let ilst = bx(b"ilst", &bx(b"\xa9gen", &data(1, b"Blues")));
+        let mut mp4 = read(&bx(b"moov", &bx(b"meta", &[&[0; 4][..], &ilst].concat()))).unwrap();
+        assert_eq!(metadata(&mp4).unwrap().genre.as_deref(), Some("Blues"));
+        set_metadata(&mut mp4, &meta).unwrap();
+        let mp4 = read(&write(&mp4)).unwrap();
+        assert_eq!(metadata(&mp4).unwrap().genre.as_deref(), Some("Jazz"));
+        assert!(first_box!(&mp4.movie().boxes, UserDataBox).is_none());
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut meta = Metadata::default();
+        assert_eq!(
+            meta.set("track", "x").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            meta.set("foo", "x").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            meta.remove("foo").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(be_int(&[1, 2, 3]), None);
+        assert_eq!(be_int(&[0, 0, 0, 1, 0, 0, 0, 0]), None);
+
+        // An integer item with a value of the wrong size is ignored.
+        let mp4 = read(&moov(&[bx(b"tves", &data(21, &[0, 0, 7]))])).unwrap();
+        assert_eq!(metadata(&mp4).unwrap().episode, None);
+
+        // A data box that is larger than its item.
+        let mut items = ilst_items();
+        items[0][11] += 4;
+        assert!(read(&moov(&items)).is_err());
+    }
+}
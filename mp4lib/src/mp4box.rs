@@ -2,8 +2,9 @@
 use std::fmt::Debug;
 use std::io;
 
-use crate::boxes::{FileTypeBox, MovieBox};
+use crate::boxes::{FileTypeBox, MovieBox, MovieFragmentBox, TrackBox};
 use crate::io::DataRef;
+use crate::sample_info::{FragmentIndex, SampleInfoIterator};
 use crate::serialize::{BoxBytes, FromBytes, ReadBytes, ToBytes, WriteBytes};
 use crate::types::*;
 
@@ -329,6 +330,8 @@ pub struct MP4 {
     pub(crate) data_ref: DataRef,
     #[allow(dead_code)]
     pub(crate) input_file: Option<String>,
+    // Where the fragments start, to seek in the samples.
+    pub(crate) fragment_index: FragmentIndex,
 }
 
 impl Debug for MP4 {
@@ -361,6 +364,7 @@ impl MP4 {
             boxes,
             data_ref,
             input_file,
+            fragment_index: FragmentIndex::default(),
         };
         if validate {
             if !mp4.is_valid() {
@@ -395,6 +399,35 @@ impl MP4 {
         first_box_mut!(&mut self.boxes, FileTypeBox).unwrap()
     }
 
+    /// Return an iterator over the samples of a track.
+    ///
+    /// Unlike [`TrackBox::sample_info_iter`], this also returns the samples
+    /// that are in the MovieFragmentBoxes of a fragmented MP4 file.
+    pub fn sample_info_iter<'a>(&'a self, trak: &'a TrackBox) -> SampleInfoIterator<'a> {
+        SampleInfoIterator::with_fragments(self, trak)
+    }
+
+    /// The smallest composition offset of the samples of a track.
+    ///
+    /// Returns `None` if the track does not have composition offsets,
+    /// either in the SampleTableBox or in the MovieFragmentBoxes.
+    pub fn min_composition_offset(&self, trak: &TrackBox) -> Option<i32> {
+        let stbl = trak.media().media_info().sample_table();
+        let ctts = stbl.composition_time_to_sample();
+        if first_box!(&self.boxes, MovieFragmentBox).is_none() {
+            return ctts.and_then(|ctts| ctts.entries.iter().map(|e| e.offset).min());
+        }
+        let mut has_offsets = ctts.is_some();
+        let min = self
+            .sample_info_iter(trak)
+            .map(|sample| {
+                has_offsets |= sample.composition_delta != 0;
+                sample.composition_delta
+            })
+            .min();
+        min.filter(|_| has_offsets)
+    }
+
     /// Check if the structure of the file is valid and contains all
     /// the primary boxes.
     pub fn is_valid(&self) -> bool {
@@ -0,0 +1,1541 @@
+//! MPEG-2 Transport Stream demuxer.
+//!
+//! [`open`] reads a `.ts` file, like the recordings of a DVR, and builds
+//! an [`MP4`] from it, the same way [`matroska::open`](crate::matroska::open)
+//! does for Matroska files.
+//!
+//! Only the first program in the `PAT` is used. Supported are `H.264`,
+//! `HEVC`, `AAC` (`ADTS`), `AC-3` and `E-AC-3` streams, other streams
+//! are skipped. Video that precedes the first sync frame is dropped.
+//!
+//! In a transport stream the elementary streams are cut up into 188 byte
+//! packets, and video is in Annex-B format, so the sample tables cannot
+//! point into the file like they do for Matroska. The sample data is
+//! converted to the MP4 format and kept in memory instead, which means
+//! that an opened file takes about as much memory as it is large.
+//!
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::os::unix::fs::FileExt;
+
+use crate::bitreader::{unescape_rbsp, BitReader};
+use crate::boxes::*;
+use crate::demux::*;
+use crate::io::DataRef;
+use crate::mp4box::{MP4Box, MP4};
+
+const TS_PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PID_PAT: u16 = 0x0000;
+
+// PTS / DTS are 33 bits, at 90 kHz.
+const TIMESCALE: u32 = 90000;
+const TIMESTAMP_WRAP: i64 = 1 << 33;
+
+/// Does this file look like a transport stream.
+pub fn is_mpegts(path: &str) -> bool {
+    match fs::File::open(path) {
+        Ok(file) => packet_size(&file).is_some(),
+        Err(_) => false,
+    }
+}
+
+// Plain 188 byte packets, or 192 byte M2TS packets that have a
+// 4 byte timecode in front. Check for 3 sync bytes in a row.
+fn packet_size(file: &fs::File) -> Option<usize> {
+    let mut data = [0u8; 3 * 192];
+    file.read_exact_at(&mut data, 0).ok()?;
+    for &(size, offset) in &[(188, 0), (192, 4)] {
+        if (0..3).all(|n| data[offset + n * size] == SYNC_BYTE) {
+            return Some(size);
+        }
+    }
+    None
+}
+
+/// Open a transport stream and build an `MP4` from it.
+pub fn open(path: &str) -> io::Result<MP4> {
+    let file = fs::File::open(path)?;
+    let packet_size =
+        packet_size(&file).ok_or_else(|| ioerr!(InvalidData, "{}: not a transport stream", path))?;
+    let file_len = file.metadata()?.len();
+
+    let mut demuxer = Demuxer::new(file_len);
+    let mut reader = BufReader::with_capacity(1000 * packet_size, &file);
+    let mut packet = vec![0u8; packet_size];
+    let sync = packet_size - TS_PACKET_SIZE;
+    let mut filled = 0;
+    loop {
+        match reader.read_exact(&mut packet[filled..]) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        filled = 0;
+        // Lost sync, skip bytes until we find a sync byte.
+        if packet[sync] != SYNC_BYTE {
+            let pos = packet.iter().skip(sync + 1).position(|&b| b == SYNC_BYTE);
+            let skip = pos.map(|p| p + 1).unwrap_or(packet_size);
+            packet.drain(..skip);
+            packet.resize(packet_size, 0);
+            filled = packet_size - skip;
+            continue;
+        }
+        demuxer.packet(&packet[sync..]);
+    }
+    demuxer.flush();
+
+    // The stream that starts first starts at time 0.
+    let movie_start = demuxer
+        .streams
+        .iter()
+        .filter_map(|s| s.first_pts())
+        .min()
+        .unwrap_or(0);
+
+    let mut tracks = Vec::new();
+    for stream in &demuxer.streams {
+        let track = match stream.build_track(movie_start) {
+            Ok(Some(track)) => track,
+            Ok(None) => continue,
+            Err(e) => {
+                log::warn!("mpegts: {}: pid {}: {}", path, stream.pid, e);
+                continue;
+            },
+        };
+        tracks.push(track);
+    }
+
+    build_mp4(path, tracks, DataRef::with_memory(file, demuxer.mem.data)?)
+}
+
+// Sample data, addressed as if it follows right after the end of the file.
+pub(crate) struct Memory {
+    pub data: Vec<u8>,
+    pub file_len: u64,
+}
+
+impl Memory {
+    pub(crate) fn new(file_len: u64) -> Memory {
+        Memory {
+            data: Vec::new(),
+            file_len,
+        }
+    }
+
+    fn pos(&self) -> u64 {
+        self.file_len + self.data.len() as u64
+    }
+}
+
+struct Demuxer {
+    pmt_pid: Option<u16>,
+    streams: Vec<Stream>,
+    last_timestamp: Option<i64>,
+    mem: Memory,
+}
+
+impl Demuxer {
+    fn new(file_len: u64) -> Demuxer {
+        Demuxer {
+            pmt_pid: None,
+            streams: Vec::new(),
+            last_timestamp: None,
+            mem: Memory::new(file_len),
+        }
+    }
+
+    // ISO/IEC 13818-1 2.4.3.2 Transport Stream packet layer.
+    fn packet(&mut self, packet: &[u8]) {
+        if packet[1] & 0x80 != 0 {
+            // transport_error_indicator.
+            return;
+        }
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let adaptation_field_control = (packet[3] >> 4) & 0x03;
+        let continuity_counter = packet[3] & 0x0f;
+        if adaptation_field_control & 0x01 == 0 {
+            // No payload.
+            return;
+        }
+        let mut payload = &packet[4..];
+        if adaptation_field_control & 0x02 != 0 {
+            payload = payload.get(1 + payload[0] as usize..).unwrap_or(&[]);
+        }
+
+        if pid == PID_PAT {
+            if unit_start && self.pmt_pid.is_none() {
+                self.pat(payload);
+            }
+            return;
+        }
+        if Some(pid) == self.pmt_pid {
+            if unit_start && self.streams.is_empty() {
+                self.pmt(payload);
+            }
+            return;
+        }
+
+        let idx = match self.streams.iter().position(|s| s.pid == pid) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let stream = &mut self.streams[idx];
+        if let Some(cc) = stream.continuity_counter {
+            if continuity_counter == cc {
+                // Duplicate packet.
+                return;
+            }
+            // Packets were lost. If this is the start of a new PES packet,
+            // the previous one might be complete, otherwise drop it.
+            if continuity_counter != (cc + 1) & 0x0f && !unit_start && stream.pes.take().is_some() {
+                log::debug!("mpegts: pid {}: discontinuity, dropping PES packet", pid);
+            }
+        }
+        stream.continuity_counter = Some(continuity_counter);
+
+        if unit_start {
+            self.end_pes(idx);
+            self.streams[idx].pes = Some(payload.to_vec());
+        } else if let Some(pes) = self.streams[idx].pes.as_mut() {
+            pes.extend_from_slice(payload);
+        }
+    }
+
+    // Program Association Table. We use the first program.
+    fn pat(&mut self, payload: &[u8]) {
+        let section = match psi_section(payload, 0x00) {
+            Some(section) => section,
+            None => return,
+        };
+        for program in section.chunks_exact(4) {
+            let program_number = u16::from_be_bytes([program[0], program[1]]);
+            if program_number != 0 {
+                self.pmt_pid = Some(u16::from_be_bytes([program[2] & 0x1f, program[3]]));
+                break;
+            }
+        }
+    }
+
+    // Program Map Table.
+    fn pmt(&mut self, payload: &[u8]) {
+        let section = match psi_section(payload, 0x02) {
+            Some(section) if section.len() >= 4 => section,
+            _ => return,
+        };
+        let program_info_length = (u16::from_be_bytes([section[2], section[3]]) & 0x0fff) as usize;
+        let mut data = section.get(4 + program_info_length..).unwrap_or(&[]);
+
+        while data.len() >= 5 {
+            let stream_type = data[0];
+            let pid = u16::from_be_bytes([data[1] & 0x1f, data[2]]);
+            let es_info_length = (u16::from_be_bytes([data[3], data[4]]) & 0x0fff) as usize;
+            let descriptors = data.get(5..5 + es_info_length).unwrap_or(&[]);
+            data = data.get(5 + es_info_length..).unwrap_or(&[]);
+
+            let mut language = "und".to_string();
+            let mut registration = [0u8; 4];
+            let mut tags = Vec::new();
+            let mut d = descriptors;
+            while d.len() >= 2 && d.len() >= 2 + d[1] as usize {
+                let (tag, body) = (d[0], &d[2..2 + d[1] as usize]);
+                match tag {
+                    0x05 if body.len() >= 4 => registration.copy_from_slice(&body[..4]),
+                    0x0a if body.len() >= 3 => language = String::from_utf8_lossy(&body[..3]).to_string(),
+                    _ => {},
+                }
+                tags.push(tag);
+                d = &d[2 + body.len()..];
+            }
+
+            let codec = match stream_type {
+                0x1b => Codec::Avc,
+                0x24 => Codec::Hevc,
+                0x0f => Codec::Aac,
+                0x81 => Codec::Ac3,
+                0x87 => Codec::Ec3,
+                // Private data, DVB signals AC-3 and E-AC-3 with a descriptor.
+                0x06 if tags.contains(&0x7a) || &registration == b"EAC3" => Codec::Ec3,
+                0x06 if tags.contains(&0x6a) || &registration == b"AC-3" => Codec::Ac3,
+                _ => {
+                    log::debug!("mpegts: pid {}: unsupported stream type {:#x}", pid, stream_type);
+                    continue;
+                },
+            };
+            let track_id = self.streams.len() as u32 + 1;
+            self.streams.push(Stream::new(pid, track_id, codec, language));
+        }
+    }
+
+    // 2.4.3.6 PES packet.
+    fn end_pes(&mut self, idx: usize) {
+        let pes = match self.streams[idx].pes.take() {
+            Some(pes) => pes,
+            None => return,
+        };
+        if pes.len() < 9 || pes[..3] != [0, 0, 1] {
+            log::debug!("mpegts: pid {}: invalid PES packet", self.streams[idx].pid);
+            return;
+        }
+        let flags = pes[7];
+        let header_length = pes[8] as usize;
+        let length = u16::from_be_bytes([pes[4], pes[5]]) as usize;
+        let end = if length > 0 {
+            cmp::min(6 + length, pes.len())
+        } else {
+            pes.len()
+        };
+        if 9 + header_length > end {
+            return;
+        }
+
+        let mut timestamps = None;
+        if flags & 0x80 != 0 && header_length >= 5 {
+            let pts = self.unwrap(timestamp(&pes[9..14]));
+            let dts = match flags & 0x40 != 0 && header_length >= 10 {
+                true => self.unwrap(timestamp(&pes[14..19])),
+                false => pts,
+            };
+            timestamps = Some((pts, dts));
+        }
+
+        let stream = &mut self.streams[idx];
+        stream.push_data(&pes[9 + header_length..end], timestamps);
+        stream.process(&mut self.mem, false);
+    }
+
+    // Timestamps wrap around after ~26.5 hours. Make them continuous,
+    // relative to the timestamp we saw last.
+    fn unwrap(&mut self, ts: i64) -> i64 {
+        let ts = match self.last_timestamp {
+            Some(last) => {
+                let mut delta = (ts - last).rem_euclid(TIMESTAMP_WRAP);
+                if delta >= TIMESTAMP_WRAP / 2 {
+                    delta -= TIMESTAMP_WRAP;
+                }
+                last + delta
+            },
+            None => ts,
+        };
+        self.last_timestamp = Some(ts);
+        ts
+    }
+
+    // End of file.
+    fn flush(&mut self) {
+        for idx in 0..self.streams.len() {
+            self.end_pes(idx);
+            self.streams[idx].process(&mut self.mem, true);
+        }
+    }
+}
+
+// 2.4.4 Program specific information. Returns the part of the
+// section between the section header and the CRC.
+fn psi_section(payload: &[u8], table_id: u8) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    if section.len() < 3 || section[0] != table_id {
+        return None;
+    }
+    let length = (u16::from_be_bytes([section[1], section[2]]) & 0x0fff) as usize;
+    if length < 9 {
+        return None;
+    }
+    section.get(8..3 + length - 4)
+}
+
+// PTS or DTS, 2.4.3.7.
+fn timestamp(data: &[u8]) -> i64 {
+    ((data[0] as i64 >> 1) & 0x07) << 30
+        | (data[1] as i64) << 22
+        | (data[2] as i64 >> 1) << 15
+        | (data[3] as i64) << 7
+        | (data[4] as i64 >> 1)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Codec {
+    Avc,
+    Hevc,
+    Aac,
+    Ac3,
+    Ec3,
+}
+
+impl Codec {
+    fn is_video(self) -> bool {
+        self == Codec::Avc || self == Codec::Hevc
+    }
+}
+
+pub(crate) struct Stream {
+    pid: u16,
+    track_id: u32,
+    codec: Codec,
+    language: String,
+    // A raw elementary stream has no PES timestamps.
+    has_timestamps: bool,
+    continuity_counter: Option<u8>,
+    // PES packet being reassembled.
+    pes: Option<Vec<u8>>,
+    // Elementary stream data that has not been processed yet.
+    es: Vec<u8>,
+    // Position of `es` in the elementary stream.
+    es_pos: u64,
+    // Where to continue looking for a start code.
+    scan_pos: usize,
+    // Position in the elementary stream, PTS and DTS of PES packets.
+    timestamps: VecDeque<(u64, i64, i64)>,
+    pub frames: Vec<Frame>,
+    video: Video,
+    // Header of the first audio frame.
+    audio_header: Vec<u8>,
+}
+
+// A sample in `Memory`.
+pub(crate) struct Frame {
+    pub fpos: u64,
+    pub size: u32,
+    timestamps: Option<(i64, i64)>,
+    pub is_sync: bool,
+}
+
+// Video state: the access unit being collected, and the parameter sets.
+#[derive(Default)]
+struct Video {
+    au: AccessUnit,
+    // NAL units that follow a picture, but might belong to the next one.
+    pending: Vec<Vec<u8>>,
+    pending_pos: u64,
+    // Parameter sets seen before the first sync frame.
+    parameter_sets: Vec<Vec<u8>>,
+    slice_params: Option<SliceParams>,
+    config: Option<VideoConfig>,
+}
+
+#[derive(Default)]
+struct AccessUnit {
+    nals: Vec<Vec<u8>>,
+    timestamps: Option<(i64, i64)>,
+    has_vcl: bool,
+    // For a field picture, is it the bottom field.
+    field: Option<bool>,
+    paired: bool,
+}
+
+// What we need from the SPS to find the field_pic_flag in a slice header.
+struct SliceParams {
+    log2_max_frame_num: u8,
+    frame_mbs_only: bool,
+    separate_colour_plane: bool,
+}
+
+pub(crate) struct VideoConfig {
+    pub sample_entry: MP4Box,
+    // The parameter sets that are in the sample entry.
+    pub parameter_sets: Vec<Vec<u8>>,
+    pub display_width: u32,
+    pub display_height: u32,
+}
+
+// How a NAL unit fits in an access unit, ISO/IEC 14496-10 7.4.1.2.3
+// and ISO/IEC 23008-2 7.4.2.4.4.
+enum NalClass {
+    // Slice, `first` if it is the first slice of a picture.
+    Vcl { first: bool },
+    // Starts a new access unit if it follows a picture.
+    Prefix,
+    // Part of the current access unit.
+    Suffix,
+    Drop,
+}
+
+impl Stream {
+    fn new(pid: u16, track_id: u32, codec: Codec, language: String) -> Stream {
+        Stream {
+            pid,
+            track_id,
+            codec,
+            language,
+            has_timestamps: true,
+            continuity_counter: None,
+            pes: None,
+            es: Vec::new(),
+            es_pos: 0,
+            scan_pos: 0,
+            timestamps: VecDeque::new(),
+            frames: Vec::new(),
+            video: Video::default(),
+            audio_header: Vec::new(),
+        }
+    }
+
+    // A stream for a raw elementary stream (a `.h264` or `.aac` file).
+    pub(crate) fn elementary(track_id: u32, codec: Codec) -> Stream {
+        let mut stream = Stream::new(0, track_id, codec, "und".to_string());
+        stream.has_timestamps = false;
+        stream
+    }
+
+    pub(crate) fn push_data(&mut self, data: &[u8], timestamps: Option<(i64, i64)>) {
+        if let Some((pts, dts)) = timestamps {
+            let pos = self.es_pos + self.es.len() as u64;
+            self.timestamps.push_back((pos, pts, dts));
+        }
+        self.es.extend_from_slice(data);
+    }
+
+    // The timestamps of the last PES packet that starts at or before `pos`.
+    fn pop_timestamps(&mut self, pos: u64) -> Option<(i64, i64)> {
+        let mut timestamps = None;
+        while let Some(&(ts_pos, pts, dts)) = self.timestamps.front() {
+            if ts_pos > pos {
+                break;
+            }
+            timestamps = Some((pts, dts));
+            self.timestamps.pop_front();
+        }
+        timestamps
+    }
+
+    // Remove data from the start of `es`.
+    fn consume(&mut self, len: usize) {
+        self.es.drain(..len);
+        self.es_pos += len as u64;
+        self.scan_pos = self.scan_pos.saturating_sub(len);
+    }
+
+    pub(crate) fn process(&mut self, mem: &mut Memory, eof: bool) {
+        match self.codec {
+            Codec::Avc | Codec::Hevc => self.video_nals(mem, eof),
+            _ => self.audio_frames(mem, eof),
+        }
+    }
+
+    // Split the Annex-B byte stream into NAL units. A NAL unit is
+    // complete when it is followed by a start code, or at the end.
+    fn video_nals(&mut self, mem: &mut Memory, eof: bool) {
+        // Without a start code, keep the last 2 bytes, they might be the
+        // start of one.
+        let mut start = find_start_code(&self.es, 0).unwrap_or_else(|| self.es.len().saturating_sub(2));
+        while start < self.es.len() {
+            let next = find_start_code(&self.es, cmp::max(start + 3, self.scan_pos));
+            let end = match next {
+                Some(next) => next,
+                None if eof => self.es.len(),
+                None => {
+                    self.scan_pos = self.es.len().saturating_sub(2);
+                    break;
+                },
+            };
+            let mut nal_end = end;
+            while nal_end > start + 3 && self.es[nal_end - 1] == 0 {
+                nal_end -= 1;
+            }
+            let nal = self.es[start + 3..nal_end].to_vec();
+            let pos = self.es_pos + start as u64;
+            if !nal.is_empty() {
+                self.video_nal(nal, pos, mem);
+            }
+            start = end;
+            if next.is_none() {
+                break;
+            }
+        }
+        self.consume(start);
+
+        if eof {
+            let pending = std::mem::take(&mut self.video.pending);
+            self.video.au.nals.extend(pending);
+            self.finish_access_unit(mem);
+        }
+    }
+
+    fn video_nal(&mut self, nal: Vec<u8>, pos: u64, mem: &mut Memory) {
+        let class = match self.codec {
+            Codec::Avc => avc_nal_class(&nal),
+            _ => hevc_nal_class(&nal),
+        };
+        match class {
+            NalClass::Drop => {},
+            NalClass::Prefix => {
+                if self.video.config.is_none() && self.is_parameter_set(&nal) {
+                    self.parameter_set(&nal);
+                }
+                if self.video.au.has_vcl {
+                    if self.video.pending.is_empty() {
+                        self.video.pending_pos = pos;
+                    }
+                    self.video.pending.push(nal);
+                } else {
+                    if self.video.au.nals.is_empty() {
+                        self.video.au.timestamps = self.pop_timestamps(pos);
+                    }
+                    self.video.au.nals.push(nal);
+                }
+            },
+            NalClass::Suffix => match self.video.pending.is_empty() {
+                true => self.video.au.nals.push(nal),
+                false => self.video.pending.push(nal),
+            },
+            NalClass::Vcl { first } => {
+                let field = match (self.codec, self.video.slice_params.as_ref()) {
+                    (Codec::Avc, Some(params)) => avc_field(&nal, params),
+                    _ => None,
+                };
+                let pending_pos = match self.video.pending.is_empty() {
+                    true => pos,
+                    false => self.video.pending_pos,
+                };
+                // A slice that follows prefix NAL units, or that is the first
+                // slice of a picture, starts a new access unit.
+                if self.video.au.has_vcl && (first || !self.video.pending.is_empty()) {
+                    let au = &self.video.au;
+                    let second_field = match (au.field, field) {
+                        (Some(bottom1), Some(bottom2)) => bottom1 != bottom2 && !au.paired,
+                        _ => false,
+                    };
+                    if second_field {
+                        // The second field of a frame goes in the same sample.
+                        self.pop_timestamps(pending_pos);
+                        self.video.au.paired = true;
+                    } else {
+                        self.finish_access_unit(mem);
+                        self.video.au.timestamps = self.pop_timestamps(pending_pos);
+                        self.video.au.field = field;
+                    }
+                } else if !self.video.au.has_vcl {
+                    if self.video.au.nals.is_empty() {
+                        self.video.au.timestamps = self.pop_timestamps(pos);
+                    }
+                    self.video.au.field = field;
+                }
+                let pending = std::mem::take(&mut self.video.pending);
+                self.video.au.nals.extend(pending);
+                self.video.au.nals.push(nal);
+                self.video.au.has_vcl = true;
+            },
+        }
+    }
+
+    fn nal_type(&self, nal: &[u8]) -> u8 {
+        match self.codec {
+            Codec::Avc => nal[0] & 0x1f,
+            _ => (nal[0] >> 1) & 0x3f,
+        }
+    }
+
+    fn is_parameter_set(&self, nal: &[u8]) -> bool {
+        let nal_type = self.nal_type(nal);
+        match self.codec {
+            Codec::Avc => nal_type == 7 || nal_type == 8,
+            _ => (32..=34).contains(&nal_type),
+        }
+    }
+
+    fn is_access_unit_delimiter(&self, nal: &[u8]) -> bool {
+        let nal_type = self.nal_type(nal);
+        match self.codec {
+            Codec::Avc => nal_type == 9,
+            _ => nal_type == 35,
+        }
+    }
+
+    fn parameter_set(&mut self, nal: &[u8]) {
+        if self.codec == Codec::Avc && self.nal_type(nal) == 7 {
+            match avc_sps_info(nal) {
+                Some(info) => self.video.slice_params = Some(info.slice_params),
+                None => log::debug!("mpegts: pid {}: cannot parse SPS", self.pid),
+            }
+        }
+        if !self.video.parameter_sets.iter().any(|p| p[..] == nal[..]) {
+            self.video.parameter_sets.push(nal.to_vec());
+        }
+    }
+
+    // Done collecting an access unit, write it to memory as a sample.
+    fn finish_access_unit(&mut self, mem: &mut Memory) {
+        let au = std::mem::take(&mut self.video.au);
+        if !au.has_vcl {
+            return;
+        }
+        let is_sync = match self.codec {
+            Codec::Avc => au.nals.iter().any(|nal| {
+                let nal_type = nal[0] & 0x1f;
+                nal_type == 5 || (nal_type == 6 && sei_has_recovery_point(nal))
+            }),
+            _ => au
+                .nals
+                .iter()
+                .any(|nal| (16..=23).contains(&((nal[0] >> 1) & 0x3f))),
+        };
+
+        // Skip everything before the first sync frame.
+        if self.video.config.is_none() {
+            if !is_sync || (au.timestamps.is_none() && self.has_timestamps) {
+                return;
+            }
+            let config = match self.codec {
+                Codec::Avc => avc_config(&self.video.parameter_sets),
+                _ => hevc_config(&self.video.parameter_sets),
+            };
+            match config {
+                Ok(config) => self.video.config = Some(config),
+                Err(e) => {
+                    log::debug!("mpegts: pid {}: {}", self.pid, e);
+                    return;
+                },
+            }
+        }
+        let config = self.video.config.as_ref().unwrap();
+
+        let fpos = mem.pos();
+        for nal in &au.nals {
+            if self.is_access_unit_delimiter(nal) || config.parameter_sets.contains(nal) {
+                continue;
+            }
+            mem.data.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            mem.data.extend_from_slice(nal);
+        }
+        self.frames.push(Frame {
+            fpos,
+            size: (mem.pos() - fpos) as u32,
+            timestamps: au.timestamps,
+            is_sync,
+        });
+    }
+
+    // Split ADTS, AC-3 or E-AC-3 data into frames.
+    fn audio_frames(&mut self, mem: &mut Memory, eof: bool) {
+        let mut pos = 0;
+        while pos < self.es.len() {
+            let data = &self.es[pos..];
+            let (header_size, frame_size) = match audio_frame_size(self.codec, data) {
+                Ok(Some(sizes)) => sizes,
+                // Need more data.
+                Ok(None) => break,
+                // No sync. Skip a byte.
+                Err(_) => {
+                    pos += 1;
+                    continue;
+                },
+            };
+            if data.len() < frame_size {
+                break;
+            }
+
+            let frame_pos = self.es_pos + pos as u64;
+            let timestamps = self.pop_timestamps(frame_pos);
+            let data = &self.es[pos..pos + frame_size];
+            pos += frame_size;
+
+            // The first frame must have a timestamp.
+            if self.frames.is_empty() {
+                if timestamps.is_none() && self.has_timestamps {
+                    continue;
+                }
+                self.audio_header = data[..cmp::min(data.len(), 16)].to_vec();
+            }
+
+            // E-AC-3 dependent substreams go in the same sample as the
+            // independent substream before them.
+            if self.codec == Codec::Ec3 && eac3_header(data).map(|h| h.strmtyp == 1).unwrap_or(false) {
+                match self.frames.last_mut() {
+                    Some(last) if last.fpos + last.size as u64 == mem.pos() => {
+                        mem.data.extend_from_slice(data);
+                        last.size += data.len() as u32;
+                    },
+                    _ => {},
+                }
+                continue;
+            }
+
+            let fpos = mem.pos();
+            mem.data.extend_from_slice(&data[header_size..]);
+            self.frames.push(Frame {
+                fpos,
+                size: (frame_size - header_size) as u32,
+                timestamps,
+                is_sync: true,
+            });
+        }
+        if eof {
+            pos = self.es.len();
+        }
+        self.consume(pos);
+    }
+
+    // Video decoder configuration, known after the first sync frame.
+    pub(crate) fn video_config(&self) -> Option<&VideoConfig> {
+        self.video.config.as_ref()
+    }
+
+    // Presentation time of the first sample.
+    fn first_pts(&self) -> Option<i64> {
+        match self.codec.is_video() {
+            true => self.frames.iter().filter_map(|f| f.timestamps.map(|t| t.0)).min(),
+            false => self.frames.first().and_then(|f| f.timestamps.map(|t| t.0)),
+        }
+    }
+
+    fn build_track(&self, movie_start: i64) -> io::Result<Option<TrackBox>> {
+        if self.frames.is_empty() {
+            return Ok(None);
+        }
+        let mut params = TrackParams {
+            track_id: self.track_id,
+            kind: TrackKind::Audio,
+            enabled: true,
+            language: self.language.clone(),
+            name: None,
+            display_width: 0,
+            display_height: 0,
+        };
+        let (samples, sample_entry) = match self.video.config.as_ref() {
+            Some(config) => {
+                params.kind = TrackKind::Video;
+                params.display_width = config.display_width;
+                params.display_height = config.display_height;
+                (self.video_samples(movie_start), config.sample_entry.clone())
+            },
+            None => self.audio_samples(movie_start)?,
+        };
+        Ok(Some(build_track(&params, samples, sample_entry)))
+    }
+
+    fn video_samples(&self, movie_start: i64) -> Samples {
+        // Fill in the timestamps of frames that did not have them.
+        let mut dts = Vec::with_capacity(self.frames.len());
+        let mut pts = Vec::with_capacity(self.frames.len());
+        let mut last = 0;
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let (p, d) = match frame.timestamps {
+                Some(ts) => ts,
+                None => {
+                    let next = self.frames[idx..]
+                        .iter()
+                        .enumerate()
+                        .find_map(|(n, f)| f.timestamps.map(|ts| (n, ts.1)));
+                    let d = match next {
+                        Some((n, next_dts)) => {
+                            dts[last] + (next_dts - dts[last]) * (idx - last) as i64 / (idx + n - last) as i64
+                        },
+                        None if last > 0 => dts[idx - 1] + (dts[last] - dts[last - 1]),
+                        None => dts[idx - 1] + 3600,
+                    };
+                    (d + pts[last] - dts[last], d)
+                },
+            };
+            if frame.timestamps.is_some() {
+                last = idx;
+            }
+            // Decode timestamps must go up.
+            let d = match dts.last() {
+                Some(&prev) if d <= prev => prev + 1,
+                _ => d,
+            };
+            dts.push(d);
+            pts.push(p);
+        }
+
+        // Composition offsets can't be negative.
+        let delay = pts
+            .iter()
+            .zip(dts.iter())
+            .map(|(p, d)| d - p)
+            .max()
+            .unwrap_or(0)
+            .max(0);
+        let min_pts = pts.iter().cloned().min().unwrap_or(0);
+
+        let mut entries = Vec::new();
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let duration = match dts.get(idx + 1) {
+                Some(next) => next - dts[idx],
+                None if idx > 0 => dts[idx] - dts[idx - 1],
+                None => 3600,
+            };
+            entries.push(Sample {
+                fpos: frame.fpos,
+                size: frame.size,
+                duration: duration as u32,
+                composition_offset: (pts[idx] - dts[idx] + delay) as i32,
+                is_sync: frame.is_sync,
+            });
+        }
+
+        Samples {
+            timescale: TIMESCALE,
+            entries,
+            start: cmp::max(min_pts - movie_start, 0) as u64,
+            shift: (min_pts - dts[0] + delay) as i32,
+        }
+    }
+
+    pub(crate) fn audio_samples(&self, movie_start: i64) -> io::Result<(Samples, MP4Box)> {
+        let hdr = &self.audio_header;
+        let (sample_rate, frame_duration, sample_entry) = match self.codec {
+            Codec::Aac => {
+                let profile = hdr[2] >> 6;
+                let rate_index = (hdr[2] >> 2) & 0x0f;
+                let channels = ((hdr[2] & 0x01) << 2) | (hdr[3] >> 6);
+                let sample_rate = *AAC_SAMPLE_RATES
+                    .get(rate_index as usize)
+                    .ok_or_else(|| ioerr!(InvalidData, "ADTS: invalid sampling frequency index"))?;
+                let asc = audio_specific_config(profile + 1, rate_index, channels);
+                let entry = aac_sample_entry(self.track_id as u16, channels as u16, 16, sample_rate, &asc)?;
+                (sample_rate, 1024, entry)
+            },
+            Codec::Ac3 => {
+                let hdr = ac3_header(hdr)?;
+                (hdr.sample_rate, 1536, ac3_sample_entry(&hdr))
+            },
+            _ => {
+                let hdr = eac3_header(hdr)?;
+                if hdr.sample_rate == 0 {
+                    return Err(ioerr!(InvalidData, "E-AC-3: invalid sample rate"));
+                }
+                (hdr.sample_rate, hdr.num_blocks * 256, eac3_sample_entry(&hdr))
+            },
+        };
+
+        // Every frame has a fixed duration, but the stream can have gaps (dropped
+        // packets, discontinuities). Keep track of where we are, and if the next PES
+        // packet starts more than half a frame later than expected, stretch the
+        // duration of the current frame to close the gap.
+        let first_pts = self.first_pts().unwrap_or(movie_start);
+        let ticks = |pts: i64| (pts - first_pts) * sample_rate as i64 / TIMESCALE as i64;
+        let mut pos = 0;
+        let mut entries = Vec::with_capacity(self.frames.len());
+        for (idx, frame) in self.frames.iter().enumerate() {
+            let mut duration = frame_duration as i64;
+            if let Some((pts, _)) = self.frames.get(idx + 1).and_then(|f| f.timestamps) {
+                let gap = ticks(pts) - (pos + duration);
+                if gap > duration / 2 {
+                    duration += gap;
+                }
+            }
+            pos += duration;
+            entries.push(Sample {
+                fpos: frame.fpos,
+                size: frame.size,
+                duration: cmp::min(duration, u32::MAX as i64) as u32,
+                composition_offset: 0,
+                is_sync: true,
+            });
+        }
+
+        let start = (first_pts - movie_start) * sample_rate as i64 / TIMESCALE as i64;
+        let samples = Samples {
+            timescale: sample_rate,
+            entries,
+            start: cmp::max(start, 0) as u64,
+            shift: 0,
+        };
+        Ok((samples, sample_entry))
+    }
+}
+
+// Position of the next 00 00 01 start code.
+fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    let mut idx = from;
+    while idx + 2 < data.len() {
+        if data[idx + 2] > 1 {
+            idx += 3;
+        } else if data[idx + 2] == 1 && data[idx + 1] == 0 && data[idx] == 0 {
+            return Some(idx);
+        } else {
+            idx += 1;
+        }
+    }
+    None
+}
+
+fn avc_nal_class(nal: &[u8]) -> NalClass {
+    match nal[0] & 0x1f {
+        // first_mb_in_slice == 0.
+        1..=5 => NalClass::Vcl {
+            first: nal.len() > 1 && nal[1] & 0x80 != 0,
+        },
+        6..=9 | 14..=18 => NalClass::Prefix,
+        12 => NalClass::Drop,
+        _ => NalClass::Suffix,
+    }
+}
+
+fn hevc_nal_class(nal: &[u8]) -> NalClass {
+    if nal.len() < 2 {
+        return NalClass::Drop;
+    }
+    match (nal[0] >> 1) & 0x3f {
+        // first_slice_segment_in_pic_flag.
+        0..=31 => NalClass::Vcl {
+            first: nal.len() > 2 && nal[2] & 0x80 != 0,
+        },
+        32..=35 | 39 | 41..=44 | 48..=55 => NalClass::Prefix,
+        38 => NalClass::Drop,
+        _ => NalClass::Suffix,
+    }
+}
+
+// The field_pic_flag and bottom_field_flag from a slice header (7.3.3).
+// Returns `None` for a frame, `Some(bottom_field_flag)` for a field.
+fn avc_field(nal: &[u8], params: &SliceParams) -> Option<bool> {
+    if params.frame_mbs_only {
+        return None;
+    }
+    let rbsp = unescape_rbsp(&nal[1..cmp::min(nal.len(), 32)]);
+    let mut reader = BitReader::new(&rbsp);
+    reader.read_ue().ok()?; // first_mb_in_slice
+    reader.read_ue().ok()?; // slice_type
+    reader.read_ue().ok()?; // pic_parameter_set_id
+    if params.separate_colour_plane {
+        reader.read_bits(2).ok()?; // colour_plane_id
+    }
+    reader.read_bits(params.log2_max_frame_num).ok()?; // frame_num
+    match reader.read_bit().ok()? {
+        true => reader.read_bit().ok(),
+        false => None,
+    }
+}
+
+// Does this SEI NAL unit have a recovery point message (D.1.8).
+fn sei_has_recovery_point(nal: &[u8]) -> bool {
+    let rbsp = unescape_rbsp(&nal[1..]);
+    let mut data = &rbsp[..];
+    let read_value = |data: &mut &[u8]| -> Option<usize> {
+        let mut value = 0;
+        loop {
+            let (&b, rest) = data.split_first()?;
+            *data = rest;
+            value += b as usize;
+            if b != 0xff {
+                return Some(value);
+            }
+        }
+    };
+    while !data.is_empty() && data[0] != 0x80 {
+        let payload_type = match read_value(&mut data) {
+            Some(t) => t,
+            None => break,
+        };
+        if payload_type == 6 {
+            return true;
+        }
+        let payload_size = match read_value(&mut data) {
+            Some(s) => s,
+            None => break,
+        };
+        data = data.get(payload_size..).unwrap_or(&[]);
+    }
+    false
+}
+
+// Header size and frame size of the audio frame at the start of `data`.
+// Returns `Ok(None)` if there is not enough data to tell.
+fn audio_frame_size(codec: Codec, data: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    if codec == Codec::Aac {
+        // ISO/IEC 13818-7 6.2 Audio Data Transport Stream.
+        if data.len() < 7 {
+            return Ok(None);
+        }
+        if data[0] != 0xff || data[1] & 0xf6 != 0xf0 {
+            return Err(ioerr!(InvalidData, "no ADTS syncword"));
+        }
+        let header_size = if data[1] & 0x01 != 0 { 7 } else { 9 };
+        let frame_size =
+            ((data[3] as usize & 0x03) << 11) | (data[4] as usize) << 3 | (data[5] as usize) >> 5;
+        if frame_size <= header_size {
+            return Err(ioerr!(InvalidData, "invalid ADTS frame length"));
+        }
+        return Ok(Some((header_size, frame_size)));
+    }
+
+    if data.len() < 16 {
+        return Ok(None);
+    }
+    let hdr = match codec {
+        Codec::Ac3 => ac3_header(data)?,
+        _ => eac3_header(data)?,
+    };
+    if hdr.frame_size < 8 {
+        return Err(ioerr!(InvalidData, "invalid AC-3 frame size"));
+    }
+    Ok(Some((0, hdr.frame_size as usize)))
+}
+
+// Parameter set NAL units as an array for a decoder configuration record.
+fn push_nals(config: &mut Vec<u8>, nals: &[&Vec<u8>]) {
+    for nal in nals {
+        config.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        config.extend_from_slice(nal);
+    }
+}
+
+// What we need from an H.264 SPS.
+struct SpsInfo {
+    width: u32,
+    height: u32,
+    sample_aspect_ratio: Option<(u16, u16)>,
+    slice_params: SliceParams,
+}
+
+// Decode an H.264 SPS, by wrapping it in an avcC box.
+fn avc_sps_info(sps: &[u8]) -> Option<SpsInfo> {
+    let config = avc_config_record(&[&sps.to_vec()], &[]);
+    let avcc = match config_box("avcC", &config).ok()? {
+        MP4Box::AvcConfigurationBox(avcc) => avcc,
+        _ => return None,
+    };
+    let sps = avcc.configuration.seq_parameter_set()?;
+    let separate_colour_plane = sps
+        .chroma_format
+        .as_ref()
+        .map(|c| c.chroma_format_idc == 3 && c.residual_color_transform_flag == Some(true))
+        .unwrap_or(false);
+    Some(SpsInfo {
+        width: sps.width(),
+        height: sps.height(),
+        sample_aspect_ratio: sps
+            .vui_parameters
+            .as_ref()
+            .and_then(|v| v.aspect_ratio_info.as_ref())
+            .and_then(|a| a.sample_aspect_ratio()),
+        slice_params: SliceParams {
+            log2_max_frame_num: sps.log2_max_frame_num_minus4 + 4,
+            frame_mbs_only: matches!(sps.frame_mbs_flags, FrameMbsFlags::Frames),
+            separate_colour_plane,
+        },
+    })
+}
+
+// ISO/IEC 14496-15 5.3.3.1 AVCDecoderConfigurationRecord.
+fn avc_config_record(sps: &[&Vec<u8>], pps: &[&Vec<u8>]) -> Vec<u8> {
+    let first = &sps[0];
+    let mut config = vec![1, first[1], first[2], first[3], 0xff, 0xe0 | sps.len() as u8];
+    push_nals(&mut config, sps);
+    config.push(pps.len() as u8);
+    push_nals(&mut config, pps);
+    config
+}
+
+fn avc_config(parameter_sets: &[Vec<u8>]) -> io::Result<VideoConfig> {
+    let sps: Vec<_> = parameter_sets
+        .iter()
+        .filter(|p| p[0] & 0x1f == 7 && p.len() >= 4)
+        .collect();
+    let pps: Vec<_> = parameter_sets.iter().filter(|p| p[0] & 0x1f == 8).collect();
+    if sps.is_empty() || pps.is_empty() {
+        return Err(ioerr!(InvalidData, "no SPS / PPS before the first sync frame"));
+    }
+    let info = avc_sps_info(sps[0]).ok_or_else(|| ioerr!(InvalidData, "cannot parse SPS"))?;
+    let config = avc_config_record(&sps, &pps);
+
+    let (h, v) = info.sample_aspect_ratio.unwrap_or((1, 1));
+    let sample_entry =
+        video_sample_entry("avcC", &config, info.width, info.height, pasp(h as u64, v as u64))?;
+    Ok(VideoConfig {
+        sample_entry,
+        parameter_sets: sps.into_iter().chain(pps).cloned().collect(),
+        display_width: info.width * h as u32 / v as u32,
+        display_height: info.height,
+    })
+}
+
+// ISO/IEC 14496-15 8.3.3.1 HEVCDecoderConfigurationRecord.
+fn hevc_config(parameter_sets: &[Vec<u8>]) -> io::Result<VideoConfig> {
+    let nals = |nal_type: u8| {
+        parameter_sets
+            .iter()
+            .filter(|p| p.len() > 2 && (p[0] >> 1) & 0x3f == nal_type)
+            .collect::<Vec<_>>()
+    };
+    let (vps, sps, pps) = (nals(32), nals(33), nals(34));
+    if vps.is_empty() || sps.is_empty() || pps.is_empty() {
+        return Err(ioerr!(
+            InvalidData,
+            "no VPS / SPS / PPS before the first sync frame"
+        ));
+    }
+
+    // The general profile_tier_level() follows the first byte of the SPS,
+    // and it has the same layout as in the configuration record.
+    let rbsp = unescape_rbsp(&sps[0][2..]);
+    if rbsp.len() < 13 {
+        return Err(ioerr!(InvalidData, "SPS too short"));
+    }
+    let max_sub_layers = ((rbsp[0] >> 1) & 0x07) + 1;
+    let temporal_id_nested = rbsp[0] & 0x01;
+
+    let mut config = vec![1];
+    config.extend_from_slice(&rbsp[1..13]);
+    config.extend_from_slice(&[0xf0, 0x00, 0xfc, 0xfd, 0xf8, 0xf8, 0, 0]);
+    config.push((max_sub_layers << 3) | (temporal_id_nested << 2) | 0x03);
+    config.push(3);
+    for (nal_type, nals) in [(32u8, &vps), (33, &sps), (34, &pps)].iter() {
+        config.push(0x80 | nal_type);
+        config.extend_from_slice(&(nals.len() as u16).to_be_bytes());
+        push_nals(&mut config, nals);
+    }
+
+    // Now decode the SPS, and fill in the chroma format and bit depth.
+    let hvcc = match config_box("hvcC", &config)? {
+        MP4Box::HEVCConfigurationBox(hvcc) => hvcc,
+        _ => return Err(ioerr!(InvalidData, "cannot parse hvcC")),
+    };
+    let info = hvcc
+        .configuration
+        .seq_parameter_set()
+        .ok_or_else(|| ioerr!(InvalidData, "cannot parse SPS"))?;
+    config[16] = 0xfc | info.chroma_format_idc as u8;
+    config[17] = 0xf8 | info.bit_depth_luma_minus8 as u8;
+    config[18] = 0xf8 | info.bit_depth_chroma_minus8 as u8;
+
+    let sar = info
+        .vui_parameters
+        .as_ref()
+        .and_then(|v| v.aspect_ratio_info.as_ref())
+        .and_then(|a| a.sample_aspect_ratio());
+    let (h, v) = sar.unwrap_or((1, 1));
+    let (width, height) = (info.width(), info.height());
+    let sample_entry = video_sample_entry("hvcC", &config, width, height, pasp(h as u64, v as u64))?;
+    Ok(VideoConfig {
+        sample_entry,
+        parameter_sets: vps.into_iter().chain(sps).chain(pps).cloned().collect(),
+        display_width: width * h as u32 / v as u32,
+        display_height: height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::{escape_rbsp, BitWriter};
+    use crate::sample_info::SampleInfoIterator;
+
+    const PID_PMT: u16 = 0x100;
+    const PID_VIDEO: u16 = 0x101;
+    const PID_AUDIO: u16 = 0x102;
+
+    // Transport stream packet, padded with an adaptation field.
+    fn packet(pid: u16, unit_start: bool, cc: u8, payload: &[u8]) -> Vec<u8> {
+        let mut p = vec![SYNC_BYTE, (unit_start as u8) << 6 | (pid >> 8) as u8, pid as u8];
+        if payload.len() < 184 {
+            p.push(0x30 | cc);
+            p.push((183 - payload.len()) as u8);
+            if payload.len() < 183 {
+                p.push(0x00);
+                p.resize(4 + 184 - payload.len(), 0xff);
+            }
+        } else {
+            p.push(0x10 | cc);
+        }
+        p.extend_from_slice(payload);
+        p
+    }
+
+    // PSI section with a dummy CRC.
+    fn section(table_id: u8, id: u16, body: &[u8]) -> Vec<u8> {
+        let mut s = vec![0, table_id];
+        s.extend_from_slice(&(0xb000 | (body.len() + 9) as u16).to_be_bytes());
+        s.extend_from_slice(&id.to_be_bytes());
+        s.extend_from_slice(&[0xc1, 0, 0]);
+        s.extend_from_slice(body);
+        s.extend_from_slice(&[0; 4]);
+        s
+    }
+
+    fn psi() -> Vec<Vec<u8>> {
+        let pat = section(0x00, 1, &[0, 1, 0xe0 | (PID_PMT >> 8) as u8, PID_PMT as u8]);
+        let mut pmt = vec![0xe0 | (PID_VIDEO >> 8) as u8, PID_VIDEO as u8, 0xf0, 0];
+        pmt.extend_from_slice(&[0x1b, 0xe0 | (PID_VIDEO >> 8) as u8, PID_VIDEO as u8, 0xf0, 0]);
+        // AAC, with an ISO 639 language descriptor.
+        pmt.extend_from_slice(&[0x0f, 0xe0 | (PID_AUDIO >> 8) as u8, PID_AUDIO as u8, 0xf0, 6]);
+        pmt.extend_from_slice(&[0x0a, 4, b'n', b'l', b'd', 0]);
+        // MPEG-2 video, not supported.
+        pmt.extend_from_slice(&[0x02, 0xe1, 0x03, 0xf0, 0]);
+        vec![
+            packet(PID_PAT, true, 0, &pat),
+            packet(PID_PMT, true, 0, &section(0x02, 1, &pmt)),
+        ]
+    }
+
+    fn pts_bytes(marker: u8, ts: i64) -> [u8; 5] {
+        [
+            marker << 4 | ((ts >> 29) & 0x0e) as u8 | 1,
+            (ts >> 22) as u8,
+            ((ts >> 14) & 0xfe) as u8 | 1,
+            (ts >> 7) as u8,
+            ((ts << 1) & 0xfe) as u8 | 1,
+        ]
+    }
+
+    // PES packet, with PTS and optionally DTS.
+    fn pes(stream_id: u8, pts: i64, dts: Option<i64>, data: &[u8]) -> Vec<u8> {
+        let mut header = match dts {
+            Some(dts) => [pts_bytes(3, pts), pts_bytes(1, dts)].concat(),
+            None => pts_bytes(2, pts).to_vec(),
+        };
+        let flags = if dts.is_some() { 0xc0 } else { 0x80 };
+        header.insert(0, header.len() as u8);
+        let length = match stream_id {
+            0xe0 => 0,
+            _ => 2 + header.len() + data.len(),
+        };
+        let mut p = vec![0, 0, 1, stream_id];
+        p.extend_from_slice(&(length as u16).to_be_bytes());
+        p.extend_from_slice(&[0x80, flags]);
+        p.extend_from_slice(&header);
+        p.extend_from_slice(data);
+        p
+    }
+
+    // Cut a PES packet up into transport stream packets.
+    fn packets(pid: u16, cc: &mut u8, pes: &[u8]) -> Vec<Vec<u8>> {
+        let mut res = Vec::new();
+        for (idx, chunk) in pes.chunks(184).enumerate() {
+            res.push(packet(pid, idx == 0, *cc, chunk));
+            *cc = (*cc + 1) & 0x0f;
+        }
+        res
+    }
+
+    // ADTS frame, AAC-LC 48 kHz stereo, payload bytes with value `n`.
+    fn adts(n: u8, payload_len: usize) -> Vec<u8> {
+        let len = payload_len + 7;
+        let mut f = vec![
+            0xff,
+            0xf1,
+            0x4c,
+            0x80 | (len >> 11) as u8,
+            (len >> 3) as u8,
+            (len << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        f.extend(vec![n; payload_len]);
+        f
+    }
+
+    // AAC PES packets, each with two frames of 200 bytes, 3840 ticks apart.
+    fn audio_pes(count: u8) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|n| {
+                let data = [adts(2 * n, 200), adts(2 * n + 1, 200)].concat();
+                pes(0xc0, 3840 * n as i64, None, &data)
+            })
+            .collect()
+    }
+
+    fn demux(packets: &[Vec<u8>]) -> Demuxer {
+        let mut demuxer = Demuxer::new(0);
+        for packet in packets {
+            demuxer.packet(packet);
+        }
+        demuxer.flush();
+        demuxer
+    }
+
+    // Payloads of the frames in memory, checked to consist of one value.
+    fn frame_values(demuxer: &Demuxer, idx: usize) -> Vec<u8> {
+        let data = &demuxer.mem.data;
+        let frames = &demuxer.streams[idx].frames;
+        frames
+            .iter()
+            .map(|f| {
+                let frame = &data[f.fpos as usize..(f.fpos + f.size as u64) as usize];
+                assert!(frame.iter().all(|&b| b == frame[0]));
+                frame[0]
+            })
+            .collect()
+    }
+
+    fn durations(stream: &Stream) -> Vec<u32> {
+        let (samples, _) = stream.audio_samples(0).unwrap();
+        samples.entries.iter().map(|s| s.duration).collect()
+    }
+
+    #[test]
+    fn pes_timestamp() {
+        let ts = (1 << 32) + 123_456_789;
+        assert_eq!(timestamp(&pts_bytes(2, ts)), ts);
+
+        // Wraps around after 33 bits.
+        let mut demuxer = Demuxer::new(0);
+        assert_eq!(demuxer.unwrap(TIMESTAMP_WRAP - 100), TIMESTAMP_WRAP - 100);
+        assert_eq!(demuxer.unwrap(50), TIMESTAMP_WRAP + 50);
+        assert_eq!(demuxer.unwrap(TIMESTAMP_WRAP - 200), TIMESTAMP_WRAP - 200);
+    }
+
+    #[test]
+    fn program_map_table() {
+        let demuxer = demux(&psi());
+        assert_eq!(demuxer.pmt_pid, Some(PID_PMT));
+        let streams: Vec<_> = demuxer.streams.iter().map(|s| (s.pid, s.codec)).collect();
+        assert_eq!(streams, [(PID_VIDEO, Codec::Avc), (PID_AUDIO, Codec::Aac)]);
+        assert_eq!(demuxer.streams[1].language, "nld");
+    }
+
+    #[test]
+    fn pes_reassembly() {
+        let mut ts = psi();
+        let mut cc = 0;
+        for pes in audio_pes(3) {
+            ts.extend(packets(PID_AUDIO, &mut cc, &pes));
+        }
+        // A duplicate packet is ignored.
+        ts.insert(4, ts[3].clone());
+
+        let demuxer = demux(&ts);
+        let stream = &demuxer.streams[1];
+        assert_eq!(frame_values(&demuxer, 1), [0, 1, 2, 3, 4, 5]);
+        assert!(stream.frames.iter().all(|f| f.size == 200));
+        assert_eq!(durations(stream), [1024; 6]);
+    }
+
+    #[test]
+    fn pes_packet_loss() {
+        let mut ts = psi();
+        let mut cc = 0;
+        for (n, pes) in audio_pes(3).into_iter().enumerate() {
+            let mut packets = packets(PID_AUDIO, &mut cc, &pes);
+            if n == 1 {
+                // Lose the middle of the second PES packet.
+                packets.remove(1);
+            }
+            ts.extend(packets);
+        }
+
+        // The second PES packet is dropped, the frame before the gap is stretched.
+        let demuxer = demux(&ts);
+        assert_eq!(frame_values(&demuxer, 1), [0, 1, 4, 5]);
+        assert_eq!(durations(&demuxer.streams[1]), [1024, 3072, 1024, 1024]);
+    }
+
+    #[test]
+    fn invalid_pes() {
+        let mut ts = psi();
+        let mut cc = 0;
+        let mut pes_data = audio_pes(3);
+        pes_data[1][2] = 0;
+        for pes in pes_data {
+            ts.extend(packets(PID_AUDIO, &mut cc, &pes));
+        }
+        let demuxer = demux(&ts);
+        assert_eq!(frame_values(&demuxer, 1), [0, 1, 4, 5]);
+
+        // Garbage between ADTS frames is skipped.
+        let mut data = adts(0, 100);
+        data.extend_from_slice(&[0xff, 0x00, 0x12]);
+        data.extend(adts(1, 100));
+        let mut ts = psi();
+        ts.extend(packets(PID_AUDIO, &mut cc, &pes(0xc0, 0, None, &data)));
+        let demuxer = demux(&ts);
+        assert_eq!(frame_values(&demuxer, 1), [0, 1]);
+    }
+
+    // 320x240 baseline SPS, a PPS, and slices of an IDR and a P frame.
+    fn sps() -> Vec<u8> {
+        let mut w = BitWriter::default();
+        w.bits(8, 66).bits(8, 0xc0).bits(8, 30);
+        // sps_id, log2_max_frame_num, POC type 0, log2_max_poc_lsb, max_num_ref_frames, no gaps.
+        w.ue(0).ue(0).ue(0).ue(0).ue(1).bit(false);
+        // 20x15 macroblocks, frames only, direct 8x8 inference, no cropping, no VUI.
+        w.ue(19)
+            .ue(14)
+            .bit(true)
+            .bit(true)
+            .bit(false)
+            .bit(false)
+            .trailing();
+        [&[0x67][..], &escape_rbsp(&w.data)].concat()
+    }
+
+    const PPS: [u8; 4] = [0x68, 0xce, 0x38, 0x80];
+    const AUD: [u8; 2] = [0x09, 0xf0];
+
+    fn slice(idr: bool, n: u8) -> Vec<u8> {
+        let mut nal = vec![if idr { 0x65 } else { 0x41 }, 0x88];
+        nal.extend(vec![n; 50]);
+        nal
+    }
+
+    fn annex_b(nals: &[&[u8]]) -> Vec<u8> {
+        nals.iter()
+            .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+            .collect()
+    }
+
+    // A P frame without the IDR before it, then IDR, P, P.
+    fn video_pes() -> Vec<Vec<u8>> {
+        let sps = sps();
+        let aus = [
+            annex_b(&[&AUD, &slice(false, 9)]),
+            annex_b(&[&AUD, &sps, &PPS, &slice(true, 1)]),
+            annex_b(&[&AUD, &slice(false, 2)]),
+            annex_b(&[&AUD, &slice(false, 3)]),
+        ];
+        aus.iter()
+            .enumerate()
+            .map(|(n, au)| pes(0xe0, 3600 * n as i64 + 3600, Some(3600 * n as i64), au))
+            .collect()
+    }
+
+    #[test]
+    fn h264_access_units() {
+        let mut ts = psi();
+        let mut cc = 0;
+        for pes in video_pes() {
+            ts.extend(packets(PID_VIDEO, &mut cc, &pes));
+        }
+        let demuxer = demux(&ts);
+        let stream = &demuxer.streams[0];
+
+        // The AUD and parameter sets are not in the samples.
+        assert_eq!(stream.frames.len(), 3);
+        assert!(stream.frames.iter().all(|f| f.size == 4 + 52));
+        let sync: Vec<_> = stream.frames.iter().map(|f| f.is_sync).collect();
+        assert_eq!(sync, [true, false, false]);
+        let data = &demuxer.mem.data;
+        let first_bytes: Vec<_> = stream.frames.iter().map(|f| data[f.fpos as usize + 6]).collect();
+        assert_eq!(first_bytes, [1, 2, 3]);
+
+        let config = stream.video_config().unwrap();
+        assert_eq!((config.display_width, config.display_height), (320, 240));
+    }
+
+    #[test]
+    fn open_file() {
+        let mut ts = psi();
+        let (mut vcc, mut acc) = (0, 0);
+        for (video, audio) in video_pes().iter().zip(audio_pes(4)) {
+            ts.extend(packets(PID_VIDEO, &mut vcc, video));
+            ts.extend(packets(PID_AUDIO, &mut acc, &audio));
+        }
+        let path = std::env::temp_dir().join(format!("mp4lib-{}-open.ts", std::process::id()));
+        fs::write(&path, ts.concat()).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(is_mpegts(path));
+        let mp4 = open(path);
+        fs::remove_file(path).unwrap();
+        let mp4 = mp4.unwrap();
+
+        let tracks = mp4.movie().tracks();
+        assert_eq!(tracks.len(), 2);
+        let count = |idx: usize| SampleInfoIterator::new(tracks[idx]).count();
+        assert_eq!((count(0), count(1)), (3, 8));
+    }
+
+    #[test]
+    fn not_mpegts() {
+        let path = std::env::temp_dir().join(format!("mp4lib-{}-other.ts", std::process::id()));
+        fs::write(&path, vec![0u8; 1000]).unwrap();
+        let path = path.to_str().unwrap();
+        let err = open(path).map(|_| ()).unwrap_err();
+        fs::remove_file(path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
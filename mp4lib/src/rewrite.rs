@@ -1,9 +1,14 @@
 //! Track rewriting / reshuffling.
 //!
-use crate::boxes::MP4Box;
+use std::io;
+
+use crate::boxes::*;
+use crate::demux::{Sample, SampleTable};
 use crate::io::CountBytes;
-use crate::mp4box::MP4;
-use crate::serialize::{BoxBytes, ToBytes};
+use crate::mp4box::{BoxInfo, MP4};
+use crate::sample_info::FragmentIndex;
+use crate::serialize::{BoxBytes, ToBytes, WriteBytes};
+use crate::types::{ArraySized32, Duration_, FourCC};
 
 /// Set the default track.
 pub fn set_default_track(mp4: &mut MP4, track_id: u32) {
@@ -110,3 +115,318 @@ pub fn movie_at_front(mp4: &mut MP4) {
     // Then move the MovieBox to the front of the MP4.
     mp4.boxes.swap(mdat_idx.unwrap(), moov_idx.unwrap());
 }
+
+// Size of the MovieBox.
+pub(crate) fn movie_size(mp4: &MP4) -> u64 {
+    let mut cb = CountBytes::new();
+    mp4.movie().to_bytes(&mut cb).unwrap();
+    cb.size()
+}
+
+// After the MovieBox changed size (it used to be `old_size` bytes), and
+// it is in front of the media data, the media data has moved. Adjust the
+// chunk offsets of all tracks, except the ones in `skip_tracks`.
+//
+// Moving can make a chunk offset table switch to 64 bits, which changes
+// the size of the MovieBox again, so repeat until it is stable.
+pub(crate) fn movie_resized(mp4: &mut MP4, old_size: u64, skip_tracks: &[u32]) {
+    let moov_idx = mp4.boxes.iter().position(|b| matches!(b, MP4Box::MovieBox(_)));
+    let mdat_idx = mp4.boxes.iter().position(|b| matches!(b, MP4Box::MediaDataBox(_)));
+    match (moov_idx, mdat_idx) {
+        (Some(moov_idx), Some(mdat_idx)) if moov_idx < mdat_idx => {},
+        _ => return,
+    }
+
+    let mut moved = 0i64;
+    loop {
+        let delta = movie_size(mp4) as i64 - old_size as i64 - moved;
+        if delta == 0 {
+            break;
+        }
+        for t in mp4.movie_mut().tracks_mut() {
+            if !skip_tracks.contains(&t.track_id()) {
+                let stbl = t.media_mut().media_info_mut().sample_table_mut();
+                stbl.chunk_offset_table_mut().add_offset(delta);
+            }
+        }
+        moved += delta;
+    }
+}
+
+/// Write the MP4 file with the MovieBox at the front, followed by one
+/// MediaDataBox with the samples of all tracks interleaved in chunks of 500ms.
+///
+/// This works for any `MP4`, but it is meant for files that were not
+/// read from an MP4 file, like [Matroska](crate::matroska) or
+/// [MPEG-TS](crate::mpegts) files, which do not have a MediaDataBox.
+pub fn write_interleaved<W: WriteBytes>(mp4: &MP4, mut file: W) -> io::Result<()> {
+    let mut movie = mp4.movie().clone();
+
+    // Build new sample to chunk and chunk offset tables. Offsets are
+    // relative to the start of the media data for now.
+    let mut chunks = Vec::new();
+    let mut mdat_size = 0u64;
+    {
+        let tracks = movie.tracks();
+        let mut sample_info: Vec<_> = tracks.iter().map(|t| t.sample_info_iter()).collect();
+        let timescale: Vec<_> = tracks
+            .iter()
+            .map(|t| t.media().media_header().timescale as f64)
+            .collect();
+        let mut tables: Vec<_> = tracks
+            .iter()
+            .map(|_| {
+                let mut stco = ChunkOffsetBox::default();
+                stco.set_large();
+                (SampleToChunkBox::default(), stco)
+            })
+            .collect();
+
+        let mut until = 0.5_f64;
+        let mut done = false;
+        while !done {
+            done = true;
+            for t in 0..tracks.len() {
+                let mut samples = Vec::new();
+                while let Some(info) = sample_info[t].next() {
+                    if info.decode_time as f64 / timescale[t] >= until {
+                        sample_info[t].push(info);
+                        done = false;
+                        break;
+                    }
+                    samples.push((info.fpos, info.size));
+                }
+                if samples.is_empty() {
+                    continue;
+                }
+                let (stsc, stco) = &mut tables[t];
+                stco.push(mdat_size);
+                stsc.entries.push(SampleToChunkEntry {
+                    first_chunk: stco.entries.len() as u32,
+                    samples_per_chunk: samples.len() as u32,
+                    sample_description_index: 1,
+                });
+                mdat_size += samples.iter().map(|s| s.1 as u64).sum::<u64>();
+                chunks.push(samples);
+                done = false;
+            }
+            until += 0.5;
+        }
+
+        for (track, (stsc, stco)) in movie.tracks_mut().into_iter().zip(tables) {
+            let stbl = track.media_mut().media_info_mut().sample_table_mut();
+            *stbl.sample_to_chunk_mut() = stsc;
+            *stbl.chunk_offset_table_mut() = stco;
+        }
+    }
+
+    // The other boxes (ftyp, ...) go in front of the MovieBox.
+    let others: Vec<_> = mp4
+        .boxes
+        .iter()
+        .filter(|b| !matches!(b, MP4Box::MovieBox(_) | MP4Box::MediaDataBox(_)))
+        .collect();
+    let mut cb = CountBytes::new();
+    for b in &others {
+        b.to_bytes(&mut cb)?;
+    }
+    let others_size = cb.size();
+
+    // Move the chunk offsets to after the MovieBox and the mdat header.
+    // The MovieBox can grow when a chunk offset table switches to
+    // 64 bits, so repeat until its size is stable.
+    let mut moved = 0u64;
+    loop {
+        let mut cb = CountBytes::new();
+        movie.to_bytes(&mut cb)?;
+        let header_size = others_size + cb.size() + 16;
+        if header_size == moved {
+            break;
+        }
+        for track in movie.tracks_mut() {
+            let stbl = track.media_mut().media_info_mut().sample_table_mut();
+            stbl.chunk_offset_table_mut().add_offset((header_size - moved) as i64);
+        }
+        moved = header_size;
+    }
+
+    for b in &others {
+        b.to_bytes(&mut file)?;
+    }
+    movie.to_bytes(&mut file)?;
+
+    // Always use a large header, so the size of the header is known in advance.
+    1u32.to_bytes(&mut file)?;
+    FourCC::new("mdat").to_bytes(&mut file)?;
+    (mdat_size + 16).to_bytes(&mut file)?;
+
+    let mut buf = Vec::new();
+    for chunk in &chunks {
+        for &(fpos, size) in chunk {
+            buf.resize(size as usize, 0);
+            mp4.data_ref.read_exact_at(&mut buf, fpos)?;
+            file.write(&buf)?;
+        }
+    }
+    Ok(())
+}
+
+/// Turn a fragmented MP4 into a standard one.
+///
+/// The samples in the MovieFragmentBoxes are added to the sample tables
+/// of the tracks, and the fragments and the MovieExtendsBox are removed.
+/// The chunk offsets still point into the original file, so the result has
+/// no MediaDataBox. Write it with [`write_interleaved`].
+///
+/// Samples past the end of the file (the last fragment of a file that
+/// is still being recorded) are left out.
+pub fn defragment(mp4: &MP4) -> io::Result<MP4> {
+    let mut movie = mp4.movie().clone();
+    movie.boxes.retain(|b| !matches!(b, MP4Box::MovieExtendsBox(_)));
+    let movie_timescale = movie.movie_header().timescale as u64;
+    if movie_timescale == 0 {
+        return Err(ioerr!(InvalidData, "MovieHeaderBox: timescale is 0"));
+    }
+    for track in movie.tracks() {
+        if track.media().media_header().timescale == 0 {
+            return Err(ioerr!(InvalidData, "track {}: MediaHeaderBox: timescale is 0", track.track_id()));
+        }
+    }
+
+    // The samples in the sample table (usually none) and in the fragments.
+    let track_samples: Vec<Vec<_>> = mp4
+        .movie()
+        .tracks()
+        .iter()
+        .map(|track| mp4.sample_info_iter(track).collect())
+        .collect();
+
+    // Time of the first sample of each track, in seconds. The first
+    // track starts at 0, and the others are delayed relative to it.
+    let first_time: Vec<_> = movie
+        .tracks()
+        .iter()
+        .zip(track_samples.iter())
+        .map(|(t, s)| {
+            let timescale = t.media().media_header().timescale as f64;
+            s.first().map(|s| s.decode_time as f64 / timescale)
+        })
+        .collect();
+    let start = first_time.iter().flatten().cloned().fold(f64::MAX, f64::min);
+
+    let mut movie_duration = 0;
+    let tracks = movie.tracks_mut();
+    for ((track, samples), first_time) in tracks.into_iter().zip(track_samples).zip(first_time) {
+        // The duration of a sample is the difference with the decode
+        // time of the next one, so gaps between fragments are kept.
+        let entries: Vec<_> = samples
+            .iter()
+            .enumerate()
+            .map(|(idx, s)| Sample {
+                fpos: s.fpos,
+                size: s.size,
+                duration: match samples.get(idx + 1) {
+                    Some(next) => next.decode_time.saturating_sub(s.decode_time) as u32,
+                    None => s.duration,
+                },
+                composition_offset: s.composition_delta,
+                is_sync: s.is_sync,
+            })
+            .collect();
+        let table = SampleTable::from_samples(&entries);
+        let media_duration = table.duration;
+
+        let media = track.media_mut();
+        let timescale = media.media_header().timescale as u64;
+        let stbl = media.media_info_mut().sample_table_mut();
+        stbl.boxes.retain(|b| matches!(b, MP4Box::SampleDescriptionBox(_)));
+        stbl.boxes.extend(table.into_boxes());
+        if let Some(mdhd) = first_box_mut!(&mut media.boxes, MediaHeaderBox) {
+            mdhd.duration = Duration_(media_duration);
+        }
+
+        let to_movie = |t: u64| (t as u128 * movie_timescale as u128 / timescale as u128) as u64;
+        let delay = first_time.map(|t| ((t - start) * movie_timescale as f64).round() as u64);
+        let duration = fix_edit_list(track, delay.unwrap_or(0), to_movie, to_movie(media_duration));
+        track.track_header_mut().duration = Duration_(duration);
+        movie_duration = std::cmp::max(movie_duration, duration);
+    }
+    if let Some(mvhd) = first_box_mut!(&mut movie.boxes, MovieHeaderBox) {
+        mvhd.duration = Duration_(movie_duration);
+    }
+
+    let mut boxes = Vec::new();
+    for b in &mp4.boxes {
+        match b {
+            MP4Box::MovieBox(_) => boxes.push(movie.clone().to_mp4box()),
+            _ => {
+                let fourcc = b.fourcc().to_be_bytes();
+                if !matches!(&fourcc, b"moof" | b"mdat" | b"sidx" | b"styp" | b"mfra" | b"emsg" | b"prft") {
+                    boxes.push(b.clone());
+                }
+            },
+        }
+    }
+
+    Ok(MP4 {
+        boxes,
+        data_ref: mp4.data_ref.clone(),
+        input_file: mp4.input_file.clone(),
+        fragment_index: FragmentIndex::default(),
+    })
+}
+
+// In a fragmented file the duration of an edit is often 0, meaning "until
+// the end". Fill in the real duration, and add an empty edit of `delay`,
+// unless the edit list already starts with an empty edit.
+// Returns the duration of the track. Everything in movie timescale units,
+// `to_movie` converts from the media timescale.
+fn fix_edit_list(
+    track: &mut TrackBox,
+    delay: u64,
+    to_movie: impl Fn(u64) -> u64,
+    media_duration: u64,
+) -> u64 {
+    let edts = match first_box_mut!(&mut track.boxes, EditBox) {
+        Some(edts) => edts,
+        None if delay == 0 => return media_duration,
+        None => {
+            let mut elst = EditListBox::default();
+            elst.entries.push(EditListEntry {
+                segment_duration: media_duration,
+                media_time: 0,
+                media_rate: 1,
+            });
+            let pos = if track.boxes.is_empty() { 0 } else { 1 };
+            track.boxes.insert(pos, EditBox { boxes: vec![elst] }.to_mp4box());
+            first_box_mut!(&mut track.boxes, EditBox).unwrap()
+        },
+    };
+    let elst = match edts.boxes.first_mut() {
+        Some(elst) => elst,
+        None => {
+            edts.boxes.push(EditListBox::default());
+            &mut edts.boxes[0]
+        },
+    };
+    for entry in elst.entries.iter_mut() {
+        if entry.segment_duration == 0 && entry.media_time >= 0 {
+            entry.segment_duration = media_duration.saturating_sub(to_movie(entry.media_time as u64));
+        }
+    }
+    let has_empty_edit = elst.entries.first().map(|e| e.media_time == -1).unwrap_or(false);
+    if delay > 0 && !has_empty_edit {
+        let empty = EditListEntry {
+            segment_duration: delay,
+            media_time: -1,
+            media_rate: 1,
+        };
+        let mut entries = ArraySized32::new();
+        entries.push(empty);
+        for entry in elst.entries.iter() {
+            entries.push(entry.clone());
+        }
+        elst.entries = entries;
+    }
+    elst.entries.iter().map(|e| e.segment_duration).sum()
+}
@@ -1,7 +1,11 @@
 //! Iterate over all samples in a track.
+use std::collections::HashMap;
 use std::io;
+use std::sync::{Arc, Mutex};
 
 use crate::boxes::*;
+use crate::mp4box::MP4;
+use crate::types::SampleFlags;
 
 use crate::boxes::ctts::CompositionOffsetIterator;
 use crate::boxes::stsc::SampleToChunkIterator;
@@ -43,6 +47,10 @@ pub struct SampleInfoIterator<'a> {
     cur_sample: u32,
     cur_chunk: u32,
     pending: Option<SampleInfo>,
+    // Samples in the MovieFragmentBoxes, after the ones in the sample table.
+    fragments: Option<FragmentSampleIterator<'a>>,
+    table_samples: u32,
+    in_fragments: bool,
 }
 
 impl SampleInfoIterator<'_> {
@@ -68,9 +76,32 @@ impl SampleInfoIterator<'_> {
             cur_sample: 1,
             cur_chunk: 1,
             pending: None,
+            fragments: None,
+            table_samples: stbl.sample_size().count,
+            in_fragments: false,
         }
     }
 
+    /// Like new, but after the samples in the SampleTableBox, this iterates
+    /// over the samples of the track in the MovieFragmentBoxes of `mp4`.
+    ///
+    /// See also [`MP4::sample_info_iter`].
+    pub fn with_fragments<'a>(mp4: &'a MP4, trak: &'a TrackBox) -> SampleInfoIterator<'a> {
+        let mut iter = SampleInfoIterator::new(trak);
+        if first_box!(mp4, MovieFragmentBox).is_some() {
+            let stbl = trak.media().media_info().sample_table();
+            let end_time = stbl
+                .time_to_sample()
+                .entries
+                .iter()
+                .fold(0, |acc, e| acc + e.count as u64 * e.delta as u64);
+            let chunks = stbl.chunk_offset_table().entries.len() as u32;
+            let fragments = FragmentSampleIterator::new(mp4, trak.track_id(), end_time, chunks);
+            iter.fragments = Some(fragments);
+        }
+        iter
+    }
+
     /// Like new, but the edit list will be taken into account for the
     /// value of the `composition_delta` field .
     ///
@@ -88,6 +119,20 @@ impl SampleInfoIterator<'_> {
 
     /// Seek to a sample.
     pub fn seek(&mut self, to_sample: u32) -> io::Result<()> {
+        if to_sample > self.table_samples {
+            if let Some(fragments) = self.fragments.as_mut() {
+                fragments.seek(to_sample - self.table_samples)?;
+                self.cur_sample = to_sample;
+                self.in_fragments = true;
+                self.pending.take();
+                return Ok(());
+            }
+        }
+        self.in_fragments = false;
+        if let Some(fragments) = self.fragments.as_mut() {
+            fragments.rewind();
+        }
+
         self.stsz_iter.seek(to_sample)?;
         self.stts_iter.seek(to_sample)?;
         self.stsc_iter.seek(to_sample)?;
@@ -129,9 +174,19 @@ impl<'a> Iterator for SampleInfoIterator<'a> {
             return Some(pending);
         }
 
-        let size = match self.stsz_iter.next() {
+        let size = match self.in_fragments {
+            false => self.stsz_iter.next(),
+            true => None,
+        };
+        let size = match size {
             Some(size) => size,
-            None => return None,
+            None => {
+                self.in_fragments = true;
+                let mut sample = self.fragments.as_mut()?.next()?;
+                sample.composition_delta += self.comp_time_shift;
+                self.cur_sample += 1;
+                return Some(sample);
+            },
         };
 
         if let Some(chunk_info) = self.stsc_iter.next() {
@@ -170,3 +225,270 @@ impl<'a> Iterator for SampleInfoIterator<'a> {
         Some(sample)
     }
 }
+
+/// Iterator over the samples of a track in the MovieFragmentBoxes of an MP4.
+///
+/// The MovieFragmentBoxes are walked one at a time. Default values come from
+/// the TrackFragmentHeaderBox, or from the TrackExtendsBox of the track. The
+/// decode time continues from the previous fragment if a fragment does not have
+/// a TrackFragmentBaseMediaDecodeTimeBox.
+///
+/// Iteration stops at the first sample that lies past the end of the file,
+/// like in the last fragment of a file that is still being recorded.
+#[derive(Clone)]
+pub struct FragmentSampleIterator<'a> {
+    boxes: &'a [MP4Box],
+    index: &'a FragmentIndex,
+    trex: TrackExtendsBox,
+    track_id: u32,
+    file_size: u64,
+    start_time: u64,
+    first_chunk: u32,
+    // Where we are.
+    next_box: usize,
+    decode_time: u64,
+    chunk: u32,
+    samples: std::vec::IntoIter<SampleInfo>,
+    done: bool,
+}
+
+impl<'a> FragmentSampleIterator<'a> {
+    /// Iterate over the samples of track `track_id` in the fragments.
+    ///
+    /// `decode_time` is the decode time of the first sample if the first
+    /// fragment does not have a TrackFragmentBaseMediaDecodeTimeBox, and
+    /// the chunk numbers continue after `chunk`.
+    pub fn new(mp4: &'a MP4, track_id: u32, decode_time: u64, chunk: u32) -> FragmentSampleIterator<'a> {
+        let trex = match first_box!(mp4, MovieBox) {
+            Some(movie) => movie.track_extends_by_id(track_id).cloned().unwrap_or_default(),
+            None => TrackExtendsBox::default(),
+        };
+        FragmentSampleIterator {
+            boxes: &mp4.boxes[..],
+            index: &mp4.fragment_index,
+            trex,
+            track_id,
+            file_size: mp4.data_ref.len(),
+            start_time: decode_time,
+            first_chunk: chunk,
+            next_box: 0,
+            decode_time,
+            chunk,
+            samples: Vec::new().into_iter(),
+            done: false,
+        }
+    }
+
+    /// Seek to a sample. The first sample in the fragments is sample 1.
+    pub fn seek(&mut self, to_sample: u32) -> io::Result<()> {
+        let to_sample = std::cmp::max(to_sample, 1);
+        let index = self.fragment_starts();
+
+        // The last fragment that starts at or before the sample.
+        let idx = index.partition_point(|f| f.first_sample <= to_sample);
+        let start = match idx.checked_sub(1) {
+            Some(idx) => &index[idx],
+            None => return Err(ioerr!(UnexpectedEof)),
+        };
+        self.next_box = start.moof;
+        self.decode_time = start.decode_time;
+        self.chunk = start.chunk;
+        self.samples = Vec::new().into_iter();
+        self.done = false;
+        if !self.next_fragment() {
+            return Err(ioerr!(UnexpectedEof));
+        }
+
+        let skip = (to_sample - start.first_sample) as usize;
+        if skip >= self.samples.len() {
+            return Err(ioerr!(UnexpectedEof));
+        }
+        if skip > 0 {
+            self.samples.nth(skip - 1);
+        }
+        Ok(())
+    }
+
+    // The start of every fragment that has samples of this track. This
+    // walks all fragments once, after that it comes from the index.
+    fn fragment_starts(&mut self) -> Arc<Vec<FragmentStart>> {
+        let key = (self.track_id, self.start_time, self.first_chunk, self.boxes.len());
+        if let Some(index) = self.index.0.lock().unwrap().get(&key) {
+            return index.clone();
+        }
+
+        self.rewind();
+        let mut index = Vec::new();
+        let mut first_sample = 1;
+        loop {
+            let (decode_time, chunk) = (self.decode_time, self.chunk);
+            if !self.next_fragment() {
+                break;
+            }
+            index.push(FragmentStart {
+                first_sample,
+                moof: self.next_box - 1,
+                decode_time,
+                chunk,
+            });
+            first_sample += self.samples.len() as u32;
+        }
+        let index = Arc::new(index);
+        self.index.0.lock().unwrap().insert(key, index.clone());
+        index
+    }
+
+    fn rewind(&mut self) {
+        self.next_box = 0;
+        self.decode_time = self.start_time;
+        self.chunk = self.first_chunk;
+        self.samples = Vec::new().into_iter();
+        self.done = false;
+    }
+
+    // Collect the samples of the next MovieFragmentBox that has any for this track.
+    fn next_fragment(&mut self) -> bool {
+        while let Some(b) = self.boxes.get(self.next_box) {
+            self.next_box += 1;
+            if let MP4Box::MovieFragmentBox(moof) = b {
+                let samples = self.fragment_samples(moof);
+                if !samples.is_empty() {
+                    self.samples = samples.into_iter();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn fragment_samples(&mut self, moof: &MovieFragmentBox) -> Vec<SampleInfo> {
+        let trex = &self.trex;
+        let mut samples = Vec::new();
+
+        // Without an explicit base offset, the data of a track fragment
+        // follows the data of the previous one (8.8.7.1).
+        let mut next_base = moof.offset;
+
+        for traf in moof.track_fragments() {
+            let tfhd = match traf.track_fragment_header() {
+                Some(tfhd) => tfhd,
+                None => {
+                    log::warn!("MovieFragmentBox at {}: no TrackFragmentHeaderBox", moof.offset);
+                    continue;
+                },
+            };
+            let base = match tfhd.base_data_offset {
+                Some(offset) => offset,
+                None if tfhd.default_base_is_moof => moof.offset,
+                None => next_base,
+            };
+            let this_track = tfhd.track_id == self.track_id;
+            if this_track {
+                if let Some(tfdt) = traf.track_fragment_decode_time() {
+                    self.decode_time = tfdt.base_media_decode_time.0;
+                }
+            }
+
+            let mut fpos = base;
+            for trun in traf.track_run_boxes() {
+                if let Some(offset) = trun.data_offset {
+                    fpos = (base as i64 + offset as i64) as u64;
+                }
+                self.chunk += this_track as u32;
+                for (idx, entry) in trun.entries.iter().enumerate() {
+                    let size = entry
+                        .sample_size
+                        .or(tfhd.default_sample_size)
+                        .unwrap_or(trex.default_sample_size);
+                    if this_track {
+                        let duration = entry
+                            .sample_duration
+                            .or(tfhd.default_sample_duration)
+                            .unwrap_or(trex.default_sample_duration);
+                        let flags = sample_flags(entry, idx, trun, tfhd, trex);
+                        samples.push(SampleInfo {
+                            fpos,
+                            size,
+                            duration,
+                            decode_time: self.decode_time,
+                            composition_delta: entry.sample_composition_time_offset.unwrap_or(0),
+                            is_sync: !flags.sample_is_non_sync_sample,
+                            chunk: self.chunk,
+                        });
+                        self.decode_time += duration as u64;
+                    }
+                    fpos += size as u64;
+                }
+            }
+            next_base = fpos;
+        }
+        samples
+    }
+}
+
+impl Iterator for FragmentSampleIterator<'_> {
+    type Item = SampleInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let sample = match self.samples.next() {
+            Some(sample) => sample,
+            None if self.next_fragment() => self.samples.next()?,
+            None => return None,
+        };
+        if sample.fpos + sample.size as u64 > self.file_size {
+            log::debug!(
+                "track {}: sample at {} is past the end of the file",
+                self.track_id,
+                sample.fpos
+            );
+            self.done = true;
+            return None;
+        }
+        Some(sample)
+    }
+}
+
+// Where a MovieFragmentBox with samples of the track starts, and the
+// state of the iterator just before it.
+#[derive(Debug)]
+struct FragmentStart {
+    first_sample: u32,
+    moof: usize,
+    decode_time: u64,
+    chunk: u32,
+}
+
+// Per track (and starting point), the start of each fragment. The `MP4`
+// keeps this, so that seeking in a fragmented file does not have to walk
+// all fragments before the target every time. It is not shared with clones.
+#[derive(Debug, Default)]
+pub(crate) struct FragmentIndex(Mutex<HashMap<FragmentIndexKey, Arc<Vec<FragmentStart>>>>);
+
+// Track id, decode time and chunk number at the start of the fragments, number of boxes.
+type FragmentIndexKey = (u32, u64, u32, usize);
+
+impl Clone for FragmentIndex {
+    fn clone(&self) -> FragmentIndex {
+        FragmentIndex::default()
+    }
+}
+
+// The flags of a sample are in the TrackRunBox entry, or for the first
+// sample in the TrackRunBox itself, or else they are the default.
+fn sample_flags<'a>(
+    entry: &'a TrackRunEntry,
+    idx: usize,
+    trun: &'a TrackRunBox,
+    tfhd: &'a TrackFragmentHeaderBox,
+    trex: &'a TrackExtendsBox,
+) -> &'a SampleFlags {
+    entry
+        .sample_flags
+        .as_ref()
+        .or_else(|| trun.first_sample_flags.as_ref().filter(|_| idx == 0))
+        .or(tfhd.default_sample_flags.as_ref())
+        .unwrap_or(&trex.default_sample_flags)
+}
@@ -0,0 +1,505 @@
+//! On the fly `MPEG-DASH` packaging.
+//!
+//! The functions in this module repackage a normal `MP4` file
+//! on-the-fly into a `DASH` `VOD` stream. Like the [`hls`](crate::streaming::hls)
+//! module, the segments are `fMP4` fragments generated by
+//! [`movie_fragment`](crate::streaming::fragment::movie_fragment).
+//!
+//! If you want to use this functionality in your own `HTTP` server, use
+//! [`handle_dash`](crate::streaming::http_handler::handle_dash).
+//!
+//! ## DASH url conventions.
+//!
+//! - `manifest.mpd`: entry point, the `MPD` (Media Presentation Description).
+//! - `manifest-wvtt.mpd`: same, but with embedded subtitles as `wvtt` segments.
+//! - `init.<TRACK_ID>.mp4`: `ISOBMFF` initialization segment for the track.
+//! - `init.<TRACK_ID>.wvtt.mp4`: same, for a subtitle track as `wvtt`.
+//! - `d/<TRACK_ID>.<NUMBER>.m4s`: media segment number `NUMBER` (starting at 1).
+//!   For subtitle tracks this is a `wvtt` segment.
+//! - `d/<TRACK_ID>.vtt`: an embedded subtitle track as one `WEBVTT` file.
+//! - `e/EXTERNALFILE.EXT[:into.vtt]`: external subtitle file.
+//!
+//! The `MPD` contains one `AdaptationSet` for the video track, and one
+//! `AdaptationSet` per audio and per subtitle track. Audio and video use a
+//! `SegmentTemplate` with a `SegmentTimeline`. The segment list is the same
+//! as the one used for `HLS`, so the segments can be looked up by number.
+//!
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+
+use scan_fmt::scan_fmt;
+
+use crate::boxes::TrackBox;
+use crate::mp4box::MP4;
+use crate::track::SpecificTrackInfo;
+
+use super::encryption;
+use super::fragment::FragmentSource;
+use super::hls::{self, ExtXMedia, HlsMaster};
+use super::http_file::{delegate_http_file, HttpFile, MemFile};
+use super::segmenter::Segment;
+
+// Format a duration in seconds as a xs:duration.
+fn iso_duration(secs: f64) -> String {
+    format!("PT{:.3}S", secs)
+}
+
+// Escape a string for use in an XML attribute.
+fn xml_escape(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => r.push_str("&amp;"),
+            '<' => r.push_str("&lt;"),
+            '>' => r.push_str("&gt;"),
+            '"' => r.push_str("&quot;"),
+            '\'' => r.push_str("&apos;"),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+// The start and the duration of each segment, in the timescale of the track.
+//
+// These come from the decode times of the samples, so that the start of
+// a segment is the same as the time in its `tfdt` box.
+fn segment_times(mp4: &MP4, track: &TrackBox, segments: &[Segment]) -> io::Result<Vec<(u64, u64)>> {
+    let shift = std::cmp::max(0, track.composition_time_shift(false)?) as u64;
+    let mut times = Vec::with_capacity(segments.len());
+    let mut segs = segments.iter();
+    let mut seg = segs.next();
+    let mut start = 0;
+    for (idx, sample) in mp4.sample_info_iter(track).enumerate() {
+        let s = match seg {
+            Some(s) => s,
+            None => break,
+        };
+        let sample_number = idx as u32 + 1;
+        if sample_number == s.start_sample {
+            start = sample.decode_time;
+        }
+        if sample_number == s.end_sample {
+            times.push((start + shift, sample.decode_time + sample.duration as u64 - start));
+            seg = segs.next();
+        }
+    }
+    if times.len() < segments.len() {
+        return Err(ioerr!(
+            InvalidData,
+            "track {}: segment past the last sample",
+            track.track_id()
+        ));
+    }
+    Ok(times)
+}
+
+// Build a SegmentTemplate with a SegmentTimeline.
+//
+// `wvtt` subtitle tracks have their own initialization segment.
+//
+// Consecutive segments with the same duration are merged
+// into one `S` element with a repeat count.
+fn segment_template(
+    m: &mut String,
+    mp4: &MP4,
+    track_id: u32,
+    segments: &[Segment],
+    wvtt: bool,
+) -> io::Result<()> {
+    let track = mp4
+        .movie()
+        .track_by_id(track_id)
+        .ok_or_else(|| ioerr!(NotFound, "track {} not found", track_id))?;
+    let timescale = track.media().media_header().timescale;
+    let init = if wvtt { "wvtt.mp4" } else { "mp4" };
+
+    let _ = writeln!(
+        m,
+        "      <SegmentTemplate timescale=\"{}\" initialization=\"init.{}.{}\" \
+         media=\"d/{}.$Number$.m4s\" startNumber=\"1\">",
+        timescale, track_id, init, track_id
+    );
+    m.push_str("        <SegmentTimeline>\n");
+
+    // A segment that does not start where the previous one ends gets a new `S`
+    // element with a `t` attribute.
+    let mut timeline: Vec<(u64, u64, u32)> = Vec::new();
+    let mut end = None;
+    for (time, duration) in segment_times(mp4, track, segments)? {
+        match timeline.last_mut() {
+            Some(last) if last.1 == duration && end == Some(time) => last.2 += 1,
+            _ => timeline.push((time, duration, 0)),
+        }
+        end = Some(time + duration);
+    }
+
+    let mut end = None;
+    for &(t, d, r) in &timeline {
+        m.push_str("          <S ");
+        if end != Some(t) {
+            let _ = write!(m, "t=\"{}\" ", t);
+        }
+        end = Some(t + d * (r as u64 + 1));
+        let _ = write!(m, "d=\"{}\"", d);
+        if r > 0 {
+            let _ = write!(m, " r=\"{}\"", r);
+        }
+        m.push_str("/>\n");
+    }
+
+    m.push_str("        </SegmentTimeline>\n");
+    m.push_str("      </SegmentTemplate>\n");
+    Ok(())
+}
+
+// Language attribute of an audio or subtitle AdaptationSet.
+fn adaptation_set_attrs(m: &mut String, media: &ExtXMedia) {
+    if let Some(lang) = media.language.as_ref() {
+        let _ = write!(m, " lang=\"{}\"", xml_escape(lang));
+    }
+}
+
+// Role and label of an audio or subtitle AdaptationSet.
+fn adaptation_set_role(m: &mut String, media: &ExtXMedia, is_subtitle: bool) {
+    let role = if media.commentary {
+        "commentary"
+    } else if media.forced {
+        "forced-subtitle"
+    } else if media.sdh {
+        "caption"
+    } else if is_subtitle {
+        "subtitle"
+    } else {
+        "main"
+    };
+    let _ = writeln!(
+        m,
+        "      <Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"{}\"/>",
+        role
+    );
+    let _ = writeln!(m, "      <Label>{}</Label>", xml_escape(&media.name));
+}
+
+// ContentProtection elements of an encrypted AdaptationSet.
+fn content_protection(m: &mut String, mp4: &MP4, track_id: u32) -> io::Result<()> {
+    if let Some(key) = encryption::content_key(mp4, track_id)? {
+        let _ = writeln!(
+            m,
+            "      <ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" \
+             value=\"{}\" cenc:default_KID=\"{}\"/>",
+            key.scheme,
+            key.kid_string()
+        );
+    }
+    Ok(())
+}
+
+/// Generate a `DASH` manifest (`mpd`) from an `MP4` object.
+///
+/// The `external_subs` and `filter_subs` parameters have the same meaning
+/// as for [`hls_master`](crate::streaming::hls::hls_master).
+///
+/// If `max_segment_size` is set, it defines the maximum size of an fMP4
+/// segment, see [`hls_track`](crate::streaming::hls::hls_track).
+///
+pub fn dash_manifest(
+    mp4: &MP4,
+    external_subs: bool,
+    filter_subs: bool,
+    max_segment_size: Option<u32>,
+) -> io::Result<String> {
+    dash_manifest2(mp4, external_subs, filter_subs, max_segment_size, false)
+}
+
+/// Generate a `DASH` manifest (`mpd`) from an `MP4` object.
+///
+/// Like [`dash_manifest`], but if `wvtt_subs` is set, embedded subtitle
+/// tracks are served as `fMP4` segments containing `wvtt` (WebVTT-in-MP4)
+/// samples instead of as a single `WEBVTT` file.
+///
+pub fn dash_manifest2(
+    mp4: &MP4,
+    external_subs: bool,
+    filter_subs: bool,
+    max_segment_size: Option<u32>,
+    wvtt_subs: bool,
+) -> io::Result<String> {
+    let mut master = HlsMaster::new(mp4, external_subs);
+    if filter_subs {
+        master.dedup_subtitles(true);
+    }
+    let video = master
+        .video
+        .as_ref()
+        .ok_or_else(|| ioerr!(NotFound, "mp4 file has no video track"))?;
+
+    let tracks = crate::track::track_info2(mp4, true);
+    let movie = mp4.movie();
+    let mvhd = movie.movie_header();
+    let duration = mvhd.duration.0 as f64 / std::cmp::max(1, mvhd.timescale) as f64;
+    // The MovieHeaderBox of a fragmented file does not include the fragments.
+    let duration = tracks.iter().map(|t| t.duration.as_secs_f64()).fold(duration, f64::max);
+    let mut m = String::new();
+    m += "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+    m += "<!-- Created by mp4lib.rs -->\n";
+    m += "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" xmlns:cenc=\"urn:mpeg:cenc:2013\" \
+          profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" type=\"static\" ";
+    let _ = writeln!(
+        m,
+        "mediaPresentationDuration=\"{}\" minBufferTime=\"PT2.000S\">",
+        iso_duration(duration)
+    );
+    m += "  <Period id=\"1\" start=\"PT0.000S\">\n";
+
+    // Video.
+    let segments = hls::track_segments(mp4, video.track_id, None, max_segment_size)?;
+    let _ = writeln!(
+        m,
+        "    <AdaptationSet id=\"{}\" contentType=\"video\" mimeType=\"video/mp4\" \
+         segmentAlignment=\"true\" startWithSAP=\"1\">",
+        video.track_id
+    );
+    content_protection(&mut m, mp4, video.track_id)?;
+    segment_template(&mut m, mp4, video.track_id, &segments, false)?;
+    let _ = writeln!(
+        m,
+        "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\" \
+         width=\"{}\" height=\"{}\" frameRate=\"{:.03}\"/>",
+        video.track_id,
+        video.codec,
+        video.peak_bandwidth,
+        video.resolution.0,
+        video.resolution.1,
+        video.frame_rate
+    );
+    m += "    </AdaptationSet>\n";
+
+    // Audio, one AdaptationSet per track.
+    for audio in master.audio_tracks.iter().filter(|t| t.in_master) {
+        let info = tracks.iter().find(|t| t.id == audio.track_id);
+        let (bandwidth, sample_rate) = match info.map(|t| (t, &t.specific_info)) {
+            Some((t, SpecificTrackInfo::AudioTrackInfo(a))) => {
+                let bw = 8 * t.size / std::cmp::max(1, t.duration.as_secs());
+                (bw, a.sample_rate)
+            },
+            _ => continue,
+        };
+        let segments = hls::track_segments(mp4, audio.track_id, None, max_segment_size)?;
+
+        let _ = write!(
+            m,
+            "    <AdaptationSet id=\"{}\" contentType=\"audio\" mimeType=\"audio/mp4\" \
+             segmentAlignment=\"true\" startWithSAP=\"1\"",
+            audio.track_id
+        );
+        adaptation_set_attrs(&mut m, audio);
+        m += ">\n";
+        adaptation_set_role(&mut m, audio, false);
+        content_protection(&mut m, mp4, audio.track_id)?;
+        segment_template(&mut m, mp4, audio.track_id, &segments, false)?;
+        let _ = write!(
+            m,
+            "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\"",
+            audio.track_id, audio.codec, bandwidth
+        );
+        if let Some(rate) = sample_rate {
+            let _ = write!(m, " audioSamplingRate=\"{}\"", rate);
+        }
+        m += ">\n";
+        if let Some(channels) = audio.channels {
+            let _ = writeln!(
+                m,
+                "        <AudioChannelConfiguration \
+                 schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" \
+                 value=\"{}\"/>",
+                channels
+            );
+        }
+        if audio.joc_complexity.is_some() {
+            m += "        <SupplementalProperty \
+                  schemeIdUri=\"tag:dolby.com,2018:dash:EC3_ExtensionType:2018\" \
+                  value=\"JOC\"/>\n";
+        }
+        m += "      </Representation>\n";
+        m += "    </AdaptationSet>\n";
+    }
+
+    // Subtitles, one AdaptationSet per track. Served as a single WEBVTT file,
+    // or as wvtt segments. TTML tracks are always served as WEBVTT file.
+    for sub in master.subtitles.iter().filter(|t| t.in_master) {
+        let ttml = match mp4.movie().track_by_id(sub.track_id) {
+            Some(trak) if sub.filename.is_none() => super::subtitle::is_ttml(trak),
+            _ => false,
+        };
+        if wvtt_subs && sub.filename.is_none() && !ttml {
+            let segments = hls::track_segments(mp4, sub.track_id, None, None)?;
+            let _ = write!(
+                m,
+                "    <AdaptationSet id=\"{}\" contentType=\"text\" mimeType=\"application/mp4\"",
+                sub.track_id
+            );
+            adaptation_set_attrs(&mut m, sub);
+            m += ">\n";
+            adaptation_set_role(&mut m, sub, true);
+            segment_template(&mut m, mp4, sub.track_id, &segments, true)?;
+            let _ = writeln!(
+                m,
+                "      <Representation id=\"{}\" codecs=\"wvtt\" bandwidth=\"256\"/>",
+                sub.track_id
+            );
+            m += "    </AdaptationSet>\n";
+            continue;
+        }
+        let url = match sub.filename.as_ref() {
+            Some(filename) => {
+                let name = filename.rsplit('/').next().unwrap();
+                if name.ends_with(".vtt") {
+                    format!("e/{}", name)
+                } else {
+                    format!("e/{}:into.vtt", name)
+                }
+            },
+            None => format!("d/{}.vtt", sub.track_id),
+        };
+        let _ = write!(
+            m,
+            "    <AdaptationSet id=\"{}\" contentType=\"text\" mimeType=\"text/vtt\"",
+            sub.track_id
+        );
+        adaptation_set_attrs(&mut m, sub);
+        m += ">\n";
+        adaptation_set_role(&mut m, sub, true);
+        let _ = writeln!(
+            m,
+            "      <Representation id=\"{}\" bandwidth=\"256\">",
+            sub.track_id
+        );
+        let _ = writeln!(m, "        <BaseURL>{}</BaseURL>", xml_escape(&url));
+        m += "      </Representation>\n";
+        m += "    </AdaptationSet>\n";
+    }
+
+    m += "  </Period>\n";
+    m += "</MPD>\n";
+
+    Ok(m)
+}
+
+/// DASH manifest data.
+///
+/// This struct `impl`s `HttpFile`.
+pub struct DashManifest(pub(crate) MemFile);
+delegate_http_file!(DashManifest);
+
+impl DashManifest {
+    /// Translates the tail of an `Url` into a `DASH` manifest.
+    ///
+    /// The `url_tail`s recognized are `manifest.mpd` and `manifest-wvtt.mpd`.
+    /// The latter serves the embedded subtitles as `wvtt` segments,
+    /// see [`dash_manifest2`].
+    pub fn from_uri(
+        mp4: &MP4,
+        url_tail: &str,
+        filter_subs: bool,
+        max_segment_size: Option<u32>,
+    ) -> io::Result<DashManifest> {
+        let wvtt_subs = match url_tail {
+            "manifest.mpd" => false,
+            "manifest-wvtt.mpd" => true,
+            _ => return Err(ioerr!(InvalidData, "415 Unsupported Media Type")),
+        };
+        let data = dash_manifest2(mp4, true, filter_subs, max_segment_size, wvtt_subs)?;
+
+        let file = &*mp4.data_ref.file;
+        let mem_file = MemFile::from_file(data.into_bytes(), "application/dash+xml", file)?;
+        Ok(DashManifest(mem_file))
+    }
+
+    /// `DASH` manifest as text.
+    pub fn manifest(&self) -> &'_ str {
+        std::str::from_utf8(&self.0.content[..]).unwrap_or("")
+    }
+}
+
+/// A `DASH` media segment.
+pub struct DashSegment(pub(crate) MemFile);
+delegate_http_file!(DashSegment);
+
+impl DashSegment {
+    /// Translates the tail of an URL into an MP4 init segment or media segment.
+    ///
+    /// - `init.TRACK_ID.mp4` => initialization segment for track `TRACK_ID`.
+    /// - `init.TRACK_ID.wvtt.mp4` => same, with `tx3g` translated to `wvtt`.
+    /// - `d/TRACK_ID.NUMBER.m4s` => moof + mdat
+    /// - `d/TRACK_ID.vtt` => embedded subtitle track as `WEBVTT`.
+    /// - `e/EXTERNALFILE.EXT[:into.ext]` => external file next to `.mp4` (`.srt`, `.vtt`, `.ttml`)
+    ///
+    pub fn from_uri(
+        mp4: &MP4,
+        url_tail: &str,
+        max_segment_size: Option<u32>,
+        range_end: Option<u64>,
+    ) -> io::Result<DashSegment> {
+        let (mime_type, content) = DashSegment::from_uri_(mp4, url_tail, max_segment_size, range_end)?;
+        let file = &*mp4.data_ref.file;
+        let mem_file = MemFile::from_file_shared(content, mime_type, file)?;
+        Ok(DashSegment(mem_file))
+    }
+
+    fn from_uri_(
+        mp4: &MP4,
+        url_tail: &str,
+        max_segment_size: Option<u32>,
+        range_end: Option<u64>,
+    ) -> io::Result<(&'static str, Arc<Vec<u8>>)> {
+        if !url_tail.starts_with("d/") {
+            // init segment or external file, same as HLS.
+            return hls::MediaSegment::from_uri_(mp4, url_tail, range_end, None);
+        }
+
+        // embedded subtitle track.
+        if let Ok(track_id) = scan_fmt!(url_tail, "d/{}.vtt{e}", u32) {
+            let track = mp4
+                .movie()
+                .track_by_id(track_id)
+                .ok_or_else(|| ioerr!(NotFound, "track not found"))?;
+            let mut data = Vec::new();
+            super::subtitle::subtitle_extract(mp4, track, super::subtitle::Format::Vtt, &mut data)?;
+            return Ok(("text/vtt; charset=utf-8", Arc::new(data)));
+        }
+
+        let (track_id, number) = match scan_fmt!(url_tail, "d/{}.{}.m4s{e}", u32, u32) {
+            Ok(tups) => tups,
+            Err(_) => return Err(ioerr!(InvalidData, "bad request")),
+        };
+        let segments = hls::track_segments(mp4, track_id, None, max_segment_size)?;
+        let segment = number
+            .checked_sub(1)
+            .and_then(|idx| segments.get(idx as usize))
+            .ok_or_else(|| ioerr!(NotFound, "segment not found"))?;
+
+        let mime = match mp4.movie().track_by_id(track_id) {
+            Some(trak) if trak.media().handler().is_audio() => "audio/mp4",
+            Some(trak) if trak.media().handler().is_subtitle() => "application/mp4",
+            _ => "video/mp4",
+        };
+        let fs = FragmentSource {
+            src_track_id: track_id,
+            dst_track_id: 1,
+            from_sample: segment.start_sample,
+            to_sample: segment.end_sample,
+        };
+        let keys = encryption::track_keys(mp4, &[track_id])?;
+        let wvtt = mime == "application/mp4";
+        let content = hls::movie_fragment(mp4, number, fs, range_end, &keys, wvtt)?;
+
+        Ok((mime, content))
+    }
+
+    /// `media segment` data as bytes.
+    pub fn media_data(&self) -> &'_ [u8] {
+        &self.0.content[..]
+    }
+}
@@ -0,0 +1,824 @@
+//! On-the-fly Common Encryption of fMP4 segments.
+//!
+//! Supports the `cenc` (AES-CTR) and `cbcs` (AES-CBC, 1:9 pattern for video)
+//! schemes of ISO/IEC 23001-7. Video samples are encrypted per NAL unit
+//! (subsample encryption), so only H.264 and HEVC video can be encrypted.
+//! The NAL unit header and slice header stay in the clear, to find their
+//! length the slice headers are parsed. Audio samples are encrypted as a whole.
+//!
+//! Keys come from a [`KeyProvider`]. [`KeyFile`] is a simple provider
+//! that reads the keys from a local file, which is enough for testing
+//! with ClearKey. A provider that talks to a KMS can be plugged in by
+//! implementing the trait.
+//!
+//! After a provider has been registered with [`set_key_provider`], the
+//! [`hls`](crate::streaming::hls) and [`dash`](crate::streaming::dash)
+//! modules encrypt every track for which the provider returns a key.
+//!
+//! To encrypt fragments directly, see
+//! [`media_init_section_encrypted`](crate::streaming::fragment::media_init_section_encrypted) and
+//! [`movie_fragment_encrypted`](crate::streaming::fragment::movie_fragment_encrypted).
+//!
+//! ## HLS
+//!
+//! For `HLS` clients that do not do `DRM`, there is [`HlsEncryption`],
+//! which enables `METHOD=AES-128` or `METHOD=SAMPLE-AES` in the `HLS`
+//! playlists. The keys are served by
+//! [`handle_hls`](crate::streaming::http_handler::handle_hls).
+//!
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use sha2::Sha256;
+
+use crate::boxes::cenc::encrypted_sample_entry;
+use crate::boxes::*;
+use crate::io::{CountBytes, MemBuffer};
+use crate::mp4box::{BoxInfo, MP4};
+use crate::serialize::{BoxBytes, ToBytes};
+use crate::types::*;
+
+use super::slice_header::SliceHeaderParser;
+
+/// Protection scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    /// AES-CTR full sample and subsample encryption.
+    Cenc,
+    /// AES-CBC subsample pattern encryption, with a constant IV.
+    Cbcs,
+}
+
+impl Scheme {
+    /// The four character code used in the `schm` box.
+    pub fn fourcc(&self) -> FourCC {
+        match self {
+            Scheme::Cenc => FourCC::new("cenc"),
+            Scheme::Cbcs => FourCC::new("cbcs"),
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Scheme> {
+        match s {
+            "cenc" => Ok(Scheme::Cenc),
+            "cbcs" => Ok(Scheme::Cbcs),
+            _ => Err(ioerr!(InvalidInput, "unknown encryption scheme: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.fourcc())
+    }
+}
+
+/// The key used to encrypt a track.
+#[derive(Clone, Debug)]
+pub struct ContentKey {
+    pub scheme: Scheme,
+    /// Key ID.
+    pub kid: [u8; 16],
+    /// AES-128 key.
+    pub key: [u8; 16],
+    /// For `cenc`, the first 8 bytes are the base of the per-sample IVs.
+    /// The track id and the sample number are added to it, so tracks
+    /// that share a key never share an IV.
+    /// For `cbcs` this is the constant IV.
+    pub iv: [u8; 16],
+    /// `pssh` boxes for the init section. If empty, a W3C common `pssh`
+    /// box with the key ID is added (which is what ClearKey uses).
+    pub pssh: Vec<ProtectionSystemSpecificHeaderBox>,
+}
+
+impl ContentKey {
+    /// Key ID as a UUID string.
+    pub fn kid_string(&self) -> String {
+        Uuid(self.kid).to_string()
+    }
+
+    /// Key ID as a hexadecimal string.
+    pub fn kid_hex(&self) -> String {
+        hex(&self.kid)
+    }
+
+    // The `pssh` boxes to put in the init section.
+    fn pssh_boxes(&self) -> Vec<ProtectionSystemSpecificHeaderBox> {
+        if !self.pssh.is_empty() {
+            return self.pssh.clone();
+        }
+        let common_system_id = [
+            0x10, 0x77, 0xef, 0xec, 0xc0, 0xb2, 0x4d, 0x02,
+            0xac, 0xe3, 0x3c, 0x1e, 0x52, 0xe2, 0xfb, 0x4b,
+        ];
+        vec![ProtectionSystemSpecificHeaderBox {
+            system_id: Uuid(common_system_id),
+            kids: vec![Uuid(self.kid)],
+            data: Data::default(),
+        }]
+    }
+
+    // The `EXT-X-KEY` tags for a `HLS` playlist with segments encrypted
+    // with this key, one for each `pssh` box.
+    pub(crate) fn hls_key_tags(&self) -> io::Result<String> {
+        let method = match self.scheme {
+            Scheme::Cenc => "SAMPLE-AES-CTR",
+            Scheme::Cbcs => "SAMPLE-AES",
+        };
+        let mut tags = String::new();
+        for pssh in self.pssh_boxes() {
+            let mut buffer = MemBuffer::new();
+            pssh.to_bytes(&mut buffer)?;
+            tags += &format!(
+                "#EXT-X-KEY:METHOD={},URI=\"data:text/plain;base64,{}\",KEYID=0x{},\
+                 KEYFORMAT=\"urn:uuid:{}\",KEYFORMATVERSIONS=\"1\"\n",
+                method,
+                base64(&buffer.into_vec()),
+                self.kid_hex(),
+                pssh.system_id,
+            );
+        }
+        Ok(tags)
+    }
+}
+
+/// Something that hands out keys.
+pub trait KeyProvider: Send + Sync {
+    /// Return the key for a track, or `None` if the track should not be encrypted.
+    fn content_key(&self, mp4: &MP4, track_id: u32) -> io::Result<Option<ContentKey>>;
+}
+
+/// Keys for a set of tracks, by (source) track id.
+pub type TrackKeys = HashMap<u32, ContentKey>;
+
+static KEY_PROVIDER: OnceCell<Box<dyn KeyProvider>> = OnceCell::new();
+
+/// Set the process-wide key provider.
+///
+/// Can be called only once, returns an error if a provider was already set.
+pub fn set_key_provider(provider: impl KeyProvider + 'static) -> io::Result<()> {
+    KEY_PROVIDER
+        .set(Box::new(provider))
+        .map_err(|_| ioerr!(AlreadyExists, "key provider already set"))
+}
+
+/// Ask the process-wide key provider for the key of this track.
+pub fn content_key(mp4: &MP4, track_id: u32) -> io::Result<Option<ContentKey>> {
+    match KEY_PROVIDER.get() {
+        Some(provider) => provider.content_key(mp4, track_id),
+        None => Ok(None),
+    }
+}
+
+// Keys for these tracks from the process-wide key provider.
+pub(crate) fn track_keys(mp4: &MP4, tracks: &[u32]) -> io::Result<TrackKeys> {
+    let mut keys = TrackKeys::new();
+    for &track_id in tracks {
+        if let Some(key) = content_key(mp4, track_id)? {
+            keys.insert(track_id, key);
+        }
+    }
+    Ok(keys)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum KeyFileTrack {
+    Any,
+    Audio,
+    Video,
+    Id(u32),
+}
+
+/// Keys from a local file.
+///
+/// Each line has the format:
+///
+/// ```text
+/// SCHEME TRACK KID KEY IV
+/// ```
+///
+/// - `SCHEME`: `cenc` or `cbcs`
+/// - `TRACK`: a track id, `audio`, `video`, or `*` for any track
+/// - `KID`, `KEY`, `IV`: 16 bytes, in hex (dashes are allowed, so a `KID` can be an UUID)
+///
+/// The `IV` should be random, and not be used with any other key. Empty
+/// lines and lines starting with `#` are ignored. The first matching line wins.
+#[derive(Clone, Debug, Default)]
+pub struct KeyFile {
+    entries: Vec<(KeyFileTrack, ContentKey)>,
+}
+
+impl KeyFile {
+    /// Read and parse a key file.
+    pub fn open(path: &str) -> io::Result<KeyFile> {
+        let text = fs::read_to_string(path)?;
+        KeyFile::parse(&text).map_err(|e| ioerr!(e.kind(), "{}: {}", path, e))
+    }
+
+    /// Parse the contents of a key file.
+    pub fn parse(text: &str) -> io::Result<KeyFile> {
+        let mut entries = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split_whitespace().collect();
+            if fields.len() != 5 {
+                return Err(ioerr!(InvalidData, "line {}: expected 5 fields", idx + 1));
+            }
+            let err = |e: io::Error| ioerr!(InvalidData, "line {}: {}", idx + 1, e);
+            let scheme = fields[0].parse::<Scheme>().map_err(err)?;
+            let track = match fields[1] {
+                "*" => KeyFileTrack::Any,
+                "audio" => KeyFileTrack::Audio,
+                "video" => KeyFileTrack::Video,
+                id => match id.parse::<u32>() {
+                    Ok(id) => KeyFileTrack::Id(id),
+                    Err(_) => return Err(ioerr!(InvalidData, "line {}: bad track: {}", idx + 1, id)),
+                },
+            };
+            let kid = parse_hex16(fields[2]).map_err(err)?;
+            let key = parse_hex16(fields[3]).map_err(err)?;
+            let iv = parse_hex16(fields[4]).map_err(err)?;
+            let key = ContentKey { scheme, kid, key, iv, pssh: Vec::new() };
+            entries.push((track, key));
+        }
+        Ok(KeyFile { entries })
+    }
+}
+
+impl KeyProvider for KeyFile {
+    fn content_key(&self, mp4: &MP4, track_id: u32) -> io::Result<Option<ContentKey>> {
+        let track = match mp4.movie().track_by_id(track_id) {
+            Some(track) => track,
+            None => return Ok(None),
+        };
+        let hdlr = track.media().handler();
+        let key = self.entries.iter().find(|(t, _)| match t {
+            KeyFileTrack::Any => hdlr.is_audio() || hdlr.is_video(),
+            KeyFileTrack::Audio => hdlr.is_audio(),
+            KeyFileTrack::Video => hdlr.is_video(),
+            KeyFileTrack::Id(id) => *id == track_id,
+        });
+        Ok(key.map(|(_, k)| k.clone()))
+    }
+}
+
+fn parse_hex16(s: &str) -> io::Result<[u8; 16]> {
+    let s = s.replace('-', "");
+    if s.len() != 32 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ioerr!(InvalidData, "expected 16 hex bytes: {}", s));
+    }
+    let mut v = [0u8; 16];
+    for (i, b) in v.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[i * 2 .. i * 2 + 2], 16).unwrap();
+    }
+    Ok(v)
+}
+
+fn base64(data: &[u8]) -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                s.push(CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+fn hex(data: &[u8]) -> String {
+    use std::fmt::Write;
+    let mut s = String::new();
+    for b in data {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s
+}
+
+// Video crypto pattern for `cbcs`: encrypt 1 block, skip 9.
+const CBCS_CRYPT_BYTE_BLOCK: u8 = 1;
+const CBCS_SKIP_BYTE_BLOCK: u8 = 9;
+
+/// Build the `sinf` box (without `frma`) for a track.
+fn protection_scheme_info(key: &ContentKey, is_video: bool) -> ProtectionSchemeInfoBox {
+    let schm = SchemeTypeBox {
+        scheme_type: key.scheme.fourcc(),
+        scheme_version: 0x10000,
+        scheme_uri: None,
+    };
+    let tenc = match key.scheme {
+        Scheme::Cenc => TrackEncryptionBox {
+            default_crypt_byte_block: 0,
+            default_skip_byte_block: 0,
+            default_is_protected: 1,
+            default_per_sample_iv_size: 8,
+            default_kid: Uuid(key.kid),
+            default_constant_iv: None,
+        },
+        Scheme::Cbcs => TrackEncryptionBox {
+            default_crypt_byte_block: if is_video { CBCS_CRYPT_BYTE_BLOCK } else { 0 },
+            default_skip_byte_block: if is_video { CBCS_SKIP_BYTE_BLOCK } else { 0 },
+            default_is_protected: 1,
+            default_per_sample_iv_size: 0,
+            default_kid: Uuid(key.kid),
+            default_constant_iv: Some(Data(key.iv.to_vec())),
+        },
+    };
+    let schi = SchemeInformationBox {
+        boxes: vec![tenc.to_mp4box()],
+    };
+    ProtectionSchemeInfoBox {
+        boxes: vec![schm.to_mp4box(), schi.to_mp4box()],
+    }
+}
+
+// Replace the sample entries with encrypted sample entries.
+pub(crate) fn encrypt_sample_description(stsd: &mut SampleDescriptionBox, key: &ContentKey) -> io::Result<()> {
+    for entry in stsd.entries.vec.iter_mut() {
+        let is_video = matches!(entry, MP4Box::AvcSampleEntry(_) | MP4Box::HEVCSampleEntry(_) |
+            MP4Box::HEV1SampleEntry(_) | MP4Box::DVH1SampleEntry(_) | MP4Box::DVHESampleEntry(_));
+        let sinf = protection_scheme_info(key, is_video);
+        match encrypted_sample_entry(entry, &sinf) {
+            Some(encrypted) => *entry = encrypted,
+            None => {
+                return Err(ioerr!(
+                    InvalidInput,
+                    "encryption of {} sample entries is not supported",
+                    entry.fourcc()
+                ))
+            },
+        }
+    }
+    Ok(())
+}
+
+// The `pssh` boxes for the init section.
+pub(crate) fn pssh_boxes(keys: &TrackKeys) -> Vec<MP4Box> {
+    let mut boxes: Vec<MP4Box> = Vec::new();
+    let mut seen = Vec::new();
+    for key in keys.values() {
+        for pssh in key.pssh_boxes() {
+            let id = (pssh.system_id.0, pssh.kids.iter().map(|k| k.0).collect::<Vec<_>>());
+            if !seen.contains(&id) {
+                seen.push(id);
+                boxes.push(pssh.to_mp4box());
+            }
+        }
+    }
+    boxes
+}
+
+/// Encrypts the samples of one track.
+pub(crate) struct SampleEncryptor {
+    scheme: Scheme,
+    cipher: Aes128,
+    iv: [u8; 16],
+    track_id: u32,
+    // Size of the NAL unit length field, if this is H.264 / HEVC video.
+    nal_length_size: Option<usize>,
+    // To find the end of the slice headers, they stay in the clear.
+    slice_headers: Option<SliceHeaderParser>,
+}
+
+impl SampleEncryptor {
+    /// Returns an error if the track's codec is not supported.
+    pub(crate) fn new(track: &TrackBox, key: &ContentKey) -> io::Result<SampleEncryptor> {
+        let stsd = track.media().media_info().sample_table().sample_description();
+        let entry = stsd.entries.iter().next().ok_or_else(|| ioerr!(InvalidData, "no sample entry"))?;
+        let (nal_length_size, slice_headers) = match entry {
+            MP4Box::AvcSampleEntry(e) => {
+                let avcc = first_box!(e.boxes, AvcConfigurationBox)
+                    .ok_or_else(|| ioerr!(InvalidData, "avc1: no avcC box"))?;
+                let config = &avcc.configuration.data.0;
+                let len = config.first().map(|b| (b & 0x03) as usize + 1);
+                (Some(len.unwrap_or(4)), Some(SliceHeaderParser::avc(config)?))
+            },
+            MP4Box::HEVCSampleEntry(e) => hevc_video(&e.boxes)?,
+            MP4Box::HEV1SampleEntry(e) => hevc_video(&e.boxes)?,
+            MP4Box::DVH1SampleEntry(e) => hevc_video(&e.boxes)?,
+            MP4Box::DVHESampleEntry(e) => hevc_video(&e.boxes)?,
+            MP4Box::AacSampleEntry(_) |
+            MP4Box::Ac3SampleEntry(_) |
+            MP4Box::Ec3SampleEntry(_) |
+            MP4Box::OpusSampleEntry(_) |
+            MP4Box::FlacSampleEntry(_) => (None, None),
+            other => {
+                return Err(ioerr!(
+                    InvalidInput,
+                    "encryption of {} tracks is not supported",
+                    other.fourcc()
+                ))
+            },
+        };
+        Ok(SampleEncryptor {
+            scheme: key.scheme,
+            cipher: Aes128::new(GenericArray::from_slice(&key.key)),
+            iv: key.iv,
+            track_id: track.track_id(),
+            nal_length_size,
+            slice_headers,
+        })
+    }
+
+    /// Encrypt one sample in-place.
+    ///
+    /// The track id and `sample_number` are used to derive the IV for `cenc`.
+    /// Samples must be passed in decode order, parameter sets in the
+    /// samples are needed to parse the slice headers that follow them.
+    pub(crate) fn encrypt(&mut self, sample_number: u32, data: &mut [u8]) -> io::Result<SampleEncryptionEntry> {
+        let subsamples = match self.nal_length_size {
+            Some(len) => self.subsamples(len, data)?,
+            None => Vec::new(),
+        };
+
+        match self.scheme {
+            Scheme::Cenc => {
+                let mut iv = [0u8; 8];
+                let base = u64::from_be_bytes(self.iv[..8].try_into().unwrap());
+                let counter = (self.track_id as u64) << 32 | sample_number as u64;
+                iv.copy_from_slice(&base.wrapping_add(counter).to_be_bytes());
+                let mut ctr = Ctr::new(&self.cipher, iv);
+                for_each_protected_range(data, &subsamples, |range| ctr.apply(range));
+                Ok(SampleEncryptionEntry {
+                    iv: Data(iv.to_vec()),
+                    subsamples,
+                })
+            },
+            Scheme::Cbcs => {
+                let (crypt, skip) = if self.nal_length_size.is_some() {
+                    (CBCS_CRYPT_BYTE_BLOCK as usize, CBCS_SKIP_BYTE_BLOCK as usize)
+                } else {
+                    (0, 0)
+                };
+                for_each_protected_range(data, &subsamples, |range| {
+                    cbc_pattern(&self.cipher, &self.iv, crypt, skip, range)
+                });
+                Ok(SampleEncryptionEntry {
+                    iv: Data::default(),
+                    subsamples,
+                })
+            },
+        }
+    }
+
+    // Split a sample into clear and protected ranges. The NAL unit header and
+    // slice header of a slice are clear, and so are all other NAL units.
+    fn subsamples(&mut self, nal_length_size: usize, data: &[u8]) -> io::Result<Vec<SubsampleEntry>> {
+        let mut subsamples = Vec::new();
+        let mut clear = 0usize;
+        let mut pos = 0usize;
+
+        while pos + nal_length_size <= data.len() {
+            let mut nal_size = 0usize;
+            for b in &data[pos .. pos + nal_length_size] {
+                nal_size = (nal_size << 8) | *b as usize;
+            }
+            let nal_start = pos + nal_length_size;
+            let nal_size = std::cmp::min(nal_size, data.len() - nal_start);
+            pos = nal_start + nal_size;
+
+            let nal = &data[nal_start .. pos];
+            let header_len = match self.slice_headers.as_mut() {
+                Some(parser) => parser.parse(nal)?,
+                None => None,
+            };
+            let protected = match header_len {
+                Some(len) => nal_size.saturating_sub(len) & !0x0f,
+                None => 0,
+            };
+            if protected == 0 {
+                clear += nal_length_size + nal_size;
+                continue;
+            }
+            clear += nal_length_size + nal_size - protected;
+            push_subsample(&mut subsamples, clear, protected as u32);
+            clear = 0;
+        }
+        clear += data.len() - std::cmp::min(pos, data.len());
+        if clear > 0 || subsamples.is_empty() {
+            push_subsample(&mut subsamples, clear, 0);
+        }
+        Ok(subsamples)
+    }
+}
+
+// The NAL unit length size and the slice header parser of a HEVC track.
+fn hevc_video(boxes: &[MP4Box]) -> io::Result<(Option<usize>, Option<SliceHeaderParser>)> {
+    let hvcc = first_box!(boxes[..], HEVCConfigurationBox)
+        .ok_or_else(|| ioerr!(InvalidData, "hvc1: no hvcC box"))?;
+    let nal_length_size = (hvcc.configuration.various & 0x03) as usize + 1;
+    let parser = SliceHeaderParser::hevc(&hvcc.configuration.data.0)?;
+    Ok((Some(nal_length_size), Some(parser)))
+}
+
+fn push_subsample(subsamples: &mut Vec<SubsampleEntry>, mut clear: usize, protected: u32) {
+    while clear > u16::MAX as usize {
+        subsamples.push(SubsampleEntry {
+            bytes_of_clear_data: u16::MAX,
+            bytes_of_protected_data: 0,
+        });
+        clear -= u16::MAX as usize;
+    }
+    subsamples.push(SubsampleEntry {
+        bytes_of_clear_data: clear as u16,
+        bytes_of_protected_data: protected,
+    });
+}
+
+// Call `f` for every protected range in the sample.
+fn for_each_protected_range(data: &mut [u8], subsamples: &[SubsampleEntry], mut f: impl FnMut(&mut [u8])) {
+    if subsamples.is_empty() {
+        f(data);
+        return;
+    }
+    let mut pos = 0;
+    for s in subsamples {
+        pos += s.bytes_of_clear_data as usize;
+        let end = pos + s.bytes_of_protected_data as usize;
+        f(&mut data[pos .. end]);
+        pos = end;
+    }
+}
+
+// AES-CTR with a 64 bit IV and a 64 bit block counter. The key
+// stream continues over all the protected ranges of a sample.
+struct Ctr<'a> {
+    cipher: &'a Aes128,
+    iv: [u8; 8],
+    counter: u64,
+    keystream: [u8; 16],
+    offset: usize,
+}
+
+impl<'a> Ctr<'a> {
+    fn new(cipher: &'a Aes128, iv: [u8; 8]) -> Ctr<'a> {
+        Ctr {
+            cipher,
+            iv,
+            counter: 0,
+            keystream: [0u8; 16],
+            offset: 16,
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        for b in data.iter_mut() {
+            if self.offset == 16 {
+                let mut block = [0u8; 16];
+                block[..8].copy_from_slice(&self.iv);
+                block[8..].copy_from_slice(&self.counter.to_be_bytes());
+                let block = GenericArray::from_mut_slice(&mut block);
+                self.cipher.encrypt_block(block);
+                self.keystream.copy_from_slice(block);
+                self.counter = self.counter.wrapping_add(1);
+                self.offset = 0;
+            }
+            *b ^= self.keystream[self.offset];
+            self.offset += 1;
+        }
+    }
+}
+
+// AES-CBC pattern encryption. The IV is reset for every range, and only
+// complete blocks are encrypted. A pattern of (0, 0) means "all blocks".
+fn cbc_pattern(cipher: &Aes128, iv: &[u8; 16], crypt: usize, skip: usize, data: &mut [u8]) {
+    let mut chain = *iv;
+    let (crypt, skip) = if crypt == 0 { (1, 0) } else { (crypt, skip) };
+    let blocks = data.len() / 16;
+    let mut idx = 0;
+    while idx < blocks {
+        for _ in 0 .. crypt {
+            if idx >= blocks {
+                break;
+            }
+            let block = &mut data[idx * 16 .. idx * 16 + 16];
+            for (b, c) in block.iter_mut().zip(chain.iter()) {
+                *b ^= c;
+            }
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+            chain.copy_from_slice(block);
+            idx += 1;
+        }
+        idx += skip;
+    }
+}
+
+/// Add the `senc`, `saiz` and `saio` boxes to a track fragment.
+///
+/// The `saio` offset is filled in later by `set_aux_info_offsets`.
+pub(crate) fn add_encryption_boxes(traf: &mut TrackFragmentBox, entries: Vec<SampleEncryptionEntry>) {
+    let sizes: Vec<u8> = entries
+        .iter()
+        .map(|e| {
+            let sub = if e.subsamples.is_empty() { 0 } else { 2 + 6 * e.subsamples.len() };
+            std::cmp::min(e.iv.len() + sub, 255) as u8
+        })
+        .collect();
+    let default_sample_info_size = match sizes.first() {
+        Some(&first) if sizes.iter().all(|&s| s == first) => first,
+        _ => 0,
+    };
+    let saiz = SampleAuxiliaryInformationSizesBox {
+        aux_info_type: None,
+        aux_info_type_parameter: 0,
+        default_sample_info_size,
+        sample_count: sizes.len() as u32,
+        sample_info_sizes: if default_sample_info_size == 0 { sizes } else { Vec::new() },
+    };
+    let saio = SampleAuxiliaryInformationOffsetsBox {
+        aux_info_type: None,
+        aux_info_type_parameter: 0,
+        offsets: vec![0],
+    };
+    traf.boxes.push(saiz.to_mp4box());
+    traf.boxes.push(saio.to_mp4box());
+    traf.boxes.push(SampleEncryptionBox { entries }.to_mp4box());
+}
+
+/// Point the `saio` boxes at the sample info in the `senc` boxes.
+///
+/// The offsets are relative to the start of the `moof` box.
+pub(crate) fn set_aux_info_offsets(moof: &mut MovieFragmentBox) {
+    // moof header.
+    let mut offset = 8;
+    for box_ in moof.boxes.iter_mut() {
+        let traf = match box_ {
+            MP4Box::TrackFragmentBox(traf) => traf,
+            other => {
+                offset += other.size();
+                continue;
+            },
+        };
+        let mut cb = CountBytes::new();
+        let _ = traf.to_bytes(&mut cb);
+        let size = cb.size();
+        // traf header.
+        let mut senc_offset = offset + 8;
+        for b in &traf.boxes {
+            if let MP4Box::SampleEncryptionBox(_) = b {
+                // full box header + sample_count.
+                senc_offset += 16;
+                break;
+            }
+            senc_offset += b.size();
+        }
+        if let Some(saio) = first_box_mut!(traf, SampleAuxiliaryInformationOffsetsBox) {
+            saio.offsets = vec![senc_offset];
+        }
+        offset += size;
+    }
+}
+
+/// `HLS` encryption method, the `METHOD` attribute of `EXT-X-KEY`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HlsMethod {
+    /// Whole segment AES-128-CBC encryption, with PKCS7 padding.
+    Aes128,
+    /// Sample encryption. For fMP4 segments this is `cbcs`.
+    SampleAes,
+}
+
+impl HlsMethod {
+    /// Name as used in the playlist.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HlsMethod::Aes128 => "AES-128",
+            HlsMethod::SampleAes => "SAMPLE-AES",
+        }
+    }
+}
+
+impl FromStr for HlsMethod {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<HlsMethod> {
+        match s.to_ascii_uppercase().as_str() {
+            "AES-128" => Ok(HlsMethod::Aes128),
+            "SAMPLE-AES" => Ok(HlsMethod::SampleAes),
+            _ => Err(ioerr!(InvalidInput, "unknown HLS encryption method {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for HlsMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `HLS` `AES-128` / `SAMPLE-AES` encryption settings.
+///
+/// The keys and IVs are derived from a master secret, the path of the
+/// `mp4` file and the track id (using HMAC-SHA256), so there is nothing
+/// to store and keys can be re-generated at any time.
+pub struct HlsEncryption {
+    pub method: HlsMethod,
+    secret: Vec<u8>,
+}
+
+impl HlsEncryption {
+    pub fn new(method: HlsMethod, secret: impl Into<Vec<u8>>) -> HlsEncryption {
+        HlsEncryption {
+            method,
+            secret: secret.into(),
+        }
+    }
+
+    /// The key and IV for a track of a file.
+    pub fn key(&self, mp4: &MP4, track_id: u32) -> io::Result<HlsKey> {
+        let d = self.derive(b"key", mp4, track_id)?;
+        Ok(HlsKey {
+            key: d[..16].try_into().unwrap(),
+            iv: d[16..].try_into().unwrap(),
+        })
+    }
+
+    // SAMPLE-AES for fMP4 is `cbcs` with the HLS key as the content key.
+    pub(crate) fn content_key(&self, mp4: &MP4, track_id: u32) -> io::Result<ContentKey> {
+        let key = self.key(mp4, track_id)?;
+        let kid = self.derive(b"kid", mp4, track_id)?;
+        Ok(ContentKey {
+            scheme: Scheme::Cbcs,
+            kid: kid[..16].try_into().unwrap(),
+            key: key.key,
+            iv: key.iv,
+            pssh: Vec::new(),
+        })
+    }
+
+    fn derive(&self, label: &[u8], mp4: &MP4, track_id: u32) -> io::Result<[u8; 32]> {
+        let path = mp4
+            .input_file
+            .as_ref()
+            .ok_or_else(|| ioerr!(InvalidInput, "cannot derive key: mp4 file has no path"))?;
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.secret).expect("HMAC accepts any key size");
+        mac.update(label);
+        mac.update(&[0]);
+        mac.update(path.as_bytes());
+        mac.update(&[0]);
+        mac.update(&track_id.to_be_bytes());
+        Ok(mac.finalize().into_bytes().into())
+    }
+}
+
+/// A `HLS` key and IV.
+#[derive(Clone, Debug)]
+pub struct HlsKey {
+    pub key: [u8; 16],
+    pub iv: [u8; 16],
+}
+
+impl HlsKey {
+    /// IV as used in the `IV` attribute of `EXT-X-KEY`.
+    pub fn iv_hex(&self) -> String {
+        format!("0x{}", hex(&self.iv))
+    }
+
+    /// AES-128-CBC encrypt a whole segment.
+    pub fn encrypt_segment(&self, data: &[u8]) -> Vec<u8> {
+        let cipher = Aes128::new(GenericArray::from_slice(&self.key));
+        // PKCS7 padding, always at least one byte.
+        let pad = 16 - data.len() % 16;
+        let mut out = Vec::with_capacity(data.len() + pad);
+        out.extend_from_slice(data);
+        out.resize(data.len() + pad, pad as u8);
+        cbc_pattern(&cipher, &self.iv, 0, 0, &mut out);
+        out
+    }
+}
+
+static HLS_ENCRYPTION: OnceCell<HlsEncryption> = OnceCell::new();
+
+/// Enable `HLS` encryption process-wide.
+///
+/// Can be called only once, returns an error if it was already set.
+pub fn set_hls_encryption(encryption: HlsEncryption) -> io::Result<()> {
+    HLS_ENCRYPTION
+        .set(encryption)
+        .map_err(|_| ioerr!(AlreadyExists, "HLS encryption already set"))
+}
+
+/// The process-wide `HLS` encryption settings, if any.
+pub fn hls_encryption() -> Option<&'static HlsEncryption> {
+    HLS_ENCRYPTION.get()
+}
@@ -0,0 +1,374 @@
+//! Elementary stream export.
+//!
+//! Converts the samples of a track to a raw elementary stream, that
+//! can be played or processed by other tools:
+//!
+//! - `H.264` and `HEVC` samples are converted to an Annex-B byte stream,
+//!   with an access unit delimiter in front of every frame and the
+//!   parameter sets from the `avcC` / `hvcC` box in front of every sync frame.
+//! - `AAC` frames get an `ADTS` header, built from the `AudioSpecificConfig`.
+//! - `AC-3` and `E-AC-3` frames are copied as-is.
+//! - subtitle tracks are exported in `SRT` format.
+//!
+//! The same conversion is used for [`MPEG-TS`](super::mpegts) segments.
+//!
+use std::cmp;
+use std::io::{self, Write};
+
+use crate::boxes::*;
+use crate::io::DataRef;
+use crate::mp4box::{BoxInfo, MP4Box, MP4};
+
+use super::subtitle::{self, Format};
+
+// The elementary stream format of a track.
+pub(crate) enum Codec {
+    Avc {
+        nal_length_size: usize,
+        parameter_sets: Vec<u8>,
+    },
+    Hevc {
+        nal_length_size: usize,
+        parameter_sets: Vec<u8>,
+    },
+    Aac {
+        profile: u8,
+        sampling_frequency_index: u8,
+        channel_config: u8,
+    },
+    Ac3,
+    Ec3,
+}
+
+impl Codec {
+    pub(crate) fn new(track: &TrackBox) -> io::Result<Codec> {
+        let stsd = track.media().media_info().sample_table().sample_description();
+        let entry = stsd
+            .entries
+            .iter()
+            .next()
+            .ok_or_else(|| ioerr!(InvalidData, "no sample entry"))?;
+        let codec = match entry {
+            MP4Box::AvcSampleEntry(e) => {
+                let avcc = first_box!(e.boxes, AvcConfigurationBox)
+                    .ok_or_else(|| ioerr!(InvalidData, "avc1: no avcC box"))?;
+                let (nal_length_size, parameter_sets) = avc_parameter_sets(&avcc.configuration.data.0)?;
+                Codec::Avc {
+                    nal_length_size,
+                    parameter_sets,
+                }
+            },
+            MP4Box::HEVCSampleEntry(e) => hevc_codec(&e.boxes)?,
+            MP4Box::HEV1SampleEntry(e) => hevc_codec(&e.boxes)?,
+            MP4Box::AacSampleEntry(e) => {
+                let esds = first_box!(e.boxes, ESDescriptorBox)
+                    .ok_or_else(|| ioerr!(InvalidData, "mp4a: no esds box"))?;
+                let audio = esds
+                    .es_descriptor
+                    .decoder_config
+                    .specific_info
+                    .audio
+                    .as_ref()
+                    .ok_or_else(|| ioerr!(InvalidData, "mp4a: no AudioSpecificConfig"))?;
+                // ADTS can only signal the object types 1 - 4 (Main, LC, SSR, LTP).
+                // HE-AAC uses implicit signaling of SBR / PS.
+                if audio.core_profile < 1 || audio.core_profile > 4 || audio.sampling_frequency_index > 12 {
+                    return Err(ioerr!(InvalidInput, "{}: cannot be put in ADTS", esds.codec_id()));
+                }
+                Codec::Aac {
+                    profile: audio.core_profile - 1,
+                    sampling_frequency_index: audio.sampling_frequency_index,
+                    channel_config: audio.channel_config,
+                }
+            },
+            MP4Box::Ac3SampleEntry(_) => Codec::Ac3,
+            MP4Box::Ec3SampleEntry(_) => Codec::Ec3,
+            other => {
+                return Err(ioerr!(
+                    InvalidInput,
+                    "{}: cannot be converted to an elementary stream",
+                    other.fourcc()
+                ))
+            },
+        };
+        Ok(codec)
+    }
+
+    pub(crate) fn is_video(&self) -> bool {
+        matches!(self, Codec::Avc { .. } | Codec::Hevc { .. })
+    }
+
+    // Convert one sample to its elementary stream format, and append it to `buf`.
+    pub(crate) fn append_sample(&self, buf: &mut Vec<u8>, data: &[u8], is_sync: bool) -> io::Result<()> {
+        match self {
+            Codec::Avc {
+                nal_length_size,
+                parameter_sets,
+            } => {
+                buf.extend_from_slice(&[0, 0, 0, 1, 0x09, 0xf0]);
+                if is_sync {
+                    buf.extend_from_slice(parameter_sets);
+                }
+                annex_b(buf, data, *nal_length_size, |nal| nal & 0x1f == 9)
+            },
+            Codec::Hevc {
+                nal_length_size,
+                parameter_sets,
+            } => {
+                buf.extend_from_slice(&[0, 0, 0, 1, 0x46, 0x01, 0x50]);
+                if is_sync {
+                    buf.extend_from_slice(parameter_sets);
+                }
+                annex_b(buf, data, *nal_length_size, |nal| (nal >> 1) & 0x3f == 35)
+            },
+            Codec::Aac {
+                profile,
+                sampling_frequency_index,
+                channel_config,
+            } => {
+                // ISO/IEC 14496-3 1.A.2.2 adts_fixed_header / adts_variable_header.
+                let len = data.len() + 7;
+                if len > 0x1fff {
+                    return Err(ioerr!(
+                        InvalidData,
+                        "AAC frame too large for ADTS: {}",
+                        data.len()
+                    ));
+                }
+                buf.extend_from_slice(&[
+                    0xff,
+                    0xf1,
+                    (profile << 6) | (sampling_frequency_index << 2) | (channel_config >> 2),
+                    ((channel_config & 0x03) << 6) | (len >> 11) as u8,
+                    (len >> 3) as u8,
+                    ((len & 0x07) << 5) as u8 | 0x1f,
+                    0xfc,
+                ]);
+                buf.extend_from_slice(data);
+                Ok(())
+            },
+            Codec::Ac3 | Codec::Ec3 => {
+                buf.extend_from_slice(data);
+                Ok(())
+            },
+        }
+    }
+
+    // The number of bytes that `append_sample` appends for this sample.
+    //
+    // This only reads the NAL unit lengths and headers of video samples,
+    // not the whole sample.
+    pub(crate) fn sample_len(
+        &self,
+        data_ref: &DataRef,
+        fpos: u64,
+        size: u32,
+        is_sync: bool,
+    ) -> io::Result<usize> {
+        match self {
+            Codec::Avc {
+                nal_length_size,
+                parameter_sets,
+            } => {
+                let len = annex_b_len(data_ref, fpos, size, *nal_length_size, |nal| nal & 0x1f == 9)?;
+                Ok(6 + if is_sync { parameter_sets.len() } else { 0 } + len)
+            },
+            Codec::Hevc {
+                nal_length_size,
+                parameter_sets,
+            } => {
+                let len = annex_b_len(data_ref, fpos, size, *nal_length_size, |nal| {
+                    (nal >> 1) & 0x3f == 35
+                })?;
+                Ok(7 + if is_sync { parameter_sets.len() } else { 0 } + len)
+            },
+            Codec::Aac { .. } => Ok(size as usize + 7),
+            Codec::Ac3 | Codec::Ec3 => Ok(size as usize),
+        }
+    }
+}
+
+/// Converts the samples of an audio or video track to elementary stream format.
+pub struct EsConverter {
+    codec: Codec,
+}
+
+impl EsConverter {
+    /// Returns an error if the codec of the track is not supported.
+    pub fn new(track: &TrackBox) -> io::Result<EsConverter> {
+        Ok(EsConverter {
+            codec: Codec::new(track)?,
+        })
+    }
+
+    /// The usual filename extension for this elementary stream.
+    pub fn extension(&self) -> &'static str {
+        match self.codec {
+            Codec::Avc { .. } => "h264",
+            Codec::Hevc { .. } => "hevc",
+            Codec::Aac { .. } => "aac",
+            Codec::Ac3 => "ac3",
+            Codec::Ec3 => "ec3",
+        }
+    }
+
+    /// Convert one sample, and append it to `buf`.
+    pub fn convert(&self, data: &[u8], is_sync: bool, buf: &mut Vec<u8>) -> io::Result<()> {
+        self.codec.append_sample(buf, data, is_sync)
+    }
+}
+
+/// The filename extension of the elementary stream of this track.
+pub fn extension(track: &TrackBox) -> io::Result<&'static str> {
+    if is_subtitle(track) {
+        return Ok("srt");
+    }
+    Ok(EsConverter::new(track)?.extension())
+}
+
+/// Write a track as an elementary stream.
+///
+/// Audio and video samples are written in decode order. Subtitle tracks
+/// are written as `SRT`.
+pub fn export(mp4: &MP4, track: &TrackBox, mut output: impl Write) -> io::Result<()> {
+    if is_subtitle(track) {
+        return subtitle::subtitle_extract(mp4, track, Format::Srt, output);
+    }
+    let converter = EsConverter::new(track)?;
+    let mut data = Vec::new();
+    let mut buf = Vec::new();
+    for sample in mp4.sample_info_iter(track) {
+        data.resize(sample.size as usize, 0);
+        mp4.data_ref.read_exact_at(&mut data, sample.fpos)?;
+        buf.clear();
+        converter.convert(&data, sample.is_sync, &mut buf)?;
+        output.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+fn is_subtitle(track: &TrackBox) -> bool {
+    let handler_type = track.media().handler().handler_type;
+    matches!(&handler_type.to_be_bytes(), b"sbtl" | b"subt" | b"text")
+}
+
+fn hevc_codec(boxes: &[MP4Box]) -> io::Result<Codec> {
+    let hvcc = first_box!(boxes[..], HEVCConfigurationBox)
+        .ok_or_else(|| ioerr!(InvalidData, "hvc1: no hvcC box"))?;
+    let config = &hvcc.configuration;
+    Ok(Codec::Hevc {
+        nal_length_size: (config.various & 0x03) as usize + 1,
+        parameter_sets: hevc_parameter_sets(&config.data.0)?,
+    })
+}
+
+// The SPS and PPS NAL units from the avcC box, in Annex-B format.
+fn avc_parameter_sets(data: &[u8]) -> io::Result<(usize, Vec<u8>)> {
+    let eof = || ioerr!(UnexpectedEof, "avcC: EOF");
+    let nal_length_size = (data.first().ok_or_else(eof)? & 0x03) as usize + 1;
+    let mut sets = Vec::new();
+    let mut idx = 1;
+    for mask in &[0x1f, 0xff] {
+        let count = data.get(idx).ok_or_else(eof)? & mask;
+        idx += 1;
+        for _ in 0..count {
+            idx = append_nal(&mut sets, data, idx)?;
+        }
+    }
+    Ok((nal_length_size, sets))
+}
+
+// All the NAL units (VPS, SPS, PPS, SEI) from the hvcC box, in Annex-B format.
+fn hevc_parameter_sets(data: &[u8]) -> io::Result<Vec<u8>> {
+    let eof = || ioerr!(UnexpectedEof, "hvcC: EOF");
+    let mut sets = Vec::new();
+    let num_arrays = *data.first().ok_or_else(eof)?;
+    let mut idx = 1;
+    for _ in 0..num_arrays {
+        let hdr = data.get(idx..idx + 3).ok_or_else(eof)?;
+        let num_nalus = u16::from_be_bytes([hdr[1], hdr[2]]);
+        idx += 3;
+        for _ in 0..num_nalus {
+            idx = append_nal(&mut sets, data, idx)?;
+        }
+    }
+    Ok(sets)
+}
+
+// Append a NAL unit with a 16 bit length prefix as an Annex-B NAL unit.
+fn append_nal(buf: &mut Vec<u8>, data: &[u8], idx: usize) -> io::Result<usize> {
+    let eof = || ioerr!(UnexpectedEof, "parameter set: EOF");
+    let len = data.get(idx..idx + 2).ok_or_else(eof)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    let nal = data.get(idx + 2..idx + 2 + len).ok_or_else(eof)?;
+    buf.extend_from_slice(&[0, 0, 0, 1]);
+    buf.extend_from_slice(nal);
+    Ok(idx + 2 + len)
+}
+
+// Convert length-prefixed NAL units to Annex-B. Access unit delimiters
+// are skipped, we always add one ourselves.
+fn annex_b(
+    buf: &mut Vec<u8>,
+    data: &[u8],
+    nal_length_size: usize,
+    is_aud: impl Fn(u8) -> bool,
+) -> io::Result<()> {
+    let mut pos = 0;
+    while pos < data.len() {
+        if pos + nal_length_size > data.len() {
+            return Err(ioerr!(InvalidData, "truncated NAL unit length"));
+        }
+        let mut len = 0usize;
+        for b in &data[pos..pos + nal_length_size] {
+            len = (len << 8) | *b as usize;
+        }
+        pos += nal_length_size;
+        if len > data.len() - pos {
+            return Err(ioerr!(InvalidData, "truncated NAL unit"));
+        }
+        let nal = &data[pos..pos + len];
+        pos += len;
+        if nal.is_empty() || is_aud(nal[0]) {
+            continue;
+        }
+        buf.extend_from_slice(&[0, 0, 0, 1]);
+        buf.extend_from_slice(nal);
+    }
+    Ok(())
+}
+
+// The length of what `annex_b` appends, for a sample at `fpos` in the file.
+fn annex_b_len(
+    data_ref: &DataRef,
+    fpos: u64,
+    size: u32,
+    nal_length_size: usize,
+    is_aud: impl Fn(u8) -> bool,
+) -> io::Result<usize> {
+    let size = size as usize;
+    let mut header = [0u8; 5];
+    let mut total = 0;
+    let mut pos = 0;
+    while pos < size {
+        if pos + nal_length_size > size {
+            return Err(ioerr!(InvalidData, "truncated NAL unit length"));
+        }
+        // The NAL unit length, and the first byte of the NAL unit if there is one.
+        let n = cmp::min(nal_length_size + 1, size - pos);
+        data_ref.read_exact_at(&mut header[..n], fpos + pos as u64)?;
+        let mut len = 0usize;
+        for b in &header[..nal_length_size] {
+            len = (len << 8) | *b as usize;
+        }
+        pos += nal_length_size;
+        if len > size - pos {
+            return Err(ioerr!(InvalidData, "truncated NAL unit"));
+        }
+        if len > 0 && !is_aud(header[nal_length_size]) {
+            total += 4 + len;
+        }
+        pos += len;
+    }
+    Ok(total)
+}
@@ -11,9 +11,12 @@ use std::convert::TryInto;
 use std::fs;
 use std::io;
 
+use super::encryption::{self, ContentKey, SampleEncryptor, TrackKeys};
+use super::subtitle;
 use crate::boxes::*;
-use crate::io::{CountBytes, DataRef};
+use crate::io::CountBytes;
 use crate::mp4box::{MP4Box, MP4};
+use crate::sample_info::{FragmentIndex, SampleInfo};
 use crate::serialize::{BoxBytes, ToBytes};
 use crate::types::*;
 
@@ -27,7 +30,29 @@ pub struct FragmentSource {
 }
 
 /// Build a Media Initialization Section for fMP4 segments.
-pub fn media_init_section(mp4: &MP4, tracks: &[u32]) -> MP4 {
+pub fn media_init_section(mp4: &MP4, tracks: &[u32]) -> io::Result<MP4> {
+    media_init_section_encrypted(mp4, tracks, &TrackKeys::new())
+}
+
+/// Build a Media Initialization Section for encrypted fMP4 segments.
+///
+/// The sample entries of the tracks that have a key in `keys` are
+/// replaced by `encv` / `enca` entries, and `pssh` boxes are added.
+/// See the [`encryption`](super::encryption) module.
+pub fn media_init_section_encrypted(mp4: &MP4, tracks: &[u32], keys: &TrackKeys) -> io::Result<MP4> {
+    init_section(mp4, tracks, keys, false)
+}
+
+/// Build a Media Initialization Section for fMP4 segments with `wvtt` subtitles.
+///
+/// Like [`media_init_section_encrypted`], but `tx3g` subtitle tracks are
+/// translated to `wvtt` (WebVTT-in-MP4). Use [`movie_fragment_wvtt`] for
+/// the segments.
+pub fn media_init_section_wvtt(mp4: &MP4, tracks: &[u32], keys: &TrackKeys) -> io::Result<MP4> {
+    init_section(mp4, tracks, keys, true)
+}
+
+fn init_section(mp4: &MP4, tracks: &[u32], keys: &TrackKeys, wvtt: bool) -> io::Result<MP4> {
     let mut boxes = Vec::new();
 
     // Start with the FileType box.
@@ -63,9 +88,12 @@ pub fn media_init_section(mp4: &MP4, tracks: &[u32]) -> MP4 {
                     continue;
                 }
                 new_track_id += 1;
-                let track_box = fmp4_track(movie, track, new_track_id);
+                let tx3g_to_wvtt = wvtt && is_tx3g(track);
+                let comp_offsets = mp4.min_composition_offset(track).is_some();
+                let key = keys.get(&track_id);
+                let track_box = fmp4_track(movie, track, new_track_id, key, tx3g_to_wvtt, comp_offsets)?;
                 movie_boxes.push(MP4Box::TrackBox(track_box));
-                let trex_box = track_extends(track, new_track_id);
+                let trex_box = track_extends(track, new_track_id, tx3g_to_wvtt);
                 mvex_boxes.push(MP4Box::TrackExtendsBox(trex_box));
             },
             _ => {},
@@ -75,18 +103,22 @@ pub fn media_init_section(mp4: &MP4, tracks: &[u32]) -> MP4 {
     // add the MovieExtendsBox.
     movie_boxes.push(MP4Box::MovieExtendsBox(MovieExtendsBox { boxes: mvex_boxes }));
 
+    // DRM system specific data for encrypted tracks.
+    movie_boxes.extend(encryption::pssh_boxes(keys));
+
     // finally add the MovieBox to the top level boxes!
     boxes.push(MP4Box::MovieBox(MovieBox { boxes: movie_boxes }));
 
-    MP4 {
+    Ok(MP4 {
         boxes,
         data_ref: mp4.data_ref.clone(),
         input_file: mp4.input_file.clone(),
-    }
+        fragment_index: FragmentIndex::default(),
+    })
 }
 
 // Create a TrackExtendsBox from the original trackbox.
-fn track_extends(trak: &TrackBox, track_id: u32) -> TrackExtendsBox {
+fn track_extends(trak: &TrackBox, track_id: u32, tx3g_to_wvtt: bool) -> TrackExtendsBox {
     let mut trex = TrackExtendsBox::default();
     trex.track_id = track_id;
 
@@ -99,15 +131,31 @@ fn track_extends(trak: &TrackBox, track_id: u32) -> TrackExtendsBox {
     if let Some(duration) = dfl.sample_duration {
         trex.default_sample_duration = duration;
     }
-    if let Some(size) = dfl.sample_size {
+    // tx3g samples are translated to wvtt, so the size changes.
+    if let Some(size) = dfl.sample_size.filter(|_| !tx3g_to_wvtt) {
         trex.default_sample_size = size;
     }
 
     trex
 }
 
+// Is this a tx3g subtitle track. For `wvtt` segments, we translate those to wvtt.
+fn is_tx3g(trak: &TrackBox) -> bool {
+    let stsd = trak.media().media_info().sample_table().sample_description();
+    trak.media().handler().is_subtitle() && first_box!(stsd.entries, Tx3gTextSampleEntry).is_some()
+}
+
 // Build a new TrackBox.
-fn fmp4_track(movie: &MovieBox, trak: &TrackBox, track_id: u32) -> TrackBox {
+//
+// `comp_offsets` is set if the samples of the track have composition offsets.
+fn fmp4_track(
+    movie: &MovieBox,
+    trak: &TrackBox,
+    track_id: u32,
+    key: Option<&ContentKey>,
+    tx3g_to_wvtt: bool,
+    comp_offsets: bool,
+) -> io::Result<TrackBox> {
     let mut boxes = Vec::new();
 
     let mdia = trak.media();
@@ -124,7 +172,7 @@ fn fmp4_track(movie: &MovieBox, trak: &TrackBox, track_id: u32) -> TrackBox {
 
     // edit box.
     let mut entry = EditListEntry::default();
-    if stbl.composition_time_to_sample().is_none() {
+    if !comp_offsets {
         // We should be able to handle this with a negative
         // sample_composition_time_offset in the TrackRunEntry,
         // but it appears that not all players support that.
@@ -148,9 +196,16 @@ fn fmp4_track(movie: &MovieBox, trak: &TrackBox, track_id: u32) -> TrackBox {
     header.duration = Duration_::default();
     media_boxes.push(MP4Box::MediaHeaderBox(header));
 
-    // add handler. copy, but change "sbtl" => "subt".
+    // add handler. copy, but change "sbtl" => "subt", and
+    // use "text" for WebVTT.
     let hdlr = mdia.handler();
-    if hdlr.is_subtitle() {
+    let wvtt = tx3g_to_wvtt || subtitle::is_wvtt(trak);
+    if wvtt {
+        media_boxes.push(MP4Box::HandlerBox(HandlerBox {
+            handler_type: FourCC::new("text"),
+            name: ZString::from("WebVTTHandler"),
+        }));
+    } else if hdlr.is_subtitle() {
         media_boxes.push(MP4Box::HandlerBox(HandlerBox {
             handler_type: FourCC::new("subt"),
             name: ZString::from("SubtitleHandler"),
@@ -173,10 +228,11 @@ fn fmp4_track(movie: &MovieBox, trak: &TrackBox, track_id: u32) -> TrackBox {
     if let Some(smhd) = first_box!(&minf.boxes, SoundMediaHeaderBox) {
         media_info_boxes.push(MP4Box::SoundMediaHeaderBox(smhd.clone()));
     }
-    if let Some(sthd) = first_box!(&minf.boxes, SubtitleMediaHeaderBox) {
+    if wvtt {
+        media_info_boxes.push(MP4Box::NullMediaHeaderBox(NullMediaHeaderBox::default()));
+    } else if let Some(sthd) = first_box!(&minf.boxes, SubtitleMediaHeaderBox) {
         media_info_boxes.push(MP4Box::SubtitleMediaHeaderBox(sthd.clone()));
-    }
-    if let Some(_) = first_box!(&minf.boxes, NullMediaHeaderBox) {
+    } else if let Some(_) = first_box!(&minf.boxes, NullMediaHeaderBox) {
         if hdlr.is_subtitle() {
             media_info_boxes.push(MP4Box::SubtitleMediaHeaderBox(SubtitleMediaHeaderBox::default()));
         } else {
@@ -192,7 +248,15 @@ fn fmp4_track(movie: &MovieBox, trak: &TrackBox, track_id: u32) -> TrackBox {
     let mut sample_boxes = Vec::new();
 
     // Boxes that need to be cloned.
-    let sample_desc = stbl.sample_description().clone();
+    let mut sample_desc = stbl.sample_description().clone();
+    if tx3g_to_wvtt {
+        let mut entries = ArraySized32::new();
+        entries.push(WebVTTSampleEntry::new().to_mp4box());
+        sample_desc.entries = entries;
+    }
+    if let Some(key) = key {
+        encryption::encrypt_sample_description(&mut sample_desc, key)?;
+    }
     sample_boxes.push(MP4Box::SampleDescriptionBox(sample_desc));
 
     // Add empty boxes.
@@ -222,10 +286,10 @@ fn fmp4_track(movie: &MovieBox, trak: &TrackBox, track_id: u32) -> TrackBox {
 
     let movie_timescale = movie.movie_header().timescale;
 
-    TrackBox {
+    Ok(TrackBox {
         movie_timescale,
         boxes,
-    }
+    })
 }
 
 // Some values are constant for the entire trackfragment, or even the
@@ -303,6 +367,33 @@ impl SampleDefaults {
             sample_composition_time_offset,
         }
     }
+
+    //
+    // Like `new()`, but for samples that are not in the SampleTableBox,
+    // like the ones in the MovieFragmentBoxes of a fragmented source.
+    //
+    fn from_samples(samples: &[SampleInfo], comp_offsets: bool) -> SampleDefaults {
+        let first = &samples[0];
+        let sample_duration = Some(first.duration).filter(|&d| samples.iter().all(|s| s.duration == d));
+        let sample_size = Some(first.size).filter(|&sz| samples.iter().all(|s| s.size == sz));
+
+        // Skip the first sample, like in `new()`.
+        let is_sync = samples.get(1).map(|s| s.is_sync).unwrap_or(first.is_sync);
+        let sample_flags = match samples.iter().skip(1).all(|s| s.is_sync == is_sync) {
+            true => Some(build_sample_flags(is_sync)),
+            false => None,
+        };
+
+        // See `new()`.
+        let sample_composition_time_offset = if comp_offsets { None } else { Some(0) };
+
+        SampleDefaults {
+            sample_duration,
+            sample_flags,
+            sample_size,
+            sample_composition_time_offset,
+        }
+    }
 }
 
 // SampleFlags has a lot of bits, but really all we know is 'is this a key frame'.
@@ -335,6 +426,43 @@ fn default_or<A: PartialEq<A>>(dfl: &Option<A>, val: A) -> Option<A> {
 /// Note that from_sample and to_sample for different tracks need to have
 /// the same composition time.
 pub fn movie_fragment(mp4: &MP4, seq_num: u32, source: &[FragmentSource]) -> io::Result<Vec<MP4Box>> {
+    movie_fragment_encrypted(mp4, seq_num, source, &TrackKeys::new())
+}
+
+/// Generate an encrypted MovieFragmentBox + MediaDataBox.
+///
+/// The samples of the tracks that have a key in `keys` (by source track id)
+/// are encrypted, and `senc`, `saiz` and `saio` boxes are added to their
+/// track fragments. See the [`encryption`](super::encryption) module.
+pub fn movie_fragment_encrypted(
+    mp4: &MP4,
+    seq_num: u32,
+    source: &[FragmentSource],
+    keys: &TrackKeys,
+) -> io::Result<Vec<MP4Box>> {
+    fragment(mp4, seq_num, source, keys, false)
+}
+
+/// Generate a MovieFragmentBox + MediaDataBox with `wvtt` subtitles.
+///
+/// Like [`movie_fragment_encrypted`], but the samples of `tx3g` subtitle
+/// tracks are translated to `wvtt`. See [`media_init_section_wvtt`].
+pub fn movie_fragment_wvtt(
+    mp4: &MP4,
+    seq_num: u32,
+    source: &[FragmentSource],
+    keys: &TrackKeys,
+) -> io::Result<Vec<MP4Box>> {
+    fragment(mp4, seq_num, source, keys, true)
+}
+
+fn fragment(
+    mp4: &MP4,
+    seq_num: u32,
+    source: &[FragmentSource],
+    keys: &TrackKeys,
+    wvtt: bool,
+) -> io::Result<Vec<MP4Box>> {
     let movie = mp4.movie();
     let mut mdat = MediaDataBox::default();
 
@@ -362,12 +490,12 @@ pub fn movie_fragment(mp4: &MP4, seq_num: u32, source: &[FragmentSource]) -> io:
             src.src_track_id
         ))?;
         let traf = track_fragment(
+            mp4,
             track,
-            src.from_sample,
-            src.to_sample,
-            src.dst_track_id,
-            mp4.data_ref.clone(),
+            src,
             &mut mdat,
+            keys.get(&src.src_track_id),
+            wvtt && is_tx3g(track),
         )?;
         moof.boxes.push(traf.to_mp4box());
     }
@@ -386,6 +514,9 @@ pub fn movie_fragment(mp4: &MP4, seq_num: u32, source: &[FragmentSource]) -> io:
             trun.data_offset.as_mut().map(|d| *d += moof_sz);
         }
     }
+    if !keys.is_empty() {
+        encryption::set_aux_info_offsets(&mut moof);
+    }
 
     // styp + moof + mdat.
     let mut boxes = Vec::new();
@@ -425,15 +556,18 @@ fn readahead(file: &fs::File, offset: u64, len: u64) {
 
 // Build a TrackFragmentBox.
 fn track_fragment(
+    mp4: &MP4,
     track: &TrackBox,
-    from: u32,
-    to: u32,
-    new_track_id: u32,
-    data_ref: DataRef,
+    src: &FragmentSource,
     mdat: &mut MediaDataBox,
+    key: Option<&ContentKey>,
+    tx3g: bool,
 ) -> io::Result<TrackFragmentBox> {
+    let (from, to, new_track_id) = (src.from_sample, src.to_sample, src.dst_track_id);
+
     // Seek to 'from' and peek at the first sample.
-    let mut samples = track.sample_info_iter();
+    let data_ref = &mp4.data_ref;
+    let mut samples = mp4.sample_info_iter(track);
     samples.seek(from)?;
     let sample_count = to - from + 1;
     let samples: Vec<_> = samples.take(sample_count as usize).collect();
@@ -441,6 +575,8 @@ fn track_fragment(
         return Err(ioerr!(UnexpectedEof));
     }
     let first_sample = samples[0].clone();
+    let mut encryptor = key.map(|key| SampleEncryptor::new(track, key)).transpose()?;
+    let mut encryption_entries = Vec::new();
 
     // Readahead, unless it's a subtitle track.
     if !track.media().handler().is_subtitle() {
@@ -457,7 +593,15 @@ fn track_fragment(
     tfhd.track_id = new_track_id;
     tfhd.default_base_is_moof = true;
     // Set sample defaults.
-    let dfl = SampleDefaults::new(track, from, to);
+    let table_samples = track.media().media_info().sample_table().sample_size().count;
+    let mut dfl = if to > table_samples {
+        SampleDefaults::from_samples(&samples, mp4.min_composition_offset(track).is_some())
+    } else {
+        SampleDefaults::new(track, from, to)
+    };
+    if tx3g {
+        dfl.sample_size = None;
+    }
     tfhd.sample_description_index = Some(1);
     tfhd.default_sample_duration = dfl.sample_duration;
     tfhd.default_sample_size = dfl.sample_size;
@@ -499,12 +643,22 @@ fn track_fragment(
         entries: ArrayUnsized::<TrackRunEntry>::new(),
     };
 
-    for sample in &samples {
+    for (idx, sample) in samples.iter().enumerate() {
+        // Translate tx3g to wvtt.
+        let wvtt = if tx3g {
+            let mut data = vec![0u8; sample.size as usize];
+            data_ref.read_exact_at(&mut data, sample.fpos)?;
+            Some(subtitle::tx3g_to_wvtt(&data)?)
+        } else {
+            None
+        };
+        let sample_size = wvtt.as_ref().map(|w| w.len() as u32).unwrap_or(sample.size);
+
         // Add entry info
         let entry = TrackRunEntry {
             sample_duration: default_or(&dfl.sample_duration, sample.duration),
             sample_flags: default_or(&dfl.sample_flags, build_sample_flags(sample.is_sync)),
-            sample_size: default_or(&dfl.sample_size, sample.size),
+            sample_size: default_or(&dfl.sample_size, sample_size),
             sample_composition_time_offset: default_or(
                 &dfl.sample_composition_time_offset,
                 sample.composition_delta + cmp::min(shift, 0) as i32,
@@ -513,15 +667,26 @@ fn track_fragment(
         trun.entries.push(entry);
 
         // Add entry mediadata.
-        let start = sample.fpos as usize;
-        let end = start + sample.size as usize;
         let oldlen = mdat.data.len() as usize;
-        let newlen = oldlen + (end - start);
+        let newlen = oldlen + sample_size as usize;
         mdat.data.resize(newlen);
-        data_ref.read_exact_at(&mut mdat.data.bytes_mut()[oldlen..newlen], sample.fpos)?;
+        match wvtt {
+            Some(data) => mdat.data.bytes_mut()[oldlen..newlen].copy_from_slice(&data),
+            None => data_ref.read_exact_at(&mut mdat.data.bytes_mut()[oldlen..newlen], sample.fpos)?,
+        }
+
+        // Encrypt it, if needed.
+        if let Some(encryptor) = encryptor.as_mut() {
+            let data = &mut mdat.data.bytes_mut()[oldlen..newlen];
+            encryption_entries.push(encryptor.encrypt(from + idx as u32, data)?);
+        }
     }
 
     traf.boxes.push(trun.to_mp4box());
 
+    if encryptor.is_some() {
+        encryption::add_encryption_boxes(&mut traf, encryption_entries);
+    }
+
     Ok(traf)
 }
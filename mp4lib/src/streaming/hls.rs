@@ -16,13 +16,20 @@
 //! mappings from URL to objects:
 //!
 //! - `master.m3u8`: entry point, the master `HLS` playlist.
+//! - `master.ts.m3u8`: same, but refers to `MPEG-TS` track playlists.
 //! - `media.<TRACK_ID>.m3u8`: per-track playlist.
+//! - `media.<TRACK_ID>.ts.m3u8`: per-track playlist with `MPEG-TS` segments.
 //! - `init.<TRACK_ID>.mp4`: `ISOBMFF` initialization segment for the track.
 //! - `init.<TRACK_ID>.vtt`: `WEBVTT` initialization segment for the track.
+//! - `init.<TRACK_ID>.wvtt.mp4`: `ISOBMFF` initialization segment for `wvtt` segments.
 //! - `a/c.<TRACK_ID>.<SEGMENT_ID>.m4a`: MP4 audio segment
 //! - `v/c.<TRACK_ID>.<SEGMENT_ID>.mp4`: MP4 video segment
+//! - `a/c.<TRACK_ID>.<SEGMENT_ID>.ts`: MPEG-TS audio segment
+//! - `v/c.<TRACK_ID>.<SEGMENT_ID>.ts`: MPEG-TS video segment
 //! - `s/c.<TRACK_ID>.<SEGMENT_ID>.vtt`: webvtt segment
+//! - `s/c.<TRACK_ID>.<SEGMENT_ID>.m4s`: same, as fMP4 `wvtt` segment
 //! - `e/EXTERNALFILE.EXT[:into.vtt]`: external single-segment subtitle track.
+//! - `key.<TRACK_ID>.bin`: `AES-128` / `SAMPLE-AES` key for the track.
 //!
 //! ## Master playlist.
 //!
@@ -67,12 +74,24 @@
 //! Can be generated by
 //! [`movie_fragment`](crate::streaming::fragment::movie_fragment).
 //!
+//! ## MPEG-TS media data segments.
+//!
+//! `a/c.<TRACK_ID>.<SEGMENT_ID>.ts`  
+//! `v/c.<TRACK_ID>.<SEGMENT_ID>.ts`
+//!
+//! `SEGMENT_ID` as above, same segment boundaries as the `fMP4` segments.
+//! For older players that do not support `fMP4`. These segments do not
+//! need an initialization segment. Generated by
+//! [`mpegts::media_segment`](crate::streaming::mpegts::media_segment).
+//!
 //! ## Subtitle media data segments.
 //!
-//! `s/c.<TRACK_ID>.<SEGMENT_ID>.vtt`
+//! `s/c.<TRACK_ID>.<SEGMENT_ID>.vtt`  
+//! `s/c.<TRACK_ID>.<SEGMENT_ID>.m4s`
 //!
-//! SEGMENT_ID as above. Subtitles tracks in the `mp4` file in `tx3g` format are
-//! transmuxed into fragmented `WEBVTT`.
+//! SEGMENT_ID as above. Subtitles tracks in the `mp4` file in `tx3g` or `wvtt`
+//! format are transmuxed into fragmented `WEBVTT`, or into `fMP4` segments
+//! with `wvtt` samples for players that need `ISOBMFF` wrapped text.
 //!
 //! ## External subtitle media data segments.
 //!
@@ -81,6 +100,18 @@
 //! External subtitles can also be included as a media data segment,
 //! basically being one big segment.
 //!
+//! ## Keys.
+//!
+//! `key.<TRACK_ID>.bin`
+//!
+//! If [`HlsEncryption`](crate::streaming::encryption::HlsEncryption) is enabled,
+//! the audio and video segments are encrypted, and the track playlists refer
+//! to this 16 byte key. `MPEG-TS` segments are always encrypted with `AES-128`.
+//!
+//! Otherwise, if a [`KeyProvider`](crate::streaming::encryption::KeyProvider) is
+//! set, the `fMP4` segments are encrypted with `cenc` or `cbcs`, and the track
+//! playlists have an `EXT-X-KEY` tag for each `pssh` box of the key.
+//!
 use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
@@ -94,12 +125,14 @@ use once_cell::sync::Lazy;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use scan_fmt::scan_fmt;
 
+use crate::boxes::Tx3gTextSampleEntry;
 use crate::io::MemBuffer;
-use crate::mp4box::MP4;
+use crate::mp4box::{MP4Box, MP4};
 use crate::serialize::ToBytes;
 use crate::track::SpecificTrackInfo;
 use crate::types::FourCC;
 
+use super::encryption::{self, HlsEncryption, HlsMethod, TrackKeys};
 use super::fragment::FragmentSource;
 use super::http_file::{delegate_http_file, HttpFile, MemFile};
 use super::lru_cache::LruCache;
@@ -135,6 +168,8 @@ pub struct ExtXMedia {
     pub codec: String,
     /// Channels, 2 = stereo, 3 = 2.1, 4 = 4.0, 5 = 5.0, 6 = 5.1, 7 = 7.0, 8 = 7.1, 9 = 7.2
     pub channels: Option<u16>,
+    /// Dolby Atmos (E-AC-3 JOC) complexity index, rendered as `CHANNELS="16/JOC"`.
+    pub joc_complexity: Option<u8>,
     /// Language in 2-letter or 3-letter form.
     pub language: Option<String>,
     auto_select: bool,
@@ -152,7 +187,7 @@ pub struct ExtXMedia {
 }
 
 impl ExtXMedia {
-    /// Build ExtXMedia type SUBTITLE from the info in a .srt or .vtt filename.
+    /// Build ExtXMedia type SUBTITLE from the info in a .srt, .vtt or .ttml filename.
     pub fn external_subtitle(filename: &str) -> ExtXMedia {
 
         // look up language and language code.
@@ -177,6 +212,7 @@ impl ExtXMedia {
             codec: "vtt".to_string(),
             language: lang.map(|s| s.to_string()),
             channels: None,
+            joc_complexity: None,
             name,
             auto_select: true,
             default: forced,
@@ -199,9 +235,18 @@ impl ExtXMedia {
 
 impl Display for ExtXMedia {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, "m3u8")
+    }
+}
+
+impl ExtXMedia {
+    // Write the EXT-X-MEDIA line, `suffix` is the suffix of the track playlist.
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, suffix: &str) -> std::fmt::Result {
         write!(f, "#EXT-X-MEDIA:TYPE={},", self.type_)?;
         write!(f, r#"GROUP-ID="{}","#, self.group_id)?;
-        if let Some(ref channels) = self.channels {
+        if let Some(complexity) = self.joc_complexity {
+            write!(f, r#"CHANNELS="{}/JOC","#, complexity)?;
+        } else if let Some(ref channels) = self.channels {
             write!(f, r#"CHANNELS="{}","#, channels)?;
         }
         write!(f, r#"NAME="{}","#, self.name)?;
@@ -233,7 +278,7 @@ impl Display for ExtXMedia {
             // note, either keep the "./", or escape the ":".
             write!(f, r#"URI="./media.ext:{}:as.m3u8""#, uri_path)?;
         } else {
-            write!(f, r#"URI="media.{}.m3u8""#, self.track_id)?;
+            write!(f, r#"URI="media.{}.{}""#, self.track_id, suffix)?;
         }
         write!(f, "\n")
     }
@@ -253,6 +298,10 @@ pub struct Video {
     pub resolution: (u16, u16),
     /// Frames per second
     pub frame_rate: f64,
+    /// Video range: "SDR", "PQ" or "HLG".
+    pub video_range: &'static str,
+    /// Supplemental codec (e.g. "dvh1.08.06/db1p")
+    pub supplemental_codec: Option<String>,
 }
 
 // EXT-X-STREAM-INF
@@ -262,7 +311,9 @@ struct ExtXStreamInf {
     bandwidth: u64,
     resolution: (u16, u16),
     frame_rate: f64,
+    video_range: &'static str,
     codecs: Vec<String>,
+    supplemental_codecs: Option<String>,
     subtitles: bool,
     audio: Option<String>,
     uri: String,
@@ -289,8 +340,12 @@ impl Display for ExtXStreamInf {
             write!(f, r#"SUBTITLES="subs","#)?;
         }
         write!(f, r#"CODECS="{}","#, codecs)?;
+        if let Some(ref supplemental) = self.supplemental_codecs {
+            write!(f, r#"SUPPLEMENTAL-CODECS="{}","#, supplemental)?;
+        }
         write!(f, r#"RESOLUTION={}x{},"#, self.resolution.0, self.resolution.1)?;
-        write!(f, r#"FRAME-RATE={:.03}"#, self.frame_rate)?;
+        write!(f, r#"FRAME-RATE={:.03},"#, self.frame_rate)?;
+        write!(f, r#"VIDEO-RANGE={}"#, self.video_range)?;
         write!(f, "\n{}\n", self.uri)
     }
 }
@@ -345,10 +400,15 @@ fn want_language(lang: Option<&str>, list: &[&str]) -> bool {
     }
 }
 
+// Is this an external subtitle file we can serve.
+fn is_subtitle_file(name: &str) -> bool {
+    [".srt", ".vtt", ".ttml", ".dfxp"].iter().any(|ext| name.ends_with(ext))
+}
+
 // Find external subtitles.
 //
-// These are the files that have the same basename without .mp4 as the main file,
-// and that end in .srt or .vtt.
+// These are the files that have the same basename without the extension as the main file,
+// and that end in .srt, .vtt, .ttml or .dfxp.
 fn lookup_subtitles(mp4path: Option<&String>) -> Vec<String> {
     let mut subs = Vec::new();
     let mp4path = match mp4path {
@@ -359,17 +419,17 @@ fn lookup_subtitles(mp4path: Option<&String>) -> Vec<String> {
         Some(p) => p,
         None => return subs,
     };
-    if !mp4path.ends_with(".mp4") {
-        return subs;
-    }
-    let prefix = &mp4path[..mp4path.len() - 3];
+    // "movie.mp4" -> "movie."
+    let prefix = match mp4path.rfind('.') {
+        Some(idx) => &mp4path[..idx + 1],
+        None => return subs,
+    };
 
     let _ = (|| {
         for entry in fs::read_dir(parent)? {
             let entry = entry?;
             if let Ok(path) = entry.path().into_os_string().into_string() {
-                if path.starts_with(prefix) && (path.ends_with(".srt") || path.ends_with(".vtt"))
-                {
+                if path.starts_with(prefix) && is_subtitle_file(&path) {
                     subs.push(path);
                 }
             }
@@ -388,6 +448,7 @@ pub struct HlsMaster {
     pub subtitles: Vec<ExtXMedia>,
     pub video: Option<Video>,
     audio_codecs: HashMap<String, u64>,
+    mpegts: bool,
 }
 
 impl Display for HlsMaster {
@@ -395,13 +456,15 @@ impl Display for HlsMaster {
         write!(f, "#EXTM3U\n")?;
         write!(f, "# Created by mp4lib.rs\n")?;
         write!(f, "#\n")?;
-        write!(f, "#EXT-X-VERSION:6\n")?;
+        write!(f, "#EXT-X-VERSION:{}\n", if self.mpegts { 3 } else { 6 })?;
         write!(f, "\n")?;
 
+        let suffix = if self.mpegts { "ts.m3u8" } else { "m3u8" };
+
         if self.audio_tracks.len() > 0 {
             write!(f, "# AUDIO\n")?;
             for a in self.audio_tracks.iter().filter(|t| t.in_master) {
-                a.fmt(f)?;
+                a.write(f, suffix)?;
             }
         }
 
@@ -422,7 +485,9 @@ impl Display for HlsMaster {
                 subtitles: self.subtitles.len() > 0,
                 resolution: video.resolution,
                 frame_rate: video.frame_rate,
-                uri: format!("media.{}.m3u8", video.track_id),
+                video_range: video.video_range,
+                supplemental_codecs: video.supplemental_codec.clone(),
+                uri: format!("media.{}.{}", video.track_id, suffix),
                 ..ExtXStreamInf::default()
             };
 
@@ -502,6 +567,7 @@ impl HlsMaster {
                 group_id: format!("audio/{}", info.codec_id),
                 language: lang.map(|s| s.to_string()),
                 channels: Some(info.channel_count + info.lfe_channel as u16),
+                joc_complexity: info.joc_complexity,
                 name,
                 auto_select: !commentary,
                 default: false,
@@ -539,12 +605,16 @@ impl HlsMaster {
             }
         }
 
-        // Embedded subtitles.
+        // Embedded subtitles. QuickTime chapter tracks are text tracks too, skip them.
+        let chapter_tracks = crate::chapters::chapter_track_ids(mp4);
         for track in crate::track::track_info(mp4).iter() {
             match &track.specific_info {
                 SpecificTrackInfo::SubtitleTrackInfo(_) => {},
                 _ => continue,
             }
+            if chapter_tracks.contains(&track.id) {
+                continue;
+            }
             // Skip empty tracks.
             if track.duration.as_secs() == 0 {
                 continue;
@@ -555,12 +625,23 @@ impl HlsMaster {
             let (lang, name) = lang(&track_lang);
             let mut name = name.to_string();
 
+            // The tx3g sample entry can mark the whole track as forced.
+            let mut forced = mp4
+                .movie()
+                .track_by_id(track.id)
+                .and_then(|trak| {
+                    let stsd = trak.media().media_info().sample_table().sample_description();
+                    first_box!(stsd.entries, Tx3gTextSampleEntry).map(|e| e.forced())
+                })
+                .unwrap_or(false);
+
             // Track name. Might be a descriptive name, but can also be one of:
             // - "Forced"
             // - "Hearing Impaired"
-            let mut forced = false;
             let mut sdh = false;
-            if let Some(ref track_name) = track.name {
+            if forced {
+                name = format!("{} (forced)", name);
+            } else if let Some(ref track_name) = track.name {
                 let lname = track_name.to_lowercase();
                 if lname.contains("forced") {
                     forced = true;
@@ -584,6 +665,7 @@ impl HlsMaster {
                 codec: "tx3g".to_string(),
                 language: lang.map(|s| s.to_string()),
                 channels: None,
+                joc_complexity: None,
                 name: name.to_string(),
                 auto_select: true,
                 default: forced,
@@ -641,7 +723,7 @@ impl HlsMaster {
             let avg_bw = track.size / cmp::max(1, track.duration.as_secs()) * 8;
             let mut peak_bw = avg_bw;
             if let Some(trak) = mp4.movie().track_by_id(track.id) {
-                if let Ok(bw) = super::segmenter::track_segment_peak_bw(trak, None, None) {
+                if let Ok(bw) = super::segmenter::track_segment_peak_bw(mp4, trak, None, None) {
                     peak_bw = bw * 8;
                 }
             }
@@ -653,6 +735,8 @@ impl HlsMaster {
                 codec: info.codec_id.clone(),
                 resolution: (info.width, info.height),
                 frame_rate: info.frame_rate,
+                video_range: info.video_range(),
+                supplemental_codec: info.supplemental_codec_id.clone(),
             });
             break;
         }
@@ -662,6 +746,23 @@ impl HlsMaster {
             audio_tracks,
             subtitles,
             video,
+            mpegts: false,
+        }
+    }
+
+    /// Refer to `MPEG-TS` track playlists instead of `fMP4` ones.
+    ///
+    /// Audio and video tracks with a codec that cannot be put in a
+    /// transport stream are removed.
+    pub fn set_mpegts(&mut self) {
+        use super::mpegts::is_supported_codec;
+        self.mpegts = true;
+        self.audio_tracks.retain(|t| is_supported_codec(&t.codec));
+        self.audio_codecs.retain(|codec, _| is_supported_codec(codec));
+        if let Some(video) = self.video.as_ref() {
+            if !is_supported_codec(&video.codec) {
+                self.video = None;
+            }
         }
     }
 
@@ -747,7 +848,7 @@ impl HlsMaster {
 /// The extra parameters are:
 ///
 /// - `external_subs`: if `true`, the directory of the original `mp4` file
-///   is scanned for `.srt` and `.vtt` files that have the same `"base"`
+///   is scanned for `.srt`, `.vtt`, `.ttml` and `.dfxp` files that have the same `"base"`
 ///   (the part of the filename before the `.mp4` extension) and those are
 ///   included in the playlist.
 ///
@@ -775,21 +876,25 @@ pub fn hls_master(mp4: &MP4, external_subs: bool, filter_subs: bool) -> String {
     master.to_string()
 }
 
-fn track_to_segments(
+// Keyed by file name, file size (a growing file gets new segments), track id,
+// and the max segment size.
+type SegmentCache = Lazy<LruCache<(String, u64, u32, Option<u32>), Arc<Vec<Segment>>>>;
+
+pub(crate) fn track_to_segments(
     mp4: &MP4,
     track_id: u32,
     duration: Option<u32>,
     max_segment_size: Option<u32>,
 ) -> io::Result<Arc<Vec<Segment>>> {
     #[rustfmt::skip]
-    static SEGMENTS: Lazy<LruCache<(String, u32), Arc<Vec<Segment>>>> = {
+    static SEGMENTS: SegmentCache = {
         Lazy::new(|| LruCache::new(Duration::new(60, 0)))
     };
     let name = mp4
         .input_file
         .as_ref()
         .ok_or_else(|| ioerr!(NotFound, "file not found"))?;
-    let key = (name.to_string(), track_id);
+    let key = (name.to_string(), mp4.data_ref.len(), track_id, max_segment_size);
     if let Some(segments) = SEGMENTS.get(&key) {
         return Ok(segments);
     }
@@ -797,12 +902,57 @@ fn track_to_segments(
         .movie()
         .track_by_id(track_id)
         .ok_or_else(|| ioerr!(NotFound, "track not found"))?;
-    let segments = super::segmenter::track_to_segments(track, duration, max_segment_size)?;
+    let segments = super::segmenter::track_to_segments(mp4, track, duration, max_segment_size)?;
     let segments = Arc::new(segments);
     SEGMENTS.put(key, segments.clone());
     Ok(segments)
 }
 
+// Get the segments for a track. Subtitle tracks and the video track are
+// segmented on their own, other tracks follow the timing of the video track.
+pub(crate) fn track_segments(
+    mp4: &MP4,
+    track_id: u32,
+    duration: Option<u32>,
+    max_segment_size: Option<u32>,
+) -> io::Result<Arc<Vec<Segment>>> {
+    #[rustfmt::skip]
+    static SEGMENTS: SegmentCache = {
+        Lazy::new(|| LruCache::new(Duration::new(60, 0)))
+    };
+    let movie = mp4.movie();
+    let trak = movie
+        .track_by_id(track_id)
+        .ok_or_else(|| ioerr!(NotFound, "track not found"))?;
+
+    if trak.media().handler().is_subtitle() {
+        // Subtitles do not have the same number of segments and duration.
+        // They are a master list, like the video.
+        return track_to_segments(mp4, track_id, None, None);
+    }
+
+    let video_id = match movie.track_idx_by_handler(FourCC::new("vide")) {
+        Some(idx) => movie.tracks()[idx].track_id(),
+        None => return Err(ioerr!(NotFound, "mp4 file has no video track")),
+    };
+    let segments = track_to_segments(mp4, video_id, duration, max_segment_size)?;
+    if track_id == video_id {
+        return Ok(segments);
+    }
+
+    let name = mp4
+        .input_file
+        .as_ref()
+        .ok_or_else(|| ioerr!(NotFound, "file not found"))?;
+    let key = (name.to_string(), mp4.data_ref.len(), track_id, max_segment_size);
+    if let Some(segments) = SEGMENTS.get(&key) {
+        return Ok(segments);
+    }
+    let segments = Arc::new(super::segmenter::track_to_segments_timed(mp4, trak, &segments)?);
+    SEGMENTS.put(key, segments.clone());
+    Ok(segments)
+}
+
 /// Generate a `HLS` track playlist.
 ///
 /// This is also an `m3u8` file. It contains a list of media segments.
@@ -812,7 +962,11 @@ fn track_to_segments(
 /// GOP-sized chunks, and that a segment can start with a non-sync sample.
 /// This is needed for Chromecasts (set around 8_000_000).
 ///
-pub fn hls_track(mp4: &MP4, track_id: u32, max_segment_size: Option<u32>) -> io::Result<String> {
+/// If `mpegts` is set, the playlist of an audio or video track refers
+/// to `MPEG-TS` segments instead of `fMP4` segments. Subtitle playlists
+/// are the same either way.
+///
+pub fn hls_track(mp4: &MP4, track_id: u32, max_segment_size: Option<u32>, mpegts: bool) -> io::Result<String> {
     let movie = mp4.movie();
     let trak = movie
         .track_by_id(track_id)
@@ -820,34 +974,21 @@ pub fn hls_track(mp4: &MP4, track_id: u32, max_segment_size: Option<u32>) -> io:
     let handler = trak.media().handler();
     let handler_type = handler.handler_type;
     let is_subtitle = handler.is_subtitle();
+    let mpegts = mpegts && !is_subtitle;
 
     let seg_duration = None; // Some(4000);
 
-    let segments = if !is_subtitle {
-        let video_id = match movie.track_idx_by_handler(FourCC::new("vide")) {
-            Some(idx) => movie.tracks()[idx].track_id(),
-            None => return Err(ioerr!(NotFound, "mp4 file has no video track")),
-        };
-
-        let mut segments = track_to_segments(mp4, video_id, seg_duration, max_segment_size)?;
-        if track_id != video_id {
-            let segs: &[Segment] = segments.as_ref();
-            segments = Arc::new(super::segmenter::track_to_segments_timed(trak, segs)?);
-        }
-        segments
-    } else {
-        // Subtitles do not have the same number of segments and duration.
-        // They are a master list, like the video.
-        track_to_segments(mp4, track_id, None, None)?
-    };
+    let segments = track_segments(mp4, track_id, seg_duration, max_segment_size)?;
 
     let (prefix, suffix) = match &handler_type.to_be_bytes()[..] {
         b"vide" => ('v', "mp4"),
         b"soun" => ('a', "m4a"),
         b"sbtl" => ('s', "vtt"),
         b"subt" => ('s', "vtt"),
+        b"text" => ('s', "vtt"),
         _ => return Err(ioerr!(InvalidInput, "unknown handler type {}", handler_type)),
     };
+    let suffix = if mpegts { "ts" } else { suffix };
 
     let longest = segments
         .iter()
@@ -856,7 +997,7 @@ pub fn hls_track(mp4: &MP4, track_id: u32, max_segment_size: Option<u32>) -> io:
 
     let mut m = String::new();
     m += "#EXTM3U\n";
-    m += "#EXT-X-VERSION:6\n";
+    m += if mpegts { "#EXT-X-VERSION:3\n" } else { "#EXT-X-VERSION:6\n" };
     m += "## Created by mp4lib.rs\n";
     m += "#\n";
     if independent || handler.is_audio() {
@@ -865,7 +1006,29 @@ pub fn hls_track(mp4: &MP4, track_id: u32, max_segment_size: Option<u32>) -> io:
     m += &format!("#EXT-X-TARGETDURATION:{}\n", longest);
     m += "#EXT-X-PLAYLIST-TYPE:VOD\n";
     if !is_subtitle {
-        m += &format!("#EXT-X-MAP:URI=\"init.{}.mp4\"\n", track_id);
+        if !mpegts {
+            m += &format!("#EXT-X-MAP:URI=\"init.{}.mp4\"\n", track_id);
+        }
+        if let Some(enc) = encryption::hls_encryption() {
+            // Transport stream segments are always encrypted as a whole.
+            let method = if mpegts { HlsMethod::Aes128 } else { enc.method };
+            let key = enc.key(mp4, track_id)?;
+            m += &format!(
+                "#EXT-X-KEY:METHOD={},URI=\"key.{}.bin\",IV={}",
+                method,
+                track_id,
+                key.iv_hex()
+            );
+            if method == HlsMethod::SampleAes {
+                m += ",KEYFORMAT=\"identity\",KEYFORMATVERSIONS=\"1\"";
+            }
+            m += "\n";
+        } else if !mpegts {
+            // Segments encrypted with a key from the key provider.
+            if let Some(key) = encryption::content_key(mp4, track_id)? {
+                m += &key.hls_key_tags()?;
+            }
+        }
     }
 
     for (mut seq, seg) in segments.iter().enumerate() {
@@ -928,12 +1091,14 @@ impl HlsManifest {
     /// The `url_tail` is the part after `...mp4/` and has this format:
     ///
     /// - `master.m3u8`              => `HLS` master playlist
+    /// - `master.ts.m3u8`           => same, refers to `MPEG-TS` track playlists
     /// - `media.<TRACK_ID>.m3u8`     => `HLS` track playlist
+    /// - `media.<TRACK_ID>.ts.m3u8`  => `HLS` track playlist with `MPEG-TS` segments
     /// - `media.ext:NAME.EXT:as.m3u8` => `HLS` external subtitle file playlist
     ///
     /// The last case looks the most complicated, but is in fact the simplest.
     /// `media.ext` is literal. `NAME.EXT` is the name of the subtitle
-    /// file in the same directory as the `mp4` file, either an `srt` or `vtt` file.
+    /// file in the same directory as the `mp4` file, an `srt`, `vtt` or `ttml` file.
     /// `:as.m3u8` means "create a manifest for this file with one entry: this file".
     ///
     /// `master.m3u8` will have entries that refer to the latter two `url`s.
@@ -953,12 +1118,23 @@ impl HlsManifest {
                 master.dedup_subtitles(true);
             }
             master.to_string()
+        } else if url_tail == "master.ts.m3u8" {
+            // HLS master playlist, MPEG-TS.
+            let mut master = HlsMaster::new(mp4, true);
+            if filter_subs {
+                master.dedup_subtitles(true);
+            }
+            master.set_mpegts();
+            master.to_string()
         } else if let Ok(track) = scan_fmt!(url_tail, "media.{}.m3u8{e}", u32) {
             // HLS media playlist.
-            hls_track(&mp4, track, max_segment_size)?
+            hls_track(&mp4, track, max_segment_size, false)?
+        } else if let Ok(track) = scan_fmt!(url_tail, "media.{}.ts.m3u8{e}", u32) {
+            // HLS media playlist, MPEG-TS.
+            hls_track(mp4, track, max_segment_size, true)?
         } else if let Ok((name, _)) = scan_fmt!(url_tail, "media.ext:{}:{}.m3u8{e}", String, String) {
             // external file next to .mp4.
-            if name.ends_with(".srt") || name.ends_with(".vtt") {
+            if is_subtitle_file(&name) {
                 // subtitles.
                 let dirname = dirname(mp4.input_file.as_ref(), &name)?;
                 hls_subtitle(&dirname, &name)?
@@ -994,30 +1170,44 @@ impl MediaSegment {
     ///
     /// - `init.TRACK_ID.mp4` => initialization segment for track `TRACK_ID`.
     /// - `init.TRACK_ID.vtt` => initialization segment for track `TRACK_ID`.
+    /// - `init.TRACK_ID.wvtt.mp4` => initialization segment for `wvtt` segments of track `TRACK_ID`.
     ///
     /// - `a/c.TRACK_ID.SEQUENCE.FROM_SAMPLE.TO_SAMPLE.m4a` => audio moof + mdat
     /// - `v/c.TRACK_ID.SEQUENCE.FROM_SAMPLE.TO_SAMPLE.mp4` => video moof + mdat
+    /// - `a/c.TRACK_ID.SEQUENCE.FROM_SAMPLE.TO_SAMPLE.ts`  => audio `MPEG-TS` segment
+    /// - `v/c.TRACK_ID.SEQUENCE.FROM_SAMPLE.TO_SAMPLE.ts`  => video `MPEG-TS` segment
     /// - `s/c.TRACK_ID.SEQUENCE.FROM_SAMPLE.TO_SAMPLE.vtt` => webvtt fragment
-    /// - `e/EXTERNALFILE.EXT[:into.ext]` => external file next to `.mp4` (`.srt`, `.vtt`)
+    /// - `s/c.TRACK_ID.SEQUENCE.FROM_SAMPLE.TO_SAMPLE.m4s` => `wvtt` moof + mdat
+    /// - `e/EXTERNALFILE.EXT[:into.ext]` => external file next to `.mp4` (`.srt`, `.vtt`, `.ttml`)
+    ///
+    /// If [`HlsEncryption`](crate::streaming::encryption::HlsEncryption) is
+    /// enabled, audio and video segments are encrypted. `MPEG-TS` segments
+    /// are always encrypted with `AES-128`.
     ///
     pub fn from_uri(mp4: &MP4, url_tail: &str, range_end: Option<u64>) -> io::Result<MediaSegment> {
-        let (mime_type, content) = MediaSegment::from_uri_(mp4, url_tail, range_end)?;
+        let hls_enc = encryption::hls_encryption();
+        let (mime_type, content) = MediaSegment::from_uri_(mp4, url_tail, range_end, hls_enc)?;
         let file = &*mp4.data_ref.file;
         let mem_file = MemFile::from_file_shared(content, mime_type, file)?;
         Ok(MediaSegment(mem_file))
     }
 
-    fn from_uri_(
+    pub(crate) fn from_uri_(
         mp4: &MP4,
         url_tail: &str,
         range_end: Option<u64>,
+        hls_enc: Option<&HlsEncryption>,
     ) -> io::Result<(&'static str, Arc<Vec<u8>>)> {
         // initialization section.
         if let Ok((track_id, ext)) = scan_fmt!(url_tail, "init.{}.{}{e}", u32, String) {
             match ext.as_str() {
-                "mp4" => {
+                "mp4" | "wvtt.mp4" => {
                     // println!("XXX DBG from_uri_: mp4: {:#?}", mp4);
-                    let init = super::fragment::media_init_section(&mp4, &[track_id]);
+                    let keys = segment_keys(mp4, track_id, hls_enc)?;
+                    let init = match ext.as_str() {
+                        "mp4" => super::fragment::media_init_section_encrypted(mp4, &[track_id], &keys)?,
+                        _ => super::fragment::media_init_section_wvtt(mp4, &[track_id], &keys)?,
+                    };
                     let mut buffer = MemBuffer::new();
                     init.write(&mut buffer)?;
                     let data = buffer.into_vec();
@@ -1051,9 +1241,13 @@ impl MediaSegment {
         };
         let (typ, track_id, seq_id, start_sample, end_sample) = tups;
 
+        let wvtt = typ == 's' && url_tail.ends_with(".m4s");
+        let mpegts = typ != 's' && url_tail.ends_with(".ts");
         let mime = match typ {
+            _ if mpegts => "video/mp2t",
             'v' => "video/mp4",
             'a' => "audio/mp4",
+            's' if wvtt => "application/mp4",
             's' => "text/vtt; charset=utf-8",
             _ => unreachable!(),
         };
@@ -1064,11 +1258,29 @@ impl MediaSegment {
             to_sample: end_sample,
         };
         let content = match typ {
-            's' => {
+            's' if !wvtt => {
                 //let ts = seq_id as f64 / 1000.0;
                 Arc::new(super::subtitle::fragment(&mp4, Format::Vtt, &fs, 0.0)?)
             },
-            _ => movie_fragment(&mp4, seq_id, fs, range_end)?,
+            _ if mpegts => {
+                let previous = previous_segments(mp4, &fs, seq_id)?;
+                let content = super::mpegts::media_segment(mp4, &fs, &previous)?;
+                match hls_enc {
+                    Some(enc) => Arc::new(enc.key(mp4, track_id)?.encrypt_segment(&content)),
+                    None => Arc::new(content),
+                }
+            },
+            _ => {
+                let keys = segment_keys(mp4, track_id, hls_enc)?;
+                let content = movie_fragment(mp4, seq_id, fs, range_end, &keys, wvtt)?;
+                match hls_enc {
+                    Some(enc) if enc.method == HlsMethod::Aes128 && typ != 's' => {
+                        let key = enc.key(mp4, track_id)?;
+                        Arc::new(key.encrypt_segment(&content))
+                    },
+                    _ => content,
+                }
+            },
         };
 
         Ok((mime, content))
@@ -1080,10 +1292,81 @@ impl MediaSegment {
     }
 }
 
+// The segments that are listed in the track playlist before segment `seq_id`.
+//
+// This is the playlist without a maximum segment size. If the segment is not
+// in it, it is treated as the first one.
+fn previous_segments(mp4: &MP4, source: &FragmentSource, seq_id: u32) -> io::Result<Vec<FragmentSource>> {
+    let segments = track_segments(mp4, source.src_track_id, None, None)?;
+    let idx = seq_id.saturating_sub(1) as usize;
+    match segments.get(idx) {
+        Some(seg) if seg.start_sample == source.from_sample && seg.end_sample == source.to_sample => {},
+        _ => {
+            log::debug!("previous_segments: segment {} not in the playlist", seq_id);
+            return Ok(Vec::new());
+        },
+    }
+    let previous = segments[..idx]
+        .iter()
+        .filter(|seg| seg.duration.partial_cmp(&0.0001) == Some(std::cmp::Ordering::Greater))
+        .map(|seg| FragmentSource {
+            from_sample: seg.start_sample,
+            to_sample: seg.end_sample,
+            ..source.clone()
+        })
+        .collect();
+    Ok(previous)
+}
+
+/// A `HLS` `AES-128` or `SAMPLE-AES` key.
+pub struct HlsKeyFile(pub(crate) MemFile);
+delegate_http_file!(HlsKeyFile);
+
+impl HlsKeyFile {
+    /// Translates `key.TRACK_ID.bin` into the 16 byte key for track `TRACK_ID`.
+    ///
+    /// Returns a `NotFound` error if `HLS` encryption is not enabled.
+    pub fn from_uri(mp4: &MP4, url_tail: &str) -> io::Result<HlsKeyFile> {
+        let track_id = scan_fmt!(url_tail, "key.{}.bin{e}", u32).map_err(|_| ioerr!(InvalidData, "bad request"))?;
+        let enc = encryption::hls_encryption().ok_or_else(|| ioerr!(NotFound, "encryption not enabled"))?;
+        mp4.movie()
+            .track_by_id(track_id)
+            .ok_or_else(|| ioerr!(NotFound, "track not found"))?;
+        let key = enc.key(mp4, track_id)?;
+        let file = &*mp4.data_ref.file;
+        let mem_file = MemFile::from_file(key.key.to_vec(), "application/octet-stream", file)?;
+        Ok(HlsKeyFile(mem_file))
+    }
+}
+
+// The keys to encrypt the segments of a track with.
+//
+// `SAMPLE-AES` uses `cbcs`, `AES-128` encrypts the whole segment
+// afterwards, and without `HLS` encryption we use the key provider.
+pub(crate) fn segment_keys(mp4: &MP4, track_id: u32, hls_enc: Option<&HlsEncryption>) -> io::Result<TrackKeys> {
+    match hls_enc {
+        Some(enc) if enc.method == HlsMethod::SampleAes => {
+            let is_subtitle = match mp4.movie().track_by_id(track_id) {
+                Some(trak) => trak.media().handler().is_subtitle(),
+                None => true,
+            };
+            let mut keys = TrackKeys::new();
+            if !is_subtitle {
+                keys.insert(track_id, enc.content_key(mp4, track_id)?);
+            }
+            Ok(keys)
+        },
+        Some(_) => Ok(TrackKeys::new()),
+        None => encryption::track_keys(mp4, &[track_id]),
+    }
+}
+
 #[derive(Hash, PartialEq, Eq, Clone)]
 struct FragmentKey {
     file: String,
     source: FragmentSource,
+    kid: Option<[u8; 16]>,
+    wvtt: bool,
 }
 
 // Caching wrapper around fragment::movie_fragment. Mainly to
@@ -1093,23 +1376,29 @@ struct FragmentKey {
 // an LRU cache. If the request has no range, or the range
 // extends to te end of the fragment, we remove the fragment
 // from the cache (if it was cached).
-fn movie_fragment(
+pub(crate) fn movie_fragment(
     mp4: &MP4,
     seq_id: u32,
     fs: FragmentSource,
     range_end: Option<u64>,
+    keys: &TrackKeys,
+    wvtt: bool,
 ) -> io::Result<Arc<Vec<u8>>> {
     #[rustfmt::skip]
     static FRAGMENTS: Lazy<LruCache<FragmentKey, Arc<Vec<u8>>>> = {
         Lazy::new(|| LruCache::new(Duration::new(60, 0)))
     };
+    let fragment = match wvtt {
+        true => super::fragment::movie_fragment_wvtt,
+        false => super::fragment::movie_fragment_encrypted,
+    };
 
     // No filename means no key. Now this can never happen,
     // but better safe than sorry.
     let file = match mp4.input_file.as_ref() {
         Some(f) => f.to_string(),
         None => {
-            let frag = super::fragment::movie_fragment(&mp4, seq_id, &[fs])?;
+            let frag = fragment(mp4, seq_id, &[fs], keys)?;
             let mut buffer = MemBuffer::new();
             frag.to_bytes(&mut buffer)?;
             return Ok(Arc::new(buffer.into_vec()));
@@ -1120,12 +1409,14 @@ fn movie_fragment(
     let key = FragmentKey {
         file,
         source: fs.clone(),
+        kid: keys.get(&fs.src_track_id).map(|k| k.kid),
+        wvtt,
     };
     let (frag, cached) = match FRAGMENTS.get(&key) {
         Some(frag) => (frag, true),
         None => {
             // Not in the cache, so generate it.
-            let frag = super::fragment::movie_fragment(&mp4, seq_id, &[fs])?;
+            let frag = fragment(mp4, seq_id, &[fs], keys)?;
             let mut buffer = MemBuffer::new();
             frag.to_bytes(&mut buffer)?;
             (Arc::new(buffer.into_vec()), false)
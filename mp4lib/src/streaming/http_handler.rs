@@ -10,11 +10,19 @@
 //! When passed an URL like `..../movie.mp4/master.m3u8`, serves the `movie.mp4`
 //! file as a `HLS` stream.
 //!
+//! - [`handle_dash`](handle_dash)
+//!
+//! When passed an URL like `..../movie.mp4/manifest.mpd`, serves the `movie.mp4`
+//! file as a `MPEG-DASH` stream.
+//!
+//! Both also accept Matroska files (`movie.mkv`, `movie.webm`) and MPEG-TS
+//! recordings (`movie.ts`).
+//!
 //! - [`handle_pseudo`](handle_pseudo)
 //!
 //! This serves an MP4 file, but re-interleaved and web-optimized. It also
 //! serves json information about the file's tracks, and it can serve
-//! `srt` files in `vtt` format.
+//! `srt` and `ttml` files in `vtt` format.
 //!
 //! - [`handle_file`](handle_file)
 //!
@@ -40,7 +48,7 @@ use percent_encoding::percent_decode_str;
 use tokio::task;
 
 use super::http_file::{self, HttpFile, MemFile};
-use super::{hls, pseudo};
+use super::{dash, hls, pseudo};
 
 macro_rules! regex {
     ($re:expr $(,)?) => {{
@@ -139,6 +147,24 @@ impl<'a> FsPath<'a> {
     }
 }
 
+/// Authorization callback for `HLS` key requests.
+///
+/// Called with the request and the filesystem path of the `mp4` file.
+/// Returns `true` if the key may be served.
+pub type KeyAuthorizer = dyn Fn(&Request<()>, &str) -> bool + Send + Sync;
+
+static KEY_AUTHORIZER: once_cell::sync::OnceCell<Box<KeyAuthorizer>> = once_cell::sync::OnceCell::new();
+
+/// Set the process-wide authorization callback for `HLS` key requests.
+///
+/// If no callback is set, key requests are refused with `403 Forbidden`.
+/// Can be called only once, returns an error if it was already set.
+pub fn set_key_authorizer(f: impl Fn(&Request<()>, &str) -> bool + Send + Sync + 'static) -> io::Result<()> {
+    KEY_AUTHORIZER
+        .set(Box::new(f))
+        .map_err(|_| ioerr!(AlreadyExists, "key authorizer already set"))
+}
+
 /// Handle `HLS` `URLs`.
 ///
 /// Handles the main entry point `...../movie.mp4/master.m3u8`.
@@ -147,6 +173,10 @@ impl<'a> FsPath<'a> {
 /// contain URLs to media segments, all of which are of the form  
 /// `...../movie.mp4/<url_tail>`.
 ///
+/// If `HLS` encryption is enabled, this also serves the keys
+/// (`...../movie.mp4/key.<TRACK_ID>.bin`), after checking with the
+/// callback set by [`set_key_authorizer`].
+///
 /// Returns `Ok(None)` if this was not a `HLS` related request.
 ///
 pub async fn handle_hls(
@@ -156,13 +186,50 @@ pub async fn handle_hls(
 ) -> io::Result<Option<Response<BoxBody>>> {
     let path = path.resolve(req)?;
 
-    const PATH_AND_EXTRA: &'static str = r#"^(.*\.mp4)/(.*\.(?:m3u8|mp4|m4a|vtt))$"#;
+    // The paths below the media file that are served here. Not the DASH
+    // `d/` segments, those are served by `handle_dash`.
+    const PATH_AND_EXTRA: &'static str = concat!(
+        r#"^(.*\.(?:mp4|mkv|webm|ts))/("#,
+        // Playlists, initialization segments, external subtitles.
+        r#"[^/]*\.(?:m3u8|mp4|vtt)|e/.*\.vtt"#,
+        // fMP4 audio and video segments, WebVTT segments.
+        r#"|[av]/c\.[^/]*\.(?:mp4|m4a)|s/c\.[^/]*\.vtt"#,
+        // Keys of encrypted segments.
+        r#"|key\.[0-9]+\.bin"#,
+        // fMP4 `wvtt` subtitle segments.
+        r#"|s/c\.[^/]*\.m4s"#,
+        // MPEG-TS audio and video segments.
+        r#"|[av]/c\.[^/]*\.ts"#,
+        r#")$"#,
+    );
     let caps = match regex!(PATH_AND_EXTRA).captures(&path) {
         Some(caps) => caps,
         None => return Ok(None),
     };
     let (path, extra) = (&caps[1], &caps[2]);
 
+    // Served by handle_pseudo.
+    if extra == "chapters.vtt" {
+        return Ok(None);
+    }
+
+    // Key. Check this before "not modified", and never cache it.
+    if extra.ends_with(".bin") {
+        // Without an authorizer the key would be public, so refuse it.
+        let authorized = KEY_AUTHORIZER.get().map(|authorize| authorize(req, path));
+        if authorized != Some(true) {
+            return Err(ioerr!(PermissionDenied, "403 Forbidden"));
+        }
+        let data = task::block_in_place(|| {
+            let mp4 = super::lru_cache::open_mp4(path, false, true)?;
+            hls::HlsKeyFile::from_uri(&mp4, extra)
+        })?;
+        let mut response = serve_file(req, data.0).await.box_body();
+        let no_store = header::HeaderValue::from_static("no-store");
+        response.headers_mut().insert(header::CACHE_CONTROL, no_store);
+        return Ok(Some(response));
+    }
+
     if let Some(response) = not_modified(&req, path).await {
         return Ok(Some(response));
     }
@@ -185,7 +252,12 @@ pub async fn handle_hls(
     }
 
     // Media data.
-    if extra.ends_with(".mp4") || extra.ends_with(".m4a") || extra.ends_with(".vtt") {
+    if extra.ends_with(".mp4") ||
+        extra.ends_with(".m4a") ||
+        extra.ends_with(".m4s") ||
+        extra.ends_with(".vtt") ||
+        extra.ends_with(".ts")
+    {
         let data = task::block_in_place(|| {
             let mp4 = super::lru_cache::open_mp4(path, false, true)?;
             hls::MediaSegment::from_uri(&*mp4, extra, range_end(req))
@@ -196,6 +268,58 @@ pub async fn handle_hls(
     Ok(None)
 }
 
+/// Handle `DASH` `URLs`.
+///
+/// Handles the main entry point `...../movie.mp4/manifest.mpd`.
+///
+/// The manifest refers to initialization and media segments, all of
+/// which are of the form `...../movie.mp4/<url_tail>`. See the
+/// [`dash`](crate::streaming::dash) module for the url conventions.
+///
+/// Returns `Ok(None)` if this was not a `DASH` related request.
+///
+pub async fn handle_dash(
+    req: &Request<()>,
+    path: FsPath<'_>,
+    filter_subs: bool,
+) -> io::Result<Option<Response<BoxBody>>> {
+    let path = path.resolve(req)?;
+
+    const PATH_AND_EXTRA: &str =
+        r#"^(.*\.(?:mp4|mkv|webm|ts))/(manifest(?:-wvtt)?\.mpd|init\.[0-9]+(?:\.wvtt)?\.mp4|d/[0-9]+\.(?:[0-9]+\.m4s|vtt)|e/.*\.vtt)$"#;
+    let caps = match regex!(PATH_AND_EXTRA).captures(&path) {
+        Some(caps) => caps,
+        None => return Ok(None),
+    };
+    let (path, extra) = (&caps[1], &caps[2]);
+
+    if let Some(response) = not_modified(req, path).await {
+        return Ok(Some(response));
+    }
+
+    // Chromecast cannot handle segments > 8M, see `handle_hls`.
+    let max_segment_size = match req.headers().typed_get::<UserAgent>() {
+        Some(ua) if ua.as_str().contains("CrKey/") => Some(8_000_000),
+        _ => None,
+    };
+
+    // DASH manifest.
+    if extra.ends_with(".mpd") {
+        let data = task::block_in_place(|| {
+            let mp4 = super::lru_cache::open_mp4(path, false, true)?;
+            dash::DashManifest::from_uri(&mp4, extra, filter_subs, max_segment_size)
+        })?;
+        return Ok(Some(serve_file(req, data.0).await.box_body()));
+    }
+
+    // Media data.
+    let data = task::block_in_place(|| {
+        let mp4 = super::lru_cache::open_mp4(path, false, true)?;
+        dash::DashSegment::from_uri(&mp4, extra, max_segment_size, range_end(req))
+    })?;
+    Ok(Some(serve_file(req, data.0).await.box_body()))
+}
+
 fn range_end(req: &Request<()>) -> Option<u64> {
     let range = req.headers().typed_get::<HttpRange>()?.iter().next()?;
     use std::ops::Bound::*;
@@ -208,18 +332,27 @@ fn range_end(req: &Request<()>) -> Option<u64> {
 
 /// Handle `pseudostreaming` `URLs`.
 ///
-/// This handler handles three types of URLs:
+/// This handler handles five types of URLs:
 ///
 /// - `/...../movie.mp4/info.json`  
 ///   return movie and track information in `JSON` format
 ///
+/// - `/...../movie.mp4/chapters.vtt`  
+///   return the chapters as `WebVTT` chapters.
+///
+/// - `/...../movie.mp4/cover.jpg`  
+///   return the embedded cover art (which might also be `PNG`).
+///
 /// - `/...../movie.srt:into.vtt`  
-///   translate `srt` subtitle file into `vtt` format.
+///   translate `srt` or `ttml` subtitle file into `vtt` format.
 ///
 /// - `/...../movie.mp4?track_id=1,2,3`  
 ///   serve a version of the MP4 file with only tracks `1`, `2`, and `3`.
 ///   See also the [`pseudo`](crate::streaming::pseudo) module.
 ///
+/// `info.json` and `?track_id=` also work on Matroska (`.mkv`, `.webm`) files.
+/// `info.json` also works on MPEG-TS (`.ts`) files.
+///
 pub async fn handle_pseudo(req: &Request<()>, path: FsPath<'_>) -> io::Result<Option<Response<BoxBody>>> {
     use std::collections::HashMap;
     use std::iter::FromIterator;
@@ -227,7 +360,7 @@ pub async fn handle_pseudo(req: &Request<()>, path: FsPath<'_>) -> io::Result<Op
     let path = path.resolve(req)?;
 
     // External subtitle format translation (subtitle.srt:into.vtt).
-    const SUBTITLE: &'static str = r#"^(.*\.(?:srt|vtt)):into\.(srt|vtt)$"#;
+    const SUBTITLE: &'static str = r#"^(.*\.(?:srt|vtt|ttml|dfxp)):into\.(srt|vtt)$"#;
     if let Some(caps) = regex!(SUBTITLE).captures(&path) {
         let (path, extra) = (&caps[1], &caps[2]);
         if let Some(response) = not_modified(&req, path).await {
@@ -242,7 +375,7 @@ pub async fn handle_pseudo(req: &Request<()>, path: FsPath<'_>) -> io::Result<Op
     }
 
     // Info.
-    const INFO: &'static str = r#"^(.*\.mp4)/info.json$"#;
+    const INFO: &'static str = r#"^(.*\.(?:mp4|mkv|webm|ts))/info.json$"#;
     if let Some(caps) = regex!(INFO).captures(&path) {
         let path = &caps[1];
         if let Some(response) = not_modified(&req, path).await {
@@ -258,7 +391,45 @@ pub async fn handle_pseudo(req: &Request<()>, path: FsPath<'_>) -> io::Result<Op
         return Ok(Some(serve_file(req, data).await.box_body()));
     }
 
-    if !path.ends_with(".mp4") {
+    // Chapters.
+    const CHAPTERS: &str = r#"^(.*\.mp4)/chapters.vtt$"#;
+    if let Some(caps) = regex!(CHAPTERS).captures(&path) {
+        let path = &caps[1];
+        if let Some(response) = not_modified(req, path).await {
+            return Ok(Some(response));
+        }
+        let file = task::block_in_place(|| fs::File::open(&caps[1]))?;
+        let mp4 = task::block_in_place(|| super::lru_cache::open_mp4(path, false, true))?;
+
+        let chapters = task::block_in_place(|| crate::chapters::chapters(&mp4))?;
+        if chapters.is_empty() {
+            return Err(ioerr!(NotFound, "{}: no chapters", path));
+        }
+        let body = crate::chapters::export(&chapters, crate::chapters::ChapterFormat::Vtt);
+
+        let data = http_file::MemFile::from_file(body.into_bytes(), "text/vtt", &file)?;
+        return Ok(Some(serve_file(req, data).await.box_body()));
+    }
+
+    // Cover art.
+    const COVER: &str = r#"^(.*\.mp4)/cover.jpg$"#;
+    if let Some(caps) = regex!(COVER).captures(&path) {
+        let path = &caps[1];
+        if let Some(response) = not_modified(req, path).await {
+            return Ok(Some(response));
+        }
+        let file = task::block_in_place(|| fs::File::open(&caps[1]))?;
+        let mp4 = task::block_in_place(|| super::lru_cache::open_mp4(path, false, true))?;
+
+        let cover = task::block_in_place(|| crate::metadata::cover_art(&mp4))?;
+        let cover = cover.ok_or_else(|| ioerr!(NotFound, "{}: no cover art", path))?;
+
+        let mime = cover.mime();
+        let data = http_file::MemFile::from_file(cover.data, mime, &file)?;
+        return Ok(Some(serve_file(req, data).await.box_body()));
+    }
+
+    if !path.ends_with(".mp4") && !path.ends_with(".mkv") && !path.ends_with(".webm") {
         return Ok(None);
     }
 
@@ -373,6 +544,9 @@ where
     // If this is a generated file, the timestamp cannot be earlier than
     // that of the executable.
     if req.uri().path().contains(".mp4/")
+        || req.uri().path().contains(".mkv/")
+        || req.uri().path().contains(".webm/")
+        || req.uri().path().contains(".ts/")
         || req.uri().path().contains(".into:")
         || req.uri().query().is_some()
     {
@@ -1,5 +1,6 @@
 //! Cache for some often-used objects.
 use std::borrow::Borrow;
+use std::fs;
 use std::hash::Hash;
 use std::io;
 use std::sync::{Arc, Mutex};
@@ -12,11 +13,21 @@ use crate::mp4box::MP4;
 
 /// A cached version of [`Mp4File::open`](crate::io::Mp4File::open) and
 /// [`MP4::read`](crate::MP4::read).
+///
+/// Matroska files are opened with [`matroska::open`](crate::matroska::open),
+/// MPEG-TS files with [`mpegts::open`](crate::mpegts::open).
+///
+/// Fragmented MP4 files are read as-is, their samples are found with
+/// [`MP4::sample_info_iter`]. If the file has grown since it was cached,
+/// like a recording that is still in progress, it is read again so that
+/// the new fragments are seen.
 pub fn open_mp4(path: impl Into<String>, mmap_all: bool, check_editlist: bool) -> io::Result<Arc<MP4>> {
-    static MP4_FILES: Lazy<LruCache<String, Arc<MP4>>> = Lazy::new(|| LruCache::new(Duration::new(60, 0)));
+    static MP4_FILES: Lazy<LruCache<String, (Arc<MP4>, u64)>> =
+        Lazy::new(|| LruCache::new(Duration::new(60, 0)));
     let path = path.into();
-    let mp4 = match MP4_FILES.get(&path) {
-        Some(mp4) => {
+    let size = fs::metadata(&path)?.len();
+    let mp4 = match MP4_FILES.get(&path).filter(|(_, sz)| *sz == size) {
+        Some((mp4, _)) => {
             if check_editlist {
                 for track in mp4.movie().tracks().iter() {
                     track.composition_time_shift(true)?;
@@ -25,8 +36,14 @@ pub fn open_mp4(path: impl Into<String>, mmap_all: bool, check_editlist: bool) -
             mp4
         },
         None => {
-            let mut reader = Mp4File::open(&path, mmap_all)?;
-            let mut mp4 = MP4::read(&mut reader)?;
+            let mut mp4 = if crate::matroska::is_matroska(&path) {
+                crate::matroska::open(&path)?
+            } else if crate::mpegts::is_mpegts(&path) {
+                crate::mpegts::open(&path)?
+            } else {
+                let mut reader = Mp4File::open(&path, mmap_all)?;
+                MP4::read(&mut reader)?
+            };
             for track in mp4.movie_mut().tracks_mut().iter_mut() {
                 if let Err(e) = track.simplify_offsets() {
                     if check_editlist {
@@ -35,7 +52,7 @@ pub fn open_mp4(path: impl Into<String>, mmap_all: bool, check_editlist: bool) -
                 }
             }
             let mp4 = Arc::new(mp4);
-            MP4_FILES.put(path, mp4.clone());
+            MP4_FILES.put(path, (mp4.clone(), size));
             mp4
         },
     };
@@ -3,16 +3,21 @@
 //! This module and submodules contain helpers to transmux a MP4
 //! file on-the-fly while streaming it over HTTP.
 //!
-//! You probably want to start at [`pseudo`](crate::streaming::pseudo) or
-//! [`hls`](crate::streaming::hls).
+//! You probably want to start at [`pseudo`](crate::streaming::pseudo),
+//! [`hls`](crate::streaming::hls) or [`dash`](crate::streaming::dash).
 //!
 //! Note, `transmuxing` is not `transcoding`.
+pub mod dash;
+pub mod encryption;
+pub mod es;
 pub mod fragment;
 pub mod hls;
 pub mod http_file;
 pub mod lru_cache;
+pub mod mpegts;
 pub mod pseudo;
 pub mod segmenter;
+mod slice_header;
 pub mod subtitle;
 
 #[cfg_attr(docsrs, doc(cfg(feature = "http-handler")))]
@@ -0,0 +1,482 @@
+//! MPEG-2 Transport Stream (`TS`) segments.
+//!
+//! Older set-top boxes and smart TVs only play `HLS` streams with
+//! `MPEG-TS` segments. This module muxes a range of samples of one
+//! audio or video track into a self-contained transport stream:
+//! a `PAT`, a `PMT`, and the samples as `PES` packets.
+//!
+//! - `H.264` and `HEVC` samples are converted to an Annex-B byte stream,
+//!   with an access unit delimiter in front of every frame and the
+//!   parameter sets from the `avcC` / `hvcC` box in front of every sync frame.
+//! - `AAC` frames get an `ADTS` header.
+//! - `AC-3` and `E-AC-3` frames are copied as-is.
+//!
+//! The segment boundaries are the same as for `fMP4` segments, see
+//! [`segmenter`](super::segmenter).
+//!
+use std::cmp;
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::boxes::TrackBox;
+use crate::mp4box::MP4;
+use crate::sample_info::SampleInfo;
+
+use super::es::Codec;
+use super::fragment::FragmentSource;
+use super::lru_cache::LruCache;
+
+const TS_PACKET_SIZE: usize = 188;
+const PID_PAT: u16 = 0x0000;
+const PID_PMT: u16 = 0x1000;
+const PID_ES: u16 = 0x0100;
+
+// All timestamps are shifted by this amount (10 seconds at 90 kHz), so
+// that the DTS of the first frames, which can lie before the PTS of
+// the first frame, never becomes negative.
+const TIMESTAMP_OFFSET: i64 = 900_000;
+
+// The largest distance between two PCRs, 100 ms at 90 kHz (ISO/IEC 13818-1 2.7.2).
+const PCR_INTERVAL: u64 = 9_000;
+
+// How far the PCR runs behind the DTS of the PES packet it is in, 100 ms at 90 kHz.
+const PCR_DELAY: u64 = 9_000;
+
+// How to put the samples of a track in a PES packet.
+impl Codec {
+    // ISO/IEC 13818-1 Table 2-34, ATSC A/52 Annex A.
+    fn stream_type(&self) -> u8 {
+        match self {
+            Codec::Avc { .. } => 0x1b,
+            Codec::Hevc { .. } => 0x24,
+            Codec::Aac { .. } => 0x0f,
+            Codec::Ac3 => 0x81,
+            Codec::Ec3 => 0x87,
+        }
+    }
+
+    // Private stream 1 for Dolby audio.
+    fn stream_id(&self) -> u8 {
+        match self {
+            Codec::Avc { .. } | Codec::Hevc { .. } => 0xe0,
+            Codec::Aac { .. } => 0xc0,
+            Codec::Ac3 | Codec::Ec3 => 0xbd,
+        }
+    }
+
+    // Registration descriptor, for the Dolby codecs.
+    fn descriptors(&self) -> &'static [u8] {
+        match self {
+            Codec::Ac3 => &[0x05, 0x04, b'A', b'C', b'-', b'3'],
+            Codec::Ec3 => &[0x05, 0x04, b'E', b'A', b'C', b'3'],
+            _ => &[],
+        }
+    }
+}
+
+/// Generate a `MPEG-TS` segment from a range of samples of one track.
+///
+/// `previous` are the segments that come before this one in the playlist.
+/// The continuity counters of the `PAT`, `PMT` and elementary stream
+/// start where they ended in the previous segment, so that they run on
+/// from one segment into the next. To find out where that is, the size of
+/// every `PES` packet in the previous segments is calculated (and cached).
+pub fn media_segment(mp4: &MP4, source: &FragmentSource, previous: &[FragmentSource]) -> io::Result<Vec<u8>> {
+    let track = mp4
+        .movie()
+        .track_by_id(source.src_track_id)
+        .ok_or_else(|| ioerr!(NotFound, "{}: no such track", source.src_track_id))?;
+    let codec = Codec::new(track)?;
+    let timing = Timing::new(mp4, track)?;
+    let samples = segment_samples(mp4, track, source)?;
+
+    // Every segment has one PAT and one PMT packet.
+    let psi_cc = previous.len() as u64;
+    let mut es_cc = 0;
+    for prev in previous {
+        es_cc += es_packet_count(mp4, track, &codec, &timing, prev)?;
+    }
+
+    let mut ts = TsWriter::new(es_cc);
+    ts.psi(PID_PAT, psi_cc, &pat());
+    ts.psi(PID_PMT, psi_cc, &pmt(&codec));
+
+    let mut data = Vec::new();
+    for pes in timing.pes_packets(&codec, &samples) {
+        let mut payload = Vec::new();
+        for sample in &samples[pes.samples.clone()] {
+            data.resize(sample.size as usize, 0);
+            mp4.data_ref.read_exact_at(&mut data, sample.fpos)?;
+            codec.append_sample(&mut payload, &data, sample.is_sync)?;
+        }
+
+        let dts = if pes.pts != pes.dts { Some(pes.dts) } else { None };
+        let pes_data = pes_packet(codec.stream_id(), pes.pts, dts, &payload);
+        ts.pes(PID_ES, &pes_data, pes.pcr(), pes.random_access);
+    }
+
+    // The PCRs up to the first PCR of the next segment.
+    let last = &samples[samples.len() - 1];
+    let end = timing.to_90k(last.decode_time as i64 + last.duration as i64 + timing.shift - timing.delay);
+    ts.pcr_gap(PID_ES, end.saturating_sub(PCR_DELAY));
+
+    Ok(ts.into_vec())
+}
+
+// The number of TS packets of the elementary stream in a segment.
+fn es_packet_count(
+    mp4: &MP4,
+    track: &TrackBox,
+    codec: &Codec,
+    timing: &Timing,
+    source: &FragmentSource,
+) -> io::Result<u64> {
+    // Keyed by file name, file size, and the segment.
+    static PACKET_COUNTS: Lazy<LruCache<(String, u64, FragmentSource), u64>> =
+        Lazy::new(|| LruCache::new(Duration::new(60, 0)));
+    let key = mp4
+        .input_file
+        .as_ref()
+        .map(|name| (name.to_string(), mp4.data_ref.len(), source.clone()));
+    if let Some(count) = key.as_ref().and_then(|key| PACKET_COUNTS.get(key)) {
+        return Ok(count);
+    }
+
+    let samples = segment_samples(mp4, track, source)?;
+    let mut count = 0;
+    for pes in timing.pes_packets(codec, &samples) {
+        let mut len = if pes.pts != pes.dts { 19 } else { 14 };
+        for sample in &samples[pes.samples] {
+            len += codec.sample_len(&mp4.data_ref, sample.fpos, sample.size, sample.is_sync)?;
+        }
+        count += TsWriter::pes_packet_count(len);
+    }
+
+    if let Some(key) = key {
+        PACKET_COUNTS.put(key, count);
+    }
+    Ok(count)
+}
+
+// The samples of a segment.
+fn segment_samples(mp4: &MP4, track: &TrackBox, source: &FragmentSource) -> io::Result<Vec<SampleInfo>> {
+    let mut samples = mp4.sample_info_iter(track);
+    samples.seek(source.from_sample)?;
+    let count = source.to_sample.saturating_sub(source.from_sample) + 1;
+    let samples: Vec<_> = samples.take(count as usize).collect();
+    if samples.is_empty() {
+        return Err(ioerr!(UnexpectedEof));
+    }
+    Ok(samples)
+}
+
+// Translates the sample times of a track to 90 kHz timestamps.
+struct Timing {
+    timescale: i64,
+    shift: i64,
+    delay: i64,
+}
+
+// The samples in one PES packet, and its timestamps.
+struct Pes {
+    samples: Range<usize>,
+    pts: u64,
+    dts: u64,
+    random_access: bool,
+}
+
+impl Pes {
+    // The PCR runs a fixed amount behind the DTS, so that the
+    // PES packet arrives before it has to be decoded.
+    fn pcr(&self) -> u64 {
+        self.dts.saturating_sub(PCR_DELAY)
+    }
+}
+
+impl Timing {
+    fn new(mp4: &MP4, track: &TrackBox) -> io::Result<Timing> {
+        // With negative composition offsets, the decode time of a sample can be
+        // later than its presentation time. That is not allowed in a transport
+        // stream, so move all decode times back by the largest negative offset.
+        let delay = mp4
+            .min_composition_offset(track)
+            .map(|offset| cmp::max(0, -(offset as i64)))
+            .unwrap_or(0);
+        Ok(Timing {
+            timescale: track.media().media_header().timescale as i64,
+            shift: track.composition_time_shift(false)?,
+            delay,
+        })
+    }
+
+    fn to_90k(&self, t: i64) -> u64 {
+        cmp::max(0, t * 90_000 / self.timescale + TIMESTAMP_OFFSET) as u64
+    }
+
+    // Video frames each get their own PES packet. Audio frames are put
+    // together, but a PES packet never lasts longer than the PCR interval.
+    fn pes_packets(&self, codec: &Codec, samples: &[SampleInfo]) -> Vec<Pes> {
+        let max_duration = PCR_INTERVAL as i64 * self.timescale / 90_000;
+        let mut packets = Vec::new();
+        let mut start = 0;
+        while start < samples.len() {
+            let mut end = start + 1;
+            if !codec.is_video() {
+                let mut duration = samples[start].duration as i64;
+                while end < samples.len() && duration + samples[end].duration as i64 <= max_duration {
+                    duration += samples[end].duration as i64;
+                    end += 1;
+                }
+            }
+            // Composition time is decode time + composition offset + edit list shift.
+            let first = &samples[start];
+            let decode_time = first.decode_time as i64 + self.shift;
+            packets.push(Pes {
+                samples: start..end,
+                pts: self.to_90k(decode_time + first.composition_delta as i64),
+                dts: self.to_90k(decode_time - self.delay),
+                random_access: !codec.is_video() || first.is_sync,
+            });
+            start = end;
+        }
+        packets
+    }
+}
+
+// Program Association Table (ISO/IEC 13818-1 2.4.4.3).
+fn pat() -> Vec<u8> {
+    let mut body = Vec::new();
+    // program_number 1.
+    body.extend_from_slice(&[0x00, 0x01]);
+    body.extend_from_slice(&(0xe000 | PID_PMT).to_be_bytes());
+    psi_section(0x00, 0x0001, &body)
+}
+
+// Program Map Table (ISO/IEC 13818-1 2.4.4.8).
+fn pmt(codec: &Codec) -> Vec<u8> {
+    let mut body = Vec::new();
+    // PCR_PID, then program_info_length 0.
+    body.extend_from_slice(&(0xe000 | PID_ES).to_be_bytes());
+    body.extend_from_slice(&[0xf0, 0x00]);
+    // The elementary stream.
+    let descriptors = codec.descriptors();
+    body.push(codec.stream_type());
+    body.extend_from_slice(&(0xe000 | PID_ES).to_be_bytes());
+    body.extend_from_slice(&(0xf000 | descriptors.len() as u16).to_be_bytes());
+    body.extend_from_slice(descriptors);
+    psi_section(0x02, 0x0001, &body)
+}
+
+// A PSI section with the long header and a CRC.
+fn psi_section(table_id: u8, id: u16, body: &[u8]) -> Vec<u8> {
+    // id, version/current_next, section_number, last_section_number, body, CRC.
+    let section_length = 5 + body.len() + 4;
+    let mut section = vec![table_id];
+    section.extend_from_slice(&(0xb000 | section_length as u16).to_be_bytes());
+    section.extend_from_slice(&id.to_be_bytes());
+    section.extend_from_slice(&[0xc1, 0x00, 0x00]);
+    section.extend_from_slice(body);
+    let crc = crc32(&section);
+    section.extend_from_slice(&crc.to_be_bytes());
+    section
+}
+
+// CRC-32/MPEG-2.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in data {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// PES packet (ISO/IEC 13818-1 2.4.3.6).
+fn pes_packet(stream_id: u8, pts: u64, dts: Option<u64>, payload: &[u8]) -> Vec<u8> {
+    let header_len = if dts.is_some() { 10 } else { 5 };
+    // Video PES packets can be unbounded (length 0).
+    let len = 3 + header_len + payload.len();
+    let len = if len > 0xffff || stream_id == 0xe0 {
+        0
+    } else {
+        len as u16
+    };
+
+    let mut pes = vec![0x00, 0x00, 0x01, stream_id];
+    pes.extend_from_slice(&len.to_be_bytes());
+    // '10', not scrambled, data_alignment_indicator.
+    pes.push(0x84);
+    pes.push(if dts.is_some() { 0xc0 } else { 0x80 });
+    pes.push(header_len as u8);
+    match dts {
+        Some(dts) => {
+            push_timestamp(&mut pes, 0x03, pts);
+            push_timestamp(&mut pes, 0x01, dts);
+        },
+        None => push_timestamp(&mut pes, 0x02, pts),
+    }
+    pes.extend_from_slice(payload);
+    pes
+}
+
+// 33 bit timestamp, spread out over 5 bytes with marker bits.
+fn push_timestamp(buf: &mut Vec<u8>, prefix: u8, ts: u64) {
+    let ts = ts & 0x1_ffff_ffff;
+    buf.extend_from_slice(&[
+        (prefix << 4) | ((ts >> 29) as u8 & 0x0e) | 0x01,
+        (ts >> 22) as u8,
+        ((ts >> 14) as u8 & 0xfe) | 0x01,
+        (ts >> 7) as u8,
+        ((ts << 1) as u8 & 0xfe) | 0x01,
+    ]);
+}
+
+// Cuts PSI sections and PES packets into transport stream packets.
+struct TsWriter {
+    buf: Vec<u8>,
+    // Continuity counter of the elementary stream.
+    cc: u8,
+    last_pcr: Option<u64>,
+}
+
+impl TsWriter {
+    // `es_packets` is the number of elementary stream packets before
+    // this segment, the continuity counter continues from there.
+    fn new(es_packets: u64) -> TsWriter {
+        TsWriter {
+            buf: Vec::new(),
+            cc: (es_packets & 0x0f) as u8,
+            last_pcr: None,
+        }
+    }
+
+    // A PSI section that fits in one packet. `psi_packets` is the number
+    // of packets of this table that came before it.
+    fn psi(&mut self, pid: u16, psi_packets: u64, section: &[u8]) {
+        let start = self.buf.len();
+        self.buf.push(0x47);
+        self.buf.extend_from_slice(&(0x4000 | pid).to_be_bytes());
+        self.buf.push(0x10 | (psi_packets & 0x0f) as u8);
+        // pointer_field.
+        self.buf.push(0x00);
+        self.buf.extend_from_slice(section);
+        self.buf.resize(start + TS_PACKET_SIZE, 0xff);
+    }
+
+    // A PES packet, with a PCR in the first packet.
+    fn pes(&mut self, pid: u16, data: &[u8], pcr: u64, random_access: bool) {
+        self.pcr_gap(pid, pcr);
+        self.last_pcr = Some(pcr);
+        let mut pos = 0;
+        let mut first = true;
+        while pos < data.len() {
+            // Adaptation field, without the length byte.
+            let mut af = Vec::new();
+            if first {
+                af.push(if random_access { 0x50 } else { 0x10 });
+                push_pcr(&mut af, pcr);
+            }
+            let mut has_af = !af.is_empty();
+            let room = TS_PACKET_SIZE - 4 - if has_af { af.len() + 1 } else { 0 };
+            let len = cmp::min(room, data.len() - pos);
+
+            // Fill up the last packet with stuffing bytes in the adaptation field.
+            let stuffing = room - len;
+            if stuffing > 0 {
+                if !has_af {
+                    has_af = true;
+                    if stuffing > 1 {
+                        af.push(0x00);
+                        af.resize(stuffing - 1, 0xff);
+                    }
+                } else {
+                    af.resize(af.len() + stuffing, 0xff);
+                }
+            }
+
+            let pusi = if first { 0x4000 } else { 0x0000 };
+            self.buf.push(0x47);
+            self.buf.extend_from_slice(&(pusi | pid).to_be_bytes());
+            self.buf.push(if has_af { 0x30 } else { 0x10 } | self.cc);
+            if has_af {
+                self.buf.push(af.len() as u8);
+                self.buf.extend_from_slice(&af);
+            }
+            self.buf.extend_from_slice(&data[pos..pos + len]);
+
+            self.cc = (self.cc + 1) & 0x0f;
+            pos += len;
+            first = false;
+        }
+    }
+
+    // The number of packets that `pes` writes for a PES packet of `len` bytes.
+    fn pes_packet_count(len: usize) -> u64 {
+        // The first packet has an adaptation field with a PCR (8 bytes).
+        let first = TS_PACKET_SIZE - 4 - 8;
+        let rest = TS_PACKET_SIZE - 4;
+        1 + len.saturating_sub(first).div_ceil(rest) as u64
+    }
+
+    // If there is a gap between the last PCR and `pcr`, the PCRs in
+    // between go in packets that only have an adaptation field.
+    fn pcr_gap(&mut self, pid: u16, pcr: u64) {
+        if let Some(mut last) = self.last_pcr {
+            while pcr > last + PCR_INTERVAL {
+                last += PCR_INTERVAL;
+                self.pcr(pid, last);
+            }
+            self.last_pcr = Some(last);
+        }
+    }
+
+    // A packet with only a PCR. It has no payload, so the
+    // continuity counter does not change (ISO/IEC 13818-1 2.4.3.3).
+    fn pcr(&mut self, pid: u16, pcr: u64) {
+        let start = self.buf.len();
+        self.buf.push(0x47);
+        self.buf.extend_from_slice(&pid.to_be_bytes());
+        self.buf.push(0x20 | self.cc.wrapping_sub(1) & 0x0f);
+        self.buf.push(183);
+        self.buf.push(0x10);
+        push_pcr(&mut self.buf, pcr);
+        self.buf.resize(start + TS_PACKET_SIZE, 0xff);
+    }
+
+    fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+// program_clock_reference_base, extension 0.
+fn push_pcr(buf: &mut Vec<u8>, pcr: u64) {
+    let base = pcr & 0x1_ffff_ffff;
+    buf.extend_from_slice(&[
+        (base >> 25) as u8,
+        (base >> 17) as u8,
+        (base >> 9) as u8,
+        (base >> 1) as u8,
+        ((base & 0x01) << 7) as u8 | 0x7e,
+        0x00,
+    ]);
+}
+
+/// Can a track with this codec (as in the `CODECS` attribute) be put
+/// in a transport stream.
+pub(crate) fn is_supported_codec(codec_id: &str) -> bool {
+    let codec = codec_id.split('.').next().unwrap_or("");
+    match codec {
+        "avc1" | "hvc1" | "hev1" | "ac-3" | "ec-3" => true,
+        "mp4a" => matches!(codec_id, "mp4a.40.2" | "mp4a.40.5" | "mp4a.40.29"),
+        _ => false,
+    }
+}
@@ -28,15 +28,19 @@ use super::lru_cache::{open_mp4, LruCache};
 use crate::boxes::*;
 use crate::io::DataRef;
 use crate::mp4box::{MP4Box, MP4};
+use crate::sample_info::FragmentIndex;
 use crate::serialize::ToBytes;
 use crate::types::FourCC;
 
 use super::http_file::{build_etag, HttpFile};
 
 // Key into INIT_SECTION / DATA_SECTION cache.
+//
+// The file size is part of the key, a fragmented file can still be growing.
 #[derive(Hash, PartialEq, Eq, Clone)]
 struct SectionKey {
     path: String,
+    size: u64,
     tracks: Vec<u32>,
 }
 
@@ -69,6 +73,7 @@ impl Mp4Stream {
 
         let file = fs::File::open(&path)?;
         let meta = file.metadata()?;
+        let file_size = meta.len();
         let modified = meta.modified().unwrap();
         let etag = build_etag(meta, super::http_file::E::GENERATED);
 
@@ -85,7 +90,11 @@ impl Mp4Stream {
         }
 
         // prime the LRU cache.
-        let key = SectionKey { path, tracks };
+        let key = SectionKey {
+            path,
+            size: file_size,
+            tracks,
+        };
         let mapping = InitSection::mapping(&key)?;
         let init_size = mapping.init_size;
         let size = init_size as u64 + 16 + mapping.virt_size;
@@ -509,13 +518,29 @@ impl InitSection {
     }
 
     fn init_and_mapping(key: &SectionKey, mp4: &MP4) -> io::Result<(InitSection, MdatMapping)> {
+        // The output needs a MovieBox with all the samples in it.
+        let defragmented;
+        let mp4 = match first_box!(mp4, MovieFragmentBox) {
+            Some(_) => {
+                defragmented = crate::rewrite::defragment(mp4)?;
+                &defragmented
+            },
+            None => mp4,
+        };
+
         let mut tracks = Vec::new();
         let moov = mp4.movie();
         for track in &key.tracks {
-            tracks.push(
-                moov.track_by_id(*track)
-                    .ok_or_else(|| ioerr!(NotFound, "track {} not found", track))?,
-            );
+            let trak = moov
+                .track_by_id(*track)
+                .ok_or_else(|| ioerr!(NotFound, "track {} not found", track))?;
+            // Generated samples (subtitles of a matroska file) are not in the file.
+            if let Some(info) = trak.sample_info_iter().next() {
+                if mp4.data_ref.in_memory(info.fpos) {
+                    return Err(ioerr!(InvalidInput, "track {} cannot be pseudo-streamed", track));
+                }
+            }
+            tracks.push(trak);
         }
         let (chunks, mut mapping) = Self::interleave(mp4, &tracks[..]);
         let mut init = Self::build_init(key, mp4, chunks);
@@ -587,6 +612,7 @@ impl InitSection {
             data_ref: DataRef::default(),
             input_file: mp4.input_file.clone(),
             boxes,
+            fragment_index: FragmentIndex::default(),
         }
     }
 
@@ -4,6 +4,7 @@
 //! or on fixed intervals.
 //!
 use crate::boxes::*;
+use crate::mp4box::MP4;
 use std::cmp::Ordering;
 use std::io;
 
@@ -169,13 +170,17 @@ fn merge_fragments(s: Vec<Fragment>, max_segment_size: u32) -> (Vec<Segment>, u6
 /// You use this to segment the video tracks into segments. The
 /// resulting timing data can then be used to segment the audio
 /// track(s) into segments with the exact same start_time and duration.
+///
+/// The samples in the MovieFragmentBoxes of a fragmented `mp4` are
+/// included, see [`MP4::sample_info_iter`].
 pub fn track_to_segments(
+    mp4: &MP4,
     trak: &TrackBox,
     segment_duration: Option<u32>,
     max_segment_size: Option<u32>,
 ) -> io::Result<Vec<Segment>> {
     let handler = trak.media().handler();
-    let fragments = track_to_fragments(trak, segment_duration, max_segment_size)?;
+    let fragments = track_to_fragments(mp4, trak, segment_duration, max_segment_size)?;
     let segments = if !handler.is_subtitle() {
         let (segments, _) = merge_fragments(fragments, max_segment_size.unwrap_or(0));
         segments
@@ -186,16 +191,18 @@ pub fn track_to_segments(
 }
 
 pub(crate) fn track_segment_peak_bw(
+    mp4: &MP4,
     trak: &TrackBox,
     segment_duration: Option<u32>,
     max_segment_size: Option<u32>,
 ) -> io::Result<u64> {
-    let fragments = track_to_fragments(trak, segment_duration, max_segment_size)?;
+    let fragments = track_to_fragments(mp4, trak, segment_duration, max_segment_size)?;
     let (_, bw) = merge_fragments(fragments, max_segment_size.unwrap_or(0));
     Ok(bw)
 }
 
 fn track_to_fragments(
+    mp4: &MP4,
     trak: &TrackBox,
     fragment_duration: Option<u32>,
     max_fragment_size: Option<u32>,
@@ -209,20 +216,14 @@ fn track_to_fragments(
     let comp_time_shift = trak.composition_time_shift(true)?;
     let mut fragment_duration = fragment_duration.map(|d| ((d as u64 * ts64) / 1000) as u32);
 
-    let mut stts_iter = table.time_to_sample().iter();
-    let mut stsz_iter = table.sample_size().iter();
-    let mut stss_iter = match table.sync_samples() {
-        Some(stss) => Some(stss.iter()),
-        None => {
-            // println!("subtitles");
-            if !handler.is_subtitle() {
-                return Err(ioerr!(InvalidData, "track {}: no SyncSampleBox"));
-            }
-            fragment_duration = None;
-            None
-        },
-    };
-    // println!("stss_iter is {:?}", stss_iter.is_some());
+    // The samples in the fragments of a fragmented file have their own sync flags.
+    let mut samples = mp4.sample_info_iter(trak);
+    match table.sync_samples() {
+        Some(_) => {},
+        None if handler.is_subtitle() => fragment_duration = None,
+        None if table.is_empty() => {},
+        None => return Err(ioerr!(InvalidData, "track {}: no SyncSampleBox")),
+    }
 
     let mut cur_time = comp_time_shift;
     let mut cur_frag_duration = comp_time_shift;
@@ -234,8 +235,8 @@ fn track_to_fragments(
 
     for cur_sample in 1..=u32::MAX {
 
-        let sample_duration = match stts_iter.next() {
-            Some((d, _)) => d,
+        let (sample_duration, sample_size, sample_is_sync) = match samples.next() {
+            Some(sample) => (sample.duration, sample.size, sample.is_sync),
             None => {
                 // No more samples, we're done. Finish the last fragment.
                 if cur_fragment.start_sample > 0 && cur_frag_duration > 0 {
@@ -248,18 +249,11 @@ fn track_to_fragments(
             },
         };
 
-        let sample_size = stsz_iter.next().unwrap_or(0);
-
         let mut is_sync = true;
         let do_next_frag = match fragment_duration {
             Some(d) => cur_frag_duration >= d.into(),
             None => {
-                is_sync = if let Some(stss_iter) = stss_iter.as_mut() {
-                    stss_iter.next().unwrap_or(false)
-                } else {
-                    // no stss iter? every sample is a sync sample.
-                    true
-                };
+                is_sync = sample_is_sync;
                 if cur_frag_duration <= 0 {
                     false
                 } else if is_sync {
@@ -300,15 +294,17 @@ fn track_to_fragments(
 /// Parse a track into segments, based on a list of segment time/duration.
 ///
 /// Used for audio tracks.
-pub fn track_to_segments_timed(trak: &TrackBox, timing_segments: &[Segment]) -> io::Result<Vec<Segment>> {
+pub fn track_to_segments_timed(
+    mp4: &MP4,
+    trak: &TrackBox,
+    timing_segments: &[Segment],
+) -> io::Result<Vec<Segment>> {
     let media = trak.media();
-    let table = media.media_info().sample_table();
 
     let timescale = media.media_header().timescale as f64;
     let comp_time_shift = trak.composition_time_shift(true)?;
 
-    let mut stts_iter = table.time_to_sample().iter();
-    let mut ctts_iter = table.composition_time_to_sample().map(|ctts| ctts.iter());
+    let mut samples = mp4.sample_info_iter(trak);
 
     let mut cur_time = comp_time_shift;
     let mut seg_duration = comp_time_shift;
@@ -331,10 +327,8 @@ pub fn track_to_segments_timed(trak: &TrackBox, timing_segments: &[Segment]) ->
     let mut segment_end_time = next_segment_end_time(0.0);
 
     for cur_sample in 1..=u32::MAX {
-        let delta = ctts_iter.as_mut().and_then(|iter| iter.next()).unwrap_or(0);
-
-        let sample_duration = match stts_iter.next() {
-            Some((d, _)) => d,
+        let (sample_duration, delta) = match samples.next() {
+            Some(sample) => (sample.duration, sample.composition_delta),
             None => {
                 // No more samples, we're done. Finish the last segment.
                 if cur_segment.start_sample > 0 && seg_duration > 0 {
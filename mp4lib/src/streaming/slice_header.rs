@@ -0,0 +1,1059 @@
+//! Find the start of the slice data in `H.264` and `HEVC` slices.
+//!
+//! With subsample encryption the NAL unit header and the slice header must
+//! stay in the clear (ISO/IEC 23001-7 10.2), only the slice data may be
+//! encrypted. The length of the slice header depends on the SPS and PPS
+//! it refers to, so those are kept as well. They come from the `avcC` or
+//! `hvcC` box, and from the samples themselves.
+//!
+use std::collections::HashMap;
+use std::io;
+
+use crate::bitreader::{unescape_rbsp, BitReader};
+use crate::boxes::avcc::{self, FrameMbsFlags, PicOrderCntType};
+use crate::boxes::hvc1::{self, ShortTermRefPicSet};
+
+// Most slice headers fit in this many bytes. Only if parsing runs
+// out of data, the rest of the NAL unit is used as well.
+const SLICE_HEADER_PREFIX: usize = 512;
+
+/// Parses the slice headers of one `H.264` or `HEVC` track.
+pub(crate) struct SliceHeaderParser {
+    hevc: bool,
+    avc_sps: HashMap<u32, avcc::SeqParameterSet>,
+    avc_pps: HashMap<u32, AvcPps>,
+    hevc_sps: HashMap<u32, hvc1::SeqParameterSet>,
+    hevc_pps: HashMap<u32, HevcPps>,
+}
+
+impl SliceHeaderParser {
+    /// Start with the parameter sets of an `avcC` box.
+    pub(crate) fn avc(config: &[u8]) -> io::Result<SliceHeaderParser> {
+        let mut parser = SliceHeaderParser::new(false);
+        let sets = avcc::ParameterSet::parse(config)?;
+        for nal in sets.sps.iter().chain(sets.pps.iter()) {
+            parser.parse(nal)?;
+        }
+        Ok(parser)
+    }
+
+    /// Start with the parameter sets of a `hvcC` box.
+    pub(crate) fn hevc(config: &[u8]) -> io::Result<SliceHeaderParser> {
+        let mut parser = SliceHeaderParser::new(true);
+        let sets = hvc1::ParameterSet::parse(config)?;
+        for nal in sets.sps.iter().chain(sets.pps.iter()) {
+            parser.parse(nal)?;
+        }
+        Ok(parser)
+    }
+
+    fn new(hevc: bool) -> SliceHeaderParser {
+        SliceHeaderParser {
+            hevc,
+            avc_sps: HashMap::new(),
+            avc_pps: HashMap::new(),
+            hevc_sps: HashMap::new(),
+            hevc_pps: HashMap::new(),
+        }
+    }
+
+    /// Look at one NAL unit, in decode order.
+    ///
+    /// Parameter sets are remembered. For a slice, this returns the length
+    /// of the NAL unit header plus the slice header, in bytes. For other
+    /// NAL units it returns `None`.
+    pub(crate) fn parse(&mut self, nal: &[u8]) -> io::Result<Option<usize>> {
+        let header_len = if self.hevc { 2 } else { 1 };
+        if nal.len() < header_len {
+            return Ok(None);
+        }
+        let nal_unit_type = if self.hevc {
+            (nal[0] >> 1) & 0x3f
+        } else {
+            nal[0] & 0x1f
+        };
+
+        // The parameter sets are small, use all of them.
+        let params = match (self.hevc, nal_unit_type) {
+            (false, 7) | (false, 8) | (true, 33) | (true, 34) => Some(unescape_rbsp(&nal[header_len..])),
+            _ => None,
+        };
+        if let Some(rbsp) = params {
+            let mut r = BitReader::new(&rbsp);
+            match (self.hevc, nal_unit_type) {
+                (false, 7) => {
+                    let sps = avcc::SeqParameterSet::read(&mut r)?;
+                    self.avc_sps.insert(sps.seq_parameter_set_id as u32, sps);
+                },
+                (false, 8) => {
+                    let (id, pps) = AvcPps::read(&mut r)?;
+                    self.avc_pps.insert(id, pps);
+                },
+                (true, 33) => {
+                    let sps = hvc1::SeqParameterSet::read(&mut r)?;
+                    self.hevc_sps.insert(sps.sps_seq_parameter_set_id, sps);
+                },
+                _ => {
+                    let (id, pps) = HevcPps::read(&mut r)?;
+                    self.hevc_pps.insert(id, pps);
+                },
+            }
+            return Ok(None);
+        }
+
+        let is_slice = match self.hevc {
+            true => nal_unit_type < 32,
+            false => matches!(nal_unit_type, 1..=5),
+        };
+        if !is_slice {
+            return Ok(None);
+        }
+
+        // Try with the first part of the NAL unit, then with all of it.
+        let mut prefix = std::cmp::min(nal.len(), SLICE_HEADER_PREFIX);
+        loop {
+            let rbsp = unescape_rbsp(&nal[header_len..prefix]);
+            let mut r = BitReader::new(&rbsp);
+            let res = match self.hevc {
+                true => self.hevc_slice_header(&mut r, nal_unit_type),
+                false => self.avc_slice_header(&mut r, nal_unit_type, nal[0] >> 5),
+            };
+            match res {
+                Ok(()) => {
+                    let rbsp_len = r.pos.div_ceil(8);
+                    return Ok(Some(header_len + escaped_len(&nal[header_len..], rbsp_len)));
+                },
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && prefix < nal.len() => {
+                    prefix = nal.len()
+                },
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(ioerr!(InvalidData, "slice header: truncated"))
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // slice_header() (ITU-T H.264 7.3.3).
+    fn avc_slice_header(&self, r: &mut BitReader, nal_unit_type: u8, nal_ref_idc: u8) -> io::Result<()> {
+        if nal_unit_type != 1 && nal_unit_type != 5 {
+            return Err(ioerr!(
+                InvalidData,
+                "slice header: data partitioning is not supported"
+            ));
+        }
+        // first_mb_in_slice
+        r.read_ue()?;
+        let slice_type = r.read_ue_max(9)? % 5;
+        let pps_id = r.read_ue_max(255)?;
+        let pps = self
+            .avc_pps
+            .get(&pps_id)
+            .ok_or_else(|| ioerr!(InvalidData, "slice header: unknown PPS {}", pps_id))?;
+        let sps = self
+            .avc_sps
+            .get(&pps.sps_id)
+            .ok_or_else(|| ioerr!(InvalidData, "slice header: unknown SPS {}", pps.sps_id))?;
+        let (is_p, is_b, is_sp) = (
+            slice_type == 0 || slice_type == 3,
+            slice_type == 1,
+            slice_type == 3,
+        );
+        let is_intra = slice_type == 2 || slice_type == 4;
+
+        let (chroma_format_idc, separate_colour_plane_flag) = match sps.chroma_format.as_ref() {
+            Some(c) => (
+                c.chroma_format_idc,
+                c.residual_color_transform_flag.unwrap_or(false),
+            ),
+            None => (1, false),
+        };
+        if separate_colour_plane_flag {
+            // colour_plane_id
+            r.read_bits(2)?;
+        }
+        // frame_num
+        r.read_bits(sps.log2_max_frame_num_minus4 + 4)?;
+        let mut field_pic_flag = false;
+        if let FrameMbsFlags::Fields { .. } = sps.frame_mbs_flags {
+            field_pic_flag = r.read_bit()?;
+            if field_pic_flag {
+                // bottom_field_flag
+                r.read_bit()?;
+            }
+        }
+        if nal_unit_type == 5 {
+            // idr_pic_id
+            r.read_ue()?;
+        }
+        let bottom_field_pic_order = pps.bottom_field_pic_order_in_frame_present_flag && !field_pic_flag;
+        match sps.pic_order_cnt_type {
+            PicOrderCntType::Zero {
+                log2_max_pic_order_cnt_lsb_minus4,
+            } => {
+                // pic_order_cnt_lsb, delta_pic_order_cnt_bottom
+                r.read_bits(log2_max_pic_order_cnt_lsb_minus4 + 4)?;
+                if bottom_field_pic_order {
+                    r.read_se()?;
+                }
+            },
+            PicOrderCntType::One {
+                delta_pic_order_always_zero_flag: false,
+                ..
+            } => {
+                // delta_pic_order_cnt[0], delta_pic_order_cnt[1]
+                r.read_se()?;
+                if bottom_field_pic_order {
+                    r.read_se()?;
+                }
+            },
+            _ => {},
+        }
+        if pps.redundant_pic_cnt_present_flag {
+            // redundant_pic_cnt
+            r.read_ue()?;
+        }
+        if is_b {
+            // direct_spatial_mv_pred_flag
+            r.read_bit()?;
+        }
+        let mut num_ref_idx_active_minus1 = pps.num_ref_idx_default_active_minus1;
+        if (is_p || is_b) && r.read_bit()? {
+            num_ref_idx_active_minus1[0] = r.read_ue_max(31)?;
+            if is_b {
+                num_ref_idx_active_minus1[1] = r.read_ue_max(31)?;
+            }
+        }
+        let num_lists = if is_b { 2 } else { 1 };
+
+        // ref_pic_list_modification()
+        if !is_intra {
+            for _ in 0..num_lists {
+                if r.read_bit()? {
+                    // modification_of_pic_nums_idc, then abs_diff_pic_num_minus1
+                    // or long_term_pic_num, until modification_of_pic_nums_idc is 3.
+                    while r.read_ue_max(3)? != 3 {
+                        r.read_ue()?;
+                    }
+                }
+            }
+        }
+
+        // pred_weight_table()
+        if (pps.weighted_pred_flag && is_p) || (pps.weighted_bipred_idc == 1 && is_b) {
+            let chroma = !separate_colour_plane_flag && chroma_format_idc != 0;
+            // luma_log2_weight_denom, chroma_log2_weight_denom
+            r.read_ue()?;
+            if chroma {
+                r.read_ue()?;
+            }
+            for &num in &num_ref_idx_active_minus1[..num_lists] {
+                for _ in 0..=num {
+                    if r.read_bit()? {
+                        // luma_weight, luma_offset
+                        r.read_se()?;
+                        r.read_se()?;
+                    }
+                    if chroma && r.read_bit()? {
+                        // chroma_weight, chroma_offset, for Cb and Cr
+                        for _ in 0..4 {
+                            r.read_se()?;
+                        }
+                    }
+                }
+            }
+        }
+
+        // dec_ref_pic_marking()
+        if nal_ref_idc != 0 {
+            if nal_unit_type == 5 {
+                // no_output_of_prior_pics_flag, long_term_reference_flag
+                r.read_bits(2)?;
+            } else if r.read_bit()? {
+                loop {
+                    let mmco = r.read_ue_max(6)?;
+                    if mmco == 0 {
+                        break;
+                    }
+                    // difference_of_pic_nums_minus1, long_term_pic_num,
+                    // long_term_frame_idx, max_long_term_frame_idx_plus1
+                    if mmco != 5 {
+                        r.read_ue()?;
+                    }
+                    if mmco == 3 {
+                        r.read_ue()?;
+                    }
+                }
+            }
+        }
+
+        if pps.entropy_coding_mode_flag && !is_intra {
+            // cabac_init_idc
+            r.read_ue_max(2)?;
+        }
+        // slice_qp_delta
+        r.read_se()?;
+        if slice_type == 3 || slice_type == 4 {
+            if is_sp {
+                // sp_for_switch_flag
+                r.read_bit()?;
+            }
+            // slice_qs_delta
+            r.read_se()?;
+        }
+        if pps.deblocking_filter_control_present_flag && r.read_ue_max(6)? != 1 {
+            // slice_alpha_c0_offset_div2, slice_beta_offset_div2
+            r.read_se()?;
+            r.read_se()?;
+        }
+        if pps.num_slice_groups_minus1 > 0 && (3..=5).contains(&pps.slice_group_map_type) {
+            // slice_group_change_cycle: Ceil(Log2(PicSizeInMapUnits ÷ SliceGroupChangeRate + 1)) bits.
+            let pic_size =
+                (sps.pic_width_in_mbs_minus1 as u64 + 1) * (sps.pic_height_in_map_units_minus1 as u64 + 1);
+            let rate = pps.slice_group_change_rate_minus1 as u64 + 1;
+            let mut bits = 0;
+            while (rate << bits) < pic_size + rate {
+                bits += 1;
+            }
+            r.read_bits(bits)?;
+        }
+        Ok(())
+    }
+
+    // slice_segment_header() (ITU-T H.265 7.3.6.1), up to and including byte_alignment().
+    fn hevc_slice_header(&self, r: &mut BitReader, nal_unit_type: u8) -> io::Result<()> {
+        if (10..=15).contains(&nal_unit_type) || nal_unit_type > 21 {
+            return Err(ioerr!(
+                InvalidData,
+                "slice header: reserved NAL unit type {}",
+                nal_unit_type
+            ));
+        }
+        let first_slice_segment_in_pic_flag = r.read_bit()?;
+        if (16..=23).contains(&nal_unit_type) {
+            // no_output_of_prior_pics_flag
+            r.read_bit()?;
+        }
+        let pps_id = r.read_ue_max(63)?;
+        let pps = self
+            .hevc_pps
+            .get(&pps_id)
+            .ok_or_else(|| ioerr!(InvalidData, "slice header: unknown PPS {}", pps_id))?;
+        let sps = self
+            .hevc_sps
+            .get(&pps.sps_id)
+            .ok_or_else(|| ioerr!(InvalidData, "slice header: unknown SPS {}", pps.sps_id))?;
+
+        let mut dependent_slice_segment_flag = false;
+        if !first_slice_segment_in_pic_flag {
+            if pps.dependent_slice_segments_enabled_flag {
+                dependent_slice_segment_flag = r.read_bit()?;
+            }
+            // slice_segment_address
+            r.read_bits(ceil_log2(pic_size_in_ctbs(sps)))?;
+        }
+
+        if !dependent_slice_segment_flag {
+            // slice_reserved_flag
+            r.read_bits(pps.num_extra_slice_header_bits)?;
+            let slice_type = r.read_ue_max(2)?;
+            let (is_b, is_p) = (slice_type == 0, slice_type == 1);
+            if pps.output_flag_present_flag {
+                // pic_output_flag
+                r.read_bit()?;
+            }
+            if sps.separate_colour_plane_flag {
+                // colour_plane_id
+                r.read_bits(2)?;
+            }
+            let chroma_array_type = if sps.separate_colour_plane_flag {
+                0
+            } else {
+                sps.chroma_format_idc
+            };
+
+            let mut num_pic_total_curr = 0;
+            let mut slice_temporal_mvp_enabled_flag = false;
+            if nal_unit_type != 19 && nal_unit_type != 20 {
+                // slice_pic_order_cnt_lsb
+                r.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u8 + 4)?;
+                let num_sets = sps.st_ref_pic_sets.len();
+                // short_term_ref_pic_set_sps_flag, then the set itself or an index.
+                let in_slice_header;
+                let st_rps = if !r.read_bit()? {
+                    in_slice_header = ShortTermRefPicSet::read(r, num_sets, num_sets, &sps.st_ref_pic_sets)?;
+                    &in_slice_header
+                } else {
+                    let idx = r.read_bits(ceil_log2(num_sets as u32))? as usize;
+                    sps.st_ref_pic_sets
+                        .get(idx)
+                        .ok_or_else(|| ioerr!(InvalidData, "slice header: bad short_term_ref_pic_set_idx"))?
+                };
+                num_pic_total_curr += st_rps.num_used();
+
+                if sps.long_term_ref_pics_present_flag {
+                    let lt_sps = &sps.used_by_curr_pic_lt_sps_flags;
+                    let num_long_term_sps = if !lt_sps.is_empty() {
+                        r.read_ue_max(lt_sps.len() as u32)?
+                    } else {
+                        0
+                    };
+                    let num_long_term_pics = r.read_ue_max(32)?;
+                    for i in 0..num_long_term_sps + num_long_term_pics {
+                        let used = if i < num_long_term_sps {
+                            let lt_idx_sps = r.read_bits(ceil_log2(lt_sps.len() as u32))? as usize;
+                            *lt_sps
+                                .get(lt_idx_sps)
+                                .ok_or_else(|| ioerr!(InvalidData, "slice header: bad lt_idx_sps"))?
+                        } else {
+                            // poc_lsb_lt, used_by_curr_pic_lt_flag
+                            r.read_bits(sps.log2_max_pic_order_cnt_lsb_minus4 as u8 + 4)?;
+                            r.read_bit()?
+                        };
+                        if used {
+                            num_pic_total_curr += 1;
+                        }
+                        // delta_poc_msb_present_flag, delta_poc_msb_cycle_lt
+                        if r.read_bit()? {
+                            r.read_ue()?;
+                        }
+                    }
+                }
+                if sps.sps_temporal_mvp_enabled_flag {
+                    slice_temporal_mvp_enabled_flag = r.read_bit()?;
+                }
+            }
+
+            let mut slice_sao = false;
+            if sps.sample_adaptive_offset_enabled_flag {
+                // slice_sao_luma_flag, slice_sao_chroma_flag
+                slice_sao = r.read_bit()?;
+                if chroma_array_type != 0 {
+                    slice_sao |= r.read_bit()?;
+                }
+            }
+
+            if is_p || is_b {
+                let mut num_ref_idx_active_minus1 = pps.num_ref_idx_default_active_minus1;
+                if r.read_bit()? {
+                    num_ref_idx_active_minus1[0] = r.read_ue_max(14)?;
+                    if is_b {
+                        num_ref_idx_active_minus1[1] = r.read_ue_max(14)?;
+                    }
+                }
+                let num_lists = if is_b { 2 } else { 1 };
+
+                // ref_pic_lists_modification()
+                if pps.lists_modification_present_flag && num_pic_total_curr > 1 {
+                    let bits = ceil_log2(num_pic_total_curr);
+                    for &num in &num_ref_idx_active_minus1[..num_lists] {
+                        // ref_pic_list_modification_flag, list_entry
+                        if r.read_bit()? {
+                            r.read_bits((num as u8 + 1) * bits)?;
+                        }
+                    }
+                }
+                if is_b {
+                    // mvd_l1_zero_flag
+                    r.read_bit()?;
+                }
+                if pps.cabac_init_present_flag {
+                    // cabac_init_flag
+                    r.read_bit()?;
+                }
+                if slice_temporal_mvp_enabled_flag {
+                    let collocated_from_l0_flag = if is_b { r.read_bit()? } else { true };
+                    let list = if collocated_from_l0_flag { 0 } else { 1 };
+                    if num_ref_idx_active_minus1[list] > 0 {
+                        // collocated_ref_idx
+                        r.read_ue()?;
+                    }
+                }
+
+                // pred_weight_table()
+                if (pps.weighted_pred_flag && is_p) || (pps.weighted_bipred_flag && is_b) {
+                    // luma_log2_weight_denom, delta_chroma_log2_weight_denom
+                    r.read_ue()?;
+                    if chroma_array_type != 0 {
+                        r.read_se()?;
+                    }
+                    for &num in &num_ref_idx_active_minus1[..num_lists] {
+                        let num = num as usize + 1;
+                        let mut luma_weight_flags = Vec::new();
+                        for _ in 0..num {
+                            luma_weight_flags.push(r.read_bit()?);
+                        }
+                        let mut chroma_weight_flags = vec![false; num];
+                        if chroma_array_type != 0 {
+                            for flag in chroma_weight_flags.iter_mut() {
+                                *flag = r.read_bit()?;
+                            }
+                        }
+                        for i in 0..num {
+                            // delta_luma_weight, luma_offset
+                            if luma_weight_flags[i] {
+                                r.read_se()?;
+                                r.read_se()?;
+                            }
+                            // delta_chroma_weight, delta_chroma_offset, for Cb and Cr
+                            if chroma_weight_flags[i] {
+                                for _ in 0..4 {
+                                    r.read_se()?;
+                                }
+                            }
+                        }
+                    }
+                }
+                // five_minus_max_num_merge_cand
+                r.read_ue()?;
+            }
+
+            // slice_qp_delta
+            r.read_se()?;
+            if pps.slice_chroma_qp_offsets_present_flag {
+                // slice_cb_qp_offset, slice_cr_qp_offset
+                r.read_se()?;
+                r.read_se()?;
+            }
+            if pps.chroma_qp_offset_list_enabled_flag {
+                // cu_chroma_qp_offset_enabled_flag
+                r.read_bit()?;
+            }
+            let deblocking_filter_override_flag =
+                pps.deblocking_filter_override_enabled_flag && r.read_bit()?;
+            let mut deblocking_filter_disabled_flag = pps.deblocking_filter_disabled_flag;
+            if deblocking_filter_override_flag {
+                deblocking_filter_disabled_flag = r.read_bit()?;
+                if !deblocking_filter_disabled_flag {
+                    // slice_beta_offset_div2, slice_tc_offset_div2
+                    r.read_se()?;
+                    r.read_se()?;
+                }
+            }
+            if pps.loop_filter_across_slices_enabled_flag && (slice_sao || !deblocking_filter_disabled_flag) {
+                // slice_loop_filter_across_slices_enabled_flag
+                r.read_bit()?;
+            }
+        }
+
+        if pps.tiles_enabled_flag || pps.entropy_coding_sync_enabled_flag {
+            let num_entry_point_offsets = r.read_ue()?;
+            if num_entry_point_offsets > 0 {
+                let offset_len_minus1 = r.read_ue_max(31)?;
+                for _ in 0..num_entry_point_offsets {
+                    // entry_point_offset_minus1
+                    r.read_bits(offset_len_minus1 as u8 + 1)?;
+                }
+            }
+        }
+        if pps.slice_segment_header_extension_present_flag {
+            let len = r.read_ue_max(256)?;
+            for _ in 0..len {
+                r.read_u8()?;
+            }
+        }
+        // byte_alignment(), the bit equal to one. The zero bits up to the
+        // next byte boundary are counted by rounding up.
+        r.read_bit()?;
+        Ok(())
+    }
+}
+
+// The fields of the H.264 PPS that are needed to parse the slice header (7.3.2.2).
+struct AvcPps {
+    sps_id: u32,
+    entropy_coding_mode_flag: bool,
+    bottom_field_pic_order_in_frame_present_flag: bool,
+    num_slice_groups_minus1: u32,
+    slice_group_map_type: u32,
+    slice_group_change_rate_minus1: u32,
+    num_ref_idx_default_active_minus1: [u32; 2],
+    weighted_pred_flag: bool,
+    weighted_bipred_idc: u32,
+    deblocking_filter_control_present_flag: bool,
+    redundant_pic_cnt_present_flag: bool,
+}
+
+impl AvcPps {
+    fn read(r: &mut BitReader) -> io::Result<(u32, AvcPps)> {
+        let pps_id = r.read_ue_max(255)?;
+        let sps_id = r.read_ue_max(31)?;
+        let entropy_coding_mode_flag = r.read_bit()?;
+        let bottom_field_pic_order_in_frame_present_flag = r.read_bit()?;
+        let num_slice_groups_minus1 = r.read_ue_max(7)?;
+        let mut slice_group_map_type = 0;
+        let mut slice_group_change_rate_minus1 = 0;
+        if num_slice_groups_minus1 > 0 {
+            slice_group_map_type = r.read_ue_max(6)?;
+            match slice_group_map_type {
+                0 => {
+                    for _ in 0..=num_slice_groups_minus1 {
+                        // run_length_minus1
+                        r.read_ue()?;
+                    }
+                },
+                2 => {
+                    for _ in 0..num_slice_groups_minus1 {
+                        // top_left, bottom_right
+                        r.read_ue()?;
+                        r.read_ue()?;
+                    }
+                },
+                3..=5 => {
+                    // slice_group_change_direction_flag
+                    r.read_bit()?;
+                    slice_group_change_rate_minus1 = r.read_ue()?;
+                },
+                6 => {
+                    let pic_size_in_map_units_minus1 = r.read_ue()?;
+                    let bits = ceil_log2(num_slice_groups_minus1 + 1);
+                    for _ in 0..=pic_size_in_map_units_minus1 {
+                        // slice_group_id
+                        r.read_bits(bits)?;
+                    }
+                },
+                _ => {},
+            }
+        }
+        let num_ref_idx_default_active_minus1 = [r.read_ue_max(31)?, r.read_ue_max(31)?];
+        let weighted_pred_flag = r.read_bit()?;
+        let weighted_bipred_idc = r.read_bits(2)?;
+        // pic_init_qp_minus26, pic_init_qs_minus26, chroma_qp_index_offset
+        r.read_se()?;
+        r.read_se()?;
+        r.read_se()?;
+        let deblocking_filter_control_present_flag = r.read_bit()?;
+        // constrained_intra_pred_flag
+        r.read_bit()?;
+        let redundant_pic_cnt_present_flag = r.read_bit()?;
+        let pps = AvcPps {
+            sps_id,
+            entropy_coding_mode_flag,
+            bottom_field_pic_order_in_frame_present_flag,
+            num_slice_groups_minus1,
+            slice_group_map_type,
+            slice_group_change_rate_minus1,
+            num_ref_idx_default_active_minus1,
+            weighted_pred_flag,
+            weighted_bipred_idc,
+            deblocking_filter_control_present_flag,
+            redundant_pic_cnt_present_flag,
+        };
+        Ok((pps_id, pps))
+    }
+}
+
+// The fields of the HEVC PPS that are needed to parse the slice header (7.3.2.3).
+struct HevcPps {
+    sps_id: u32,
+    dependent_slice_segments_enabled_flag: bool,
+    output_flag_present_flag: bool,
+    num_extra_slice_header_bits: u8,
+    cabac_init_present_flag: bool,
+    num_ref_idx_default_active_minus1: [u32; 2],
+    slice_chroma_qp_offsets_present_flag: bool,
+    weighted_pred_flag: bool,
+    weighted_bipred_flag: bool,
+    tiles_enabled_flag: bool,
+    entropy_coding_sync_enabled_flag: bool,
+    loop_filter_across_slices_enabled_flag: bool,
+    deblocking_filter_override_enabled_flag: bool,
+    deblocking_filter_disabled_flag: bool,
+    lists_modification_present_flag: bool,
+    slice_segment_header_extension_present_flag: bool,
+    chroma_qp_offset_list_enabled_flag: bool,
+}
+
+impl HevcPps {
+    fn read(r: &mut BitReader) -> io::Result<(u32, HevcPps)> {
+        let pps_id = r.read_ue_max(63)?;
+        let sps_id = r.read_ue_max(15)?;
+        let dependent_slice_segments_enabled_flag = r.read_bit()?;
+        let output_flag_present_flag = r.read_bit()?;
+        let num_extra_slice_header_bits = r.read_bits(3)? as u8;
+        // sign_data_hiding_enabled_flag
+        r.read_bit()?;
+        let cabac_init_present_flag = r.read_bit()?;
+        let num_ref_idx_default_active_minus1 = [r.read_ue_max(14)?, r.read_ue_max(14)?];
+        // init_qp_minus26, constrained_intra_pred_flag
+        r.read_se()?;
+        r.read_bit()?;
+        let transform_skip_enabled_flag = r.read_bit()?;
+        if r.read_bit()? {
+            // diff_cu_qp_delta_depth
+            r.read_ue()?;
+        }
+        // pps_cb_qp_offset, pps_cr_qp_offset
+        r.read_se()?;
+        r.read_se()?;
+        let slice_chroma_qp_offsets_present_flag = r.read_bit()?;
+        let weighted_pred_flag = r.read_bit()?;
+        let weighted_bipred_flag = r.read_bit()?;
+        // transquant_bypass_enabled_flag
+        r.read_bit()?;
+        let tiles_enabled_flag = r.read_bit()?;
+        let entropy_coding_sync_enabled_flag = r.read_bit()?;
+        if tiles_enabled_flag {
+            let num_tile_columns_minus1 = r.read_ue()?;
+            let num_tile_rows_minus1 = r.read_ue()?;
+            let uniform_spacing_flag = r.read_bit()?;
+            if !uniform_spacing_flag {
+                // column_width_minus1, row_height_minus1
+                for _ in 0..num_tile_columns_minus1 + num_tile_rows_minus1 {
+                    r.read_ue()?;
+                }
+            }
+            // loop_filter_across_tiles_enabled_flag
+            r.read_bit()?;
+        }
+        let loop_filter_across_slices_enabled_flag = r.read_bit()?;
+        let mut deblocking_filter_override_enabled_flag = false;
+        let mut deblocking_filter_disabled_flag = false;
+        if r.read_bit()? {
+            deblocking_filter_override_enabled_flag = r.read_bit()?;
+            deblocking_filter_disabled_flag = r.read_bit()?;
+            if !deblocking_filter_disabled_flag {
+                // pps_beta_offset_div2, pps_tc_offset_div2
+                r.read_se()?;
+                r.read_se()?;
+            }
+        }
+        if r.read_bit()? {
+            hvc1::skip_scaling_list_data(r)?;
+        }
+        let lists_modification_present_flag = r.read_bit()?;
+        // log2_parallel_merge_level_minus2
+        r.read_ue()?;
+        let slice_segment_header_extension_present_flag = r.read_bit()?;
+
+        let mut chroma_qp_offset_list_enabled_flag = false;
+        if r.read_bit()? {
+            let pps_range_extension_flag = r.read_bit()?;
+            let _pps_multilayer_extension_flag = r.read_bit()?;
+            let _pps_3d_extension_flag = r.read_bit()?;
+            let pps_scc_extension_flag = r.read_bit()?;
+            // pps_extension_4bits
+            r.read_bits(4)?;
+            if pps_scc_extension_flag {
+                return Err(ioerr!(InvalidData, "PPS: screen content coding is not supported"));
+            }
+            if pps_range_extension_flag {
+                if transform_skip_enabled_flag {
+                    // log2_max_transform_skip_block_size_minus2
+                    r.read_ue()?;
+                }
+                // cross_component_prediction_enabled_flag
+                r.read_bit()?;
+                chroma_qp_offset_list_enabled_flag = r.read_bit()?;
+            }
+        }
+
+        let pps = HevcPps {
+            sps_id,
+            dependent_slice_segments_enabled_flag,
+            output_flag_present_flag,
+            num_extra_slice_header_bits,
+            cabac_init_present_flag,
+            num_ref_idx_default_active_minus1,
+            slice_chroma_qp_offsets_present_flag,
+            weighted_pred_flag,
+            weighted_bipred_flag,
+            tiles_enabled_flag,
+            entropy_coding_sync_enabled_flag,
+            loop_filter_across_slices_enabled_flag,
+            deblocking_filter_override_enabled_flag,
+            deblocking_filter_disabled_flag,
+            lists_modification_present_flag,
+            slice_segment_header_extension_present_flag,
+            chroma_qp_offset_list_enabled_flag,
+        };
+        Ok((pps_id, pps))
+    }
+}
+
+// PicSizeInCtbsY (7-10 .. 7-19).
+fn pic_size_in_ctbs(sps: &hvc1::SeqParameterSet) -> u32 {
+    let ctb_log2_size =
+        sps.log2_min_luma_coding_block_size_minus3 + 3 + sps.log2_diff_max_min_luma_coding_block_size;
+    let ctb_size = 1u64 << std::cmp::min(ctb_log2_size, 16);
+    let width = (sps.pic_width_in_luma_samples as u64).div_ceil(ctb_size);
+    let height = (sps.pic_height_in_luma_samples as u64).div_ceil(ctb_size);
+    std::cmp::min(width * height, u32::MAX as u64) as u32
+}
+
+// Ceil(Log2(n)).
+fn ceil_log2(n: u32) -> u8 {
+    let mut bits = 0;
+    while bits < 32 && (1u64 << bits) < n as u64 {
+        bits += 1;
+    }
+    bits
+}
+
+// The number of bytes in the NAL unit payload that hold the first
+// `rbsp_len` bytes of the RBSP, counting the emulation prevention bytes.
+fn escaped_len(data: &[u8], rbsp_len: usize) -> usize {
+    let mut zeroes = 0;
+    let mut done = 0;
+    for (idx, &b) in data.iter().enumerate() {
+        if done == rbsp_len {
+            return idx;
+        }
+        if zeroes >= 2 && b == 3 {
+            zeroes = 0;
+            continue;
+        }
+        zeroes = if b == 0 { zeroes + 1 } else { 0 };
+        done += 1;
+    }
+    data.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitreader::{escape_rbsp, BitWriter};
+
+    // NAL unit with the emulation prevention bytes added.
+    fn nal(header: &[u8], w: &BitWriter) -> Vec<u8> {
+        let mut nal = header.to_vec();
+        nal.extend_from_slice(&escape_rbsp(&w.data));
+        nal
+    }
+
+    fn avc_parser() -> SliceHeaderParser {
+        let mut sps = BitWriter::default();
+        // Main profile, level 3.0, sps_id 0, log2_max_frame_num 4, POC type 0 with 4 bits.
+        sps.bits(8, 77).bits(8, 0).bits(8, 30).ue(0).ue(0).ue(0).ue(0);
+        // num_ref_frames, gaps, 320x240, frame_mbs_only, direct_8x8, no cropping, no VUI.
+        sps.ue(2)
+            .bit(false)
+            .ue(19)
+            .ue(14)
+            .bit(true)
+            .bit(true)
+            .bit(false)
+            .bit(false)
+            .trailing();
+
+        let mut pps = BitWriter::default();
+        // pps_id 0, sps_id 0, CABAC, one slice group, one reference per list by default.
+        pps.ue(0).ue(0).bit(true).bit(false).ue(0).ue(0).ue(0);
+        // no weighted prediction, QP offsets, deblocking control, no redundant_pic_cnt.
+        pps.bit(false)
+            .bits(2, 0)
+            .se(0)
+            .se(0)
+            .se(0)
+            .bit(true)
+            .bit(false)
+            .bit(false)
+            .trailing();
+
+        let mut parser = SliceHeaderParser::new(false);
+        assert_eq!(parser.parse(&nal(&[0x67], &sps)).unwrap(), None);
+        assert_eq!(parser.parse(&nal(&[0x68], &pps)).unwrap(), None);
+        parser
+    }
+
+    // Add the slice data after the slice header: the CABAC alignment
+    // bits and some bytes, then return the NAL unit and the header length.
+    fn slice(header: &[u8], mut w: BitWriter) -> (Vec<u8>, usize) {
+        while w.pos % 8 != 0 {
+            w.bit(true);
+        }
+        let header_len = header.len() + nal(&[], &w).len();
+        w.bits(32, 0x00000001).bits(16, 0xabcd);
+        (nal(header, &w), header_len)
+    }
+
+    #[test]
+    fn avc_idr_slice() {
+        let mut parser = avc_parser();
+        let mut w = BitWriter::default();
+        // first_mb_in_slice, I slice, pps_id, frame_num, idr_pic_id, pic_order_cnt_lsb.
+        w.ue(0).ue(7).ue(0).bits(4, 0).ue(0).bits(4, 0);
+        // dec_ref_pic_marking(), slice_qp_delta, disable_deblocking_filter_idc and offsets.
+        w.bits(2, 0).se(-2).ue(0).se(1).se(-1);
+        let (nal, len) = slice(&[0x65], w);
+        assert_eq!(parser.parse(&nal).unwrap(), Some(len));
+    }
+
+    #[test]
+    fn avc_p_slice() {
+        let mut parser = avc_parser();
+        let mut w = BitWriter::default();
+        // first_mb_in_slice, P slice, pps_id, frame_num, pic_order_cnt_lsb.
+        w.ue(0).ue(5).ue(0).bits(4, 1).bits(4, 2);
+        // num_ref_idx_active_override_flag with two references.
+        w.bit(true).ue(1);
+        // ref_pic_list_modification(): two entries.
+        w.bit(true).ue(0).ue(0).ue(1).ue(2).ue(3);
+        // dec_ref_pic_marking(): MMCO 1, then 0.
+        w.bit(true).ue(1).ue(0).ue(0);
+        // cabac_init_idc, slice_qp_delta, disable_deblocking_filter_idc.
+        w.ue(1).se(3).ue(1);
+        let (nal, len) = slice(&[0x41], w);
+        assert_eq!(parser.parse(&nal).unwrap(), Some(len));
+    }
+
+    #[test]
+    fn avc_unknown_pps() {
+        let mut parser = avc_parser();
+        let mut w = BitWriter::default();
+        w.ue(0).ue(7).ue(3).bits(4, 0).ue(0).bits(4, 0);
+        let (nal, _) = slice(&[0x65], w);
+        assert_eq!(parser.parse(&nal).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn avc_truncated_slice() {
+        let mut parser = avc_parser();
+        let mut w = BitWriter::default();
+        w.ue(0).ue(5).ue(0).bits(4, 1);
+        let nal = nal(&[0x41], &w);
+        assert_eq!(parser.parse(&nal).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn avc_other_nal_units() {
+        let mut parser = avc_parser();
+        // SEI, access unit delimiter, empty.
+        assert_eq!(parser.parse(&[0x06, 0x05, 0x01, 0x00, 0x80]).unwrap(), None);
+        assert_eq!(parser.parse(&[0x09, 0xf0]).unwrap(), None);
+        assert_eq!(parser.parse(&[]).unwrap(), None);
+    }
+
+    fn hevc_parser(pps_extra: impl Fn(&mut BitWriter)) -> SliceHeaderParser {
+        let mut sps = BitWriter::default();
+        // vps_id, max_sub_layers_minus1, temporal_id_nesting, profile_tier_level().
+        sps.bits(4, 0).bits(3, 0).bit(true);
+        sps.bits(8, 1).bits(32, 0x6000_0000).bits(48, 0).bits(8, 93);
+        // sps_id, 4:2:0, 64x64, no conformance window, 8 bits, log2_max_pic_order_cnt_lsb 8.
+        sps.ue(0).ue(1).ue(64).ue(64).bit(false).ue(0).ue(0).ue(4);
+        // sub_layer_ordering_info.
+        sps.bit(true).ue(4).ue(2).ue(0);
+        // 8x8 .. 16x16 coding blocks (16 CTBs), 4x4 .. 8x8 transform blocks.
+        sps.ue(0).ue(1).ue(0).ue(1).ue(0).ue(0);
+        // no scaling lists, no AMP, SAO, no PCM.
+        sps.bit(false).bit(false).bit(true).bit(false);
+        // Two short-term RPS: {-1} and, predicted from it with deltaRps -1, {-1, -2}.
+        sps.ue(2);
+        sps.ue(1).ue(0).ue(0).bit(true);
+        sps.bit(true).bit(true).ue(0).bit(true).bit(true);
+        // long-term references with one entry in the SPS, temporal MVP, no VUI, no extensions.
+        sps.bit(true).ue(1).bits(8, 0).bit(true);
+        sps.bit(true).bit(false).bit(false).bit(false).trailing();
+
+        let mut pps = BitWriter::default();
+        // pps_id, sps_id, dependent slices, no output flag, one extra slice header bit.
+        pps.ue(0).ue(0).bit(true).bit(false).bits(3, 1);
+        // sign data hiding, cabac_init_present, num_ref_idx defaults 1, init_qp, constrained intra.
+        pps.bit(false).bit(true).ue(0).ue(0).se(0).bit(false);
+        // transform skip, no cu_qp_delta, QP offsets, slice QP offsets, weighted pred, bipred.
+        pps.bit(false)
+            .bit(false)
+            .se(0)
+            .se(0)
+            .bit(true)
+            .bit(false)
+            .bit(false);
+        // transquant bypass, no tiles, WPP, loop filter across slices.
+        pps.bit(false).bit(false).bit(true).bit(true);
+        // deblocking control: override enabled, not disabled, offsets.
+        pps.bit(true).bit(true).bit(false).se(0).se(0);
+        // no scaling list, lists_modification_present, log2_parallel_merge_level, header extension.
+        pps.bit(false).bit(true).ue(0).bit(true);
+        pps_extra(&mut pps);
+        pps.trailing();
+
+        let mut parser = SliceHeaderParser::new(true);
+        assert_eq!(parser.parse(&nal(&[0x42, 0x01], &sps)).unwrap(), None);
+        assert_eq!(parser.parse(&nal(&[0x44, 0x01], &pps)).unwrap(), None);
+        parser
+    }
+
+    // Add the slice data after byte_alignment(), return the NAL unit and the header length.
+    fn hevc_slice(header: &[u8], mut w: BitWriter) -> (Vec<u8>, usize) {
+        w.bit(true);
+        while w.pos % 8 != 0 {
+            w.bit(false);
+        }
+        let header_len = header.len() + nal(&[], &w).len();
+        w.bits(24, 0x000003).bits(16, 0xabcd);
+        (nal(header, &w), header_len)
+    }
+
+    #[test]
+    fn hevc_idr_slice() {
+        let mut parser = hevc_parser(|pps| {
+            pps.bit(false);
+        });
+        let mut w = BitWriter::default();
+        // first_slice_segment_in_pic_flag, no_output_of_prior_pics_flag, pps_id, reserved bit, I slice.
+        w.bit(true).bit(false).ue(0).bit(false).ue(2);
+        // SAO luma and chroma, slice_qp_delta, cb/cr offsets, deblocking override with offsets.
+        w.bit(true)
+            .bit(false)
+            .se(4)
+            .se(0)
+            .se(-1)
+            .bit(true)
+            .bit(false)
+            .se(1)
+            .se(1);
+        // slice_loop_filter_across_slices_enabled_flag, two entry points, header extension.
+        w.bit(true)
+            .ue(2)
+            .ue(11)
+            .bits(12, 100)
+            .bits(12, 200)
+            .ue(2)
+            .bits(16, 0);
+        let (nal, len) = hevc_slice(&[0x26, 0x01], w);
+        assert_eq!(parser.parse(&nal).unwrap(), Some(len));
+    }
+
+    #[test]
+    fn hevc_p_slice() {
+        // PPS range extension with chroma_qp_offset_list_enabled_flag.
+        let mut parser = hevc_parser(|pps| {
+            pps.bit(true).bit(true).bits(3, 0).bits(4, 0).bit(false).bit(true);
+        });
+        let mut w = BitWriter::default();
+        // Second slice segment: not dependent, address 5 of 16 CTBs, reserved bit, P slice.
+        w.bit(false).ue(0).bit(false).bits(4, 5).bit(false).ue(1);
+        // pic_order_cnt_lsb, the second RPS of the SPS.
+        w.bits(8, 3).bit(true).bits(1, 1);
+        // Long-term: one from the SPS, one in the header with delta_poc_msb_cycle_lt.
+        w.ue(1).ue(1).bit(false).bits(8, 200).bit(true).bit(true).ue(2);
+        // slice_temporal_mvp_enabled_flag, SAO, override num_ref_idx to 3.
+        w.bit(true).bit(false).bit(false).bit(true).ue(2);
+        // ref_pic_lists_modification(): NumPicTotalCurr 4, three 2-bit entries.
+        w.bit(true).bits(6, 0b00_01_11);
+        // cabac_init_flag, collocated_ref_idx, five_minus_max_num_merge_cand.
+        w.bit(false).ue(1).ue(0);
+        // slice_qp_delta, cb/cr offsets, cu_chroma_qp_offset_enabled_flag, no deblocking override.
+        w.se(-3).se(0).se(0).bit(true).bit(false);
+        // slice_loop_filter_across_slices_enabled_flag, no entry points, no header extension.
+        w.bit(false).ue(0).ue(0);
+        let (nal, len) = hevc_slice(&[0x02, 0x01], w);
+        assert_eq!(parser.parse(&nal).unwrap(), Some(len));
+    }
+
+    #[test]
+    fn hevc_reserved_nal_unit_type() {
+        let mut parser = hevc_parser(|pps| {
+            pps.bit(false);
+        });
+        let nal = [0x16, 0x01, 0x80];
+        assert_eq!(parser.parse(&nal).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn escaped_length() {
+        assert_eq!(escaped_len(&[0, 0, 3, 1, 5], 3), 4);
+        assert_eq!(escaped_len(&[0, 0, 3, 1, 5], 2), 2);
+        assert_eq!(escaped_len(&[1, 2], 5), 2);
+    }
+}
@@ -1,6 +1,7 @@
 //! Subtitle handling.
 //!
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fs;
 use std::io::{self, BufRead, BufReader, Write};
 use std::str::FromStr;
@@ -10,16 +11,22 @@ use scan_fmt::scan_fmt;
 use super::fragment::FragmentSource;
 use crate::boxes::sbtl::Tx3GTextSample;
 use crate::boxes::*;
+use crate::io::MemBuffer;
 use crate::mp4box::MP4;
-use crate::serialize::FromBytes;
+use crate::serialize::{FromBytes, ToBytes};
 use crate::track::SampleInfo;
 
+mod ttml;
+
 /// Subtitle format.
+///
+/// `Ttml` (also for `.dfxp` files) can only be used as an input format.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Format {
     Vtt,
     Srt,
     Tx3g,
+    Ttml,
 }
 
 impl FromStr for Format {
@@ -34,6 +41,8 @@ impl FromStr for Format {
             "srt" => Ok(Format::Srt),
             "tx3g" => Ok(Format::Tx3g),
             "3gpp" => Ok(Format::Tx3g),
+            "ttml" => Ok(Format::Ttml),
+            "dfxp" => Ok(Format::Ttml),
             _ => Err(ioerr!(InvalidInput, "Could not parse format")),
         }
     }
@@ -53,19 +62,179 @@ fn ptime(secs: f64, format: Format) -> String {
     format!("{:02}:{:02}:{:02}{}{:03}", tm, mins, secs, sep, millis)
 }
 
+// A cue from a `tx3g`, `wvtt` or `stpp` sample.
+struct SampleCue {
+    id: Option<String>,
+    settings: Option<String>,
+    // In WebVTT cue text format.
+    text: String,
+    // Start and end time in seconds, if different from the sample.
+    time: Option<(f64, f64)>,
+}
+
+// Sample format of a subtitle track.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SampleFormat {
+    Tx3g,
+    Wvtt,
+    Ttml,
+}
+
+fn sample_format(track: &TrackBox) -> SampleFormat {
+    let stsd = track.media().media_info().sample_table().sample_description();
+    if first_box!(stsd.entries, WebVTTSampleEntry).is_some() {
+        SampleFormat::Wvtt
+    } else if first_box!(stsd.entries, XMLSubtitleSampleEntry).is_some() {
+        SampleFormat::Ttml
+    } else {
+        SampleFormat::Tx3g
+    }
+}
+
+// Is this a `wvtt` (WebVTT-in-MP4) track.
+pub(crate) fn is_wvtt(track: &TrackBox) -> bool {
+    sample_format(track) == SampleFormat::Wvtt
+}
+
+// Is this a `stpp` (TTML-in-MP4) track.
+pub(crate) fn is_ttml(track: &TrackBox) -> bool {
+    sample_format(track) == SampleFormat::Ttml
+}
+
+// Get the cues from a subtitle sample.
+fn sample_cues(data: &[u8], format: SampleFormat, sample: &SampleInfo, timescale: u32) -> Vec<SampleCue> {
+    match format {
+        SampleFormat::Tx3g => tx3g_cues(data),
+        SampleFormat::Wvtt => wvtt_cues(data),
+        SampleFormat::Ttml => ttml_cues(data, sample, timescale),
+    }
+}
+
+fn wvtt_cues(data: &[u8]) -> Vec<SampleCue> {
+    let boxes = match Vec::<MP4Box>::from_bytes(&mut &data[..]) {
+        Ok(boxes) => boxes,
+        Err(_) => return Vec::new(),
+    };
+    iter_box!(boxes[..], VTTCueBox)
+        .filter(|c| !c.payload().is_empty())
+        .map(|c| SampleCue {
+            id: c.id().map(|s| s.to_string()),
+            settings: c.settings().map(|s| s.to_string()),
+            text: c.payload().to_string(),
+            time: None,
+        })
+        .collect()
+}
+
+// A `stpp` sample is a complete TTML document, which can contain
+// multiple cues. Those can start before or end after the sample,
+// so they are clipped to the sample's time span.
+fn ttml_cues(data: &[u8], sample: &SampleInfo, timescale: u32) -> Vec<SampleCue> {
+    // The document may be followed by subsamples (images), skip those.
+    let len = data
+        .windows(3)
+        .rposition(|w| w == b"tt>")
+        .map(|pos| pos + 3)
+        .unwrap_or(data.len());
+    let doc = String::from_utf8_lossy(&data[..len]);
+    let tt = match ttml::parse(doc.trim_start_matches('\u{feff}')) {
+        Ok(tt) => tt,
+        Err(_) => return Vec::new(),
+    };
+
+    let start = sample.decode_time as f64 / (timescale as f64);
+    let end = start + sample.duration as f64 / (timescale as f64);
+
+    // Times should be on the track timeline, but some muxers use times
+    // relative to the start of the sample. If none of the cues overlap
+    // with the sample, assume the latter.
+    let relative = start > 0.0
+        && tt
+            .cues
+            .iter()
+            .all(|c| c.end.unwrap_or(c.begin) <= start + 0.001);
+    let offset = if relative { start } else { 0.0 };
+
+    tt.cues
+        .into_iter()
+        .filter_map(|c| {
+            let b = (c.begin + offset).max(start);
+            let e = c.end.map(|e| (e + offset).min(end)).unwrap_or(end);
+            (e > b).then_some(SampleCue {
+                id: None,
+                settings: c.settings,
+                text: c.text,
+                time: Some((b, e)),
+            })
+        })
+        .collect()
+}
+
+fn tx3g_cues(data: &[u8]) -> Vec<SampleCue> {
+    let subt = match Tx3GTextSample::from_bytes(&mut &data[..]) {
+        Ok(subt) => subt,
+        Err(_) => return Vec::new(),
+    };
+    let mut text = String::new();
+    for line in subt.text.split('\n') {
+        if line.is_empty() {
+            continue;
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        for c in line.chars() {
+            match c {
+                '&' => text.push_str("&amp;"),
+                '<' => text.push_str("&lt;"),
+                '>' => text.push_str("&gt;"),
+                c => text.push(c),
+            }
+        }
+    }
+    if text.is_empty() {
+        return Vec::new();
+    }
+    vec![SampleCue {
+        id: None,
+        settings: None,
+        text,
+        time: None,
+    }]
+}
+
+// Convert a `tx3g` sample to a `wvtt` sample.
+pub(crate) fn tx3g_to_wvtt(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut boxes = Vec::new();
+    for cue in tx3g_cues(data) {
+        boxes.push(VTTCueBox::new(None, None, &cue.text).to_mp4box());
+    }
+    if boxes.is_empty() {
+        boxes.push(VTTEmptyCueBox::default().to_mp4box());
+    }
+    let mut buffer = MemBuffer::new();
+    boxes.to_bytes(&mut buffer)?;
+    Ok(buffer.into_vec())
+}
+
 fn cue(
     format: Format,
     timescale: u32,
     seq: Option<u32>,
-    sample: SampleInfo,
-    subt: Tx3GTextSample,
+    sample: &SampleInfo,
+    subt: &SampleCue,
     tm_off: f64,
 ) -> String {
     use std::fmt::Write;
     let eol = if format == Format::Vtt { "\n" } else { "\r\n" };
 
-    let starttime = sample.decode_time as f64 / (timescale as f64);
-    let mut duration = sample.duration as f64 / (timescale as f64);
+    let (starttime, mut duration) = match subt.time {
+        Some((start, end)) => (start, end - start),
+        None => (
+            sample.decode_time as f64 / (timescale as f64),
+            sample.duration as f64 / (timescale as f64),
+        ),
+    };
     // If two cues are back-to-back, the endtime of the first cue can
     // be the same as the starttime of the second cue. That is valid
     // and the spec says that they do not overlap. However, it's
@@ -76,29 +245,37 @@ fn cue(
     let endtime = starttime + duration;
     let mut cue = String::new();
 
-    if let Some(seq) = seq {
-        let _ = write!(cue, "{}{}", seq, eol);
+    match (format, subt.id.as_ref(), seq) {
+        (Format::Vtt, Some(id), _) => {
+            let _ = write!(cue, "{}{}", id, eol);
+        },
+        (_, _, Some(seq)) => {
+            let _ = write!(cue, "{}{}", seq, eol);
+        },
+        _ => {},
     }
 
     let _ = write!(
         cue,
-        "{} --> {}{}",
+        "{} --> {}",
         ptime(starttime - tm_off, format),
         ptime(endtime - tm_off, format),
-        eol
     );
+    match subt.settings.as_ref() {
+        Some(settings) if format == Format::Vtt && !settings.is_empty() => {
+            let _ = write!(cue, " {}{}", settings, eol);
+        },
+        _ => cue.push_str(eol),
+    }
 
     for line in subt.text.split('\n') {
         if line == "" {
             continue;
         }
-        for c in line.chars() {
-            match c {
-                '&' => cue.push_str("&amp;"),
-                '<' => cue.push_str("&lt;"),
-                '>' => cue.push_str("&gt;"),
-                c => cue.push(c),
-            }
+        if format == Format::Vtt {
+            cue.push_str(line);
+        } else {
+            cue.push_str(&remove_unsupported_tags(line));
         }
         cue.push_str(eol);
     }
@@ -109,6 +286,9 @@ fn cue(
 
 /// Extract a subtitle track into VTT / SRT or 3GPP.
 ///
+/// The track can be a `tx3g`, `wvtt` or `stpp` (TTML) track. Only
+/// a `tx3g` track can be extracted in 3GPP format.
+///
 /// Note that if the `mp4` file resides on a classical spinning disk,
 /// this can be quite slow, since usually the subtitle track is
 /// interleaved with the video/audio tracks. Meaning that the disk
@@ -120,7 +300,9 @@ pub fn subtitle_extract(
     format: Format,
     mut output: impl Write,
 ) -> io::Result<()> {
-    let iter = track.sample_info_iter();
+    let sample_format = sample_format(track);
+    check_output_format(sample_format, format)?;
+    let iter = mp4.sample_info_iter(track);
     let timescale = iter.timescale();
     let mut seq = 1;
 
@@ -153,29 +335,38 @@ pub fn subtitle_extract(
         } else {
             mp4.data_ref.read_exact_at(data, sample.fpos)?;
         }
-        let subt = match Tx3GTextSample::from_bytes(&mut &data[..]) {
-            Ok(subt) => subt,
-            Err(_) => continue,
-        };
         if format == Format::Tx3g {
-            output.write(&buf[..sample.size as usize])?;
+            if Tx3GTextSample::from_bytes(&mut &data[..]).is_ok() {
+                output.write(&buf[..sample.size as usize])?;
+            }
             continue;
         }
-        if subt.text.as_str() == "" {
-            continue;
+        for subt in sample_cues(data, sample_format, &sample, timescale) {
+            let cue = cue(format, timescale, Some(seq), &sample, &subt, 0f64);
+            output.write(cue.as_bytes())?;
+            seq += 1;
         }
-        let cue = cue(format, timescale, Some(seq), sample, subt, 0f64);
-        output.write(cue.as_bytes())?;
-        seq += 1;
     }
 
     Ok(())
 }
 
+// Can we convert from this sample format to this output format.
+fn check_output_format(sample_format: SampleFormat, format: Format) -> io::Result<()> {
+    match (sample_format, format) {
+        (_, Format::Ttml) => Err(ioerr!(InvalidInput, "cannot convert to ttml")),
+        (SampleFormat::Wvtt, Format::Tx3g) => Err(ioerr!(InvalidInput, "cannot convert wvtt to tx3g")),
+        (SampleFormat::Ttml, Format::Tx3g) => Err(ioerr!(InvalidInput, "cannot convert ttml to tx3g")),
+        _ => Ok(()),
+    }
+}
+
 /// Create a fragment containing the cue(s).
 ///
 /// Outputs raw data. If this is to be sent in a CMAF container, it
 /// still needs to be wrapped by a moof + mdat.
+/// [`movie_fragment`](super::fragment::movie_fragment) does that, and
+/// translates `tx3g` to `wvtt` while doing so.
 pub fn fragment(mp4: &MP4, format: Format, frag: &FragmentSource, tm_off: f64) -> io::Result<Vec<u8>> {
     let mut buffer = Vec::new();
 
@@ -194,7 +385,9 @@ pub fn fragment(mp4: &MP4, format: Format, frag: &FragmentSource, tm_off: f64) -
         .movie()
         .track_by_id(frag.src_track_id)
         .ok_or_else(|| ioerr!(NotFound, "track not found"))?;
-    let mut iter = track.sample_info_iter();
+    let sample_format = sample_format(track);
+    check_output_format(sample_format, format)?;
+    let mut iter = mp4.sample_info_iter(track);
     let timescale = iter.timescale();
 
     let mut seq = frag.from_sample;
@@ -214,18 +407,15 @@ pub fn fragment(mp4: &MP4, format: Format, frag: &FragmentSource, tm_off: f64) -
             }
             let data = &mut buf[..sample.size as usize];
             mp4.data_ref.read_exact_at(data, sample.fpos)?;
-            match Tx3GTextSample::from_bytes(&mut &data[..]) {
-                Ok(subt) => {
-                    if format == Format::Tx3g {
-                        buffer.extend_from_slice(&buf[..sample.size as usize]);
-                    } else {
-                        if subt.text.len() > 0 {
-                            let cue = cue(format, timescale, None, sample, subt, tm_off);
-                            buffer.extend_from_slice(cue.as_bytes());
-                        }
-                    }
-                },
-                Err(_) => {},
+            if format == Format::Tx3g {
+                if Tx3GTextSample::from_bytes(&mut &data[..]).is_ok() {
+                    buffer.extend_from_slice(&buf[..sample.size as usize]);
+                }
+            } else {
+                for subt in sample_cues(data, sample_format, &sample, timescale) {
+                    let cue = cue(format, timescale, None, &sample, &subt, tm_off);
+                    buffer.extend_from_slice(cue.as_bytes());
+                }
             }
         }
         seq += 1;
@@ -239,8 +429,9 @@ pub fn fragment(mp4: &MP4, format: Format, frag: &FragmentSource, tm_off: f64) -
 
 /// Read an external subtitle file.
 ///
-/// Input and output formats can be webvtt and srt, format will be
-/// converted if needed. Output character set is always utf-8.
+/// Input formats can be webvtt, srt and ttml, output formats webvtt
+/// and srt. The format will be converted if needed. Output character
+/// set is always utf-8.
 ///
 /// Return value is `(mime_type, raw_data)`.
 pub fn external(path: &str, to_format: &str) -> io::Result<(&'static str, Vec<u8>)> {
@@ -280,11 +471,18 @@ pub fn external(path: &str, to_format: &str) -> io::Result<(&'static str, Vec<u8
         format_time(&mut buf, outfmt, sample.start);
         buf.push_str(" --> ");
         format_time(&mut buf, outfmt, sample.start + sample.duration);
+        match sample.settings.as_ref() {
+            Some(settings) if outfmt == Format::Vtt => {
+                buf.push(' ');
+                buf.push_str(settings);
+            },
+            _ => {},
+        }
         buf.push_str(eol);
 
         // text.
         if outfmt == Format::Srt {
-            for c in sample.text.chars() {
+            for c in remove_unsupported_tags(sample.text.as_str()).chars() {
                 if c == '\n' {
                     buf.push_str("\r\n");
                 } else {
@@ -303,7 +501,7 @@ pub fn external(path: &str, to_format: &str) -> io::Result<(&'static str, Vec<u8
     Ok((mime, buf.into_bytes()))
 }
 
-/// Open a subtitle track file (`vtt` or `srt`) and calculate its duration.
+/// Open a subtitle track file (`vtt`, `srt` or `ttml`) and calculate its duration.
 pub fn duration(fspath: &str) -> io::Result<f64> {
     let format = Format::from_str(fspath).map_err(|e| ioerr!(InvalidData, "{}: {}", fspath, e))?;
     let mut stf = SubtitleFile::open(fspath, format)?;
@@ -318,21 +516,46 @@ struct SubtitleSample {
     start: u32,
     duration: u32,
     text: String,
+    settings: Option<String>,
 }
 
 struct SubtitleFile {
     data: String,
     pos: usize,
+    // TTML files are parsed in one go.
+    cues: Option<VecDeque<SubtitleSample>>,
 }
 
 impl SubtitleFile {
     fn open(path: &str, format: Format) -> io::Result<SubtitleFile> {
         match format {
             Format::Vtt | Format::Srt => {},
+            Format::Ttml => {
+                let mut cues = read_ttml(path)?
+                    .cues
+                    .into_iter()
+                    .filter_map(|c| {
+                        let start = (c.begin * 1000.0).round() as u32;
+                        let end = (c.end? * 1000.0).round() as u32;
+                        (end > start).then(|| SubtitleSample {
+                            start,
+                            duration: end - start,
+                            text: c.text,
+                            settings: c.settings,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                cues.sort_by_key(|c| c.start);
+                return Ok(SubtitleFile {
+                    data: String::new(),
+                    pos: 0,
+                    cues: Some(cues.into()),
+                });
+            },
             other => return Err(ioerr!(InvalidData, "unsupported input format {:?}", other)),
         }
         let data = read_subtitle(path)?;
-        Ok(SubtitleFile{ data, pos: 0 })
+        Ok(SubtitleFile{ data, pos: 0, cues: None })
     }
 
     fn read_line(&mut self, line: &mut String) -> io::Result<usize> {
@@ -357,6 +580,9 @@ impl SubtitleFile {
     }
 
     fn next(&mut self) -> Option<SubtitleSample> {
+        if let Some(cues) = self.cues.as_mut() {
+            return cues.pop_front();
+        }
         let mut line = String::new();
 
         loop {
@@ -396,6 +622,7 @@ impl SubtitleFile {
                     start,
                     duration,
                     text,
+                    settings: None,
                 });
             }
         }
@@ -547,6 +774,29 @@ fn read_subtitle(filename: &str) -> io::Result<String> {
     Ok(subs)
 }
 
+// Read and parse a .ttml or .dfxp file.
+//
+// XML is UTF-8 unless declared otherwise, and in practice it always is.
+fn read_ttml(filename: &str) -> io::Result<ttml::Ttml> {
+    let data = fs::read(filename)?;
+    let data = String::from_utf8_lossy(&data);
+    ttml::parse(data.trim_start_matches('\u{feff}')).map_err(|e| ioerr!(InvalidData, "{}: {}", filename, e))
+}
+
+// Get the language from the xml:lang attribute of a .ttml or .dfxp file.
+fn ttml_lang(filename: &str) -> Option<&'static str> {
+    use isolang::Language;
+
+    let lang = read_ttml(filename).ok()?.lang?;
+    let lang = lang.split(['-', '_']).next()?.to_lowercase();
+    let lang = match lang.len() {
+        2 => Language::from_639_1(&lang)?,
+        3 => Language::from_639_3(&lang)?,
+        _ => return None,
+    };
+    lang.to_639_1().or_else(|| Some(lang.to_639_3()))
+}
+
 // See if a language string in the form "nl", "dut", "nl_NL" is a known
 // language, then map that to the shortest ISO-639 string (2 or 3 chars).
 fn valid_lang(lang: &str) -> Option<&'_ str> {
@@ -593,7 +843,7 @@ pub fn subtitle_info(filename: &str, mp4file: Option<&str>) -> io::Result<(Strin
     let mut lang = None;
     let mut used_prefix = false;
 
-    if let Some(prefix) = mp4file.and_then(|f| f.strip_suffix("mp4")) {
+    if let Some(prefix) = mp4file.and_then(|f| f.rfind('.').map(|idx| &f[..idx + 1])) {
         if let Some(suffix) = filename.strip_prefix(prefix) {
             let mut flags = suffix.split('.');
             // First flag is the language.
@@ -627,6 +877,7 @@ pub fn subtitle_info(filename: &str, mp4file: Option<&str>) -> io::Result<(Strin
     if let Some(l) = lang {
         lang = match valid_lang(l) {
             Some(l) => Some(l),
+            None if Format::from_str(filename).ok() == Some(Format::Ttml) => ttml_lang(filename),
             None => {
                 let (_, l, _) = decode_and_read(filename, true)?;
                 (l != "und").then(|| valid_lang(l)).flatten()
@@ -0,0 +1,583 @@
+//! TTML (IMSC1, EBU-TT-D, DFXP) to WebVTT.
+//!
+//! Only what is needed to build WebVTT cues is supported: timing,
+//! line breaks, italic / bold / underline, color, text alignment,
+//! and the vertical position of the region the text is in.
+//!
+//! Attributes and elements are matched on their local name, so that
+//! both the TTML and the older DFXP namespaces work.
+use std::collections::HashMap;
+use std::io;
+
+use roxmltree::{Document, Node, ParsingOptions};
+
+/// A cue from a TTML document. Times are in seconds.
+pub(super) struct TtmlCue {
+    pub begin: f64,
+    pub end: Option<f64>,
+    pub settings: Option<String>,
+    // In WebVTT cue text format.
+    pub text: String,
+}
+
+/// A parsed TTML document.
+pub(super) struct Ttml {
+    pub lang: Option<String>,
+    pub cues: Vec<TtmlCue>,
+}
+
+/// Parse a TTML document.
+pub(super) fn parse(data: &str) -> io::Result<Ttml> {
+    let opts = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let doc = Document::parse_with_options(data, opts).map_err(|e| ioerr!(InvalidData, "ttml: {}", e))?;
+    let tt = doc.root_element();
+    if tt.tag_name().name() != "tt" {
+        return Err(ioerr!(InvalidData, "ttml: root element is not <tt>"));
+    }
+    let ctx = Context::new(tt);
+
+    let mut cues = Vec::new();
+    if let Some(body) = child(tt, "body") {
+        ctx.walk(body, 0.0, None, &Style::default(), None, &mut cues);
+    }
+
+    Ok(Ttml {
+        lang: attr(tt, "lang").filter(|l| !l.is_empty()).map(|l| l.to_string()),
+        cues,
+    })
+}
+
+// Get an attribute by its local name.
+fn attr<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes().find(|a| a.name() == name).map(|a| a.value())
+}
+
+// Get the first child element with this local name.
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+// Inheritable styles that we can map to WebVTT.
+#[derive(Clone, Default)]
+struct Style {
+    italic: bool,
+    bold: bool,
+    underline: bool,
+    color: Option<&'static str>,
+    text_align: Option<&'static str>,
+}
+
+struct Context<'a, 'input> {
+    frame_rate: f64,
+    sub_frame_rate: f64,
+    tick_rate: f64,
+    // Root container extent in pixels.
+    extent: Option<(f64, f64)>,
+    cell_resolution: (f64, f64),
+    styles: HashMap<&'a str, Node<'a, 'input>>,
+    regions: HashMap<&'a str, Node<'a, 'input>>,
+}
+
+impl<'a, 'input> Context<'a, 'input> {
+    fn new(tt: Node<'a, 'input>) -> Context<'a, 'input> {
+        let pair = |name: &str, unit: &str| {
+            let v = attr(tt, name)?;
+            let mut v = v.split_whitespace().map(|x| x.trim_end_matches(unit).parse::<f64>().ok());
+            match (v.next()??, v.next()??) {
+                (x, y) if x > 0.0 && y > 0.0 => Some((x, y)),
+                _ => None,
+            }
+        };
+
+        let mut frame_rate = attr(tt, "frameRate").and_then(|f| f.parse::<f64>().ok()).unwrap_or(30.0);
+        if let Some((num, den)) = pair("frameRateMultiplier", "") {
+            frame_rate = frame_rate * num / den;
+        }
+        let sub_frame_rate = attr(tt, "subFrameRate").and_then(|f| f.parse::<f64>().ok()).unwrap_or(1.0);
+        let tick_rate = match attr(tt, "tickRate").and_then(|f| f.parse::<f64>().ok()) {
+            Some(rate) => rate,
+            None if attr(tt, "frameRate").is_some() => frame_rate * sub_frame_rate,
+            None => 1.0,
+        };
+
+        let mut styles = HashMap::new();
+        let mut regions = HashMap::new();
+        if let Some(head) = child(tt, "head") {
+            for node in head.descendants().filter(|n| n.is_element()) {
+                let id = match attr(node, "id") {
+                    Some(id) => id,
+                    None => continue,
+                };
+                match node.tag_name().name() {
+                    "style" => {
+                        styles.insert(id, node);
+                    },
+                    "region" => {
+                        regions.insert(id, node);
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        Context {
+            frame_rate: if frame_rate > 0.0 { frame_rate } else { 30.0 },
+            sub_frame_rate: if sub_frame_rate > 0.0 { sub_frame_rate } else { 1.0 },
+            tick_rate: if tick_rate > 0.0 { tick_rate } else { 1.0 },
+            extent: pair("extent", "px"),
+            cell_resolution: pair("cellResolution", "").unwrap_or((32.0, 15.0)),
+            styles,
+            regions,
+        }
+    }
+
+    // Parse a clock-time (hh:mm:ss.fraction or hh:mm:ss:frames.subframes)
+    // or an offset-time (12.3s, 100ms, 25f, 90000t, etc).
+    fn parse_time(&self, time: &str) -> Option<f64> {
+        let time = time.trim();
+        if time.contains(':') {
+            let mut fields = time.split(':');
+            let h = fields.next()?.parse::<f64>().ok()?;
+            let m = fields.next()?.parse::<f64>().ok()?;
+            let s = fields.next()?.parse::<f64>().ok()?;
+            let f = match fields.next() {
+                Some(frames) => {
+                    let mut frames = frames.split('.');
+                    let f = frames.next()?.parse::<f64>().ok()?;
+                    let sf = match frames.next() {
+                        Some(sf) => sf.parse::<f64>().ok()?,
+                        None => 0.0,
+                    };
+                    (f + sf / self.sub_frame_rate) / self.frame_rate
+                },
+                None => 0.0,
+            };
+            return Some(h * 3600.0 + m * 60.0 + s + f);
+        }
+
+        let idx = time.find(|c: char| c.is_ascii_alphabetic())?;
+        let (value, metric) = time.split_at(idx);
+        let value = value.parse::<f64>().ok()?;
+        match metric {
+            "h" => Some(value * 3600.0),
+            "m" => Some(value * 60.0),
+            "s" => Some(value),
+            "ms" => Some(value / 1000.0),
+            "f" => Some(value / self.frame_rate),
+            "t" => Some(value / self.tick_rate),
+            _ => None,
+        }
+    }
+
+    // Begin and end of an element, relative to the begin of the document.
+    fn timing(&self, node: Node, begin: f64, end: Option<f64>) -> (f64, Option<f64>) {
+        let time = |name| attr(node, name).and_then(|t| self.parse_time(t));
+        let b = begin + time("begin").unwrap_or(0.0);
+        let e = match (time("end"), time("dur")) {
+            (Some(e), _) => Some(begin + e),
+            (None, Some(d)) => Some(b + d),
+            (None, None) => None,
+        };
+        let e = match (e, end) {
+            (Some(e), Some(end)) => Some(e.min(end)),
+            (e, end) => e.or(end),
+        };
+        (b, e)
+    }
+
+    // Collect the style sources of an element in order of precedence: the
+    // referenced styles, nested <style> elements, and the element itself.
+    fn sources(&self, node: Node<'a, 'input>, out: &mut Vec<Node<'a, 'input>>, depth: u32) {
+        if depth > 8 {
+            return;
+        }
+        for id in attr(node, "style").unwrap_or("").split_whitespace() {
+            if let Some(style) = self.styles.get(id) {
+                self.sources(*style, out, depth + 1);
+            }
+        }
+        if node.tag_name().name() == "region" {
+            for style in node.children().filter(|n| n.is_element() && n.tag_name().name() == "style") {
+                self.sources(style, out, depth + 1);
+            }
+        }
+        out.push(node);
+    }
+
+    // Apply the styling of an element.
+    fn apply(&self, node: Node<'a, 'input>, style: &mut Style) {
+        let mut sources = Vec::new();
+        self.sources(node, &mut sources, 0);
+        for source in sources.into_iter() {
+            for a in source.attributes() {
+                let value = a.value().trim();
+                match a.name() {
+                    "fontStyle" => style.italic = value == "italic" || value == "oblique",
+                    "fontWeight" => style.bold = value == "bold",
+                    "textDecoration" => {
+                        if value.contains("noUnderline") || value == "none" {
+                            style.underline = false;
+                        } else if value.contains("underline") {
+                            style.underline = true;
+                        }
+                    },
+                    "color" => style.color = vtt_color(value),
+                    "textAlign" => {
+                        style.text_align = match value {
+                            "left" => Some("left"),
+                            "right" => Some("right"),
+                            "start" => Some("start"),
+                            "end" => Some("end"),
+                            _ => None,
+                        }
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    // Convert a length to a percentage of the root container.
+    fn length(&self, length: &str, axis: usize) -> Option<f64> {
+        let (size, cells) = match axis {
+            0 => (self.extent.map(|e| e.0), self.cell_resolution.0),
+            _ => (self.extent.map(|e| e.1), self.cell_resolution.1),
+        };
+        if let Some(v) = length.strip_suffix('%') {
+            v.parse::<f64>().ok()
+        } else if let Some(v) = length.strip_suffix("px") {
+            Some(v.parse::<f64>().ok()? * 100.0 / size?)
+        } else if let Some(v) = length.strip_suffix('c') {
+            Some(v.parse::<f64>().ok()? * 100.0 / cells)
+        } else {
+            None
+        }
+    }
+
+    // WebVTT `line` setting from the position of a region.
+    fn line_setting(&self, region: Node<'a, 'input>) -> Option<String> {
+        let mut origin = None;
+        let mut extent = None;
+        let mut align = "before";
+        let mut sources = Vec::new();
+        self.sources(region, &mut sources, 0);
+        for source in sources.into_iter() {
+            if let Some(v) = attr(source, "origin") {
+                origin = v.split_whitespace().nth(1).and_then(|y| self.length(y, 1));
+            }
+            if let Some(v) = attr(source, "extent") {
+                extent = v.split_whitespace().nth(1).and_then(|h| self.length(h, 1));
+            }
+            if let Some(v) = attr(source, "displayAlign") {
+                align = v.trim();
+            }
+        }
+        let top = origin?;
+        let height = extent.unwrap_or(0.0);
+        let (line, align) = match align {
+            "center" => (top + height / 2.0, ",center"),
+            "after" => (top + height, ",end"),
+            _ => (top, ""),
+        };
+        Some(format!("line:{}%{}", line.clamp(0.0, 100.0).round(), align))
+    }
+
+    // Walk the body, divs and paragraphs.
+    fn walk(
+        &self,
+        node: Node<'a, 'input>,
+        begin: f64,
+        end: Option<f64>,
+        parent: &Style,
+        region: Option<Node<'a, 'input>>,
+        cues: &mut Vec<TtmlCue>,
+    ) {
+        let (begin, end) = self.timing(node, begin, end);
+        let mut style = parent.clone();
+        let own_region = attr(node, "region").and_then(|r| self.regions.get(r)).copied();
+        if let Some(region) = own_region {
+            self.apply(region, &mut style);
+        }
+        let region = own_region.or(region);
+        self.apply(node, &mut style);
+
+        if node.tag_name().name() != "p" {
+            for child in node.children().filter(|n| n.is_element()) {
+                match child.tag_name().name() {
+                    "div" | "p" => self.walk(child, begin, end, &style, region, cues),
+                    _ => {},
+                }
+            }
+            return;
+        }
+
+        let mut text = Text::default();
+        self.text(node, &Style::default(), &style, &mut text);
+        if !text.has_text {
+            return;
+        }
+
+        let mut settings = Vec::new();
+        if let Some(line) = region.and_then(|r| self.line_setting(r)) {
+            settings.push(line);
+        }
+        if let Some(align) = style.text_align {
+            settings.push(format!("align:{}", align));
+        }
+        cues.push(TtmlCue {
+            begin,
+            end,
+            settings: if settings.is_empty() { None } else { Some(settings.join(" ")) },
+            text: text.text.trim_end_matches('\n').to_string(),
+        });
+    }
+
+    // Paragraph or span content.
+    fn text(&self, node: Node<'a, 'input>, parent: &Style, style: &Style, out: &mut Text) {
+        let mut close = Vec::new();
+        if style.italic && !parent.italic {
+            out.tag("<i>");
+            close.push("</i>");
+        }
+        if style.bold && !parent.bold {
+            out.tag("<b>");
+            close.push("</b>");
+        }
+        if style.underline && !parent.underline {
+            out.tag("<u>");
+            close.push("</u>");
+        }
+        match style.color {
+            Some("white") if parent.color.is_none() => {},
+            Some(color) if parent.color != Some(color) => {
+                out.tag(&format!("<c.{}>", color));
+                close.push("</c>");
+            },
+            _ => {},
+        }
+
+        for child in node.children() {
+            if child.is_text() {
+                out.push_text(child.text().unwrap_or(""));
+                continue;
+            }
+            match child.tag_name().name() {
+                "br" => out.newline(),
+                "span" => {
+                    let mut span_style = style.clone();
+                    self.apply(child, &mut span_style);
+                    self.text(child, style, &span_style, out);
+                },
+                _ => {},
+            }
+        }
+
+        for tag in close.iter().rev() {
+            out.tag(tag);
+        }
+    }
+}
+
+// Builds WebVTT cue text, collapsing whitespace the way TTML does.
+#[derive(Default)]
+struct Text {
+    text: String,
+    space: bool,
+    line_has_text: bool,
+    has_text: bool,
+}
+
+impl Text {
+    fn push_text(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.space = self.line_has_text;
+                continue;
+            }
+            if self.space {
+                self.text.push(' ');
+                self.space = false;
+            }
+            match c {
+                '&' => self.text.push_str("&amp;"),
+                '<' => self.text.push_str("&lt;"),
+                '>' => self.text.push_str("&gt;"),
+                c => self.text.push(c),
+            }
+            self.line_has_text = true;
+            self.has_text = true;
+        }
+    }
+
+    fn tag(&mut self, tag: &str) {
+        // Keep the space outside of the tag, unless the tag opens.
+        if self.space && !tag.starts_with("</") {
+            self.text.push(' ');
+            self.space = false;
+        }
+        self.text.push_str(tag);
+    }
+
+    fn newline(&mut self) {
+        self.text.push('\n');
+        self.space = false;
+        self.line_has_text = false;
+    }
+}
+
+// Map a TTML color to the closest WebVTT default color class.
+fn vtt_color(color: &str) -> Option<&'static str> {
+    const PALETTE: [(&str, [u8; 3]); 8] = [
+        ("white", [255, 255, 255]),
+        ("lime", [0, 255, 0]),
+        ("cyan", [0, 255, 255]),
+        ("red", [255, 0, 0]),
+        ("yellow", [255, 255, 0]),
+        ("magenta", [255, 0, 255]),
+        ("blue", [0, 0, 255]),
+        ("black", [0, 0, 0]),
+    ];
+    let (rgb, alpha) = parse_color(color)?;
+    if alpha == 0 {
+        return None;
+    }
+    let dist = |p: &[u8; 3]| -> u32 {
+        (0..3)
+            .map(|i| (rgb[i] as i32 - p[i] as i32).pow(2) as u32)
+            .sum()
+    };
+    PALETTE.iter().min_by_key(|(_, p)| dist(p)).map(|(name, _)| *name)
+}
+
+// Parse #rrggbb, #rrggbbaa, rgb(r,g,b), rgba(r,g,b,a) and named colors.
+fn parse_color(color: &str) -> Option<([u8; 3], u8)> {
+    let color = color.trim();
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() != 6 && hex.len() != 8 {
+            return None;
+        }
+        let v = u32::from_str_radix(hex, 16).ok()?;
+        let v = if hex.len() == 6 { (v << 8) | 0xff } else { v };
+        let b = v.to_be_bytes();
+        return Some(([b[0], b[1], b[2]], b[3]));
+    }
+    if let Some(args) = color.strip_prefix("rgba(").or_else(|| color.strip_prefix("rgb(")) {
+        let mut v = args
+            .trim_end_matches(')')
+            .split(',')
+            .map(|x| x.trim().parse::<u8>().ok());
+        let rgb = [v.next()??, v.next()??, v.next()??];
+        let alpha = v.next().unwrap_or(Some(255))?;
+        return Some((rgb, alpha));
+    }
+    let rgb = match color {
+        "transparent" => return Some(([0, 0, 0], 0)),
+        "black" => [0, 0, 0],
+        "silver" => [192, 192, 192],
+        "gray" => [128, 128, 128],
+        "white" => [255, 255, 255],
+        "maroon" => [128, 0, 0],
+        "red" => [255, 0, 0],
+        "purple" => [128, 0, 128],
+        "fuchsia" | "magenta" => [255, 0, 255],
+        "green" => [0, 128, 0],
+        "lime" => [0, 255, 0],
+        "olive" => [128, 128, 0],
+        "yellow" => [255, 255, 0],
+        "navy" => [0, 0, 128],
+        "blue" => [0, 0, 255],
+        "teal" => [0, 128, 128],
+        "aqua" | "cyan" => [0, 255, 255],
+        _ => return None,
+    };
+    Some((rgb, 255))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMSC1: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<tt xmlns="http://www.w3.org/ns/ttml" xmlns:tts="http://www.w3.org/ns/ttml#styling"
+    xmlns:ttp="http://www.w3.org/ns/ttml#parameter" xml:lang="nl"
+    ttp:frameRate="25" ttp:tickRate="10000000" tts:extent="1920px 1080px">
+  <head>
+    <styling>
+      <style xml:id="s1" tts:color="#ffff00" tts:textAlign="end"/>
+      <style xml:id="s2" style="s1" tts:fontStyle="italic"/>
+    </styling>
+    <layout>
+      <region xml:id="top" tts:origin="10% 5%" tts:extent="80% 15%"/>
+      <region xml:id="bottom" tts:origin="10% 540px" tts:extent="80% 540px" tts:displayAlign="after"/>
+    </layout>
+  </head>
+  <body region="bottom">
+    <div begin="00:00:10.000">
+      <p begin="00:00:01:12" end="00:00:03:00">First   line<br/>second <span tts:fontWeight="bold">bold</span> line</p>
+      <p begin="50000000t" dur="1.5s" style="s2" region="top">Yellow &amp; italic</p>
+      <p begin="100ms" end="200ms"> </p>
+    </div>
+    <div begin="20s" end="22s">
+      <p begin="1s" end="5s"><span tts:color="rgba(255,0,0,0)">a</span> <span tts:color="red">b</span></p>
+    </div>
+  </body>
+</tt>"##;
+
+    #[test]
+    fn imsc1() {
+        let tt = parse(IMSC1).unwrap();
+        assert_eq!(tt.lang.as_deref(), Some("nl"));
+
+        let cue = |idx: usize| {
+            let c = &tt.cues[idx];
+            (c.begin, c.end, c.settings.as_deref(), c.text.as_str())
+        };
+        assert_eq!(tt.cues.len(), 3);
+        let text = "First line\nsecond <b>bold</b> line";
+        assert_eq!(cue(0), (11.48, Some(13.0), Some("line:100%,end"), text));
+        let text = "<i><c.yellow>Yellow &amp; italic</c></i>";
+        assert_eq!(cue(1), (15.0, Some(16.5), Some("line:5% align:end"), text));
+        // The end time of the paragraph is clipped to the end of the div.
+        let text = "a <c.red>b</c>";
+        assert_eq!(cue(2), (21.0, Some(22.0), Some("line:100%,end"), text));
+    }
+
+    #[test]
+    fn dfxp() {
+        let dfxp = r#"<tt xmlns="http://www.w3.org/2006/10/ttaf1" xmlns:tts="http://www.w3.org/2006/10/ttaf1#style">
+  <body><div>
+    <p begin="2.5s" end="4s" tts:fontStyle="italic" tts:textDecoration="underline">x &lt; y</p>
+    <p begin="5s">open</p>
+  </div></body>
+</tt>"#;
+        let tt = parse(dfxp).unwrap();
+        assert_eq!(tt.lang, None);
+        assert_eq!(tt.cues.len(), 2);
+        assert_eq!((tt.cues[0].begin, tt.cues[0].end), (2.5, Some(4.0)));
+        assert_eq!(tt.cues[0].settings, None);
+        assert_eq!(tt.cues[0].text, "<i><u>x &lt; y</u></i>");
+        assert_eq!((tt.cues[1].begin, tt.cues[1].end), (5.0, None));
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#ff000080"), Some(([255, 0, 0], 0x80)));
+        assert_eq!(parse_color("rgb(1, 2, 3)"), Some(([1, 2, 3], 255)));
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(vtt_color("#f0f0f0"), Some("white"));
+        assert_eq!(vtt_color("teal"), Some("cyan"));
+        assert_eq!(vtt_color("transparent"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |doc| parse(doc).map(|_| ()).unwrap_err().kind();
+        assert_eq!(error("<tt><body>"), io::ErrorKind::InvalidData);
+        assert_eq!(error("<html/>"), io::ErrorKind::InvalidData);
+        assert_eq!(error(""), io::ErrorKind::InvalidData);
+        assert!(parse("<tt/>").unwrap().cues.is_empty());
+    }
+}
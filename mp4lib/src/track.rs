@@ -8,6 +8,8 @@ use std::time::Duration;
 use serde::{Serialize, Serializer};
 
 use crate::boxes::*;
+use crate::boxes::cenc::{original_sample_entry, protection_scheme_info};
+use crate::boxes::dvcc::dolby_vision_config;
 use crate::mp4box::{BoxInfo, MP4};
 use crate::types::*;
 
@@ -26,6 +28,8 @@ pub struct TrackInfo {
     #[serde(serialize_with = "display")]
     pub language: IsoLanguageCode,
     pub specific_info: SpecificTrackInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protection: Option<ProtectionInfo>,
 }
 
 /// Track-type specific info.
@@ -81,6 +85,8 @@ pub struct AudioTrackInfo {
     pub channel_configuration: Option<String>,
     pub avg_bitrate: Option<u32>,
     pub max_bitrate: Option<u32>,
+    pub joc_complexity: Option<u8>,
+    pub pre_skip: Option<u16>,
 }
 
 impl Display for AudioTrackInfo {
@@ -101,6 +107,38 @@ pub struct VideoTrackInfo {
     pub width: u16,
     pub height: u16,
     pub frame_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit_depth: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mastering_display: Option<MasteringDisplay>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_light_level: Option<ContentLightLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dolby_vision: Option<DolbyVisionInfo>,
+    /// Dolby Vision codec id for cross-compatible streams (e.g. "dvh1.08.06/db1p").
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supplemental_codec_id: Option<String>,
+}
+
+impl VideoTrackInfo {
+    /// Video range: "PQ", "HLG" or "SDR".
+    pub fn video_range(&self) -> &'static str {
+        if let Some(dv) = self.dolby_vision.as_ref() {
+            match (dv.profile, dv.bl_signal_compatibility_id) {
+                (5, _) | (_, 1) | (_, 6) => return "PQ",
+                (_, 2) => return "SDR",
+                (_, 4) => return "HLG",
+                _ => {},
+            }
+        }
+        match self.color.as_ref().map(|c| c.transfer_characteristics) {
+            Some(16) => "PQ",
+            Some(18) => "HLG",
+            _ => "SDR",
+        }
+    }
 }
 
 impl Display for VideoTrackInfo {
@@ -113,6 +151,64 @@ impl Display for VideoTrackInfo {
     }
 }
 
+/// Video color information, see ISO/IEC 23091-2 for the values.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ColorInfo {
+    pub colour_primaries: u16,
+    pub transfer_characteristics: u16,
+    pub matrix_coefficients: u16,
+    pub full_range: bool,
+}
+
+/// Mastering display metadata (SMPTE ST 2086).
+///
+/// Chromaticity coordinates are (x, y), luminance is in cd/m2.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MasteringDisplay {
+    pub red: (f64, f64),
+    pub green: (f64, f64),
+    pub blue: (f64, f64),
+    pub white_point: (f64, f64),
+    pub max_luminance: f64,
+    pub min_luminance: f64,
+}
+
+/// Dolby Vision profile and level.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct DolbyVisionInfo {
+    pub profile: u8,
+    pub level: u8,
+    pub bl_signal_compatibility_id: u8,
+}
+
+/// Content light level (CTA-861.3), in cd/m2.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ContentLightLevel {
+    pub max_cll: u16,
+    pub max_fall: u16,
+}
+
+/// Encryption details of a protected track.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ProtectionInfo {
+    /// Protection scheme, e.g. "cenc" or "cbcs".
+    pub scheme_type: String,
+    /// Original format of the sample entry, e.g. "avc1".
+    pub original_format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_kid: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pssh: Vec<PsshInfo>,
+}
+
+/// DRM system that has a `pssh` box in the movie.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PsshInfo {
+    pub system_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_name: Option<String>,
+}
+
 /// Subtitle track details.
 #[derive(Debug, Default, Serialize)]
 pub struct SubtitleTrackInfo {
@@ -143,6 +239,23 @@ impl Display for UnknownTrackInfo {
     }
 }
 
+/// Encryption info for a sample entry.
+fn protection_info(movie: &MovieBox, entry: &MP4Box) -> Option<ProtectionInfo> {
+    let sinf = protection_scheme_info(entry)?;
+    let pssh = iter_box!(movie, ProtectionSystemSpecificHeaderBox)
+        .map(|p| PsshInfo {
+            system_id: p.system_id.to_string(),
+            system_name: p.system_name().map(|n| n.to_string()),
+        })
+        .collect();
+    Some(ProtectionInfo {
+        scheme_type: sinf.scheme_type().map(|s| s.to_string()).unwrap_or_default(),
+        original_format: sinf.original_format().map(|f| f.to_string()).unwrap_or_default(),
+        default_kid: sinf.track_encryption().map(|t| t.default_kid.to_string()),
+        pssh,
+    })
+}
+
 /// Extract general track information for all tracks in the movie.
 pub fn track_info(mp4: &MP4) -> Vec<TrackInfo> {
     track_info2(mp4, false)
@@ -171,7 +284,6 @@ pub(crate) fn track_info2(mp4: &MP4, for_hls: bool) -> Vec<TrackInfo> {
         let mdia = track.media();
 
         let mdhd = mdia.media_header();
-        info.duration = Duration::from_millis((1000 * mdhd.duration.0) / (mdhd.timescale as u64));
         info.language = mdhd.language;
 
         let hdlr = mdia.handler();
@@ -179,57 +291,92 @@ pub(crate) fn track_info2(mp4: &MP4, for_hls: bool) -> Vec<TrackInfo> {
 
         let stbl = mdia.media_info().sample_table();
         info.size = stbl.sample_size().iter().fold(0, |acc: u64, sz| acc + sz as u64);
+        let mut sample_count = stbl.sample_size().count;
+        let mut media_duration = mdhd.duration.0;
+
+        // In a fragmented file, most or all samples are in the MovieFragmentBoxes.
+        if first_box!(mp4, MovieFragmentBox).is_some() {
+            info.size = 0;
+            sample_count = 0;
+            for sample in mp4.sample_info_iter(track) {
+                info.size += sample.size as u64;
+                sample_count += 1;
+                media_duration = std::cmp::max(media_duration, sample.decode_time + sample.duration as u64);
+            }
+        }
+        info.duration = Duration::from_millis((1000 * media_duration) / (mdhd.timescale as u64));
 
         info.name = first_box!(track, UserDataBox)
             .and_then(|b| first_box!(b, NameBox))
             .map(|n| n.name.clone());
 
-        let stsd = stbl.sample_description();
-        if let Some(avc1) = first_box!(stsd.entries, AvcSampleEntry) {
-            let mut avc1_info = avc1.track_info();
-            if avc1_info.frame_rate == 0f64 {
-                let sample_count = stbl.sample_size().count;
-                let timescale = std::cmp::max(1000, mdhd.timescale) as f64;
-                let fr = sample_count as f64 / (mdhd.duration.0 as f64 / timescale);
-                log::debug!("track::track_info: avcc.framerate == 0, estimate: {}", fr);
-                avc1_info.frame_rate = fr;
+        // Encrypted tracks: look through encv / enca to the original format.
+        let mut stsd = stbl.sample_description().clone();
+        info.protection = stsd.entries.iter().next().and_then(|e| protection_info(movie, e));
+        for entry in stsd.entries.vec.iter_mut() {
+            if let Some(original) = original_sample_entry(entry) {
+                *entry = original;
             }
-            avc1_info.frame_rate = (avc1_info.frame_rate * 1000.0).round() / 1000.0;
+        }
+
+        if let Some(avc1) = first_box!(stsd.entries, AvcSampleEntry) {
+            let avc1_info = video_frame_rate(avc1.track_info(), sample_count, media_duration, mdhd.timescale);
+            let avc1_info = video_color_info(avc1_info, &avc1.boxes);
             info.specific_info = SpecificTrackInfo::VideoTrackInfo(avc1_info);
         } else if let Some(hevc) = first_box!(stsd.entries, HEVCSampleEntry) {
-            let mut hevc_info = hevc.track_info();
-            if hevc_info.frame_rate == 0f64 {
-                let sample_count = stbl.sample_size().count;
-                let timescale = std::cmp::max(1000, mdhd.timescale) as f64;
-                let fr = sample_count as f64 / (mdhd.duration.0 as f64 / timescale);
-                log::debug!("track::track_info: hvcc.framerate == 0, estimate: {}", fr);
-                hevc_info.frame_rate = fr;
-            }
-            hevc_info.frame_rate = (hevc_info.frame_rate * 1000.0).round() / 1000.0;
+            let hevc_info = video_frame_rate(hevc.track_info(), sample_count, media_duration, mdhd.timescale);
+            let hevc_info = video_color_info(hevc_info, &hevc.boxes);
             info.specific_info = SpecificTrackInfo::VideoTrackInfo(hevc_info);
         } else if let Some(hevc) = first_box!(stsd.entries, HEV1SampleEntry) {
-            let mut hevc_info = hevc.track_info();
-            if hevc_info.frame_rate == 0f64 {
-                let sample_count = stbl.sample_size().count;
-                let timescale = std::cmp::max(1000, mdhd.timescale) as f64;
-                let fr = sample_count as f64 / (mdhd.duration.0 as f64 / timescale);
-                log::debug!("track::track_info: hvcc.framerate == 0, estimate: {}", fr);
-                hevc_info.frame_rate = fr;
-            }
-            hevc_info.frame_rate = (hevc_info.frame_rate * 1000.0).round() / 1000.0;
+            let hevc_info = video_frame_rate(hevc.track_info(), sample_count, media_duration, mdhd.timescale);
+            let hevc_info = video_color_info(hevc_info, &hevc.boxes);
             info.specific_info = SpecificTrackInfo::VideoTrackInfo(hevc_info);
+        } else if let Some(dvh1) = first_box!(stsd.entries, DVH1SampleEntry) {
+            let dv_info = video_frame_rate(dvh1.track_info(), sample_count, media_duration, mdhd.timescale);
+            let dv_info = video_color_info(dv_info, &dvh1.boxes);
+            info.specific_info = SpecificTrackInfo::VideoTrackInfo(dv_info);
+        } else if let Some(dvhe) = first_box!(stsd.entries, DVHESampleEntry) {
+            let dv_info = video_frame_rate(dvhe.track_info(), sample_count, media_duration, mdhd.timescale);
+            let dv_info = video_color_info(dv_info, &dvhe.boxes);
+            info.specific_info = SpecificTrackInfo::VideoTrackInfo(dv_info);
+        } else if let Some(av1) = first_box!(stsd.entries, Av1SampleEntry) {
+            let av1_info = video_frame_rate(av1.track_info(), sample_count, media_duration, mdhd.timescale);
+            let av1_info = video_color_info(av1_info, &av1.boxes);
+            info.specific_info = SpecificTrackInfo::VideoTrackInfo(av1_info);
+        } else if let Some(vp9) = first_box!(stsd.entries, Vp9SampleEntry) {
+            let vp9_info = video_frame_rate(vp9.track_info(), sample_count, media_duration, mdhd.timescale);
+            let vp9_info = video_color_info(vp9_info, &vp9.boxes);
+            info.specific_info = SpecificTrackInfo::VideoTrackInfo(vp9_info);
         } else if let Some(ac3) = first_box!(stsd.entries, Ac3SampleEntry) {
             let mut ac3 = ac3.track_info();
             if ac3.avg_bitrate.is_none() && info.duration.as_secs() > 0 {
                 ac3.avg_bitrate = Some((8 * info.size / info.duration.as_secs()) as u32);
             }
             info.specific_info = SpecificTrackInfo::AudioTrackInfo(ac3);
+        } else if let Some(ec3) = first_box!(stsd.entries, Ec3SampleEntry) {
+            let mut ec3 = ec3.track_info();
+            if ec3.avg_bitrate.is_none() && info.duration.as_secs() > 0 {
+                ec3.avg_bitrate = Some((8 * info.size / info.duration.as_secs()) as u32);
+            }
+            info.specific_info = SpecificTrackInfo::AudioTrackInfo(ec3);
         } else if let Some(aac) = first_box!(stsd.entries, AacSampleEntry) {
             let mut aac = aac.track_info();
             if aac.avg_bitrate.is_none() && info.duration.as_secs() > 0 {
                 aac.avg_bitrate = Some((8 * info.size / info.duration.as_secs()) as u32);
             }
             info.specific_info = SpecificTrackInfo::AudioTrackInfo(aac);
+        } else if let Some(opus) = first_box!(stsd.entries, OpusSampleEntry) {
+            let mut opus = opus.track_info();
+            if info.duration.as_secs() > 0 {
+                opus.avg_bitrate = Some((8 * info.size / info.duration.as_secs()) as u32);
+            }
+            info.specific_info = SpecificTrackInfo::AudioTrackInfo(opus);
+        } else if let Some(flac) = first_box!(stsd.entries, FlacSampleEntry) {
+            let mut flac = flac.track_info();
+            if info.duration.as_secs() > 0 {
+                flac.avg_bitrate = Some((8 * info.size / info.duration.as_secs()) as u32);
+            }
+            info.specific_info = SpecificTrackInfo::AudioTrackInfo(flac);
         } else {
             let id = stsd
                 .entries
@@ -242,6 +389,10 @@ pub(crate) fn track_info2(mp4: &MP4, for_hls: bool) -> Vec<TrackInfo> {
                     codec_id: id.to_string(),
                     codec_name: Some(String::from("3GPP Timed Text")),
                 }),
+                "wvtt" => SpecificTrackInfo::SubtitleTrackInfo(SubtitleTrackInfo {
+                    codec_id: id.to_string(),
+                    codec_name: Some(String::from("WebVTT")),
+                }),
                 "stpp" | "sbtt" => SpecificTrackInfo::SubtitleTrackInfo(SubtitleTrackInfo {
                     codec_id: id.to_string(),
                     codec_name: None,
@@ -260,6 +411,55 @@ pub(crate) fn track_info2(mp4: &MP4, for_hls: bool) -> Vec<TrackInfo> {
     v
 }
 
+// If the codec configuration did not tell us the frame rate,
+// estimate it from the number of samples and the duration.
+fn video_frame_rate(
+    mut info: VideoTrackInfo,
+    sample_count: u32,
+    duration: u64,
+    timescale: u32,
+) -> VideoTrackInfo {
+    if info.frame_rate == 0f64 {
+        let timescale = std::cmp::max(1000, timescale) as f64;
+        let fr = sample_count as f64 / (duration as f64 / timescale);
+        log::debug!("track::track_info: {}: framerate == 0, estimate: {}", info.codec_id, fr);
+        info.frame_rate = fr;
+    }
+    info.frame_rate = (info.frame_rate * 1000.0).round() / 1000.0;
+    info
+}
+
+// Add color and HDR info from the colr, mdcv, clli, and dvcC / dvvC boxes
+// in the sample entry. A colr box overrides what the codec configuration said.
+fn video_color_info(mut info: VideoTrackInfo, boxes: &[MP4Box]) -> VideoTrackInfo {
+    if let Some(color) = first_box!(boxes[..], ColourInformationBox).and_then(|c| c.color_info()) {
+        info.color = Some(color);
+    }
+    if let Some(mdcv) = first_box!(boxes[..], MasteringDisplayColourVolumeBox) {
+        info.mastering_display = Some(mdcv.mastering_display());
+    }
+    if let Some(clli) = first_box!(boxes[..], ContentLightLevelBox) {
+        info.content_light_level = Some(clli.content_light_level());
+    }
+    if let Some(dv) = dolby_vision_config(boxes) {
+        info.dolby_vision = Some(dv.info());
+        // A Dolby Vision configuration in a non-DV sample entry means
+        // that the base layer is playable by non-DV decoders.
+        let fourcc = match info.codec_id.split('.').next() {
+            Some("hvc1") => Some("dvh1"),
+            Some("hev1") => Some("dvhe"),
+            Some("avc1") => Some("dva1"),
+            Some("avc3") => Some("dvav"),
+            Some("av01") => Some("dav1"),
+            _ => None,
+        };
+        if let Some(fourcc) = fourcc {
+            info.supplemental_codec_id = Some(dv.supplemental_codec_id(fourcc));
+        }
+    }
+    info
+}
+
 // Serialize helper.
 fn display<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
 where
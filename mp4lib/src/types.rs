@@ -361,6 +361,18 @@ impl Default for IsoLanguageCode {
     }
 }
 
+// Anything that is not a 3-letter lowercase code becomes "und".
+impl From<&str> for IsoLanguageCode {
+    fn from(s: &str) -> IsoLanguageCode {
+        let b = s.as_bytes();
+        if b.len() != 3 || !b.iter().all(|c| c.is_ascii_lowercase()) {
+            return IsoLanguageCode::default();
+        }
+        let c = |i: usize| (b[i] - 0x60) as u16;
+        IsoLanguageCode((c(0) << 10) | (c(1) << 5) | c(2))
+    }
+}
+
 /// Zero terminated ASCII string.
 #[derive(Clone, Default, Serialize)]
 pub struct ZString(String);
@@ -426,6 +438,18 @@ impl Debug for ZString {
 #[derive(Clone, Default)]
 pub struct Matrix([(FixedFloat16_16, FixedFloat16_16, FixedFloat2_30); 3]);
 
+impl Matrix {
+    /// The unity matrix.
+    pub fn identity() -> Matrix {
+        let zero = (FixedFloat16_16(0), FixedFloat16_16(0), FixedFloat2_30(0));
+        let mut m = [zero; 3];
+        m[0].0 = FixedFloat16_16::from(1.0);
+        m[1].1 = FixedFloat16_16::from(1.0);
+        m[2].2 = FixedFloat2_30::from(1.0);
+        Matrix(m)
+    }
+}
+
 impl FromBytes for Matrix {
     fn from_bytes<R: ReadBytes>(bytes: &mut R) -> io::Result<Self> {
         let mut m = [(FixedFloat16_16(0), FixedFloat16_16(0), FixedFloat2_30(0)); 3];
@@ -1007,6 +1031,12 @@ impl std::ops::Deref for PString {
     }
 }
 
+impl From<&str> for PString {
+    fn from(s: &str) -> Self {
+        PString(s.to_string())
+    }
+}
+
 impl FromBytes for PString {
     fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<PString> {
         let len = u8::from_bytes(stream)? as u64;
@@ -1028,7 +1058,10 @@ impl FromBytes for PString {
 
 impl ToBytes for PString {
     fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
-        let len = std::cmp::min(self.0.len(), 254);
+        let mut len = std::cmp::min(self.0.len(), 254);
+        while !self.0.is_char_boundary(len) {
+            len -= 1;
+        }
         (len as u8).to_bytes(stream)?;
         stream.write(self.0[..len].as_bytes())
     }
@@ -1081,6 +1114,61 @@ impl ToBytes for P16String {
     }
 }
 
+/// String that fills the rest of the box, not zero terminated.
+///
+/// Used by the `WebVTT` boxes (ISO/IEC 14496-30).
+#[derive(Clone, Default)]
+pub struct BoxString(String);
+
+impl BoxString {
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl std::ops::Deref for BoxString {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_str()
+    }
+}
+
+impl From<&str> for BoxString {
+    fn from(s: &str) -> Self {
+        BoxString(s.to_string())
+    }
+}
+
+impl FromBytes for BoxString {
+    fn from_bytes<R: ReadBytes>(stream: &mut R) -> io::Result<BoxString> {
+        let left = stream.left();
+        let data = if left > 0 { stream.read(left)? } else { b"" };
+        Ok(BoxString(String::from_utf8_lossy(data).into_owned()))
+    }
+    fn min_size() -> usize {
+        0
+    }
+}
+
+impl ToBytes for BoxString {
+    fn to_bytes<W: WriteBytes>(&self, stream: &mut W) -> io::Result<()> {
+        stream.write(self.0.as_bytes())
+    }
+}
+
+impl Display for BoxString {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Debug for BoxString {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
 def_struct! {
     /// OpColor
     OpColor,
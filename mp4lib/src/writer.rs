@@ -0,0 +1,428 @@
+//! Write an MP4 file sample by sample.
+//!
+//! [`Mp4Writer`] builds a standard (progressive, not fragmented) MP4 file
+//! from samples that are passed in one at a time, for example by a
+//! demuxer or an encoder. The sample tables are built while the samples
+//! are written, and at the end the MovieBox is put in front of the media
+//! data.
+//!
+//! The MovieBox can only be built when all samples are known. There are
+//! two ways to still get it at the front of the file:
+//!
+//! - reserve space for it with [`Mp4Writer::set_reserved_space`]. If the
+//!   MovieBox fits, it is written in that space, and what is left over
+//!   becomes a `free` box.
+//! - if no space was reserved, or not enough, the media data is moved
+//!   forward in the file to make room. That means reading and writing
+//!   all the data a second time.
+//!
+//! Samples of each track are collected in chunks of (by default) 500ms,
+//! and a chunk is written to the file when it is full. For the chunks of
+//! the tracks to be interleaved in the file, write the samples in (about)
+//! decode order over all tracks.
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io;
+//! use mp4lib::boxes::MP4Box;
+//! use mp4lib::writer::{Mp4Writer, TrackConfig, TrackKind};
+//!
+//! // Write video frames: (data, decode time, composition offset, is keyframe).
+//! fn write(sample_entry: MP4Box, frames: &[(Vec<u8>, i64, i32, bool)]) -> io::Result<()> {
+//!     let mut writer = Mp4Writer::new(File::create("out.mp4")?);
+//!     writer.set_reserved_space(64 * 1024);
+//!     let track_id = writer.add_track(TrackConfig::new(TrackKind::Video, 90000, sample_entry))?;
+//!     for (data, dts, cts_offset, is_sync) in frames {
+//!         writer.write_sample(track_id, data, *dts, *cts_offset, *is_sync)?;
+//!     }
+//!     writer.finish()?;
+//!     Ok(())
+//! }
+//! ```
+//!
+use std::cmp;
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::boxes::*;
+use crate::demux::{file_type_box, movie_box, track_box, SampleTable, TrackParams};
+use crate::io::{CountBytes, MemBuffer};
+use crate::serialize::{BoxBytes, ToBytes};
+
+pub use crate::demux::TrackKind;
+
+/// Configuration of a track in a [`Mp4Writer`].
+#[derive(Clone, Debug)]
+pub struct TrackConfig {
+    /// Video, audio or subtitles.
+    pub kind: TrackKind,
+    /// Number of time units per second of the sample timestamps.
+    pub timescale: u32,
+    /// Sample entry (`avc1`, `mp4a`, ...) that describes the samples.
+    pub sample_entry: MP4Box,
+    /// ISO 639-2 language code.
+    pub language: String,
+    /// Name of the track.
+    pub name: Option<String>,
+    /// Is the track enabled.
+    pub enabled: bool,
+    /// Display width (video only).
+    pub width: u32,
+    /// Display height (video only).
+    pub height: u32,
+    /// Duration of a sample that has no sample after it and none before
+    /// it, like the only sample of a track. The default is 1/25 second.
+    pub default_duration: u32,
+}
+
+impl TrackConfig {
+    /// New enabled track, with language `und`.
+    ///
+    /// For video tracks the display size is taken from the sample entry.
+    pub fn new(kind: TrackKind, timescale: u32, sample_entry: MP4Box) -> TrackConfig {
+        let (width, height) = match &sample_entry {
+            MP4Box::AvcSampleEntry(e) => (e.width as u32, e.height as u32),
+            MP4Box::HEVCSampleEntry(e) => (e.width as u32, e.height as u32),
+            MP4Box::HEV1SampleEntry(e) => (e.width as u32, e.height as u32),
+            _ => (0, 0),
+        };
+        TrackConfig {
+            kind,
+            timescale,
+            sample_entry,
+            language: "und".to_string(),
+            name: None,
+            enabled: true,
+            width,
+            height,
+            default_duration: cmp::max(1, timescale / 25),
+        }
+    }
+}
+
+// A sample of which the duration is not known yet.
+struct Pending {
+    size: u32,
+    dts: i64,
+    cts_offset: i32,
+    is_sync: bool,
+}
+
+struct TrackWriter {
+    config: TrackConfig,
+    table: SampleTable,
+    // The chunk that is being collected.
+    chunk: Vec<u8>,
+    chunk_samples: u32,
+    chunk_dts: i64,
+    // The last sample. Its duration is known when the next sample arrives.
+    pending: Option<Pending>,
+    last_duration: u32,
+    first_dts: Option<i64>,
+    first_pts: i64,
+}
+
+/// Writes an MP4 file sample by sample.
+///
+/// See the [module documentation](crate::writer).
+pub struct Mp4Writer<W> {
+    file: W,
+    tracks: Vec<TrackWriter>,
+    interleave: f64,
+    reserved: u64,
+    // Position of the MediaDataBox, 0 if it was not started yet.
+    mdat_pos: u64,
+    pos: u64,
+}
+
+impl<W: Read + Write + Seek> Mp4Writer<W> {
+    /// Create a new writer. Nothing is written to `file` until the first sample.
+    pub fn new(file: W) -> Mp4Writer<W> {
+        Mp4Writer {
+            file,
+            tracks: Vec::new(),
+            interleave: 0.5,
+            reserved: 0,
+            mdat_pos: 0,
+            pos: 0,
+        }
+    }
+
+    /// Set the duration of a chunk, in seconds. The default is 0.5.
+    ///
+    /// With a duration of 0, every sample is a chunk by itself.
+    pub fn set_interleave(&mut self, duration: f64) {
+        self.interleave = duration;
+    }
+
+    /// Reserve space for the MovieBox in front of the media data.
+    ///
+    /// Must be called before the first sample is written.
+    pub fn set_reserved_space(&mut self, size: u64) {
+        if self.mdat_pos > 0 {
+            log::warn!("Mp4Writer::set_reserved_space: already started writing");
+            return;
+        }
+        self.reserved = if size > 0 { cmp::max(size, 8) } else { 0 };
+    }
+
+    /// Add a track. Returns the track id.
+    pub fn add_track(&mut self, config: TrackConfig) -> io::Result<u32> {
+        if config.timescale == 0 {
+            return Err(ioerr!(InvalidInput, "Mp4Writer: timescale is 0"));
+        }
+        self.tracks.push(TrackWriter {
+            config,
+            table: SampleTable::default(),
+            chunk: Vec::new(),
+            chunk_samples: 0,
+            chunk_dts: 0,
+            pending: None,
+            last_duration: 0,
+            first_dts: None,
+            first_pts: i64::MAX,
+        });
+        Ok(self.tracks.len() as u32)
+    }
+
+    /// Write a sample.
+    ///
+    /// `dts` is the decode time, and `cts_offset` the composition time minus
+    /// the decode time, both in the timescale of the track. The decode time
+    /// must increase with every sample of a track. Composition time 0 is the
+    /// start of the movie, so with B-frames the first decode time is usually
+    /// negative.
+    ///
+    /// The duration of a sample is the difference with the decode time of
+    /// the next sample. The last sample of a track gets the same duration
+    /// as the sample before it, or [`TrackConfig::default_duration`] if
+    /// there is none.
+    pub fn write_sample(
+        &mut self,
+        track_id: u32,
+        data: &[u8],
+        dts: i64,
+        cts_offset: i32,
+        is_sync: bool,
+    ) -> io::Result<()> {
+        if track_id == 0 || track_id as usize > self.tracks.len() {
+            return Err(ioerr!(
+                InvalidInput,
+                "Mp4Writer: track {}: no such track",
+                track_id
+            ));
+        }
+        if self.mdat_pos == 0 {
+            self.start()?;
+        }
+        let idx = track_id as usize - 1;
+        let track = &mut self.tracks[idx];
+
+        if let Some(pending) = track.pending.as_ref() {
+            if dts < pending.dts {
+                return Err(ioerr!(
+                    InvalidInput,
+                    "Mp4Writer: track {}: decode time goes backwards",
+                    track_id
+                ));
+            }
+            if dts - pending.dts > u32::MAX as i64 {
+                return Err(ioerr!(
+                    InvalidInput,
+                    "Mp4Writer: track {}: sample duration too large",
+                    track_id
+                ));
+            }
+        }
+        if let Some(pending) = track.pending.take() {
+            track.last_duration = (dts - pending.dts) as u32;
+            track.table.add_sample(
+                pending.size,
+                track.last_duration,
+                pending.cts_offset,
+                pending.is_sync,
+            );
+        }
+
+        let interleave = (self.interleave * track.config.timescale as f64) as i64;
+        if track.chunk_samples > 0 && dts - track.chunk_dts >= interleave {
+            self.write_chunk(idx)?;
+        }
+
+        let track = &mut self.tracks[idx];
+        if track.chunk_samples == 0 {
+            track.chunk_dts = dts;
+        }
+        track.chunk.extend_from_slice(data);
+        track.chunk_samples += 1;
+        track.first_dts.get_or_insert(dts);
+        track.first_pts = cmp::min(track.first_pts, dts + cts_offset as i64);
+        track.pending = Some(Pending {
+            size: data.len() as u32,
+            dts,
+            cts_offset,
+            is_sync,
+        });
+        Ok(())
+    }
+
+    /// Write the last chunks and the MovieBox, and return the file.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.mdat_pos == 0 {
+            self.start()?;
+        }
+        for idx in 0..self.tracks.len() {
+            let track = &mut self.tracks[idx];
+            if let Some(pending) = track.pending.take() {
+                let duration = match track.last_duration {
+                    0 => cmp::max(1, track.config.default_duration),
+                    d => d,
+                };
+                track
+                    .table
+                    .add_sample(pending.size, duration, pending.cts_offset, pending.is_sync);
+            }
+            if track.chunk_samples > 0 {
+                self.write_chunk(idx)?;
+            }
+        }
+
+        // Now that the size of the media data is known, fill it in.
+        self.file.seek(SeekFrom::Start(self.mdat_pos + 8))?;
+        self.file.write_all(&(self.pos - self.mdat_pos).to_be_bytes())?;
+
+        let mut tracks = Vec::new();
+        for (idx, track) in self.tracks.drain(..).enumerate() {
+            let config = track.config;
+            let params = TrackParams {
+                track_id: idx as u32 + 1,
+                kind: config.kind,
+                enabled: config.enabled,
+                language: config.language,
+                name: config.name,
+                display_width: config.width,
+                display_height: config.height,
+            };
+            // The media timeline starts at the first decode time. If the first
+            // composition time is negative, the movie starts at composition
+            // time 0 and the samples before it are not presented.
+            let (start, shift) = match track.first_dts {
+                Some(first_dts) => {
+                    let start = cmp::max(track.first_pts, 0);
+                    let shift = (start - first_dts).try_into().map_err(|_| {
+                        ioerr!(InvalidInput, "Mp4Writer: track {}: start time too large", idx + 1)
+                    })?;
+                    (start as u64, shift)
+                },
+                None => (0, 0),
+            };
+            tracks.push(track_box(
+                &params,
+                config.timescale,
+                track.table,
+                config.sample_entry,
+                start,
+                shift,
+            ));
+        }
+        let mut movie = movie_box(tracks);
+
+        // The space between the FileTypeBox and the MediaDataBox. If the
+        // MovieBox does not fit, move the media data forward. The MovieBox can
+        // grow when a chunk offset table switches to 64 bits, so repeat until
+        // it fits. If there is space left, it must be large enough for a `free`
+        // box header.
+        let ftyp_size = self.mdat_pos - self.reserved;
+        let mut moved = 0u64;
+        let movie_size = loop {
+            let mut cb = CountBytes::new();
+            movie.to_bytes(&mut cb)?;
+            let size = cb.size();
+            let space = self.reserved + moved;
+            if size == space || size + 8 <= space {
+                break size;
+            }
+            let delta = if size > space { size - space } else { 8 };
+            for track in movie.tracks_mut() {
+                let stbl = track.media_mut().media_info_mut().sample_table_mut();
+                stbl.chunk_offset_table_mut().add_offset(delta as i64);
+            }
+            moved += delta;
+        };
+        if moved > 0 {
+            self.move_data(moved)?;
+        }
+
+        let mut buf = MemBuffer::new();
+        movie.to_bytes(&mut buf)?;
+        self.file.seek(SeekFrom::Start(ftyp_size))?;
+        self.file.write_all(&buf.into_vec())?;
+        let free = self.reserved + moved - movie_size;
+        if free > 0 {
+            self.file.write_all(&free_header(free))?;
+        }
+
+        self.file.seek(SeekFrom::Start(self.pos + moved))?;
+        self.file.flush()?;
+        Ok(self.file)
+    }
+
+    // Write the FileTypeBox, the reserved space, and the MediaDataBox header.
+    fn start(&mut self) -> io::Result<()> {
+        let mut mem = MemBuffer::new();
+        file_type_box().to_bytes(&mut mem)?;
+        let mut buf = mem.into_vec();
+
+        if self.reserved > 0 {
+            buf.extend_from_slice(&free_header(self.reserved));
+            buf.resize(buf.len() + self.reserved as usize - 8, 0);
+        }
+        self.mdat_pos = buf.len() as u64;
+
+        // Always use a large header, the size is filled in at the end.
+        buf.extend_from_slice(&1u32.to_be_bytes());
+        buf.extend_from_slice(b"mdat");
+        buf.extend_from_slice(&0u64.to_be_bytes());
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&buf)?;
+        self.pos = buf.len() as u64;
+        Ok(())
+    }
+
+    // Write the chunk that was collected for a track.
+    fn write_chunk(&mut self, idx: usize) -> io::Result<()> {
+        let track = &mut self.tracks[idx];
+        self.file.write_all(&track.chunk)?;
+        track.table.add_chunk(self.pos, track.chunk_samples);
+        self.pos += track.chunk.len() as u64;
+        track.chunk.clear();
+        track.chunk_samples = 0;
+        Ok(())
+    }
+
+    // Move everything from the MediaDataBox to the end of the file forward
+    // by `delta` bytes. Start at the end, so no data is overwritten.
+    fn move_data(&mut self, delta: u64) -> io::Result<()> {
+        let mut buf = vec![0u8; 1024 * 1024];
+        let mut end = self.pos;
+        while end > self.mdat_pos {
+            let len = cmp::min(end - self.mdat_pos, buf.len() as u64);
+            let buf = &mut buf[..len as usize];
+            self.file.seek(SeekFrom::Start(end - len))?;
+            self.file.read_exact(buf)?;
+            self.file.seek(SeekFrom::Start(end - len + delta))?;
+            self.file.write_all(buf)?;
+            end -= len;
+        }
+        Ok(())
+    }
+}
+
+// Header of a `free` box of `size` bytes.
+fn free_header(size: u64) -> [u8; 8] {
+    let mut hdr = [0u8; 8];
+    hdr[..4].copy_from_slice(&(size as u32).to_be_bytes());
+    hdr[4..].copy_from_slice(b"free");
+    hdr
+}
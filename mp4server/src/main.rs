@@ -16,6 +16,7 @@ use tower_http::compression::{
 use tower_http::cors::{self, CorsLayer};
 use tower_http::trace::TraceLayer;
 
+use mp4lib::streaming::encryption::{self, HlsEncryption, HlsMethod, KeyFile};
 use mp4lib::streaming::http_handler::{self, FsPath};
 
 #[derive(StructOpt, Debug)]
@@ -45,6 +46,22 @@ pub struct ServeOpts {
     #[structopt(short, long)]
     /// Root directory.
     pub dir: String,
+
+    #[structopt(long)]
+    /// Encrypt HLS / DASH segments with the keys from this file.
+    pub key_file: Option<String>,
+
+    #[structopt(long, requires_all = &["hls-secret-file", "hls-key-token-file"])]
+    /// Encrypt HLS segments (AES-128 or SAMPLE-AES).
+    pub hls_encryption: Option<HlsMethod>,
+
+    #[structopt(long)]
+    /// File with the master secret for HLS encryption keys.
+    pub hls_secret_file: Option<String>,
+
+    #[structopt(long)]
+    /// File with the token that clients send (Authorization: Bearer <token>) to get HLS encryption keys.
+    pub hls_key_token_file: Option<String>,
 }
 
 #[tokio::main]
@@ -75,6 +92,21 @@ async fn serve(opts: ServeOpts) -> Result<()> {
 
     let dir = opts.dir.clone();
 
+    if let Some(key_file) = opts.key_file.as_ref() {
+        encryption::set_key_provider(KeyFile::open(key_file)?)?;
+    }
+    if let (Some(method), Some(secret_file)) = (opts.hls_encryption, opts.hls_secret_file.as_ref()) {
+        let secret = std::fs::read_to_string(secret_file)?;
+        encryption::set_hls_encryption(HlsEncryption::new(method, secret.trim()))?;
+    }
+    if let Some(token_file) = opts.hls_key_token_file.as_ref() {
+        let token = std::fs::read_to_string(token_file)?.trim().to_string();
+        if token.is_empty() {
+            anyhow::bail!("{}: empty token", token_file);
+        }
+        http_handler::set_key_authorizer(move |req, _| has_bearer_token(req, &token))?;
+    }
+
     let x_app = HeaderName::from_static("x-application");
     let x_plb = HeaderName::from_static("x-playback-session-id");
 
@@ -188,6 +220,10 @@ async fn handle_request2(path: String, dir: String, req: &Request<()>) -> io::Re
         return Ok(response);
     }
 
+    if let Some(response) = http_handler::handle_dash(&req, path, filter_subs).await? {
+        return Ok(response);
+    }
+
     if let Some(response) = http_handler::handle_pseudo(&req, path).await? {
         return Ok(response);
     }
@@ -195,6 +231,24 @@ async fn handle_request2(path: String, dir: String, req: &Request<()>) -> io::Re
     http_handler::handle_file(&req, path, None).await
 }
 
+// Does the request have an `Authorization: Bearer <token>` header with this token.
+fn has_bearer_token(req: &Request<()>, token: &str) -> bool {
+    let value = match req.headers().get(http::header::AUTHORIZATION).map(|v| v.to_str()) {
+        Some(Ok(v)) => v,
+        _ => return false,
+    };
+    let given = match value.strip_prefix("Bearer ") {
+        Some(given) => given.trim().as_bytes(),
+        None => return false,
+    };
+    // Compare in constant time.
+    let diff = given
+        .iter()
+        .zip(token.as_bytes())
+        .fold(0, |acc, (a, b)| acc | (a ^ b));
+    given.len() == token.len() && diff == 0
+}
+
 fn translate_io_error(err: io::Error) -> StatusCode {
     use http::StatusCode as SC;
     match err.kind() {